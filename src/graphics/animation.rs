@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use glam::{Vec3, Mat4, Quat};
 
-use crate::graphics::texture::Texture;
+use crate::graphics::texture::{Texture, TextureRegion};
 
 pub struct Joint {
     pub id: i32,
@@ -254,15 +254,20 @@ impl AnimationPlayer {
 
 #[derive(Clone)]
 pub struct SpriteAnimation {
-    pub frames: Vec<Texture>,
+    pub frames: Vec<TextureRegion>,
     pub current_frame: usize,
     pub elapsed_time: Duration,
 }
 
 impl SpriteAnimation {
     pub fn new(frames: &Vec<Texture>) -> Self {
+        Self::from_regions(&frames.iter().map(TextureRegion::from_texture).collect::<Vec<_>>())
+    }
+
+    // Frames that live in one atlas or sprite sheet, so playing the animation never switches textures.
+    pub fn from_regions(frames: &[TextureRegion]) -> Self {
         Self {
-            frames: frames.clone(),
+            frames: frames.to_vec(),
             current_frame: 0,
             elapsed_time: Duration::from_secs(0),
         }
@@ -284,7 +289,7 @@ impl SpriteAnimation {
         }
     }
 
-    pub fn get_current_frame(&self) -> &Texture {
+    pub fn get_current_frame(&self) -> &TextureRegion {
         &self.frames[self.current_frame]
     }
 }
\ No newline at end of file
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use glam::UVec2;
+use image::{imageops, io::Reader, RgbaImage};
+use serde_json::Value;
+
+use super::texture::{Texture, TextureDescriptor, TextureRegion};
+
+#[derive(Debug, Clone)]
+pub struct AtlasError {
+    pub error_log: String,
+}
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error_log.as_str())
+    }
+}
+
+impl Error for AtlasError {}
+
+impl AtlasError {
+    pub fn new(string: String) -> Self {
+        AtlasError {
+            error_log: string,
+        }
+    }
+}
+
+// Shelf packing: rectangles go into rows left to right, tallest first, and a full row starts the next one. Positions
+// are top left corners in image pixels. Tries square-ish power of two sizes from the smallest that could fit upwards.
+pub struct RectanglePacker {
+    pub padding: u32,
+    pub max_size: u32,
+}
+
+impl RectanglePacker {
+    pub fn new(padding: u32, max_size: u32) -> Self {
+        RectanglePacker {
+            padding,
+            max_size,
+        }
+    }
+
+    // Returns the atlas size and a position for every size, in the same order, or None if they don't fit.
+    pub fn pack(&self, sizes: &[UVec2]) -> Option<(UVec2, Vec<UVec2>)> {
+        if sizes.is_empty() {
+            return Some((UVec2::ONE, Vec::new()));
+        }
+
+        let area: u64 = sizes.iter().map(|size| (size.x + self.padding) as u64 * (size.y + self.padding) as u64).sum();
+        let widest = sizes.iter().map(|size| size.x + self.padding * 2).max().unwrap();
+
+        let mut width = ((area as f64).sqrt() as u32).max(widest).next_power_of_two();
+
+        while width <= self.max_size {
+            if let Some((height, positions)) = self.pack_shelves(sizes, width) {
+                if height <= width {
+                    return Some((UVec2::new(width, height.next_power_of_two()), positions));
+                }
+            }
+
+            width *= 2;
+        }
+
+        None
+    }
+
+    fn pack_shelves(&self, sizes: &[UVec2], width: u32) -> Option<(u32, Vec<UVec2>)> {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by(|a, b| sizes[*b].y.cmp(&sizes[*a].y).then(sizes[*b].x.cmp(&sizes[*a].x)));
+
+        let mut positions = vec![UVec2::ZERO; sizes.len()];
+        let mut cursor = UVec2::new(self.padding, self.padding);
+        let mut shelf_height = 0;
+
+        for i in order {
+            let size = sizes[i];
+
+            if size.x + self.padding * 2 > width {
+                return None;
+            }
+
+            if cursor.x + size.x + self.padding > width {
+                cursor = UVec2::new(self.padding, cursor.y + shelf_height + self.padding);
+                shelf_height = 0;
+            }
+
+            positions[i] = cursor;
+            cursor.x += size.x + self.padding;
+            shelf_height = shelf_height.max(size.y);
+        }
+
+        Some((cursor.y + shelf_height + self.padding, positions))
+    }
+}
+
+// Packs separate images into one texture, so everything drawn from it can share a bind and a batch.
+pub struct TextureAtlasBuilder {
+    pub images: Vec<(String, RgbaImage)>,
+    pub padding: u32,
+    pub max_size: u32,
+    pub descriptor: TextureDescriptor,
+}
+
+impl Default for TextureAtlasBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextureAtlasBuilder {
+    pub fn new() -> Self {
+        TextureAtlasBuilder {
+            images: Vec::new(),
+            padding: 2,
+            max_size: 4096,
+            descriptor: TextureDescriptor::new(),
+        }
+    }
+
+    pub fn with_descriptor(mut self, descriptor: &TextureDescriptor) -> Self {
+        self.descriptor = *descriptor;
+        self
+    }
+
+    // Empty pixels around every image, so linear filtering doesn't bleed neighbours into each other.
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn with_max_size(mut self, max_size: u32) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn add_image(&mut self, name: &str, image: &RgbaImage) {
+        self.images.push((String::from(name), image.clone()));
+    }
+
+    pub fn with_image(mut self, name: &str, image: &RgbaImage) -> Self {
+        self.add_image(name, image);
+        self
+    }
+
+    // The region is named after the file path, like TextureCache.
+    pub fn add_file(&mut self, file: &str) {
+        self.add_image(file, &Reader::open(file).unwrap().decode().unwrap().into_rgba8());
+    }
+
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.add_file(file);
+        self
+    }
+
+    pub fn build(&self) -> TextureAtlas {
+        let sizes: Vec<UVec2> = self.images.iter().map(|(_, image)| UVec2::new(image.width(), image.height())).collect();
+
+        let (size, positions) = RectanglePacker::new(self.padding, self.max_size)
+            .pack(&sizes)
+            .unwrap_or_else(|| panic!("{} images don't fit in a {1}x{1} texture atlas", self.images.len(), self.max_size));
+
+        let mut atlas_image = RgbaImage::new(size.x, size.y);
+
+        for ((_, image), position) in self.images.iter().zip(positions.iter()) {
+            imageops::replace(&mut atlas_image, image, position.x as i64, position.y as i64);
+        }
+
+        let mut atlas = TextureAtlas::new(&Texture::from_image_with(&atlas_image, &self.descriptor), &size);
+
+        for ((name, image), position) in self.images.iter().zip(positions.iter()) {
+            atlas.add_region(name, position, &UVec2::new(image.width(), image.height()));
+        }
+
+        atlas
+    }
+}
+
+// One texture and named rectangles of it. Regions keep the order they were added in, which is the frame order for
+// sprite sheets and atlas files.
+pub struct TextureAtlas {
+    pub texture: Texture,
+    pub size: UVec2,
+    pub regions: HashMap<String, TextureRegion>,
+    pub names: Vec<String>,
+}
+
+impl TextureAtlas {
+    pub fn new(texture: &Texture, size: &UVec2) -> Self {
+        TextureAtlas {
+            texture: *texture,
+            size: *size,
+            regions: HashMap::new(),
+            names: Vec::new(),
+        }
+    }
+
+    // position is the top left corner in image pixels.
+    pub fn add_region(&mut self, name: &str, position: &UVec2, size: &UVec2) {
+        let region = TextureRegion::from_pixels(&self.texture, &self.size, position, size);
+
+        if self.regions.insert(String::from(name), region).is_none() {
+            self.names.push(String::from(name));
+        }
+    }
+
+    // A sprite sheet of equally sized cells, named "0", "1", ... left to right, top to bottom.
+    pub fn from_grid(texture: &Texture, cell_size: &UVec2) -> Result<Self, AtlasError> {
+        if cell_size.x == 0 || cell_size.y == 0 {
+            return Err(AtlasError::new(format!("invalid cell size {}x{}", cell_size.x, cell_size.y)));
+        }
+
+        let size = texture.get_size();
+        let mut atlas = TextureAtlas::new(texture, &size);
+
+        let columns = size.x / cell_size.x;
+        let rows = size.y / cell_size.y;
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let name = (row * columns + column).to_string();
+                atlas.add_region(&name, &UVec2::new(column * cell_size.x, row * cell_size.y), cell_size);
+            }
+        }
+
+        Ok(atlas)
+    }
+
+    pub fn from_grid_file(file: &str, cell_size: &UVec2) -> Result<Self, AtlasError> {
+        if cell_size.x == 0 || cell_size.y == 0 {
+            return Err(AtlasError::new(format!("{}: invalid cell size {}x{}", file, cell_size.x, cell_size.y)));
+        }
+
+        Self::from_grid(&load_texture(Path::new(file))?, cell_size)
+    }
+
+    // TexturePacker's JSON export, either the hash or the array flavour. The image is looked up next to the json
+    // file. Trimmed frames use their packed rectangle, rotated frames aren't supported.
+    pub fn from_texture_packer_json(file: &str) -> Result<Self, AtlasError> {
+        let source = fs::read_to_string(file).map_err(|error| AtlasError::new(format!("{}: {}", file, error)))?;
+        let json: Value = serde_json::from_str(&source).map_err(|error| AtlasError::new(format!("{}: {}", file, error)))?;
+        let error = |message: String| AtlasError::new(format!("{}: {}", file, message));
+
+        let image = json["meta"]["image"].as_str().ok_or_else(|| error(String::from("no meta.image")))?;
+
+        let frames: Vec<(String, &Value)> = match &json["frames"] {
+            Value::Object(frames) => frames.iter().map(|(name, frame)| (name.clone(), frame)).collect(),
+            Value::Array(frames) => frames.iter().map(|frame| {
+                frame["filename"].as_str().map(|name| (String::from(name), frame)).ok_or_else(|| error(String::from("frame without a filename")))
+            }).collect::<Result<_, _>>()?,
+            _ => return Err(error(String::from("no frames"))),
+        };
+
+        // Read every frame before the image is uploaded, so a broken file doesn't leave a texture behind.
+        let mut regions = Vec::with_capacity(frames.len());
+
+        for (name, frame) in frames {
+            if frame["rotated"].as_bool().unwrap_or(false) {
+                return Err(error(format!("rotated frame {name} isn't supported, export without rotation")));
+            }
+
+            let rect = &frame["frame"];
+            let read = |key: &str| rect[key].as_u64().map(|value| value as u32).ok_or_else(|| error(format!("frame {name} has no frame.{key}")));
+
+            regions.push((UVec2::new(read("x")?, read("y")?), UVec2::new(read("w")?, read("h")?), name));
+        }
+
+        let texture = load_texture(&Path::new(file).parent().unwrap_or(Path::new("")).join(image))?;
+        let size = texture.get_size();
+        let mut atlas = TextureAtlas::new(&texture, &size);
+
+        for (position, region_size, name) in regions {
+            atlas.add_region(&name, &position, &region_size);
+        }
+
+        Ok(atlas)
+    }
+
+    pub fn get(&self, name: &str) -> Option<TextureRegion> {
+        self.regions.get(name).copied()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.regions.contains_key(name)
+    }
+
+    // Every region in order, for example the frames of a sprite sheet animation.
+    pub fn get_regions(&self) -> Vec<TextureRegion> {
+        self.names.iter().map(|name| self.regions[name]).collect()
+    }
+
+    // Regions whose names start with prefix, in order, for atlases holding several animations.
+    pub fn get_regions_with_prefix(&self, prefix: &str) -> Vec<TextureRegion> {
+        self.names.iter().filter(|name| name.starts_with(prefix)).map(|name| self.regions[name]).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn delete(&self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture.texture_id);
+        }
+    }
+}
+
+fn load_texture(path: &Path) -> Result<Texture, AtlasError> {
+    let image = image::open(path).map_err(|error| AtlasError::new(format!("{}: {}", path.display(), error)))?;
+
+    Ok(Texture::from_image(&image.into_rgba8()))
+}
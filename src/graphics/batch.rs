@@ -0,0 +1,289 @@
+use std::cell::Cell;
+
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+use crate::graphics::view::View;
+use super::material::Material;
+use super::math::Deg;
+use super::renderable::{Renderable, RenderableSprite};
+use super::shader::{ShaderProgram, UniformValue};
+use super::texture::{Texture, TextureRegion};
+use super::vertex::{Vertex, IBO, VAO, VBO};
+use super::view::GraphicsLayer;
+
+// Texture units used by one draw call. 16 is the minimum every GL 4 driver has for fragment shaders.
+pub const MAX_BATCH_TEXTURES: usize = 16;
+
+#[derive(Clone, Copy)]
+pub struct BatchedSprite {
+    pub texture: Texture,
+    pub shader_index: usize,
+    pub transform: Mat4,
+    // (u_min, v_min, u_max, v_max)
+    pub uv_rect: Vec4,
+    pub color: Vec3,
+    // Like RenderableSprite's, higher draws in front.
+    pub z_order: i32,
+}
+
+// Collects sprites for a frame and draws them with as few draw calls as possible. Sprites are sorted by z order,
+// then shader and texture, and up to MAX_BATCH_TEXTURES textures share a call through the sampler index in
+// tex_coords.z, which the texture fragment shader template already reads. In 2D the depth buffer is ignored, the
+// z order alone decides what's in front.
+pub struct SpriteBatch {
+    pub vao: VAO,
+    pub vbo: VBO,
+    pub ibo: IBO,
+    pub shader_programs: Vec<ShaderProgram>,
+    pub sprites: Vec<BatchedSprite>,
+    pub max_sprites: usize,
+    pub last_draw_calls: Cell<usize>,
+}
+
+impl SpriteBatch {
+    // max_sprites is how many quads fit in one draw call. More sprites than that still work, they just take more calls.
+    // It's at least one.
+    pub fn new(max_sprites: usize, shader_program: &ShaderProgram) -> Self {
+        let max_sprites = max_sprites.max(1);
+        let mut indices: Vec<u32> = Vec::with_capacity(max_sprites * 6);
+
+        for i in 0..max_sprites as u32 {
+            let first = i * 4;
+            indices.extend_from_slice(&[first, first + 1, first + 2, first + 2, first + 3, first]);
+        }
+
+        let mut vao = VAO::new();
+        let vbo = VBO::new();
+        let ibo = IBO::new();
+
+        vao.bind(true);
+
+        vbo.bind(true);
+        vbo.allocate(max_sprites * 4);
+
+        ibo.bind(true);
+        ibo.add_large_data(&indices);
+
+        vao.set_vertex_attribute(shader_program.clone(), "in_position", 3);
+        vao.set_vertex_attribute(shader_program.clone(), "in_tex_coords", 3);
+        vao.set_vertex_attribute(shader_program.clone(), "in_normal", 3);
+        vao.set_vertex_attribute(shader_program.clone(), "in_bone_ids", 3);
+        vao.set_vertex_attribute(shader_program.clone(), "in_bone_weights", 3);
+        vao.set_vertex_attribute(shader_program.clone(), "in_color", 3);
+
+        vao.bind(false);
+        vbo.bind(false);
+
+        let mut batch = SpriteBatch {
+            vao,
+            vbo,
+            ibo,
+            shader_programs: Vec::new(),
+            sprites: Vec::new(),
+            max_sprites,
+            last_draw_calls: Cell::new(0),
+        };
+
+        batch.add_shader(shader_program);
+
+        batch
+    }
+
+    // Extra shaders must use the same vertex layout as the template shaders. Returns the index to draw with.
+    pub fn add_shader(&mut self, shader_program: &ShaderProgram) -> usize {
+        shader_program.set_uniform_vec_i32("sampler_objs", &(0..32).collect());
+        self.shader_programs.push(shader_program.clone());
+
+        self.shader_programs.len() - 1
+    }
+
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    pub fn push(&mut self, sprite: BatchedSprite) {
+        self.sprites.push(sprite);
+    }
+
+    // Rotation is in degrees around z, like RenderableSprite.
+    pub fn draw(&mut self, texture: &Texture, position: &Vec3, size: &Vec2, rotation: f32) {
+        let transform = Mat4::from_translation(*position)
+            * Mat4::from_rotation_z(Deg(rotation).to_radians().as_float())
+            * Mat4::from_scale(Vec3::new(size.x, size.y, 1.0));
+
+        self.draw_transformed(texture, &transform, &Vec4::new(0.0, 0.0, 1.0, 1.0));
+    }
+
+    // Draws part of an atlas or sprite sheet. Regions from the same atlas share a texture, so they batch together.
+    pub fn draw_region(&mut self, region: &TextureRegion, position: &Vec3, size: &Vec2, rotation: f32) {
+        let transform = Mat4::from_translation(*position)
+            * Mat4::from_rotation_z(Deg(rotation).to_radians().as_float())
+            * Mat4::from_scale(Vec3::new(size.x, size.y, 1.0));
+
+        self.draw_transformed(&region.texture, &transform, &region.uv_rect);
+    }
+
+    pub fn draw_transformed(&mut self, texture: &Texture, transform: &Mat4, uv_rect: &Vec4) {
+        self.push(BatchedSprite {
+            texture: *texture,
+            shader_index: 0,
+            transform: *transform,
+            uv_rect: *uv_rect,
+            color: Vec3::new(1.0, 1.0, 1.0),
+            z_order: 0,
+        });
+    }
+
+    // Takes the sprite's current texture or region, transform and z order. Tint and opacity aren't batched. Sprites
+    // without a texture have nothing to batch and are skipped.
+    pub fn draw_sprite(&mut self, sprite: &RenderableSprite) {
+        if let Some((texture, uv_rect)) = sprite.get_current_texture() {
+            self.push(BatchedSprite {
+                texture,
+                shader_index: 0,
+                transform: sprite.get_model_matrix(),
+                uv_rect,
+                color: Vec3::new(1.0, 1.0, 1.0),
+                z_order: sprite.z_order,
+            });
+        }
+    }
+
+    fn push_quad(vertices: &mut Vec<Vertex>, sprite: &BatchedSprite, slot: usize) {
+        let corners = [
+            (Vec3::new(-0.5, -0.5, 0.0), Vec2::new(sprite.uv_rect.x, sprite.uv_rect.y)),
+            (Vec3::new(0.5, -0.5, 0.0), Vec2::new(sprite.uv_rect.z, sprite.uv_rect.y)),
+            (Vec3::new(0.5, 0.5, 0.0), Vec2::new(sprite.uv_rect.z, sprite.uv_rect.w)),
+            (Vec3::new(-0.5, 0.5, 0.0), Vec2::new(sprite.uv_rect.x, sprite.uv_rect.w)),
+        ];
+
+        for (corner, uv) in corners {
+            let mut vertex = Vertex::new(
+                &sprite.transform.transform_point3(corner),
+                &Vec3::new(uv.x, uv.y, slot as f32),
+                &Vec3::new(0.0, 0.0, 1.0),
+            );
+            vertex.color = sprite.color;
+
+            vertices.push(vertex);
+        }
+    }
+
+    fn draw_call(&self, layer: &GraphicsLayer, shader_index: usize, textures: &[Texture], vertices: &[Vertex]) {
+        if vertices.is_empty() {
+            return;
+        }
+
+        let shader_program = &self.shader_programs[shader_index];
+
+        let view_matrix = match &layer.view {
+            View::View2D(view) => view.get_view_matrix(),
+            View::View3D(view) => view.get_view_matrix(),
+        };
+
+        // Tints and uv rects left on the program by sprites and materials are reset, batched sprites bake them in.
+        Material::apply_uniforms(shader_program, &[("should_sample_texture", UniformValue::Bool(true))]);
+        shader_program.set_uniform_mat4_f32("mvp", &(view_matrix * layer.get_graphics_layer_matrix()));
+
+        for (slot, texture) in textures.iter().enumerate() {
+            texture.bind(slot as u32, true);
+        }
+
+        self.vbo.bind(true);
+        self.vbo.update_data(0, vertices);
+        self.vbo.bind(false);
+
+        shader_program.use_program(true);
+        self.vao.render(vertices.len() / 4 * 6);
+        shader_program.use_program(false);
+
+        self.last_draw_calls.set(self.last_draw_calls.get() + 1);
+    }
+
+    // Draws everything and empties the batch for the next frame.
+    pub fn flush(&mut self, layer: &GraphicsLayer) {
+        self.render(layer);
+        self.clear();
+    }
+}
+
+impl Renderable for SpriteBatch {
+    // Sprites carry their own transforms.
+    fn get_model_matrix(&self) -> Mat4 {
+        Mat4::IDENTITY
+    }
+
+    fn render(&self, layer: &GraphicsLayer) {
+        self.last_draw_calls.set(0);
+
+        let is_2d = matches!(layer.view, View::View2D(_));
+        let depth_test_enabled = unsafe { gl::IsEnabled(gl::DEPTH_TEST) == gl::TRUE };
+
+        if is_2d {
+            unsafe {
+                gl::Disable(gl::DEPTH_TEST);
+            }
+        }
+
+        let mut order: Vec<usize> = (0..self.sprites.len()).collect();
+        order.sort_by_key(|i| (self.sprites[*i].z_order, self.sprites[*i].shader_index, self.sprites[*i].texture.texture_id));
+
+        let mut vertices: Vec<Vertex> = Vec::with_capacity(self.sprites.len().min(self.max_sprites) * 4);
+        let mut textures: Vec<Texture> = Vec::new();
+        let mut current_shader = order.first().map(|i| self.sprites[*i].shader_index).unwrap_or(0);
+
+        for i in order {
+            let sprite = &self.sprites[i];
+
+            // Sorted by texture within a z order, so a texture we've seen this call is always the last one.
+            let is_new_texture = textures.last().map(|texture| texture.texture_id != sprite.texture.texture_id).unwrap_or(true);
+
+            let is_full = vertices.len() / 4 >= self.max_sprites || (is_new_texture && textures.len() >= MAX_BATCH_TEXTURES);
+
+            if sprite.shader_index != current_shader || is_full {
+                self.draw_call(layer, current_shader, &textures, &vertices);
+
+                vertices.clear();
+                textures.clear();
+                current_shader = sprite.shader_index;
+            }
+
+            if textures.last().map(|texture| texture.texture_id != sprite.texture.texture_id).unwrap_or(true) {
+                textures.push(sprite.texture);
+            }
+
+            Self::push_quad(&mut vertices, sprite, textures.len() - 1);
+        }
+
+        self.draw_call(layer, current_shader, &textures, &vertices);
+
+        if is_2d && depth_test_enabled {
+            unsafe {
+                gl::Enable(gl::DEPTH_TEST);
+            }
+        }
+    }
+
+    fn is_transparent(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for SpriteBatch {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao.id);
+        }
+
+        self.vbo.delete();
+        self.ibo.delete();
+    }
+}
@@ -17,11 +17,15 @@ impl ColladaLoader {
         let mut normal_array: Vec<Vec3> = Vec::new();
         let mut texture_array: Vec<Vec2> = Vec::new();
         let mut color_array: Vec<Vec3> = Vec::new();
+        let mut tangent_array: Vec<Vec3> = Vec::new();
+        let mut bitangent_array: Vec<Vec3> = Vec::new();
 
         let mut indices: Vec<u32> = Vec::new();
         let mut normal_indices: Vec<u32> = Vec::new();
         let mut texture_indices: Vec<u32> = Vec::new();
         let mut color_indices: Vec<u32> = Vec::new();
+        let mut tangent_indices: Vec<u32> = Vec::new();
+        let mut bitangent_indices: Vec<u32> = Vec::new();
 
         for geometry in doc.iter::<Geometry>() {
             let mesh = geometry.element.as_mesh().unwrap();
@@ -44,6 +48,26 @@ impl ColladaLoader {
                     }
                 }
 
+                let source_id = source.id.clone().unwrap();
+
+                // Checked first, "binormals" would pass for normals and Blender's "map-0-tangents" for texture coordinates.
+                if source_id.contains("binormal") || source_id.contains("bitangent") || source_id.contains("tangent") {
+                    let tangents = source.array.clone().unwrap();
+
+                    if let ArrayElement::Float(tangents) = tangents {
+                        for i in 0..tangents.len() / 3 {
+                            let tangent = Vec3::new(tangents[i * 3], tangents[i * 3 + 1], tangents[i * 3 + 2]);
+
+                            match source_id.contains("binormal") || source_id.contains("bitangent") {
+                                true => bitangent_array.push(tangent),
+                                false => tangent_array.push(tangent),
+                            }
+                        }
+                    }
+
+                    continue;
+                }
+
                 if source.id.clone().unwrap().contains("normal") {
                     let normals = source.array.clone().unwrap();
 
@@ -121,7 +145,10 @@ impl ColladaLoader {
                 let mut normal_offset = 1 as usize;
                 let mut texture_offset = 2 as usize;
                 let mut color_offset = 3 as usize;
+                let mut tangent_offset = 0_usize;
+                let mut bitangent_offset = 0_usize;
                 let mut found_semantics: (bool, bool, bool) = (false, false, false);
+                let mut found_tangents: (bool, bool) = (false, false);
 
                 let mut max_offset = stride - 1;
                 for input in inputs.iter() {
@@ -143,6 +170,16 @@ impl ColladaLoader {
                         found_semantics.2 = true;
                     }
 
+                    if semantic == "TEXTANGENT" || semantic == "TANGENT" {
+                        tangent_offset = offset;
+                        found_tangents.0 = true;
+                    }
+
+                    if semantic == "TEXBINORMAL" || semantic == "BINORMAL" {
+                        bitangent_offset = offset;
+                        found_tangents.1 = true;
+                    }
+
                     if offset > max_offset {
                         max_offset = offset;
                     }
@@ -164,6 +201,14 @@ impl ColladaLoader {
                     if found_semantics.2 {
                         color_indices.push(prim_vec[i + color_offset]);
                     }
+
+                    if found_tangents.0 {
+                        tangent_indices.push(prim_vec[i + tangent_offset]);
+                    }
+
+                    if found_tangents.1 {
+                        bitangent_indices.push(prim_vec[i + bitangent_offset]);
+                    }
                 }
                 
             }
@@ -209,6 +254,16 @@ impl ColladaLoader {
             vertex.texture = Vec3::new(texture.x, texture.y, 0.0);
             vertex.color = color;
 
+            if i < tangent_indices.len() && (tangent_indices[i] as usize) < tangent_array.len() {
+                vertex.tangent = tangent_array[tangent_indices[i] as usize];
+            }
+
+            if i < bitangent_indices.len() && (bitangent_indices[i] as usize) < bitangent_array.len() {
+                vertex.bitangent = bitangent_array[bitangent_indices[i] as usize];
+            } else if vertex.tangent != Vec3::ZERO {
+                vertex.bitangent = vertex.normals.cross(vertex.tangent);
+            }
+
             vertices[vertex_index] = vertex.clone();
         }
 
@@ -130,11 +130,33 @@ impl ColorBuffer {
     }
 
     pub fn get_color_at_pixel(&self, x: u32, y: u32) -> Color {
-        self.buffer[x as usize + y as usize * 4]
+        self.buffer[x as usize + y as usize * self.width as usize]
     }
 
     pub fn set_color_at_pixel(&mut self, x: u32, y: u32, color: &Color) {
-        self.buffer[x as usize + y as usize * 4] = color.clone();
+        self.buffer[x as usize + y as usize * self.width as usize] = color.clone();
+    }
+
+    // Copies a rectangle out of the buffer. x and y count from the first row, which is the bottom row for buffers
+    // that came from images or textures. Whatever is past the edges is cut off, so the copy can be smaller than asked.
+    pub fn get_sub_buffer(&self, x: u32, y: u32, width: u32, height: u32) -> ColorBuffer {
+        let x = x.min(self.width);
+        let y = y.min(self.height);
+        let width = width.min(self.width - x);
+        let height = height.min(self.height - y);
+
+        let mut color_vec = Vec::<Color>::with_capacity((width * height) as usize);
+
+        for row in y..y + height {
+            let start = (x + row * self.width) as usize;
+            color_vec.extend_from_slice(&self.buffer[start..start + width as usize]);
+        }
+
+        ColorBuffer {
+            buffer: color_vec,
+            width,
+            height,
+        }
     }
 
     pub fn build_texture(&self) -> Texture {
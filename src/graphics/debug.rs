@@ -0,0 +1,477 @@
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use gl::types::*;
+use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+
+use super::color::Color;
+use super::font::{Font, TextLayout};
+use super::shader::{ShaderBuilderTemplate, ShaderError, ShaderProgram};
+use super::view::View;
+
+const DEBUG_SHADER_VERSION: &str = "#version 450 core";
+
+// Segments in a full circle, spheres are three of them.
+const CIRCLE_SEGMENTS: usize = 32;
+
+struct DebugLine {
+    start: Vec3,
+    end: Vec3,
+    color: Vec4,
+    depth_test: bool,
+    expires: Option<Instant>,
+}
+
+struct DebugLabel {
+    position: Vec3,
+    text: String,
+    color: Color,
+    expires: Option<Instant>,
+}
+
+pub struct DebugDrawData {
+    lines: Vec<DebugLine>,
+    labels: Vec<DebugLabel>,
+    shader_program: Option<ShaderProgram>,
+    line_vao: GLuint,
+    line_buffer: GLuint,
+    label_vao: GLuint,
+    label_buffer: GLuint,
+}
+
+// Lines, shapes and labels collected from anywhere during a frame and drawn together by GraphicsLayer::
+// flush_debug_draw, in one draw call for the lines. Clones share what was drawn, so a handle can be kept around or
+// read from App's resources by systems. Each handle has its own transform, duration and depth test, which apply to
+// what's drawn through it.
+#[derive(Clone)]
+pub struct DebugDraw {
+    pub data: Arc<Mutex<DebugDrawData>>,
+    pub transform: Mat4,
+    // How long shapes stay after being drawn. Zero draws them in the next flush only, which is fine from render but
+    // flickers from update when ticks are slower than frames, a tick's duration fixes that.
+    pub duration: Duration,
+    // Off draws on top of everything.
+    pub depth_test: bool,
+}
+
+impl Default for DebugDraw {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        DebugDraw {
+            data: Arc::new(Mutex::new(DebugDrawData {
+                lines: Vec::new(),
+                labels: Vec::new(),
+                shader_program: None,
+                line_vao: 0,
+                line_buffer: 0,
+                label_vao: 0,
+                label_buffer: 0,
+            })),
+            transform: Mat4::IDENTITY,
+            duration: Duration::ZERO,
+            depth_test: true,
+        }
+    }
+
+    pub fn with_transform(mut self, transform: &Mat4) -> Self {
+        self.transform = *transform;
+        self
+    }
+
+    pub fn with_duration(mut self, duration: &Duration) -> Self {
+        self.duration = *duration;
+        self
+    }
+
+    pub fn with_depth_test(mut self, depth_test: bool) -> Self {
+        self.depth_test = depth_test;
+        self
+    }
+
+    fn get_expiry(&self) -> Option<Instant> {
+        match self.duration.is_zero() {
+            true => None,
+            false => Some(Instant::now() + self.duration),
+        }
+    }
+
+    pub fn line(&self, start: &Vec3, end: &Vec3, color: &Color) {
+        self.lines(&[(*start, *end)], color);
+    }
+
+    fn lines(&self, lines: &[(Vec3, Vec3)], color: &Color) {
+        let expires = self.get_expiry();
+        let mut data = self.data.lock().unwrap();
+
+        for (start, end) in lines {
+            data.lines.push(DebugLine {
+                start: self.transform.transform_point3(*start),
+                end: self.transform.transform_point3(*end),
+                color: color.to_vec4(),
+                depth_test: self.depth_test,
+                expires,
+            });
+        }
+    }
+
+    // Connects the points in order, and the last one back to the first if closed.
+    pub fn polyline(&self, points: &[Vec3], closed: bool, color: &Color) {
+        let mut lines: Vec<(Vec3, Vec3)> = points.windows(2).map(|pair| (pair[0], pair[1])).collect();
+
+        if closed && points.len() > 2 {
+            lines.push((points[points.len() - 1], points[0]));
+        }
+
+        self.lines(&lines, color);
+    }
+
+    // The head is a quarter of the arrow's length.
+    pub fn arrow(&self, start: &Vec3, end: &Vec3, color: &Color) {
+        let direction = *end - *start;
+        let length = direction.length();
+
+        if length == 0.0 {
+            return;
+        }
+
+        let (side, up) = (direction / length).any_orthonormal_pair();
+        let back = *end - direction * 0.25;
+        let width = length * 0.1;
+
+        self.lines(&[
+            (*start, *end),
+            (*end, back + side * width),
+            (*end, back - side * width),
+            (*end, back + up * width),
+            (*end, back - up * width),
+        ], color);
+    }
+
+    pub fn rect(&self, min: &Vec2, max: &Vec2, color: &Color) {
+        self.polyline(&[
+            Vec3::new(min.x, min.y, 0.0),
+            Vec3::new(max.x, min.y, 0.0),
+            Vec3::new(max.x, max.y, 0.0),
+            Vec3::new(min.x, max.y, 0.0),
+        ], true, color);
+    }
+
+    pub fn wire_box(&self, min: &Vec3, max: &Vec3, color: &Color) {
+        let center = (*min + *max) / 2.0;
+        self.wire_box_transformed(&Mat4::from_scale_rotation_translation(*max - *min, Quat::IDENTITY, center), color);
+    }
+
+    // The cube from -0.5 to 0.5 through transform, for oriented bounds.
+    pub fn wire_box_transformed(&self, transform: &Mat4, color: &Color) {
+        let corners: Vec<Vec3> = (0..8).map(|i| {
+            transform.transform_point3(Vec3::new(
+                if i & 1 == 0 { -0.5 } else { 0.5 },
+                if i & 2 == 0 { -0.5 } else { 0.5 },
+                if i & 4 == 0 { -0.5 } else { 0.5 },
+            ))
+        }).collect();
+
+        // Corners one bit apart share an edge.
+        let mut lines = Vec::with_capacity(12);
+
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    lines.push((corners[i], corners[i | bit]));
+                }
+            }
+        }
+
+        self.lines(&lines, color);
+    }
+
+    pub fn circle(&self, center: &Vec3, radius: f32, normal: &Vec3, color: &Color) {
+        let (x_axis, y_axis) = normal.normalize().any_orthonormal_pair();
+
+        let points: Vec<Vec3> = (0..CIRCLE_SEGMENTS).map(|i| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+            *center + (x_axis * angle.cos() + y_axis * angle.sin()) * radius
+        }).collect();
+
+        self.polyline(&points, true, color);
+    }
+
+    pub fn sphere(&self, center: &Vec3, radius: f32, color: &Color) {
+        self.circle(center, radius, &Vec3::X, color);
+        self.circle(center, radius, &Vec3::Y, color);
+        self.circle(center, radius, &Vec3::Z, color);
+    }
+
+    // cells by cells squares of cell_size, centered on center and facing normal. Use Vec3::Y for a floor and
+    // Vec3::Z in 2D.
+    pub fn grid(&self, center: &Vec3, normal: &Vec3, cell_size: f32, cells: u32, color: &Color) {
+        let (x_axis, y_axis) = normal.normalize().any_orthonormal_pair();
+        let half = cells as f32 * cell_size / 2.0;
+
+        let mut lines = Vec::with_capacity((cells as usize + 1) * 2);
+
+        for i in 0..=cells {
+            let offset = i as f32 * cell_size - half;
+
+            lines.push((*center + x_axis * offset - y_axis * half, *center + x_axis * offset + y_axis * half));
+            lines.push((*center + y_axis * offset - x_axis * half, *center + y_axis * offset + x_axis * half));
+        }
+
+        self.lines(&lines, color);
+    }
+
+    // Red, green and blue for the transform's X, Y and Z.
+    pub fn axes(&self, transform: &Mat4, length: f32) {
+        let origin = transform.transform_point3(Vec3::ZERO);
+
+        self.line(&origin, &transform.transform_point3(Vec3::X * length), &Color::new(255, 0, 0, 255));
+        self.line(&origin, &transform.transform_point3(Vec3::Y * length), &Color::new(0, 255, 0, 255));
+        self.line(&origin, &transform.transform_point3(Vec3::Z * length), &Color::new(0, 0, 255, 255));
+    }
+
+    // Screen aligned text at the font's size, always on top. Needs a font on the flushing GraphicsLayer.
+    pub fn label(&self, position: &Vec3, text: &str, color: &Color) {
+        let expires = self.get_expiry();
+
+        self.data.lock().unwrap().labels.push(DebugLabel {
+            position: self.transform.transform_point3(*position),
+            text: String::from(text),
+            color: *color,
+            expires,
+        });
+    }
+
+    // Frees the GL objects, the next flush makes them again.
+    pub fn delete(&self) {
+        self.data.lock().unwrap().delete();
+    }
+
+    // Drops everything, including shapes with time left.
+    pub fn clear(&self) {
+        let mut data = self.data.lock().unwrap();
+        data.lines.clear();
+        data.labels.clear();
+    }
+
+    // Draws and then forgets everything whose time is up. Needs a current GL context.
+    pub fn flush(&self, view: &View, font: Option<&Font>) -> Result<(), ShaderError> {
+        let mut data = self.data.lock().unwrap();
+
+        if data.shader_program.is_none() {
+            data.init()?;
+        }
+
+        let (view_projection, view_size) = match view {
+            View::View2D(view) => (view.get_view_matrix(), view.size),
+            View::View3D(view) => (view.get_view_matrix(), view.size),
+        };
+
+        data.render_lines(&view_projection);
+
+        if let Some(font) = font {
+            data.render_labels(&view_projection, &view_size, font);
+        }
+
+        let now = Instant::now();
+        data.lines.retain(|line| line.expires.is_some_and(|expires| expires > now));
+        data.labels.retain(|label| label.expires.is_some_and(|expires| expires > now));
+
+        Ok(())
+    }
+}
+
+impl DebugDrawData {
+    fn init(&mut self) -> Result<(), ShaderError> {
+        self.shader_program = Some(ShaderProgram::from_builders(
+            &ShaderBuilderTemplate::debug_vertex_shader(DEBUG_SHADER_VERSION),
+            &ShaderBuilderTemplate::debug_fragment_shader(DEBUG_SHADER_VERSION),
+        )?);
+
+        unsafe {
+            gl::GenVertexArrays(1, &mut self.line_vao);
+            gl::GenBuffers(1, &mut self.line_buffer);
+            Self::set_attributes(self.line_vao, self.line_buffer, &[3, 4]);
+
+            gl::GenVertexArrays(1, &mut self.label_vao);
+            gl::GenBuffers(1, &mut self.label_buffer);
+            Self::set_attributes(self.label_vao, self.label_buffer, &[3, 3]);
+        }
+
+        Ok(())
+    }
+
+    // Interleaved floats, attribute i has sizes[i] of them at location i.
+    unsafe fn set_attributes(vao: GLuint, buffer: GLuint, sizes: &[i32]) {
+        let stride = sizes.iter().sum::<i32>() * std::mem::size_of::<f32>() as i32;
+        let mut offset = 0;
+
+        gl::BindVertexArray(vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+
+        for (location, size) in sizes.iter().enumerate() {
+            gl::EnableVertexAttribArray(location as GLuint);
+            gl::VertexAttribPointer(
+                location as GLuint, *size, gl::FLOAT, gl::FALSE, stride,
+                (offset * std::mem::size_of::<f32>()) as *const std::ffi::c_void,
+            );
+
+            offset += *size as usize;
+        }
+
+        gl::BindVertexArray(0);
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    }
+
+    unsafe fn upload(buffer: GLuint, floats: &[f32]) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            std::mem::size_of_val(floats) as GLsizeiptr,
+            floats.as_ptr() as *const std::ffi::c_void,
+            gl::STREAM_DRAW,
+        );
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    }
+
+    // Depth tested lines go first, the rest after them with the depth test off.
+    fn render_lines(&self, view_projection: &Mat4) {
+        if self.lines.is_empty() {
+            return;
+        }
+
+        let mut floats: Vec<f32> = Vec::with_capacity(self.lines.len() * 14);
+        let mut depth_tested = 0;
+
+        for depth_test in [true, false] {
+            for line in self.lines.iter().filter(|line| line.depth_test == depth_test) {
+                floats.extend_from_slice(&line.start.to_array());
+                floats.extend_from_slice(&line.color.to_array());
+                floats.extend_from_slice(&line.end.to_array());
+                floats.extend_from_slice(&line.color.to_array());
+
+                if depth_test {
+                    depth_tested += 2;
+                }
+            }
+        }
+
+        let shader_program = self.shader_program.as_ref().unwrap();
+        shader_program.set_uniform_mat4_f32("view_projection", view_projection);
+
+        unsafe {
+            Self::upload(self.line_buffer, &floats);
+
+            let depth_test_enabled = gl::IsEnabled(gl::DEPTH_TEST) == gl::TRUE;
+
+            shader_program.use_program(true);
+            gl::BindVertexArray(self.line_vao);
+
+            gl::DrawArrays(gl::LINES, 0, depth_tested);
+
+            gl::Disable(gl::DEPTH_TEST);
+            gl::DrawArrays(gl::LINES, depth_tested, (self.lines.len() * 2) as GLsizei - depth_tested);
+
+            if depth_test_enabled {
+                gl::Enable(gl::DEPTH_TEST);
+            }
+
+            gl::BindVertexArray(0);
+            shader_program.use_program(false);
+        }
+    }
+
+    // Labels are placed in pixels from the center of the screen, with whole pixel positions so small text stays
+    // sharp. Labels behind a 3D camera are skipped.
+    fn render_labels(&self, view_projection: &Mat4, view_size: &Vec2, font: &Font) {
+        let mut floats: Vec<f32> = Vec::new();
+        let mut ranges: Vec<(GLint, GLsizei, Color)> = Vec::new();
+
+        for label in self.labels.iter() {
+            let clip = *view_projection * label.position.extend(1.0);
+
+            if clip.w <= 0.0 {
+                continue;
+            }
+
+            let screen = (Vec2::new(clip.x, clip.y) / clip.w * *view_size / 2.0).round();
+            let first = floats.len() / 6;
+
+            for positioned_glyph in font.layout_text(&label.text, &TextLayout::new()) {
+                let min = screen + positioned_glyph.position;
+                let max = min + positioned_glyph.glyph.size;
+                let uv_rect = positioned_glyph.glyph.region.unwrap().uv_rect;
+
+                for (x, y, u, v) in [
+                    (min.x, min.y, uv_rect.x, uv_rect.y),
+                    (max.x, min.y, uv_rect.z, uv_rect.y),
+                    (max.x, max.y, uv_rect.z, uv_rect.w),
+                    (max.x, max.y, uv_rect.z, uv_rect.w),
+                    (min.x, max.y, uv_rect.x, uv_rect.w),
+                    (min.x, min.y, uv_rect.x, uv_rect.y),
+                ] {
+                    floats.extend_from_slice(&[x, y, 0.0, u, v, 0.0]);
+                }
+            }
+
+            ranges.push((first as GLint, (floats.len() / 6 - first) as GLsizei, label.color));
+        }
+
+        if floats.is_empty() {
+            return;
+        }
+
+        let screen_matrix = Mat4::orthographic_lh(
+            -view_size.x / 2.0, view_size.x / 2.0, -view_size.y / 2.0, view_size.y / 2.0, -1.0, 1.0,
+        );
+
+        let shader_program = &font.shader_program;
+        shader_program.set_uniform_mat4_f32("mvp", &screen_matrix);
+        shader_program.set_uniform_bool("use_sdf", font.is_sdf());
+        shader_program.set_uniform_i32("glyph_atlas", 0);
+        font.atlas.texture.bind(0, true);
+
+        unsafe {
+            Self::upload(self.label_buffer, &floats);
+
+            let depth_test_enabled = gl::IsEnabled(gl::DEPTH_TEST) == gl::TRUE;
+            gl::Disable(gl::DEPTH_TEST);
+
+            gl::BindVertexArray(self.label_vao);
+
+            for (first, count, color) in ranges {
+                shader_program.set_uniform_vec4_f32("text_color", &color.to_vec4());
+
+                shader_program.use_program(true);
+                gl::DrawArrays(gl::TRIANGLES, first, count);
+                shader_program.use_program(false);
+            }
+
+            gl::BindVertexArray(0);
+
+            if depth_test_enabled {
+                gl::Enable(gl::DEPTH_TEST);
+            }
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.shader_program.is_none() {
+            return;
+        }
+
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.line_vao);
+            gl::DeleteBuffers(1, &self.line_buffer);
+            gl::DeleteVertexArrays(1, &self.label_vao);
+            gl::DeleteBuffers(1, &self.label_buffer);
+        }
+
+        self.shader_program = None;
+    }
+}
@@ -0,0 +1,531 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+use glam::Vec2;
+use image::{Rgba, RgbaImage};
+use rayon::prelude::*;
+
+use super::atlas::{TextureAtlas, TextureAtlasBuilder};
+use super::shader::{ShaderBuilderTemplate, ShaderError, ShaderProgram};
+use super::texture::{TextureDescriptor, TextureRegion};
+
+const TEXT_SHADER_VERSION: &str = "#version 450 core";
+
+// Distance fields are worked out on glyphs rasterized this many times bigger, so curves and corners stay smooth.
+const SDF_SUPERSAMPLING: u32 = 4;
+
+// Far enough that no real distance gets near it, without the NaNs infinity brings into the transform.
+const SDF_FAR: f32 = 1e20;
+
+#[derive(Clone, Copy)]
+pub struct Glyph {
+    // None for glyphs with nothing to draw, like space.
+    pub region: Option<TextureRegion>,
+    // Bottom left corner of the bitmap from the pen on the baseline, and its size, in pixels at the font's size.
+    pub offset: Vec2,
+    pub size: Vec2,
+    pub advance: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextAlignment {
+    Left,
+    Center,
+    Right,
+}
+
+// How glyphs get placed around the text's position: Left starts lines there, Right ends them there and Center
+// centers them on it. The first line's top is at the position and later lines go down.
+#[derive(Clone, Copy, Debug)]
+pub struct TextLayout {
+    pub alignment: TextAlignment,
+    // Lines break between words to stay narrower than this, in pixels at the font's size. Words that are too long on
+    // their own break between characters.
+    pub max_width: Option<f32>,
+    // Multiplies the font's line height.
+    pub line_spacing: f32,
+    pub kerning: bool,
+}
+
+impl Default for TextLayout {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextLayout {
+    pub fn new() -> Self {
+        TextLayout {
+            alignment: TextAlignment::Left,
+            max_width: None,
+            line_spacing: 1.0,
+            kerning: true,
+        }
+    }
+
+    pub fn with_alignment(mut self, alignment: TextAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    pub fn with_max_width(mut self, max_width: f32) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    pub fn with_line_spacing(mut self, line_spacing: f32) -> Self {
+        self.line_spacing = line_spacing;
+        self
+    }
+
+    pub fn with_kerning(mut self, kerning: bool) -> Self {
+        self.kerning = kerning;
+        self
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PositionedGlyph {
+    pub character: char,
+    pub glyph: Glyph,
+    // Bottom left corner of the glyph's bitmap.
+    pub position: Vec2,
+}
+
+#[derive(Debug, Clone)]
+pub struct FontError {
+    pub error_log: String,
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error_log.as_str())
+    }
+}
+
+impl Error for FontError {}
+
+impl FontError {
+    pub fn new(string: String) -> Self {
+        FontError {
+            error_log: string,
+        }
+    }
+}
+
+pub struct FontBuilder {
+    pub font: fontdue::Font,
+    pub size: f32,
+    pub sdf_spread: Option<u32>,
+    pub characters: Vec<char>,
+    pub padding: u32,
+}
+
+impl FontBuilder {
+    // Anything fontdue reads, TrueType and OpenType.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FontError> {
+        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
+            .map_err(|error| FontError::new(format!("couldn't load font: {error}")))?;
+
+        Ok(FontBuilder {
+            font,
+            size: 32.0,
+            sdf_spread: None,
+            // Printable ASCII.
+            characters: (' '..='~').collect(),
+            padding: 2,
+        })
+    }
+
+    pub fn from_file(file: &str) -> Result<Self, FontError> {
+        let bytes = fs::read(file).map_err(|error| FontError::new(format!("{file}: {error}")))?;
+        Self::from_bytes(&bytes).map_err(|error| FontError::new(format!("{file}: {error}")))
+    }
+
+    // Pixel height the glyphs get rasterized at.
+    pub fn with_size(mut self, size: f32) -> Self {
+        self.size = size;
+        self
+    }
+
+    // Stores the distance to each glyph's outline instead of its coverage, out to spread pixels either side, so
+    // text stays sharp scaled far past its size. Bigger spreads allow outlines and glow but need more atlas space.
+    pub fn with_sdf(mut self, spread: u32) -> Self {
+        self.sdf_spread = Some(spread);
+        self
+    }
+
+    // Characters to rasterize on top of printable ASCII. Anything else draws as '?'.
+    pub fn with_characters(mut self, characters: &str) -> Self {
+        for character in characters.chars() {
+            if !self.characters.contains(&character) {
+                self.characters.push(character);
+            }
+        }
+
+        self
+    }
+
+    pub fn build(&self) -> Result<Font, ShaderError> {
+        let shader_program = ShaderProgram::from_builders(
+            &ShaderBuilderTemplate::text_vertex_shader(TEXT_SHADER_VERSION),
+            &ShaderBuilderTemplate::text_fragment_shader(TEXT_SHADER_VERSION),
+        )?;
+
+        let rasterized: Vec<(char, Option<RgbaImage>, Vec2, f32)> = self.characters.par_iter().map(|character| {
+            let (image, offset) = match self.sdf_spread {
+                Some(spread) => self.rasterize_sdf(*character, spread),
+                None => self.rasterize(*character),
+            };
+
+            (*character, image, offset, self.font.metrics(*character, self.size).advance_width)
+        }).collect();
+
+        let mut atlas_builder = TextureAtlasBuilder::new()
+            .with_descriptor(&TextureDescriptor::ui())
+            .with_padding(self.padding);
+
+        for (character, image, _, _) in rasterized.iter() {
+            if let Some(image) = image {
+                atlas_builder.add_image(&character.to_string(), image);
+            }
+        }
+
+        let atlas = atlas_builder.build();
+        let mut glyphs = HashMap::new();
+
+        for (character, image, offset, advance) in rasterized {
+            let size = image.map(|image| Vec2::new(image.width() as f32, image.height() as f32)).unwrap_or(Vec2::ZERO);
+
+            glyphs.insert(character, Glyph {
+                region: atlas.get(&character.to_string()),
+                offset,
+                size,
+                advance,
+            });
+        }
+
+        let line_metrics = self.font.horizontal_line_metrics(self.size).unwrap_or(fontdue::LineMetrics {
+            ascent: self.size,
+            descent: 0.0,
+            line_gap: 0.0,
+            new_line_size: self.size,
+        });
+
+        Ok(Font {
+            font: self.font.clone(),
+            size: self.size,
+            sdf_spread: self.sdf_spread,
+            atlas,
+            glyphs,
+            ascent: line_metrics.ascent,
+            descent: line_metrics.descent,
+            line_height: line_metrics.new_line_size,
+            shader_program,
+        })
+    }
+
+    // White pixels with the coverage in alpha, so the text color can be anything.
+    fn rasterize(&self, character: char) -> (Option<RgbaImage>, Vec2) {
+        let (metrics, coverage) = self.font.rasterize(character, self.size);
+
+        if metrics.width == 0 || metrics.height == 0 {
+            return (None, Vec2::ZERO);
+        }
+
+        let image = RgbaImage::from_fn(metrics.width as u32, metrics.height as u32, |x, y| {
+            Rgba([255, 255, 255, coverage[y as usize * metrics.width + x as usize]])
+        });
+
+        (Some(image), Vec2::new(metrics.xmin as f32, metrics.ymin as f32))
+    }
+
+    // Alpha is 0.5 on the outline, rising to 1.0 spread pixels inside it and falling to 0.0 spread pixels outside.
+    fn rasterize_sdf(&self, character: char, spread: u32) -> (Option<RgbaImage>, Vec2) {
+        let (metrics, coverage) = self.font.rasterize(character, self.size * SDF_SUPERSAMPLING as f32);
+
+        if metrics.width == 0 || metrics.height == 0 {
+            return (None, Vec2::ZERO);
+        }
+
+        // Padded by the spread on every side and rounded up to whole pixels at the font's size.
+        let padding = (spread * SDF_SUPERSAMPLING) as usize;
+        let width = (metrics.width + padding * 2).div_ceil(SDF_SUPERSAMPLING as usize);
+        let height = (metrics.height + padding * 2).div_ceil(SDF_SUPERSAMPLING as usize);
+        let (big_width, big_height) = (width * SDF_SUPERSAMPLING as usize, height * SDF_SUPERSAMPLING as usize);
+
+        let mut inside = vec![false; big_width * big_height];
+
+        for y in 0..metrics.height {
+            for x in 0..metrics.width {
+                inside[(y + padding) * big_width + x + padding] = coverage[y * metrics.width + x] >= 128;
+            }
+        }
+
+        // Squared distances to the nearest pixel inside the outline and to the nearest one outside it.
+        let outside: Vec<bool> = inside.iter().map(|inside| !*inside).collect();
+        let to_inside = get_distance_transform(&inside, big_width, big_height);
+        let to_outside = get_distance_transform(&outside, big_width, big_height);
+
+        let half = (SDF_SUPERSAMPLING / 2) as usize;
+        let range = (spread * SDF_SUPERSAMPLING * 2) as f32;
+
+        let image = RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+            let i = (y as usize * SDF_SUPERSAMPLING as usize + half) * big_width + x as usize * SDF_SUPERSAMPLING as usize + half;
+            let distance = to_outside[i].sqrt() - to_inside[i].sqrt();
+
+            Rgba([255, 255, 255, ((0.5 + distance / range).clamp(0.0, 1.0) * 255.0).round() as u8])
+        });
+
+        // ymin counts from the bitmap's bottom edge, which moved down by the padding and the rounding up.
+        let bottom_padding = (height * SDF_SUPERSAMPLING as usize - metrics.height - padding) as f32;
+        let offset = Vec2::new(
+            (metrics.xmin as f32 - padding as f32) / SDF_SUPERSAMPLING as f32,
+            (metrics.ymin as f32 - bottom_padding) / SDF_SUPERSAMPLING as f32,
+        );
+
+        (Some(image), offset)
+    }
+}
+
+// Squared euclidean distance from every pixel to the nearest set one, Felzenszwalb and Huttenlocher's two pass
+// transform: columns first, then rows over the column results.
+fn get_distance_transform(set: &[bool], width: usize, height: usize) -> Vec<f32> {
+    let mut distances: Vec<f32> = set.iter().map(|set| if *set { 0.0 } else { SDF_FAR }).collect();
+    let mut line = vec![0.0; width.max(height)];
+    let mut result = vec![0.0; width.max(height)];
+
+    for x in 0..width {
+        for y in 0..height {
+            line[y] = distances[y * width + x];
+        }
+
+        get_distance_transform_1d(&line[..height], &mut result[..height]);
+
+        for y in 0..height {
+            distances[y * width + x] = result[y];
+        }
+    }
+
+    for y in 0..height {
+        line[..width].copy_from_slice(&distances[y * width..(y + 1) * width]);
+        get_distance_transform_1d(&line[..width], &mut result[..width]);
+        distances[y * width..(y + 1) * width].copy_from_slice(&result[..width]);
+    }
+
+    distances
+}
+
+// Lower envelope of the parabolas rooted at every sample.
+fn get_distance_transform_1d(samples: &[f32], result: &mut [f32]) {
+    let mut roots = vec![0usize; samples.len()];
+    let mut boundaries = vec![0.0f32; samples.len() + 1];
+    let mut k = 0;
+
+    boundaries[0] = -SDF_FAR;
+    boundaries[1] = SDF_FAR;
+
+    for q in 1..samples.len() {
+        loop {
+            let r = roots[k];
+            let s = ((samples[q] + (q * q) as f32) - (samples[r] + (r * r) as f32)) / (2 * q - 2 * r) as f32;
+
+            if s <= boundaries[k] {
+                k -= 1;
+                continue;
+            }
+
+            k += 1;
+            roots[k] = q;
+            boundaries[k] = s;
+            boundaries[k + 1] = SDF_FAR;
+            break;
+        }
+    }
+
+    k = 0;
+
+    for (q, result) in result.iter_mut().enumerate() {
+        while boundaries[k + 1] < q as f32 {
+            k += 1;
+        }
+
+        let r = roots[k];
+        *result = (q as f32 - r as f32).powi(2) + samples[r];
+    }
+}
+
+// A rasterized font: the glyph atlas, where every glyph sits in it and the program RenderableText draws with.
+pub struct Font {
+    pub font: fontdue::Font,
+    pub size: f32,
+    pub sdf_spread: Option<u32>,
+    pub atlas: TextureAtlas,
+    pub glyphs: HashMap<char, Glyph>,
+    // Above and below the baseline, descent is negative. line_height also has the font's line gap.
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_height: f32,
+    pub shader_program: ShaderProgram,
+}
+
+impl Font {
+    pub fn from_file(file: &str, size: f32) -> Result<Self, FontError> {
+        FontBuilder::from_file(file)?.with_size(size).build().map_err(|error| FontError::new(error.error_log))
+    }
+
+    pub fn is_sdf(&self) -> bool {
+        self.sdf_spread.is_some()
+    }
+
+    // Characters that weren't rasterized fall back to '?', or nothing if that's missing too.
+    pub fn get_glyph(&self, character: char) -> Option<&Glyph> {
+        self.glyphs.get(&character).or_else(|| self.glyphs.get(&'?'))
+    }
+
+    pub fn get_kerning(&self, left: char, right: char) -> f32 {
+        self.font.horizontal_kern(left, right, self.size).unwrap_or(0.0)
+    }
+
+    // Glyphs with their positions relative to the text's position, in pixels at the font's size. Whitespace and
+    // characters without a glyph take up space but aren't returned.
+    pub fn layout_text(&self, text: &str, layout: &TextLayout) -> Vec<PositionedGlyph> {
+        let mut positioned_glyphs = Vec::new();
+
+        for (i, line) in self.get_lines(text, layout).iter().enumerate() {
+            let x = match layout.alignment {
+                TextAlignment::Left => 0.0,
+                TextAlignment::Center => -self.get_line_width(line, layout) / 2.0,
+                TextAlignment::Right => -self.get_line_width(line, layout),
+            };
+
+            let mut pen = Vec2::new(x, -self.ascent - i as f32 * self.line_height * layout.line_spacing);
+            let mut previous: Option<char> = None;
+
+            for character in line.iter() {
+                if let (Some(previous), true) = (previous, layout.kerning) {
+                    pen.x += self.get_kerning(previous, *character);
+                }
+
+                if let Some(glyph) = self.get_glyph(*character) {
+                    if glyph.region.is_some() {
+                        positioned_glyphs.push(PositionedGlyph {
+                            character: *character,
+                            glyph: *glyph,
+                            position: pen + glyph.offset,
+                        });
+                    }
+
+                    pen.x += glyph.advance;
+                }
+
+                previous = Some(*character);
+            }
+        }
+
+        positioned_glyphs
+    }
+
+    // Width of the widest line and height from the first line's top to the last one's bottom.
+    pub fn measure_text(&self, text: &str, layout: &TextLayout) -> Vec2 {
+        let lines = self.get_lines(text, layout);
+        let width = lines.iter().map(|line| self.get_line_width(line, layout)).fold(0.0, f32::max);
+        let height = (lines.len().max(1) - 1) as f32 * self.line_height * layout.line_spacing + self.ascent - self.descent;
+
+        Vec2::new(width, height)
+    }
+
+    fn get_advance(&self, character: char) -> f32 {
+        self.get_glyph(character).map(|glyph| glyph.advance).unwrap_or(0.0)
+    }
+
+    fn get_line_width(&self, line: &[char], layout: &TextLayout) -> f32 {
+        let mut width = 0.0;
+
+        for (i, character) in line.iter().enumerate() {
+            width += self.get_advance(*character);
+
+            if i > 0 && layout.kerning {
+                width += self.get_kerning(line[i - 1], *character);
+            }
+        }
+
+        width
+    }
+
+    // Splits at newlines, then wraps to layout.max_width. Whitespace a line was wrapped at is dropped.
+    fn get_lines(&self, text: &str, layout: &TextLayout) -> Vec<Vec<char>> {
+        let mut lines = Vec::new();
+
+        for paragraph in text.split('\n') {
+            let paragraph: Vec<char> = paragraph.trim_end_matches('\r').chars().collect();
+
+            let max_width = match layout.max_width {
+                Some(max_width) => max_width,
+                None => {
+                    lines.push(paragraph);
+                    continue;
+                }
+            };
+
+            let mut line: Vec<char> = Vec::new();
+            let mut line_width = 0.0;
+
+            // Runs of whitespace and runs of everything else.
+            for word in paragraph.chunk_by(|a, b| a.is_whitespace() == b.is_whitespace()) {
+                let word_width = self.get_line_width(word, layout);
+
+                if word[0].is_whitespace() {
+                    if !line.is_empty() {
+                        line.extend_from_slice(word);
+                        line_width += word_width;
+                    }
+
+                    continue;
+                }
+
+                if line_width + word_width > max_width && !line.is_empty() {
+                    while line.last().is_some_and(|character| character.is_whitespace()) {
+                        line.pop();
+                    }
+
+                    lines.push(line);
+                    line = Vec::new();
+                    line_width = 0.0;
+                }
+
+                if word_width > max_width {
+                    for character in word {
+                        let advance = self.get_advance(*character);
+
+                        if line_width + advance > max_width && !line.is_empty() {
+                            lines.push(line);
+                            line = Vec::new();
+                            line_width = 0.0;
+                        }
+
+                        line.push(*character);
+                        line_width += advance;
+                    }
+                } else {
+                    line.extend_from_slice(word);
+                    line_width += word_width;
+                }
+            }
+
+            while line.last().is_some_and(|character| character.is_whitespace()) {
+                line.pop();
+            }
+
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    pub fn delete(&self) {
+        self.atlas.delete();
+    }
+}
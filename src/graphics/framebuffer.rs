@@ -0,0 +1,235 @@
+use std::cell::Cell;
+
+use gl::types::*;
+use glam::{IVec2, UVec2};
+
+use crate::util::entity::Entity;
+use super::color::Color;
+use super::renderable::Renderable;
+use super::texture::{Texture, TextureDescriptor, TextureFilter, TextureFormat};
+use super::view::GraphicsLayer;
+
+// An offscreen framebuffer with a color and a depth texture. Anything rendered between bind(true) and bind(false)
+// ends up in color_texture instead of on screen. With samples > 1 rendering goes into multisampled renderbuffers
+// that resolve() copies into the textures.
+pub struct RenderTarget {
+    pub framebuffer_id: GLuint,
+    pub size: UVec2,
+    pub samples: u32,
+    pub descriptor: TextureDescriptor,
+    pub color_texture: Texture,
+    pub depth_texture: Texture,
+    pub multisample_framebuffer_id: Option<GLuint>,
+    pub multisample_renderbuffer_ids: Vec<GLuint>,
+    previous_framebuffer: Cell<GLint>,
+    previous_viewport: Cell<[GLint; 4]>,
+}
+
+impl RenderTarget {
+    // The color texture uses TextureDescriptor::ui(), mipmaps would have to be regenerated every frame.
+    pub fn new(size: &UVec2, samples: u32) -> Self {
+        Self::new_with(size, samples, &TextureDescriptor::ui())
+    }
+
+    pub fn new_with(size: &UVec2, samples: u32, descriptor: &TextureDescriptor) -> Self {
+        let texture_size = IVec2::new(size.x as i32, size.y as i32);
+
+        let color_texture = Texture::empty(&texture_size, descriptor);
+        let depth_texture = Texture::empty(
+            &texture_size,
+            &TextureDescriptor::ui().with_filter(TextureFilter::Nearest).with_format(TextureFormat::Depth24),
+        );
+
+        let mut framebuffer_id: GLuint = 0;
+
+        unsafe {
+            gl::GenFramebuffers(1, &mut framebuffer_id);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer_id);
+
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, color_texture.texture_id, 0);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, depth_texture.texture_id, 0);
+
+            Self::check_status("render target");
+        }
+
+        let mut multisample_framebuffer_id = None;
+        let mut multisample_renderbuffer_ids = Vec::new();
+
+        if samples > 1 {
+            let mut ms_framebuffer_id: GLuint = 0;
+            let mut renderbuffer_ids: [GLuint; 2] = [0; 2];
+
+            unsafe {
+                gl::GenFramebuffers(1, &mut ms_framebuffer_id);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, ms_framebuffer_id);
+
+                gl::GenRenderbuffers(2, renderbuffer_ids.as_mut_ptr());
+
+                gl::BindRenderbuffer(gl::RENDERBUFFER, renderbuffer_ids[0]);
+                gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples as i32, descriptor.format.internal_format(), size.x as i32, size.y as i32);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, renderbuffer_ids[0]);
+
+                gl::BindRenderbuffer(gl::RENDERBUFFER, renderbuffer_ids[1]);
+                gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples as i32, gl::DEPTH_COMPONENT24, size.x as i32, size.y as i32);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, renderbuffer_ids[1]);
+
+                gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+                Self::check_status("multisampled render target");
+            }
+
+            multisample_framebuffer_id = Some(ms_framebuffer_id);
+            multisample_renderbuffer_ids = renderbuffer_ids.to_vec();
+        }
+
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+
+        RenderTarget {
+            framebuffer_id,
+            size: *size,
+            samples,
+            descriptor: *descriptor,
+            color_texture,
+            depth_texture,
+            multisample_framebuffer_id,
+            multisample_renderbuffer_ids,
+            previous_framebuffer: Cell::new(0),
+            previous_viewport: Cell::new([0; 4]),
+        }
+    }
+
+    unsafe fn check_status(name: &str) {
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            panic!("{name} framebuffer is incomplete: 0x{status:X}");
+        }
+    }
+
+    // Binding remembers the framebuffer and viewport that were current, unbinding restores them, so targets can
+    // be nested.
+    pub fn bind(&self, should_bind: bool) {
+        unsafe {
+            match should_bind {
+                true => {
+                    let mut previous_framebuffer: GLint = 0;
+                    let mut previous_viewport: [GLint; 4] = [0; 4];
+
+                    gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut previous_framebuffer);
+                    gl::GetIntegerv(gl::VIEWPORT, previous_viewport.as_mut_ptr());
+
+                    self.previous_framebuffer.set(previous_framebuffer);
+                    self.previous_viewport.set(previous_viewport);
+
+                    gl::BindFramebuffer(gl::FRAMEBUFFER, self.multisample_framebuffer_id.unwrap_or(self.framebuffer_id));
+                    gl::Viewport(0, 0, self.size.x as i32, self.size.y as i32);
+                }
+                false => {
+                    let viewport = self.previous_viewport.get();
+
+                    gl::BindFramebuffer(gl::FRAMEBUFFER, self.previous_framebuffer.get() as GLuint);
+                    gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+                }
+            }
+        }
+    }
+
+    // Copies the multisampled buffers into the textures. Does nothing for targets without multisampling.
+    pub fn resolve(&self) {
+        if let Some(ms_framebuffer_id) = self.multisample_framebuffer_id {
+            self.blit(ms_framebuffer_id, self.framebuffer_id, &self.size, gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    // Copies the resolved color into another framebuffer, 0 being the window, scaled to size.
+    pub fn blit_to(&self, framebuffer_id: GLuint, size: &UVec2) {
+        self.blit(self.framebuffer_id, framebuffer_id, size, gl::COLOR_BUFFER_BIT);
+    }
+
+    fn blit(&self, from: GLuint, to: GLuint, size: &UVec2, mask: GLbitfield) {
+        let filter = match self.size == *size {
+            true => gl::NEAREST,
+            false => gl::LINEAR,
+        };
+
+        unsafe {
+            let mut previous_read_framebuffer: GLint = 0;
+            let mut previous_draw_framebuffer: GLint = 0;
+
+            gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut previous_read_framebuffer);
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut previous_draw_framebuffer);
+
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, from);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, to);
+
+            gl::BlitFramebuffer(
+                0, 0, self.size.x as i32, self.size.y as i32,
+                0, 0, size.x as i32, size.y as i32,
+                mask, filter,
+            );
+
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, previous_read_framebuffer as GLuint);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, previous_draw_framebuffer as GLuint);
+        }
+    }
+
+    // Clears the target and renders the entity tree into it, like the window does for the screen.
+    pub fn render(&self, layer: &mut GraphicsLayer, entity: &mut Entity, clear_color: Color) {
+        self.bind(true);
+        layer.clear_screen(clear_color);
+        entity.render(layer);
+        self.bind(false);
+
+        self.resolve();
+    }
+
+    // Draws on top of what the target already holds.
+    pub fn render_object(&self, layer: &GraphicsLayer, obj: &mut dyn Renderable) {
+        self.bind(true);
+        obj.render(layer);
+        self.bind(false);
+
+        self.resolve();
+    }
+
+    // The result as a texture. It belongs to the target, so give sprites a TextureRegion of it rather than the
+    // texture itself, which they would delete when dropped.
+    pub fn get_texture(&self) -> Texture {
+        self.color_texture
+    }
+
+    pub fn get_depth_texture(&self) -> Texture {
+        self.depth_texture
+    }
+
+    // Recreates the attachments, the old contents are lost.
+    pub fn resize(&mut self, size: &UVec2) {
+        if self.size == *size {
+            return;
+        }
+
+        // The old target is dropped, and deleted, after the new one exists so GL can't hand out the same names.
+        *self = Self::new_with(size, self.samples, &self.descriptor);
+    }
+
+    pub fn delete(&self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.framebuffer_id);
+            gl::DeleteTextures(1, &self.color_texture.texture_id);
+            gl::DeleteTextures(1, &self.depth_texture.texture_id);
+
+            if let Some(ms_framebuffer_id) = self.multisample_framebuffer_id {
+                gl::DeleteFramebuffers(1, &ms_framebuffer_id);
+                gl::DeleteRenderbuffers(self.multisample_renderbuffer_ids.len() as i32, self.multisample_renderbuffer_ids.as_ptr());
+            }
+        }
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        self.delete();
+    }
+}
@@ -0,0 +1,243 @@
+use gl::types::*;
+use glam::{Vec3, Vec4};
+
+use super::math::Deg;
+use super::shader::ShaderProgram;
+use super::view::View;
+
+pub const DEFAULT_MAX_LIGHTS: usize = 16;
+
+// Uniform buffer binding point the Lights block of ShaderBuilderTemplate::lighting_fragment_shader is read from.
+pub const LIGHTS_BLOCK_NAME: &str = "Lights";
+pub const LIGHTS_BLOCK_BINDING: u32 = 0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: Vec3,
+    // Where the light shines to. Used by directional and spot lights.
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    // Constant, linear and quadratic falloff with distance. Directional lights don't fall off.
+    pub attenuation: Vec3,
+    // Spot lights are fully lit inside inner_angle and fade out towards outer_angle, both from the center.
+    pub inner_angle: Deg,
+    pub outer_angle: Deg,
+    // Only directional and spot lights cast shadows.
+    pub casts_shadows: bool,
+    // First layer of the shadow maps this light was rendered into, set by ShadowMaps::render.
+    pub shadow_map: Option<usize>,
+}
+
+impl Light {
+    fn new(kind: LightKind) -> Self {
+        Light {
+            kind,
+            position: Vec3::ZERO,
+            direction: Vec3::new(0.0, 0.0, 1.0),
+            color: Vec3::ONE,
+            intensity: 1.0,
+            attenuation: Vec3::ONE,
+            inner_angle: Deg(12.5),
+            outer_angle: Deg(17.5),
+            casts_shadows: false,
+            shadow_map: None,
+        }
+    }
+
+    // Like the sun, lights everything from one direction.
+    pub fn directional(direction: &Vec3) -> Self {
+        Self::new(LightKind::Directional).with_direction(direction)
+    }
+
+    pub fn point(position: &Vec3) -> Self {
+        Self::new(LightKind::Point).with_position(position).with_range(50.0)
+    }
+
+    pub fn spot(position: &Vec3, direction: &Vec3) -> Self {
+        Self::new(LightKind::Spot).with_position(position).with_direction(direction).with_range(50.0)
+    }
+
+    pub fn with_position(mut self, position: &Vec3) -> Self {
+        self.position = *position;
+        self
+    }
+
+    pub fn with_direction(mut self, direction: &Vec3) -> Self {
+        self.direction = direction.normalize_or_zero();
+        self
+    }
+
+    pub fn with_color(mut self, color: &Vec3) -> Self {
+        self.color = *color;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_attenuation(mut self, attenuation: &Vec3) -> Self {
+        self.attenuation = *attenuation;
+        self
+    }
+
+    // Picks an attenuation that has faded to almost nothing at range.
+    pub fn with_range(mut self, range: f32) -> Self {
+        self.attenuation = Vec3::new(1.0, 4.5 / range, 75.0 / (range * range));
+        self
+    }
+
+    pub fn with_angles(mut self, inner_angle: Deg, outer_angle: Deg) -> Self {
+        self.inner_angle = inner_angle;
+        self.outer_angle = outer_angle;
+        self
+    }
+
+    pub fn with_shadows(mut self, casts_shadows: bool) -> Self {
+        self.casts_shadows = casts_shadows;
+        self
+    }
+
+    // The four vec4s the lighting shader reads per light.
+    fn get_shader_data(&self) -> [Vec4; 4] {
+        let kind = match self.kind {
+            LightKind::Directional => 0.0,
+            LightKind::Point => 1.0,
+            LightKind::Spot => 2.0,
+        };
+
+        [
+            self.position.extend(kind),
+            self.direction.extend(self.inner_angle.to_radians().as_float().cos()),
+            (self.color * self.intensity).extend(self.outer_angle.to_radians().as_float().cos()),
+            self.attenuation.extend(self.shadow_map.map(|shadow_map| shadow_map as f32).unwrap_or(-1.0)),
+        ]
+    }
+}
+
+// The lights of a scene, uploaded together into one uniform buffer every lit program reads from. Lights past
+// max_lights are ignored, and max_lights has to match the one the lighting shader was built with.
+pub struct LightSet {
+    pub lights: Vec<Light>,
+    pub ambient: Vec3,
+    pub max_lights: usize,
+    pub buffer_id: GLuint,
+}
+
+impl LightSet {
+    pub fn new(max_lights: usize) -> Self {
+        LightSet {
+            lights: Vec::new(),
+            ambient: Vec3::new(0.1, 0.1, 0.1),
+            max_lights,
+            buffer_id: 0,
+        }
+    }
+
+    pub fn with_ambient(mut self, ambient: &Vec3) -> Self {
+        self.ambient = *ambient;
+        self
+    }
+
+    pub fn with_light(mut self, light: Light) -> Self {
+        self.add_light(light);
+        self
+    }
+
+    // Returns the light's index, for get_light and remove_light.
+    pub fn add_light(&mut self, light: Light) -> usize {
+        self.lights.push(light);
+        self.lights.len() - 1
+    }
+
+    pub fn get_light(&mut self, index: usize) -> Option<&mut Light> {
+        self.lights.get_mut(index)
+    }
+
+    // Later lights move down one index.
+    pub fn remove_light(&mut self, index: usize) -> Light {
+        self.lights.remove(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.lights.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    // Connects a program's Lights block to the buffer. Needed once per program.
+    pub fn bind_shader(shader_program: &ShaderProgram) {
+        shader_program.bind_uniform_block(LIGHTS_BLOCK_NAME, LIGHTS_BLOCK_BINDING);
+    }
+
+    // Writes every light and the camera position into the buffer. Call it once a frame before drawing lit things.
+    pub fn upload(&mut self, view: &View) {
+        let view_position = match view {
+            View::View2D(view) => view.position,
+            View::View3D(view) => view.position,
+        };
+
+        let light_count = self.lights.len().min(self.max_lights);
+
+        // The shader always has room for at least one light, even with a max_lights of 0.
+        let light_slots = self.max_lights.max(1);
+
+        let mut data: Vec<Vec4> = Vec::with_capacity(3 + light_slots * 4);
+        data.push(self.ambient.extend(1.0));
+        data.push(view_position.extend(1.0));
+        // ivec4 light_count, the int is written as raw bits below.
+        data.push(Vec4::ZERO);
+
+        for light in self.lights.iter().take(light_count) {
+            data.extend_from_slice(&light.get_shader_data());
+        }
+
+        data.resize(3 + light_slots * 4, Vec4::ZERO);
+
+        let mut floats: Vec<f32> = data.iter().flat_map(|vec| vec.to_array()).collect();
+        floats[8] = f32::from_bits(light_count as u32);
+
+        unsafe {
+            if self.buffer_id == 0 {
+                gl::GenBuffers(1, &mut self.buffer_id);
+            }
+
+            gl::BindBuffer(gl::UNIFORM_BUFFER, self.buffer_id);
+            gl::BufferData(
+                gl::UNIFORM_BUFFER,
+                (floats.len() * std::mem::size_of::<f32>()) as GLsizeiptr,
+                floats.as_ptr() as *const std::ffi::c_void,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, LIGHTS_BLOCK_BINDING, self.buffer_id);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.buffer_id != 0 {
+            unsafe {
+                gl::DeleteBuffers(1, &self.buffer_id);
+            }
+
+            self.buffer_id = 0;
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use gl::types::*;
+
+use super::shader::{ShaderProgram, UniformValue};
+use super::texture::Texture;
+
+static NEXT_MATERIAL_ID: AtomicU64 = AtomicU64::new(1);
+
+// The unit ShaderBuilderTemplate::lighting_fragment_shader reads normal_map from, past the ones sampler_objs covers.
+pub const NORMAL_MAP_TEXTURE_UNIT: u32 = 14;
+
+thread_local! {
+    // What each program's uniforms were last set to through materials and per draw uniforms. GL keeps uniform values
+    // per program, so only values that differ have to be uploaded, and values nothing sets anymore can be reset.
+    static APPLIED_UNIFORMS: RefCell<HashMap<GLuint, AppliedUniforms>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Default)]
+struct AppliedUniforms {
+    // Id and version of the material the values came from, None after a draw without one or an invalidate.
+    material: Option<(u64, u64)>,
+    values: HashMap<String, UniformValue>,
+    // Set by invalidate, the values may not be on the program anymore.
+    is_stale: bool,
+}
+
+pub struct MaterialData {
+    pub id: u64,
+    pub shader_program: ShaderProgram,
+    // Value and the version it was last changed at.
+    pub uniforms: HashMap<String, (UniformValue, u64)>,
+    // Sampler uniform name, texture and the unit it's bound to.
+    pub textures: Vec<(String, Texture, u32)>,
+    pub version: u64,
+}
+
+// A shader program with the uniform values and textures it should draw with. Cloning a Material shares it, so
+// changing one clone changes every renderable using it. Per object values like mvp are set by the renderables.
+#[derive(Clone)]
+pub struct Material {
+    pub data: Rc<RefCell<MaterialData>>,
+}
+
+impl Material {
+    pub fn new(shader_program: &ShaderProgram) -> Self {
+        Material {
+            data: Rc::new(RefCell::new(MaterialData {
+                id: NEXT_MATERIAL_ID.fetch_add(1, Ordering::Relaxed),
+                shader_program: shader_program.clone(),
+                uniforms: HashMap::new(),
+                textures: Vec::new(),
+                version: 1,
+            })),
+        }
+    }
+
+    pub fn with_uniform(self, name: &str, value: UniformValue) -> Self {
+        self.set_uniform(name, value);
+        self
+    }
+
+    // Setting a uniform to the value it already has doesn't count as a change.
+    pub fn set_uniform(&self, name: &str, value: UniformValue) {
+        let mut data = self.data.borrow_mut();
+
+        if data.uniforms.get(name).map(|(current, _)| *current == value).unwrap_or(false) {
+            return;
+        }
+
+        data.version += 1;
+        let version = data.version;
+        data.uniforms.insert(String::from(name), (value, version));
+    }
+
+    pub fn get_uniform(&self, name: &str) -> Option<UniformValue> {
+        self.data.borrow().uniforms.get(name).map(|(value, _)| *value)
+    }
+
+    pub fn remove_uniform(&self, name: &str) {
+        let mut data = self.data.borrow_mut();
+
+        if data.uniforms.remove(name).is_some() {
+            data.version += 1;
+        }
+    }
+
+    pub fn with_texture(self, sampler_name: &str, texture: &Texture) -> Self {
+        self.set_texture(sampler_name, texture);
+        self
+    }
+
+    // Puts the texture in the sampler's unit, or the lowest free one, and points the sampler uniform at it.
+    pub fn set_texture(&self, sampler_name: &str, texture: &Texture) {
+        let unit = {
+            let data = self.data.borrow();
+
+            match data.textures.iter().find(|(name, _, _)| name == sampler_name) {
+                Some((_, _, unit)) => *unit,
+                None => (0..).find(|unit| data.textures.iter().all(|(_, _, used)| used != unit)).unwrap(),
+            }
+        };
+
+        self.set_texture_at(sampler_name, texture, unit);
+    }
+
+    pub fn with_texture_at(self, sampler_name: &str, texture: &Texture, unit: u32) -> Self {
+        self.set_texture_at(sampler_name, texture, unit);
+        self
+    }
+
+    // For samplers that have to be on a particular unit.
+    pub fn set_texture_at(&self, sampler_name: &str, texture: &Texture, unit: u32) {
+        {
+            let mut data = self.data.borrow_mut();
+            data.textures.retain(|(name, _, _)| name != sampler_name);
+            data.textures.push((String::from(sampler_name), *texture, unit));
+        }
+
+        self.set_uniform(sampler_name, UniformValue::I32(unit as i32));
+    }
+
+    pub fn with_normal_map(self, texture: &Texture) -> Self {
+        self.set_normal_map(texture);
+        self
+    }
+
+    // A tangent space normal map for the lit shader. The mesh needs tangents, see StaticMeshData::with_tangents.
+    pub fn set_normal_map(&self, texture: &Texture) {
+        self.set_texture_at("normal_map", texture, NORMAL_MAP_TEXTURE_UNIT);
+        self.set_uniform("use_normal_map", UniformValue::Bool(true));
+    }
+
+    pub fn get_texture(&self, sampler_name: &str) -> Option<Texture> {
+        self.data.borrow().textures.iter().find(|(name, _, _)| name == sampler_name).map(|(_, texture, _)| *texture)
+    }
+
+    // Whether there is a color texture, on one of the units sampler_objs reads from.
+    pub fn has_textures(&self) -> bool {
+        self.data.borrow().textures.iter().any(|(_, _, unit)| *unit < NORMAL_MAP_TEXTURE_UNIT)
+    }
+
+    pub fn get_shader_program(&self) -> ShaderProgram {
+        self.data.borrow().shader_program.clone()
+    }
+
+    pub fn get_id(&self) -> u64 {
+        self.data.borrow().id
+    }
+
+    // Uploads the uniforms the program doesn't have yet and binds the textures to their units.
+    pub fn apply(&self) {
+        self.apply_with(&[]);
+    }
+
+    // Per draw uniforms are the renderable's own values, like a sprite's tint. A value the material sets for the same
+    // name wins, so materials can override them for everything drawn with them.
+    pub fn apply_with(&self, per_draw: &[(&str, UniformValue)]) {
+        let data = self.data.borrow();
+
+        apply_uniforms(&data.shader_program, Some((data.id, data.version, &data.uniforms)), per_draw);
+
+        for (_, texture, unit) in &data.textures {
+            texture.bind(*unit, true);
+        }
+    }
+
+    // For renderables that draw with a program materials may use, but without a material of their own. Uniforms the
+    // last material set go back to their defaults.
+    pub fn apply_uniforms(shader_program: &ShaderProgram, per_draw: &[(&str, UniformValue)]) {
+        apply_uniforms(shader_program, None, per_draw);
+    }
+
+    // For code that sets uniforms on the program directly, so the next apply() uploads everything again.
+    pub fn invalidate(shader_program: &ShaderProgram) {
+        APPLIED_UNIFORMS.with(|applied| {
+            if let Some(applied) = applied.borrow_mut().get_mut(&shader_program.program_id) {
+                applied.material = None;
+                applied.is_stale = true;
+            }
+        });
+    }
+}
+
+type MaterialUniforms<'a> = (u64, u64, &'a HashMap<String, (UniformValue, u64)>);
+
+fn apply_uniforms(shader_program: &ShaderProgram, material: Option<MaterialUniforms>, per_draw: &[(&str, UniformValue)]) {
+    APPLIED_UNIFORMS.with(|applied| {
+        let mut applied = applied.borrow_mut();
+        let applied = applied.entry(shader_program.program_id).or_default();
+
+        let material_key = material.map(|(id, version, _)| (id, version));
+        let material_uniforms = material.map(|(_, _, uniforms)| uniforms);
+        let is_material_set = |name: &str| material_uniforms.map(|uniforms| uniforms.contains_key(name)).unwrap_or(false);
+
+        // Uniforms nothing sets anymore, from the last material or the last draw, go back to the zero GL starts them at.
+        applied.values.retain(|name, value| {
+            let is_used = is_material_set(name) || per_draw.iter().any(|(per_draw_name, _)| per_draw_name == name);
+
+            if !is_used {
+                shader_program.set_uniform(name, &value.zeroed());
+            }
+
+            is_used
+        });
+
+        let is_stale = applied.is_stale;
+        let values = &mut applied.values;
+        let mut upload = |name: &str, value: &UniformValue| {
+            if is_stale || values.get(name) != Some(value) {
+                shader_program.set_uniform(name, value);
+                values.insert(String::from(name), *value);
+            }
+        };
+
+        if is_stale || applied.material != material_key {
+            for (name, (value, _)) in material_uniforms.into_iter().flatten() {
+                upload(name, value);
+            }
+        }
+
+        for (name, value) in per_draw {
+            if !is_material_set(name) {
+                upload(name, value);
+            }
+        }
+
+        applied.material = material_key;
+        applied.is_stale = false;
+    });
+}
@@ -1,4 +1,5 @@
 use std::f32::consts::PI;
+use std::ops::{Add, Mul};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Deg(pub f32);
@@ -24,4 +25,50 @@ impl Rad {
     pub fn as_float(&self) -> f32 {
         self.0
     }
-}
\ No newline at end of file
+}
+
+// Values at points in time, linearly blended in between and held before the first key and after the last. Used for
+// things that change over a lifetime, where time goes from 0 to 1.
+#[derive(Debug, Clone)]
+pub struct Curve<T> {
+    pub keys: Vec<(f32, T)>,
+}
+
+impl<T: Copy + Add<Output = T> + Mul<f32, Output = T>> Curve<T> {
+    pub fn constant(value: T) -> Self {
+        Curve {
+            keys: vec![(0.0, value)],
+        }
+    }
+
+    pub fn linear(start: T, end: T) -> Self {
+        Curve {
+            keys: vec![(0.0, start), (1.0, end)],
+        }
+    }
+
+    // Keys can be added in any order.
+    pub fn with_key(mut self, time: f32, value: T) -> Self {
+        let index = self.keys.partition_point(|(key_time, _)| *key_time <= time);
+        self.keys.insert(index, (time, value));
+        self
+    }
+
+    pub fn evaluate(&self, time: f32) -> T {
+        let index = self.keys.partition_point(|(key_time, _)| *key_time <= time);
+
+        if index == 0 {
+            return self.keys[0].1;
+        }
+
+        if index == self.keys.len() {
+            return self.keys[index - 1].1;
+        }
+
+        let (start_time, start) = self.keys[index - 1];
+        let (end_time, end) = self.keys[index];
+        let progression = (time - start_time) / (end_time - start_time);
+
+        start * (1.0 - progression) + end * progression
+    }
+}
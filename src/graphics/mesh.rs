@@ -35,7 +35,12 @@ impl StaticMeshData {
     
     pub fn from_collada(path: &str) -> Self {
         let doc = Document::from_file(path).unwrap();
-        let (vertices, indices) = ColladaLoader::load_collada_mesh_data(&doc);
+        let (mut vertices, indices) = ColladaLoader::load_collada_mesh_data(&doc);
+
+        if !Vertex::has_tangents(&vertices) {
+            Vertex::generate_tangents(&mut vertices, &indices);
+        }
+
         let y_up = match ColladaLoader::get_collada_up_axis(&doc) {
             UpAxis::YUp => true,
             UpAxis::ZUp => false,
@@ -49,6 +54,15 @@ impl StaticMeshData {
         }
     }
 
+    pub fn generate_tangents(&mut self) {
+        Vertex::generate_tangents(&mut self.vertex_array, &self.index_array);
+    }
+
+    pub fn with_tangents(mut self) -> Self {
+        self.generate_tangents();
+        self
+    }
+
     pub fn build(self, shader_program: &ShaderProgram) -> StaticMesh {
         StaticMesh::new(&self, shader_program)
     }
@@ -84,6 +98,11 @@ impl AnimatedMeshData {
     pub fn from_collada(path: &str) -> AnimatedMeshData {
         let doc = Document::from_file(path).unwrap();
         let (mut vertices, indices) = ColladaLoader::load_collada_mesh_data(&doc);
+
+        if !Vertex::has_tangents(&vertices) {
+            Vertex::generate_tangents(&mut vertices, &indices);
+        }
+
         let (mut root_joint, joints) = ColladaLoader::load_collada_skeleton(&doc, &mut vertices);
         let animation = ColladaLoader::load_collada_animations(&doc, &joints);
         let y_up = match ColladaLoader::get_collada_up_axis(&doc) {
@@ -102,6 +121,15 @@ impl AnimatedMeshData {
         }
     }
 
+    pub fn generate_tangents(&mut self) {
+        Vertex::generate_tangents(&mut self.vertex_array, &self.index_array);
+    }
+
+    pub fn with_tangents(mut self) -> Self {
+        self.generate_tangents();
+        self
+    }
+
     pub fn build(self, shader_program: &ShaderProgram) -> AnimatedMesh {
         AnimatedMesh::new(&self, shader_program)
     }
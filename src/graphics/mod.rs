@@ -10,4 +10,18 @@ pub mod texture;
 pub mod color;
 pub mod animation;
 pub mod math;
-pub mod collada;
\ No newline at end of file
+pub mod collada;
+pub mod batch;
+pub mod atlas;
+pub mod framebuffer;
+pub mod post_process;
+pub mod material;
+pub mod light;
+pub mod shadow;
+pub mod font;
+pub mod debug;
+pub mod shape;
+pub mod particle;
+pub mod tilemap;
+pub mod ui;
+pub mod render_queue;
\ No newline at end of file
@@ -0,0 +1,559 @@
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
+
+use gl::types::*;
+use glam::{Mat4, Vec3, Vec4};
+use rand::Rng;
+
+use super::color::Color;
+use super::math::{Curve, Deg};
+use super::renderable::Renderable;
+use super::shader::{ShaderBuilderTemplate, ShaderProgram};
+use super::texture::{Texture, TextureRegion};
+use super::view::{GraphicsLayer, View};
+
+const PARTICLE_SHADER_VERSION: &str = "#version 450 core";
+
+// Floats per particle in the instance buffer: center, size, rotation, color and uv rect.
+const INSTANCE_FLOATS: usize = 13;
+
+thread_local! {
+    // Compiled the first time particles are drawn and shared by every emitter.
+    static PARTICLE_SHADER_PROGRAM: RefCell<Option<ShaderProgram>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Particle {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    // Seconds.
+    pub age: f32,
+    pub lifetime: f32,
+    pub size: f32,
+    // Degrees, like sprites.
+    pub rotation: f32,
+    pub angular_velocity: f32,
+    pub frame: usize,
+}
+
+// Where particles spawn around the emitter's position. Circle is in the XY plane.
+#[derive(Clone, Copy, Debug)]
+pub enum EmitterShape {
+    Point,
+    Circle(f32),
+    Sphere(f32),
+    // Half extents.
+    Box(Vec3),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParticleBlend {
+    Alpha,
+    // Colors add up, for fire, sparks and glows. Order doesn't matter, so these aren't sorted.
+    Additive,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameMode {
+    // Each particle keeps one frame picked when it spawns.
+    Random,
+    // Every particle plays all frames over its lifetime.
+    OverLifetime,
+}
+
+// Spawns and simulates particles and draws them all in one instanced call as quads facing the camera. Particles live
+// in world space, so moving the emitter leaves the ones already out behind. Ranges are (min, max) and picked from
+// uniformly per particle. Call update every tick.
+pub struct ParticleEmitter {
+    pub position: Vec3,
+    pub shape: EmitterShape,
+    pub direction: Vec3,
+    // Particles leave up to this far from direction.
+    pub spread: Deg,
+    pub speed: (f32, f32),
+    pub lifetime: (f32, f32),
+    pub start_size: (f32, f32),
+    pub start_rotation: (f32, f32),
+    pub angular_velocity: (f32, f32),
+    pub gravity: Vec3,
+    // Fraction of the velocity lost per second.
+    pub drag: f32,
+    // Multiplies the start size, over the particle's lifetime from 0 to 1.
+    pub size_over_lifetime: Curve<f32>,
+    pub color_over_lifetime: Curve<Vec4>,
+    // Particles per second while emitting.
+    pub spawn_rate: f32,
+    // (time, count) from the emitter's start.
+    pub bursts: Vec<(f32, u32)>,
+    // Seconds the emitter spawns for, forever when None. Looping emitters start over after it.
+    pub duration: Option<f32>,
+    pub looping: bool,
+    pub emitting: bool,
+    // All frames have to be from the same texture, an atlas or sprite sheet. Without any, particles are soft dots.
+    pub frames: Vec<TextureRegion>,
+    pub frame_mode: FrameMode,
+    pub blend: ParticleBlend,
+    // Keeps spawn positions and velocities in the XY plane, for View2D.
+    pub is_2d: bool,
+    pub max_particles: usize,
+    pub particles: Vec<Particle>,
+    pub time: f32,
+    spawn_accumulator: f32,
+    next_burst: usize,
+    vertex_array_id: Cell<GLuint>,
+    buffer_id: Cell<GLuint>,
+}
+
+impl ParticleEmitter {
+    pub fn new(max_particles: usize) -> Self {
+        ParticleEmitter {
+            position: Vec3::ZERO,
+            shape: EmitterShape::Point,
+            direction: Vec3::new(0.0, 1.0, 0.0),
+            spread: Deg(15.0),
+            speed: (1.0, 2.0),
+            lifetime: (1.0, 2.0),
+            start_size: (0.1, 0.2),
+            start_rotation: (0.0, 0.0),
+            angular_velocity: (0.0, 0.0),
+            gravity: Vec3::ZERO,
+            drag: 0.0,
+            size_over_lifetime: Curve::constant(1.0),
+            color_over_lifetime: Curve::linear(Vec4::ONE, Vec4::new(1.0, 1.0, 1.0, 0.0)),
+            spawn_rate: 10.0,
+            bursts: Vec::new(),
+            duration: None,
+            looping: false,
+            emitting: true,
+            frames: Vec::new(),
+            frame_mode: FrameMode::Random,
+            blend: ParticleBlend::Alpha,
+            is_2d: false,
+            max_particles,
+            particles: Vec::with_capacity(max_particles),
+            time: 0.0,
+            spawn_accumulator: 0.0,
+            next_burst: 0,
+            vertex_array_id: Cell::new(0),
+            buffer_id: Cell::new(0),
+        }
+    }
+
+    pub fn with_position(mut self, position: &Vec3) -> Self {
+        self.position = *position;
+        self
+    }
+
+    pub fn with_shape(mut self, shape: EmitterShape) -> Self {
+        self.shape = shape;
+        self
+    }
+
+    pub fn with_direction(mut self, direction: &Vec3, spread: Deg) -> Self {
+        self.direction = direction.normalize_or_zero();
+        self.spread = spread;
+        self
+    }
+
+    pub fn with_speed(mut self, min: f32, max: f32) -> Self {
+        self.speed = (min, max);
+        self
+    }
+
+    pub fn with_lifetime(mut self, min: f32, max: f32) -> Self {
+        self.lifetime = (min, max);
+        self
+    }
+
+    pub fn with_size(mut self, min: f32, max: f32) -> Self {
+        self.start_size = (min, max);
+        self
+    }
+
+    pub fn with_rotation(mut self, min: f32, max: f32) -> Self {
+        self.start_rotation = (min, max);
+        self
+    }
+
+    pub fn with_angular_velocity(mut self, min: f32, max: f32) -> Self {
+        self.angular_velocity = (min, max);
+        self
+    }
+
+    pub fn with_gravity(mut self, gravity: &Vec3) -> Self {
+        self.gravity = *gravity;
+        self
+    }
+
+    pub fn with_drag(mut self, drag: f32) -> Self {
+        self.drag = drag;
+        self
+    }
+
+    pub fn with_size_over_lifetime(mut self, curve: &Curve<f32>) -> Self {
+        self.size_over_lifetime = curve.clone();
+        self
+    }
+
+    pub fn with_color_over_lifetime(mut self, curve: &Curve<Vec4>) -> Self {
+        self.color_over_lifetime = curve.clone();
+        self
+    }
+
+    // Fades linearly from start to end.
+    pub fn with_colors(mut self, start: &Color, end: &Color) -> Self {
+        self.color_over_lifetime = Curve::linear(start.to_vec4(), end.to_vec4());
+        self
+    }
+
+    pub fn with_spawn_rate(mut self, spawn_rate: f32) -> Self {
+        self.spawn_rate = spawn_rate;
+        self
+    }
+
+    pub fn with_burst(mut self, time: f32, count: u32) -> Self {
+        let index = self.bursts.partition_point(|(burst_time, _)| *burst_time <= time);
+        self.bursts.insert(index, (time, count));
+        self
+    }
+
+    pub fn with_duration(mut self, duration: f32, looping: bool) -> Self {
+        self.duration = Some(duration);
+        self.looping = looping;
+        self
+    }
+
+    pub fn with_texture(self, texture: &Texture) -> Self {
+        self.with_region(&TextureRegion::from_texture(texture))
+    }
+
+    pub fn with_region(mut self, region: &TextureRegion) -> Self {
+        self.frames = vec![*region];
+        self
+    }
+
+    pub fn with_frames(mut self, frames: &[TextureRegion], frame_mode: FrameMode) -> Self {
+        self.frames = frames.to_vec();
+        self.frame_mode = frame_mode;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: ParticleBlend) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn with_2d(mut self) -> Self {
+        self.is_2d = true;
+        self
+    }
+
+    pub fn move_to(&mut self, position: &Vec3) {
+        self.position = *position;
+    }
+
+    // Starts the emitter's timeline over, bursts included. Particles already out keep going.
+    pub fn restart(&mut self) {
+        self.time = 0.0;
+        self.spawn_accumulator = 0.0;
+        self.next_burst = 0;
+        self.emitting = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.emitting && self.particles.is_empty()
+    }
+
+    fn get_random(rng: &mut impl Rng, range: (f32, f32)) -> f32 {
+        if range.1 > range.0 {
+            rng.gen_range(range.0..range.1)
+        } else {
+            range.0
+        }
+    }
+
+    // Spawns count particles right away, on top of the spawn rate and bursts.
+    pub fn emit(&mut self, count: u32) {
+        let mut rng = rand::thread_rng();
+        let count = (count as usize).min(self.max_particles - self.particles.len().min(self.max_particles));
+
+        for _ in 0..count {
+            let mut offset = match self.shape {
+                EmitterShape::Point => Vec3::ZERO,
+                EmitterShape::Circle(radius) => {
+                    let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                    Vec3::new(angle.cos(), angle.sin(), 0.0) * radius * rng.gen::<f32>().sqrt()
+                },
+                EmitterShape::Sphere(radius) => {
+                    let direction = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+                    direction.normalize_or_zero() * radius * rng.gen::<f32>().cbrt()
+                },
+                EmitterShape::Box(half_extents) => {
+                    Vec3::new(rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0)) * half_extents
+                },
+            };
+
+            let mut direction = self.get_spread_direction(&mut rng);
+
+            if self.is_2d {
+                direction = Vec3::new(direction.x, direction.y, 0.0).normalize_or_zero();
+                offset.z = 0.0;
+            }
+
+            let frame = match self.frame_mode {
+                FrameMode::Random if !self.frames.is_empty() => rng.gen_range(0..self.frames.len()),
+                _ => 0,
+            };
+
+            self.particles.push(Particle {
+                position: self.position + offset,
+                velocity: direction * Self::get_random(&mut rng, self.speed),
+                age: 0.0,
+                lifetime: Self::get_random(&mut rng, self.lifetime).max(0.001),
+                size: Self::get_random(&mut rng, self.start_size),
+                rotation: Self::get_random(&mut rng, self.start_rotation),
+                angular_velocity: Self::get_random(&mut rng, self.angular_velocity),
+                frame,
+            });
+        }
+    }
+
+    // A direction within spread of direction. In 2D the cone is a fan in the XY plane.
+    fn get_spread_direction(&self, rng: &mut impl Rng) -> Vec3 {
+        let spread = self.spread.to_radians().as_float();
+
+        if self.is_2d {
+            let angle = rng.gen_range(-1.0..=1.0) * spread;
+            return Mat4::from_rotation_z(angle).transform_vector3(self.direction);
+        }
+
+        // Uniform over the cap of the unit sphere the cone cuts out.
+        let cos_angle = rng.gen_range(spread.cos()..=1.0);
+        let sin_angle = (1.0 - cos_angle * cos_angle).sqrt();
+        let around = rng.gen_range(0.0..std::f32::consts::TAU);
+        let (x_axis, y_axis) = self.direction.any_orthonormal_pair();
+
+        self.direction * cos_angle + (x_axis * around.cos() + y_axis * around.sin()) * sin_angle
+    }
+
+    pub fn update(&mut self, delta: &Duration) {
+        let delta = delta.as_secs_f32();
+
+        for particle in self.particles.iter_mut() {
+            particle.age += delta;
+            particle.velocity += self.gravity * delta;
+            particle.velocity *= (1.0 - self.drag * delta).max(0.0);
+            particle.position += particle.velocity * delta;
+            particle.rotation += particle.angular_velocity * delta;
+        }
+
+        self.particles.retain(|particle| particle.age < particle.lifetime);
+
+        if !self.emitting {
+            return;
+        }
+
+        let previous_time = self.time;
+        self.time += delta;
+
+        while self.next_burst < self.bursts.len() && self.bursts[self.next_burst].0 <= self.time {
+            self.emit(self.bursts[self.next_burst].1);
+            self.next_burst += 1;
+        }
+
+        // Without a duration there's nothing to loop.
+        let end = self.duration.unwrap_or(f32::INFINITY);
+        let spawn_time = self.time.min(end) - previous_time.min(end);
+
+        self.spawn_accumulator += spawn_time * self.spawn_rate;
+        let count = self.spawn_accumulator.floor();
+        self.spawn_accumulator -= count;
+        self.emit(count as u32);
+
+        if self.time >= end {
+            if self.looping {
+                let overshoot = self.time - end;
+                self.restart();
+                self.time = overshoot;
+            } else {
+                self.emitting = false;
+            }
+        }
+    }
+
+    fn get_shader_program() -> ShaderProgram {
+        PARTICLE_SHADER_PROGRAM.with(|shader_program| {
+            shader_program.borrow_mut().get_or_insert_with(|| {
+                ShaderProgram::from_builders(
+                    &ShaderBuilderTemplate::particle_vertex_shader(PARTICLE_SHADER_VERSION),
+                    &ShaderBuilderTemplate::particle_fragment_shader(PARTICLE_SHADER_VERSION),
+                ).unwrap()
+            }).clone()
+        })
+    }
+
+    fn init_buffers(&self) {
+        let (mut vertex_array_id, mut buffer_id) = (0, 0);
+
+        unsafe {
+            gl::GenVertexArrays(1, &mut vertex_array_id);
+            gl::GenBuffers(1, &mut buffer_id);
+
+            gl::BindVertexArray(vertex_array_id);
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
+
+            let stride = (INSTANCE_FLOATS * std::mem::size_of::<f32>()) as GLsizei;
+            let mut offset = 0;
+
+            // One set per particle, the quad's corners come from gl_VertexID.
+            for (location, size) in [3, 1, 1, 4, 4].iter().enumerate() {
+                gl::EnableVertexAttribArray(location as GLuint);
+                gl::VertexAttribPointer(
+                    location as GLuint, *size, gl::FLOAT, gl::FALSE, stride,
+                    (offset * std::mem::size_of::<f32>()) as *const std::ffi::c_void,
+                );
+                gl::VertexAttribDivisor(location as GLuint, 1);
+
+                offset += *size as usize;
+            }
+
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+
+        self.vertex_array_id.set(vertex_array_id);
+        self.buffer_id.set(buffer_id);
+    }
+
+    fn get_uv_rect(&self, particle: &Particle, life: f32) -> Vec4 {
+        if self.frames.is_empty() {
+            return Vec4::new(0.0, 0.0, 1.0, 1.0);
+        }
+
+        let frame = match self.frame_mode {
+            FrameMode::Random => particle.frame,
+            FrameMode::OverLifetime => ((life * self.frames.len() as f32) as usize).min(self.frames.len() - 1),
+        };
+
+        self.frames[frame].uv_rect
+    }
+
+    pub fn delete(&self) {
+        if self.vertex_array_id.get() != 0 {
+            unsafe {
+                gl::DeleteVertexArrays(1, &self.vertex_array_id.get());
+                gl::DeleteBuffers(1, &self.buffer_id.get());
+            }
+
+            self.vertex_array_id.set(0);
+            self.buffer_id.set(0);
+        }
+    }
+}
+
+impl Renderable for ParticleEmitter {
+    // Particles carry their own positions.
+    fn get_model_matrix(&self) -> Mat4 {
+        Mat4::IDENTITY
+    }
+
+    fn render(&self, layer: &GraphicsLayer) {
+        if self.particles.is_empty() {
+            return;
+        }
+
+        if self.vertex_array_id.get() == 0 {
+            self.init_buffers();
+        }
+
+        // Quads face the camera, in 2D that's always the screen.
+        let (view_matrix, camera_position, camera_front, camera_right, camera_up) = match &layer.view {
+            View::View2D(view) => (view.get_view_matrix(), view.position, view.front, Vec3::X, Vec3::Y),
+            View::View3D(view) => {
+                let right = view.up.cross(view.front).normalize();
+                (view.get_view_matrix(), view.position, view.front, right, view.front.cross(right))
+            },
+        };
+
+        let mut order: Vec<usize> = (0..self.particles.len()).collect();
+
+        // Alpha blended particles go back to front, or near ones get hidden behind far ones' transparent edges.
+        if self.blend == ParticleBlend::Alpha {
+            let get_depth = |i: &usize| (self.particles[*i].position - camera_position).dot(camera_front);
+            order.sort_by(|a, b| get_depth(b).total_cmp(&get_depth(a)));
+        }
+
+        let mut instances: Vec<f32> = Vec::with_capacity(self.particles.len() * INSTANCE_FLOATS);
+
+        for i in order {
+            let particle = &self.particles[i];
+            let life = particle.age / particle.lifetime;
+
+            instances.extend_from_slice(&particle.position.to_array());
+            instances.push(particle.size * self.size_over_lifetime.evaluate(life));
+            instances.push(Deg(particle.rotation).to_radians().as_float());
+            instances.extend_from_slice(&self.color_over_lifetime.evaluate(life).to_array());
+            instances.extend_from_slice(&self.get_uv_rect(particle, life).to_array());
+        }
+
+        let shader_program = Self::get_shader_program();
+        shader_program.set_uniform_mat4_f32("view_projection", &(view_matrix * layer.get_graphics_layer_matrix()));
+        shader_program.set_uniform_vec3_f32("camera_right", &camera_right);
+        shader_program.set_uniform_vec3_f32("camera_up", &camera_up);
+        shader_program.set_uniform_bool("use_texture", !self.frames.is_empty());
+        shader_program.set_uniform_i32("particle_texture", 0);
+
+        if let Some(frame) = self.frames.first() {
+            frame.texture.bind(0, true);
+        }
+
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer_id.get());
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                std::mem::size_of_val(instances.as_slice()) as GLsizeiptr,
+                instances.as_ptr() as *const std::ffi::c_void,
+                gl::STREAM_DRAW,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+            let (mut source, mut destination, mut depth_mask): (GLint, GLint, GLboolean) = (0, 0, gl::TRUE);
+            gl::GetIntegerv(gl::BLEND_SRC_RGB, &mut source);
+            gl::GetIntegerv(gl::BLEND_DST_RGB, &mut destination);
+            gl::GetBooleanv(gl::DEPTH_WRITEMASK, &mut depth_mask);
+
+            // Particles are tested against the scene's depth but don't write their own, so they don't cut each
+            // other out.
+            gl::DepthMask(gl::FALSE);
+
+            match self.blend {
+                ParticleBlend::Alpha => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA),
+                ParticleBlend::Additive => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE),
+            }
+
+            shader_program.use_program(true);
+            gl::BindVertexArray(self.vertex_array_id.get());
+            gl::DrawArraysInstanced(gl::TRIANGLES, 0, 6, self.particles.len() as GLsizei);
+            gl::BindVertexArray(0);
+            shader_program.use_program(false);
+
+            gl::BlendFunc(source as GLenum, destination as GLenum);
+            gl::DepthMask(depth_mask);
+        }
+    }
+
+    fn is_transparent(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for ParticleEmitter {
+    fn drop(&mut self) {
+        self.delete();
+    }
+}
+
@@ -0,0 +1,324 @@
+use gl::types::*;
+use glam::{UVec2, Vec2, Vec3};
+
+use super::framebuffer::RenderTarget;
+use super::shader::{ShaderBuilder, ShaderBuilderTemplate, ShaderClosure, ShaderError, ShaderProgram, UniformValue};
+use super::texture::Texture;
+
+const POST_PROCESS_SHADER_VERSION: &str = "#version 450 core";
+
+// One fullscreen shader pass. The fragment shader gets the previous result in screen_texture, its size in
+// screen_size and tex_coords from ShaderBuilderTemplate::fullscreen_vertex_shader. The program is compiled when
+// the stack is initialized, since there is no GL context before that.
+pub struct PostProcessPass {
+    pub name: String,
+    pub fragment_shader: ShaderBuilder,
+    pub uniforms: Vec<(String, UniformValue)>,
+    pub enabled: bool,
+    pub shader_program: Option<ShaderProgram>,
+}
+
+impl PostProcessPass {
+    pub fn new(name: &str, fragment_shader: &ShaderBuilder) -> Self {
+        PostProcessPass {
+            name: String::from(name),
+            fragment_shader: fragment_shader.clone(),
+            uniforms: Vec::new(),
+            enabled: true,
+            shader_program: None,
+        }
+    }
+
+    pub fn with_uniform(mut self, name: &str, value: UniformValue) -> Self {
+        self.set_uniform(name, value);
+        self
+    }
+
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    // Takes effect the next frame, values are uploaded every time the pass runs.
+    pub fn set_uniform(&mut self, name: &str, value: UniformValue) {
+        match self.uniforms.iter_mut().find(|(uniform_name, _)| uniform_name == name) {
+            Some((_, uniform_value)) => *uniform_value = value,
+            None => self.uniforms.push((String::from(name), value)),
+        }
+    }
+
+    pub fn init(&mut self) -> Result<(), ShaderError> {
+        let vertex_shader = ShaderBuilderTemplate::fullscreen_vertex_shader(&self.fragment_shader.version);
+        self.shader_program = Some(ShaderProgram::from_builders(&vertex_shader, &self.fragment_shader)?);
+
+        Ok(())
+    }
+
+    // Gaussian blur over a 5x5 grid of samples spaced radius pixels apart.
+    pub fn blur(radius: f32) -> Self {
+        let mut shader_builder = ShaderBuilderTemplate::post_process_fragment_shader(POST_PROCESS_SHADER_VERSION);
+
+        shader_builder.dec_uniform("float", "blur_radius");
+
+        shader_builder.main.dec_var("vec4", "blurred", "vec4(0.0)");
+        shader_builder.main.dec_var("float", "total_weight", "0.0");
+
+        shader_builder.main.dec_for_loop("int x = -2; x <= 2; x++", &ShaderClosure::new()
+            .with_for_loop("int y = -2; y <= 2; y++", &ShaderClosure::new()
+                .with_dec_var("float", "weight", "exp(-float(x * x + y * y) / 4.0)")
+                .with_do_action("blurred += texture(screen_texture, tex_coords + vec2(x, y) * blur_radius / screen_size) * weight")
+                .with_do_action("total_weight += weight")
+            )
+        );
+
+        shader_builder.main.do_action("output_color = blurred / total_weight");
+
+        Self::new("blur", &shader_builder)
+            .with_uniform("blur_radius", UniformValue::F32(radius))
+    }
+
+    // Darkens the corners. radius is where darkening starts, as a distance from the center in texture coordinates.
+    pub fn vignette(strength: f32, radius: f32) -> Self {
+        let mut shader_builder = ShaderBuilderTemplate::post_process_fragment_shader(POST_PROCESS_SHADER_VERSION);
+
+        shader_builder.dec_uniform("float", "vignette_strength");
+        shader_builder.dec_uniform("float", "vignette_radius");
+
+        shader_builder.main.dec_var("float", "vignette", "smoothstep(vignette_radius, vignette_radius + 0.25, distance(tex_coords, vec2(0.5)))");
+
+        shader_builder.main.do_action("color.rgb *= 1.0 - vignette * vignette_strength");
+        shader_builder.main.do_action("output_color = color");
+
+        Self::new("vignette", &shader_builder)
+            .with_uniform("vignette_strength", UniformValue::F32(strength))
+            .with_uniform("vignette_radius", UniformValue::F32(radius))
+    }
+
+    // 1.0 everywhere and a white tint leaves the image unchanged.
+    pub fn color_grading(brightness: f32, contrast: f32, saturation: f32, tint: &Vec3) -> Self {
+        let mut shader_builder = ShaderBuilderTemplate::post_process_fragment_shader(POST_PROCESS_SHADER_VERSION);
+
+        shader_builder.dec_uniform("float", "brightness");
+        shader_builder.dec_uniform("float", "contrast");
+        shader_builder.dec_uniform("float", "saturation");
+        shader_builder.dec_uniform("vec3", "tint");
+
+        shader_builder.main.do_action("color.rgb *= brightness");
+        shader_builder.main.do_action("color.rgb = (color.rgb - 0.5) * contrast + 0.5");
+        shader_builder.main.do_action("color.rgb = mix(vec3(dot(color.rgb, vec3(0.2126, 0.7152, 0.0722))), color.rgb, saturation)");
+        shader_builder.main.do_action("color.rgb *= tint");
+        shader_builder.main.do_action("output_color = vec4(clamp(color.rgb, 0.0, 1.0), color.a)");
+
+        Self::new("color_grading", &shader_builder)
+            .with_uniform("brightness", UniformValue::F32(brightness))
+            .with_uniform("contrast", UniformValue::F32(contrast))
+            .with_uniform("saturation", UniformValue::F32(saturation))
+            .with_uniform("tint", UniformValue::Vec3(*tint))
+    }
+
+    // Big square pixels, pixel_size screen pixels wide.
+    pub fn pixelate(pixel_size: f32) -> Self {
+        let mut shader_builder = ShaderBuilderTemplate::post_process_fragment_shader(POST_PROCESS_SHADER_VERSION);
+
+        shader_builder.dec_uniform("float", "pixel_size");
+
+        shader_builder.main.do_action("color = texture(screen_texture, (floor(tex_coords * screen_size / pixel_size) + 0.5) * pixel_size / screen_size)");
+        shader_builder.main.do_action("output_color = color");
+
+        Self::new("pixelate", &shader_builder)
+            .with_uniform("pixel_size", UniformValue::F32(pixel_size))
+    }
+}
+
+// Renders the scene into a RenderTarget and runs it through the enabled passes in order, the last one drawing to
+// whatever framebuffer was bound before begin(), normally the window.
+pub struct PostProcessStack {
+    pub passes: Vec<PostProcessPass>,
+    pub samples: u32,
+    pub size: UVec2,
+    pub scene_target: Option<RenderTarget>,
+    pub pass_targets: Vec<RenderTarget>,
+    pub vertex_array_id: GLuint,
+}
+
+impl Default for PostProcessStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostProcessStack {
+    pub fn new() -> Self {
+        PostProcessStack {
+            passes: Vec::new(),
+            samples: 1,
+            size: UVec2::ZERO,
+            scene_target: None,
+            pass_targets: Vec::new(),
+            vertex_array_id: 0,
+        }
+    }
+
+    // Multisampling for the scene, the passes themselves don't need it.
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn add_pass(&mut self, pass: PostProcessPass) {
+        self.passes.push(pass);
+    }
+
+    pub fn with_pass(mut self, pass: PostProcessPass) -> Self {
+        self.add_pass(pass);
+        self
+    }
+
+    pub fn get_pass(&mut self, name: &str) -> Option<&mut PostProcessPass> {
+        self.passes.iter_mut().find(|pass| pass.name == name)
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        if let Some(pass) = self.get_pass(name) {
+            pass.enabled = enabled;
+        }
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.scene_target.is_some()
+    }
+
+    // Compiles the passes and creates the targets. Needs a current GL context.
+    pub fn init(&mut self, size: &UVec2) -> Result<(), ShaderError> {
+        for pass in &mut self.passes {
+            if pass.shader_program.is_none() {
+                pass.init()?;
+            }
+        }
+
+        unsafe {
+            gl::GenVertexArrays(1, &mut self.vertex_array_id);
+        }
+
+        self.size = *size;
+        self.scene_target = Some(RenderTarget::new(size, self.samples));
+        self.pass_targets = vec![RenderTarget::new(size, 1), RenderTarget::new(size, 1)];
+
+        Ok(())
+    }
+
+    pub fn resize(&mut self, size: &UVec2) {
+        // Minimized windows report 0x0, which no framebuffer can have.
+        if size.x == 0 || size.y == 0 || !self.is_initialized() {
+            return;
+        }
+
+        self.size = *size;
+
+        if let Some(scene_target) = &mut self.scene_target {
+            scene_target.resize(size);
+        }
+
+        for target in &mut self.pass_targets {
+            target.resize(size);
+        }
+    }
+
+    // Everything rendered until end() goes into the scene target.
+    pub fn begin(&self) {
+        if let Some(scene_target) = &self.scene_target {
+            scene_target.bind(true);
+        }
+    }
+
+    pub fn end(&self) {
+        let scene_target = match &self.scene_target {
+            Some(scene_target) => scene_target,
+            None => return,
+        };
+
+        scene_target.bind(false);
+        scene_target.resolve();
+
+        let mut output_framebuffer: GLint = 0;
+
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut output_framebuffer);
+        }
+
+        let passes: Vec<&PostProcessPass> = self.passes.iter().filter(|pass| pass.enabled && pass.shader_program.is_some()).collect();
+
+        if passes.is_empty() {
+            let mut viewport: [GLint; 4] = [0; 4];
+
+            unsafe {
+                gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            }
+
+            scene_target.blit_to(output_framebuffer as GLuint, &UVec2::new(viewport[2] as u32, viewport[3] as u32));
+            return;
+        }
+
+        let mut input = scene_target.get_texture();
+
+        for (i, pass) in passes.iter().enumerate() {
+            if i == passes.len() - 1 {
+                self.draw_pass(pass, &input);
+            } else {
+                let target = &self.pass_targets[i % 2];
+
+                target.bind(true);
+                self.draw_pass(pass, &input);
+                target.bind(false);
+
+                input = target.get_texture();
+            }
+        }
+    }
+
+    fn draw_pass(&self, pass: &PostProcessPass, input: &Texture) {
+        let shader_program = pass.shader_program.as_ref().unwrap();
+
+        shader_program.set_uniform_i32("screen_texture", 0);
+        shader_program.set_uniform_vec2_f32("screen_size", &Vec2::new(self.size.x as f32, self.size.y as f32));
+
+        for (name, value) in &pass.uniforms {
+            shader_program.set_uniform(name, value);
+        }
+
+        unsafe {
+            let depth_test = gl::IsEnabled(gl::DEPTH_TEST) == gl::TRUE;
+            let blend = gl::IsEnabled(gl::BLEND) == gl::TRUE;
+
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::BLEND);
+
+            input.bind(0, true);
+            shader_program.use_program(true);
+
+            gl::BindVertexArray(self.vertex_array_id);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::BindVertexArray(0);
+
+            shader_program.use_program(false);
+
+            if depth_test {
+                gl::Enable(gl::DEPTH_TEST);
+            }
+
+            if blend {
+                gl::Enable(gl::BLEND);
+            }
+        }
+    }
+}
+
+impl Drop for PostProcessStack {
+    fn drop(&mut self) {
+        if self.vertex_array_id != 0 {
+            unsafe {
+                gl::DeleteVertexArrays(1, &self.vertex_array_id);
+            }
+        }
+    }
+}
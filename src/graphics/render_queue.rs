@@ -0,0 +1,285 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use gl::types::*;
+use glam::{Mat4, Vec4Swizzles};
+
+use super::renderable::Renderable;
+use super::view::{GraphicsLayer, View};
+
+#[derive(Clone)]
+struct RenderQueueItem {
+    renderable: Rc<dyn Renderable>,
+    // The view and transform of the layer it was submitted through, so it keeps them whichever layer flushes it.
+    view: View,
+    layer_matrix: Mat4,
+    is_transparent: bool,
+    state_key: u64,
+    z_order: i32,
+}
+
+// Renderables submitted from anywhere during a frame and drawn together by GraphicsLayer::flush_render_queue, so
+// the order they're drawn in no longer depends on the order they were submitted in. Opaque objects go first, grouped
+// by state key so objects sharing a shader and texture are drawn one after another, front to back within a group so
+// hidden pixels fail the depth test early. Transparent objects go after them, by z order then back to front, with
+// depth writes off so overlapping ones blend instead of cutting each other out.
+//
+// Renderables are shared, keep an Rc<RefCell<T>> around for ones that change between frames. Clones share the queue,
+// and so do layers and their children, so one flush draws everything submitted through any of them.
+#[derive(Clone)]
+pub struct RenderQueue {
+    items: Rc<RefCell<Vec<RenderQueueItem>>>,
+}
+
+impl Default for RenderQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderQueue {
+    pub fn new() -> Self {
+        RenderQueue {
+            items: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    // Renderables keep their sorting information and the layer's view and transform from the time they were
+    // submitted. Nothing else of the layer is kept, so the queue holds no layers and no references back to itself.
+    pub fn submit<T: Renderable + 'static>(&self, layer: &GraphicsLayer, renderable: &Rc<T>) {
+        self.items.borrow_mut().push(RenderQueueItem {
+            view: layer.view.clone(),
+            layer_matrix: layer.get_graphics_layer_matrix(),
+            is_transparent: renderable.is_transparent(),
+            state_key: renderable.get_state_key(),
+            z_order: renderable.get_z_order(),
+            renderable: renderable.clone(),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.borrow().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.items.borrow_mut().clear();
+    }
+
+    // Packs a shader program and texture into a state key for Renderable::get_state_key.
+    pub fn get_state_key(program_id: GLuint, texture_id: GLuint) -> u64 {
+        ((program_id as u64) << 32) | texture_id as u64
+    }
+
+    // Draws everything submitted, each with the layer it was submitted through, and empties the queue. Renderables
+    // submitted while it draws wait for the next flush. Flushing from a shadow depth pass draws depth only and keeps
+    // the queue, so the same submissions can be drawn into the shadow maps and then to the screen.
+    pub fn flush(&self, layer: &GraphicsLayer) {
+        let is_depth_pass = layer.get_depth_pass().is_some();
+
+        let items: Vec<RenderQueueItem> = if is_depth_pass {
+            self.items.borrow().clone()
+        } else {
+            self.items.borrow_mut().drain(..).collect()
+        };
+
+        let (opaque, transparent) = Self::sort_items(items);
+
+        // Everything but the view and transform comes from the flushing layer, like the depth pass.
+        let mut item_layer = GraphicsLayer {
+            view: layer.view.clone(),
+            position: layer.position,
+            rotation: layer.rotation,
+            scale: layer.scale,
+            parent: None,
+            depth_pass: layer.depth_pass.clone(),
+            debug_draw: layer.debug_draw.clone(),
+            debug_font: layer.debug_font.clone(),
+            render_queue: layer.render_queue.clone(),
+            matrix_override: None,
+        };
+
+        for item in opaque.iter() {
+            Self::render_item(&mut item_layer, item);
+        }
+
+        // Shadow casters write depth whether they're transparent or not.
+        if transparent.is_empty() || is_depth_pass {
+            for item in transparent.iter() {
+                Self::render_item(&mut item_layer, item);
+            }
+
+            return;
+        }
+
+        unsafe {
+            let mut depth_mask: GLboolean = gl::TRUE;
+            gl::GetBooleanv(gl::DEPTH_WRITEMASK, &mut depth_mask);
+            gl::DepthMask(gl::FALSE);
+
+            for item in transparent.iter() {
+                Self::render_item(&mut item_layer, item);
+            }
+
+            gl::DepthMask(depth_mask);
+        }
+    }
+
+    // Splits the items into opaque and transparent ones, each in the order they're drawn in.
+    fn sort_items(items: Vec<RenderQueueItem>) -> (Vec<RenderQueueItem>, Vec<RenderQueueItem>) {
+        // Distance in front of the camera along its view direction. Sorting by it isn't affected by the projection,
+        // so it works the same for both views.
+        let get_depth = |item: &RenderQueueItem| {
+            let (camera_position, camera_front) = match &item.view {
+                View::View2D(view) => (view.position, view.front),
+                View::View3D(view) => (view.position, view.front),
+            };
+
+            let position = (item.layer_matrix * item.renderable.get_model_matrix()).w_axis.xyz();
+            (position - camera_position).dot(camera_front)
+        };
+
+        let (mut opaque, mut transparent): (Vec<_>, Vec<_>) = items.into_iter()
+            .map(|item| (get_depth(&item), item))
+            .partition(|(_, item)| !item.is_transparent);
+
+        // Stable sorts, so equal keys keep the order they were submitted in.
+        opaque.sort_by(|(a_depth, a), (b_depth, b)| a.state_key.cmp(&b.state_key).then(a_depth.total_cmp(b_depth)));
+        transparent.sort_by(|(a_depth, a), (b_depth, b)| a.z_order.cmp(&b.z_order).then(b_depth.total_cmp(a_depth)));
+
+        (
+            opaque.into_iter().map(|(_, item)| item).collect(),
+            transparent.into_iter().map(|(_, item)| item).collect(),
+        )
+    }
+
+    fn render_item(item_layer: &mut GraphicsLayer, item: &RenderQueueItem) {
+        item_layer.view = item.view.clone();
+        item_layer.matrix_override = Some(item.layer_matrix);
+
+        match item_layer.get_depth_pass() {
+            Some(depth_pass) => item.renderable.render_depth(item_layer, depth_pass),
+            None => item.renderable.render(item_layer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec3};
+
+    use super::*;
+    use crate::graphics::view::{View2D, View3D};
+
+    // Told apart by id, which is also the x of its position.
+    struct TestRenderable {
+        id: f32,
+        depth: f32,
+        transparent: bool,
+        state_key: u64,
+        z_order: i32,
+    }
+
+    impl Renderable for TestRenderable {
+        fn render(&self, _layer: &GraphicsLayer) {}
+
+        fn get_model_matrix(&self) -> Mat4 {
+            Mat4::from_translation(Vec3::new(self.id, 0.0, self.depth))
+        }
+
+        fn is_transparent(&self) -> bool {
+            self.transparent
+        }
+
+        fn get_state_key(&self) -> u64 {
+            self.state_key
+        }
+
+        fn get_z_order(&self) -> i32 {
+            self.z_order
+        }
+    }
+
+    fn submit(queue: &RenderQueue, layer: &GraphicsLayer, id: f32, depth: f32, transparent: bool, state_key: u64, z_order: i32) {
+        queue.submit(layer, &Rc::new(TestRenderable { id, depth, transparent, state_key, z_order }));
+    }
+
+    fn get_order(queue: &RenderQueue) -> (Vec<f32>, Vec<f32>) {
+        let (opaque, transparent) = RenderQueue::sort_items(queue.items.borrow_mut().drain(..).collect());
+        let get_ids = |items: Vec<RenderQueueItem>| items.iter().map(|item| item.renderable.get_model_matrix().w_axis.x).collect();
+
+        (get_ids(opaque), get_ids(transparent))
+    }
+
+    #[test]
+    fn opaque_items_group_by_state_then_front_to_back() {
+        let layer = GraphicsLayer::new(&View::View3D(View3D::new(Vec2::new(800.0, 600.0))));
+        let queue = RenderQueue::new();
+
+        submit(&queue, &layer, 1.0, 5.0, false, 2, 0);
+        submit(&queue, &layer, 2.0, 1.0, false, 2, 0);
+        submit(&queue, &layer, 3.0, 9.0, false, 1, 0);
+        submit(&queue, &layer, 4.0, 3.0, false, 1, 0);
+
+        assert_eq!(get_order(&queue), (vec![4.0, 3.0, 2.0, 1.0], vec![]));
+    }
+
+    #[test]
+    fn transparent_items_sort_by_z_order_then_back_to_front() {
+        let layer = GraphicsLayer::new(&View::View3D(View3D::new(Vec2::new(800.0, 600.0))));
+        let queue = RenderQueue::new();
+
+        submit(&queue, &layer, 1.0, 1.0, true, 0, 1);
+        submit(&queue, &layer, 2.0, 1.0, true, 0, 0);
+        submit(&queue, &layer, 3.0, 8.0, true, 0, 0);
+        submit(&queue, &layer, 4.0, 4.0, false, 0, 5);
+
+        assert_eq!(get_order(&queue), (vec![4.0], vec![3.0, 2.0, 1.0]));
+    }
+
+    #[test]
+    fn equal_items_keep_the_submission_order() {
+        let layer = GraphicsLayer::new(&View::View2D(View2D::new(Vec2::new(800.0, 600.0))));
+        let queue = RenderQueue::new();
+
+        for id in 0..4 {
+            submit(&queue, &layer, id as f32, 0.0, id % 2 == 0, 0, 0);
+        }
+
+        assert_eq!(get_order(&queue), (vec![1.0, 3.0], vec![0.0, 2.0]));
+    }
+
+    #[test]
+    fn depth_uses_the_submitting_layer_and_view() {
+        let view = View::View3D(View3D::new(Vec2::new(800.0, 600.0)));
+        let near_layer = GraphicsLayer::new(&view);
+        let far_layer = GraphicsLayer::new(&view).with_position(Vec3::new(0.0, 0.0, 10.0));
+        let behind_view = View::View3D(View3D::new(Vec2::new(800.0, 600.0)).with_position(Vec3::new(0.0, 0.0, 20.0)));
+        let behind_layer = GraphicsLayer::new(&behind_view);
+        let queue = RenderQueue::new();
+
+        submit(&queue, &far_layer, 1.0, 1.0, false, 0, 0);
+        submit(&queue, &near_layer, 2.0, 5.0, false, 0, 0);
+        // 15 behind its camera.
+        submit(&queue, &behind_layer, 3.0, 5.0, false, 0, 0);
+
+        assert_eq!(get_order(&queue), (vec![3.0, 2.0, 1.0], vec![]));
+    }
+
+    #[test]
+    fn clones_share_the_queue() {
+        let layer = GraphicsLayer::new(&View::View2D(View2D::new(Vec2::new(800.0, 600.0))));
+        let queue = RenderQueue::new();
+
+        submit(&queue.clone(), &layer, 1.0, 0.0, false, 0, 0);
+
+        assert_eq!(queue.len(), 1);
+
+        queue.clear();
+
+        assert!(queue.is_empty());
+    }
+}
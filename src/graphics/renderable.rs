@@ -1,23 +1,80 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use std::time::Duration;
 
+use crate::graphics::color::Color;
+use crate::graphics::font::{Font, TextLayout};
 use crate::graphics::mesh::{AnimatedMesh, StaticMesh, StaticMeshData};
 use crate::graphics::view::View;
-use crate::graphics::shader::ShaderProgram;
-use crate::graphics::texture::Texture;
+use crate::graphics::material::Material;
+use crate::graphics::shader::{ShaderBuilderTemplate, ShaderError, ShaderProgram, UniformValue};
+use crate::graphics::render_queue::RenderQueue;
+use crate::graphics::shadow::DepthPass;
+use crate::graphics::shape::{LineCap, LineJoin, Paint, ShapePath, ShapeTessellator};
+use crate::graphics::texture::{Cubemap, SharedTexture, Texture, TextureRegion};
 use super::animation::SpriteAnimation;
 use super::{math::Deg, vertex::Vertex};
 use super::mesh::Mesh;
 use super::view::GraphicsLayer;
-use glam::{Mat4, Vec2, Vec3};
+use gl::types::*;
+use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
 
 pub trait Renderable {
     fn render(&self, layer: &GraphicsLayer);
     fn get_model_matrix(&self) -> Mat4;
+
+    // Draws only depth, from the light, for shadow maps. Things that don't cast shadows leave it empty.
+    fn render_depth(&self, _layer: &GraphicsLayer, _depth_pass: &DepthPass) {}
+
+    // Drawn after opaque objects by RenderQueue, back to front.
+    fn is_transparent(&self) -> bool {
+        false
+    }
+
+    // Opaque objects with the same key are drawn one after another by RenderQueue, see RenderQueue::get_state_key.
+    fn get_state_key(&self) -> u64 {
+        0
+    }
+
+    // Orders transparent objects before their depth does, higher is drawn later.
+    fn get_z_order(&self) -> i32 {
+        0
+    }
+}
+
+// So renderables that change between frames can be shared with a RenderQueue as Rc<RefCell<T>>.
+impl<T: Renderable> Renderable for RefCell<T> {
+    fn render(&self, layer: &GraphicsLayer) {
+        self.borrow().render(layer);
+    }
+
+    fn get_model_matrix(&self) -> Mat4 {
+        self.borrow().get_model_matrix()
+    }
+
+    fn render_depth(&self, layer: &GraphicsLayer, depth_pass: &DepthPass) {
+        self.borrow().render_depth(layer, depth_pass);
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.borrow().is_transparent()
+    }
+
+    fn get_state_key(&self) -> u64 {
+        self.borrow().get_state_key()
+    }
+
+    fn get_z_order(&self) -> i32 {
+        self.borrow().get_z_order()
+    }
 }
 
 pub struct RenderableMesh {
     pub mesh: Mesh,
     pub texture: Option<Texture>,
+    // Set when texture came from with_shared_texture. It keeps the texture alive, so it isn't deleted with the mesh.
+    pub shared_texture: Option<SharedTexture>,
+    pub material: Material,
     pub position: Vec3,
     pub rotation: Vec3,
     pub scale: Vec3,
@@ -25,9 +82,16 @@ pub struct RenderableMesh {
 
 impl RenderableMesh {
     pub fn new(mesh: Mesh) -> Self {
+        let material = match &mesh {
+            Mesh::StaticMesh(mesh) => Material::new(&mesh.shader_program),
+            Mesh::AnimatedMesh(mesh) => Material::new(&mesh.shader_program),
+        };
+
         RenderableMesh {
             mesh: mesh,
             texture: None,
+            shared_texture: None,
+            material,
             position: Vec3::new(0.0, 0.0, 0.0),
             rotation: Vec3::new(0.0, 0.0, 0.0),
             scale: Vec3::new(1.0, 1.0, 1.0),
@@ -51,6 +115,19 @@ impl RenderableMesh {
 
     pub fn with_texture(mut self, texture: &Texture) -> Self {
         self.texture = Some(*texture);
+        self.shared_texture = None;
+        self
+    }
+
+    pub fn with_shared_texture(mut self, texture: &SharedTexture) -> Self {
+        self.texture = Some(texture.texture);
+        self.shared_texture = Some(texture.clone());
+        self
+    }
+
+    // Materials can be shared, the mesh's own texture still goes in unit 0 on top of them.
+    pub fn with_material(mut self, material: &Material) -> Self {
+        self.material = material.clone();
         self
     }
 
@@ -115,6 +192,22 @@ impl RenderableMesh {
     pub fn scale_by(&mut self, scale: &Vec3) {
         self.scale += *scale;
     }
+
+    // The model matrix with the layer's transform and the z up fix for meshes exported that way.
+    fn get_world_matrix(&self, layer: &GraphicsLayer) -> Mat4 {
+        let y_up = match &self.mesh {
+            Mesh::StaticMesh(mesh) => mesh.y_up,
+            Mesh::AnimatedMesh(mesh) => mesh.y_up,
+        };
+
+        let mut model_matrix = self.get_model_matrix();
+
+        if !y_up {
+            model_matrix = model_matrix * Mat4::from_rotation_x(Deg(-90.0).to_radians().as_float());
+        }
+
+        layer.get_graphics_layer_matrix() * model_matrix
+    }
 }
 
 impl Renderable for RenderableMesh {
@@ -133,61 +226,66 @@ impl Renderable for RenderableMesh {
     }
 
     fn render(&self, layer: &GraphicsLayer) {
-        let shader_program = match &self.mesh {
-            Mesh::StaticMesh(mesh) => &mesh.shader_program,
-            Mesh::AnimatedMesh(mesh) => &mesh.shader_program,
-        };
-
-        let y_up = match &self.mesh {
-            Mesh::StaticMesh(mesh) => mesh.y_up,
-            Mesh::AnimatedMesh(mesh) => mesh.y_up,
-        };
-
-        let mut model_matrix = self.get_model_matrix();
-
-        if !y_up {
-            model_matrix = model_matrix * Mat4::from_rotation_x(Deg(-90.0).to_radians().as_float());
-        }
+        let shader_program = self.material.get_shader_program();
+        let world_matrix = self.get_world_matrix(layer);
 
         let view_matrix = match &layer.view {
             View::View2D(view) => view.get_view_matrix(),
             View::View3D(view) => view.get_view_matrix(),
         };
 
-        shader_program.use_program(true);
-
-        let light_position = Vec3::new(0.5, 0.2, 1.0);
-        shader_program.set_uniform_vec3_f32("light_position_uni", &light_position);
+        // Sprite values like use_tint on a shared program are reset by the material cache, since meshes don't set them.
+        self.material.apply_with(&[
+            ("should_sample_texture", UniformValue::Bool(self.texture.is_some() || self.material.has_textures())),
+        ]);
 
-        let mvp = view_matrix * layer.get_graphics_layer_matrix() * model_matrix;
+        let mvp = view_matrix * world_matrix;
         shader_program.set_uniform_mat4_f32("mvp", &mvp);
+        shader_program.set_uniform_mat4_f32("model", &world_matrix);
+
+        if let Some(texture) = &self.texture {
+            texture.bind(0, true);
+        }
 
-        if self.texture.is_none() {
-            shader_program.set_uniform_bool("should_sample_texture", false);
-        } else {
-            self.texture.unwrap().bind(0, true);
-            shader_program.set_uniform_bool("should_sample_texture", true);
+        // Static meshes have no bone ids, so the skinning shader leaves them alone whatever joint_transforms holds.
+        if let Mesh::AnimatedMesh(mesh) = &self.mesh {
+            shader_program.set_uniform_vec_mat4_f32("joint_transforms", &mesh.animation_player.skeleton.get_global_transform_matrices());
         }
 
+        shader_program.use_program(true);
+
         match &self.mesh {
-            Mesh::StaticMesh(mesh) => {
-                let bone_matrices = [Mat4::IDENTITY; 100];
-                shader_program.set_uniform_vec_mat4_f32("joint_transforms", &bone_matrices.to_vec());
+            Mesh::StaticMesh(mesh) => mesh.vao.render(mesh.index_count),
+            Mesh::AnimatedMesh(mesh) => mesh.vao.render(mesh.index_count),
+        }
 
-                mesh.render()
-            },
-            Mesh::AnimatedMesh(mesh) => {
-                shader_program.set_uniform_vec_mat4_f32("joint_transforms", &mesh.animation_player.skeleton.get_global_transform_matrices());
-                mesh.render();
-            },
+        shader_program.use_program(false);
+    }
+
+    fn render_depth(&self, layer: &GraphicsLayer, depth_pass: &DepthPass) {
+        let shader_program = &depth_pass.shader_program;
+
+        shader_program.set_uniform_mat4_f32("mvp", &(depth_pass.light_matrix * self.get_world_matrix(layer)));
+
+        // The same pose as render, so animated meshes cast the shadow of the frame they're showing.
+        if let Mesh::AnimatedMesh(mesh) = &self.mesh {
+            shader_program.set_uniform_vec_mat4_f32("joint_transforms", &mesh.animation_player.skeleton.get_global_transform_matrices());
         }
 
-        if self.texture.is_some() {
-            self.texture.unwrap().bind(0, false);
+        shader_program.use_program(true);
+
+        match &self.mesh {
+            Mesh::StaticMesh(mesh) => mesh.vao.render(mesh.index_count),
+            Mesh::AnimatedMesh(mesh) => mesh.vao.render(mesh.index_count),
         }
 
         shader_program.use_program(false);
     }
+
+    fn get_state_key(&self) -> u64 {
+        let texture_id = self.texture.map(|texture| texture.texture_id).unwrap_or(0);
+        RenderQueue::get_state_key(self.material.get_shader_program().program_id, texture_id)
+    }
 }
 
 impl Drop for RenderableMesh {
@@ -201,8 +299,9 @@ impl Drop for RenderableMesh {
             },
         }
 
-        if self.texture.is_some() {
-            self.texture.unwrap().delete();
+        // Shared textures are deleted by their last user.
+        if let (Some(texture), None) = (&self.texture, &self.shared_texture) {
+            texture.delete();
         }
     }
 }
@@ -210,10 +309,26 @@ impl Drop for RenderableMesh {
 pub struct RenderableSprite {
     pub mesh: StaticMesh,
     pub texture: Option<Texture>,
+    // Set when texture came from with_shared_texture. It keeps the texture alive, so it isn't deleted with the sprite.
+    pub shared_texture: Option<SharedTexture>,
+    // Drawn instead of texture when set. The region's texture is shared, so it isn't deleted with the sprite.
+    pub region: Option<TextureRegion>,
+    pub material: Material,
     pub animation: Option<SpriteAnimation>,
     pub position: Vec3,
     pub rotation: Vec3,
     pub scale: Vec3,
+    // The point position, rotation and scale are around. (0, 0) is the bottom left corner and (1, 1) the top right.
+    pub origin: Vec2,
+    // Part of the texture or region to draw, (u_min, v_min, u_max, v_max) from 0 to 1 of it.
+    pub uv_rect: Vec4,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    pub tint: Color,
+    pub opacity: f32,
+    // Draw order in 2D, higher is in front. 2D sprites ignore the depth buffer, so this is what orders them when
+    // they're sorted by SpriteBatch or GraphicsLayer::render_sprites. Equal orders keep the order they were given in.
+    pub z_order: i32,
 }
 
 impl RenderableSprite {
@@ -238,10 +353,20 @@ impl RenderableSprite {
         RenderableSprite {
             mesh: mesh,
             texture: None,
+            shared_texture: None,
+            region: None,
+            material: Material::new(shader),
             animation: None,
             position: Vec3::new(0.0, 0.0, 0.0),
             rotation: Vec3::new(0.0, 0.0, 0.0),
             scale: Vec3::new(w, h, 1.0),
+            origin: Vec2::new(0.5, 0.5),
+            uv_rect: Vec4::new(0.0, 0.0, 1.0, 1.0),
+            flip_horizontal: false,
+            flip_vertical: false,
+            tint: Color::new(255, 255, 255, 255),
+            opacity: 1.0,
+            z_order: 0,
         }
     }
 
@@ -262,6 +387,26 @@ impl RenderableSprite {
 
     pub fn with_texture(mut self, texture: &Texture) -> Self {
         self.texture = Some(*texture);
+        self.shared_texture = None;
+        self.region = None;
+        self
+    }
+
+    pub fn with_shared_texture(mut self, texture: &SharedTexture) -> Self {
+        self.texture = Some(texture.texture);
+        self.shared_texture = Some(texture.clone());
+        self.region = None;
+        self
+    }
+
+    pub fn with_region(mut self, region: &TextureRegion) -> Self {
+        self.region = Some(*region);
+        self
+    }
+
+    // The sprite's texture or region is bound to unit 0 on top of the material's textures.
+    pub fn with_material(mut self, material: &Material) -> Self {
+        self.material = material.clone();
         self
     }
 
@@ -270,6 +415,37 @@ impl RenderableSprite {
         self
     }
 
+    pub fn with_origin(mut self, origin: &Vec2) -> Self {
+        self.origin = *origin;
+        self
+    }
+
+    pub fn with_uv_rect(mut self, uv_rect: &Vec4) -> Self {
+        self.uv_rect = *uv_rect;
+        self
+    }
+
+    pub fn with_flip(mut self, horizontal: bool, vertical: bool) -> Self {
+        self.flip_horizontal = horizontal;
+        self.flip_vertical = vertical;
+        self
+    }
+
+    pub fn with_tint(mut self, tint: &Color) -> Self {
+        self.tint = *tint;
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
+    pub fn with_z_order(mut self, z_order: i32) -> Self {
+        self.z_order = z_order;
+        self
+    }
+
     pub fn move_to(&mut self, position: &Vec3) {
         self.position = *position;
     }
@@ -321,8 +497,35 @@ impl RenderableSprite {
     pub fn animate(&mut self, delta: &Duration, length: &Duration) {
         if self.animation.is_some() {
             self.animation.as_mut().unwrap().animate(delta, length);
-            self.texture = Some(self.animation.as_ref().unwrap().get_current_frame().clone());
+            self.region = Some(*self.animation.as_ref().unwrap().get_current_frame());
+        }
+    }
+
+    // The texture the sprite currently shows and the part of it to draw, (u_min, v_min, u_max, v_max), with uv_rect
+    // and flips applied. A flipped side has its min above its max.
+    pub fn get_current_texture(&self) -> Option<(Texture, Vec4)> {
+        match (self.region, self.texture) {
+            (Some(region), _) => Some((region.texture, self.get_uv_rect(&region.uv_rect))),
+            (None, Some(texture)) => Some((texture, self.get_uv_rect(&Vec4::new(0.0, 0.0, 1.0, 1.0)))),
+            (None, None) => None,
+        }
+    }
+
+    // Maps uv_rect into the given rectangle and flips it.
+    fn get_uv_rect(&self, rect: &Vec4) -> Vec4 {
+        let min = rect.xy() + (rect.zw() - rect.xy()) * self.uv_rect.xy();
+        let max = rect.xy() + (rect.zw() - rect.xy()) * self.uv_rect.zw();
+        let mut uv_rect = Vec4::new(min.x, min.y, max.x, max.y);
+
+        if self.flip_horizontal {
+            uv_rect = Vec4::new(uv_rect.z, uv_rect.y, uv_rect.x, uv_rect.w);
+        }
+
+        if self.flip_vertical {
+            uv_rect = Vec4::new(uv_rect.x, uv_rect.w, uv_rect.z, uv_rect.y);
         }
+
+        uv_rect
     }
 
 }
@@ -339,11 +542,14 @@ impl Renderable for RenderableSprite {
 
         let scale = Mat4::from_scale(self.scale);
 
-        translation * rotation * scale
+        // The quad is centered, so the origin is moved onto position before everything else.
+        let origin = Mat4::from_translation(Vec3::new(0.5 - self.origin.x, 0.5 - self.origin.y, 0.0));
+
+        translation * rotation * scale * origin
     }
 
     fn render(&self, layer: &GraphicsLayer) {
-        let shader_program = &self.mesh.shader_program;
+        let shader_program = self.material.get_shader_program();
         let y_up = self.mesh.y_up;
         let mut model_matrix = self.get_model_matrix();
 
@@ -356,27 +562,63 @@ impl Renderable for RenderableSprite {
             View::View3D(view) => view.get_view_matrix(),
         };
 
-        shader_program.use_program(true);
+        let current_texture = self.get_current_texture();
+
+        let (should_sample_texture, uv_rect) = match current_texture {
+            Some((_, uv_rect)) => (true, uv_rect),
+            None => (self.material.has_textures(), self.get_uv_rect(&Vec4::new(0.0, 0.0, 1.0, 1.0))),
+        };
+
+        // A material setting any of these, like a shared tint, overrides the sprite's own.
+        self.material.apply_with(&[
+            ("should_sample_texture", UniformValue::Bool(should_sample_texture)),
+            ("use_uv_rect", UniformValue::Bool(true)),
+            ("uv_rect", UniformValue::Vec4(uv_rect)),
+            ("use_tint", UniformValue::Bool(true)),
+            ("tint", UniformValue::Vec4(self.tint.to_vec4())),
+            ("opacity", UniformValue::F32(self.opacity)),
+        ]);
+
+        if let Some((texture, _)) = current_texture {
+            texture.bind(0, true);
+        }
+
         let mvp = view_matrix * layer.get_graphics_layer_matrix() * model_matrix;
         shader_program.set_uniform_mat4_f32("mvp", &mvp);
+        shader_program.set_uniform_mat4_f32("model", &(layer.get_graphics_layer_matrix() * model_matrix));
 
-        if self.texture.is_none() {
-            shader_program.set_uniform_bool("should_sample_texture", false);
-        } else {
-            self.texture.unwrap().bind(0, true);
-            shader_program.set_uniform_bool("should_sample_texture", true);
-        }
+        let is_2d = matches!(layer.view, View::View2D(_));
+
+        unsafe {
+            let depth_test_enabled = gl::IsEnabled(gl::DEPTH_TEST) == gl::TRUE;
 
-        let bone_matrices = [Mat4::IDENTITY; 100];
-        shader_program.set_uniform_vec_mat4_f32("joint_transforms", &bone_matrices.to_vec());
+            // Sprites at the same depth would cut each other out, in 2D the draw order decides instead.
+            if is_2d {
+                gl::Disable(gl::DEPTH_TEST);
+            }
 
-        self.mesh.render();            
+            shader_program.use_program(true);
+            self.mesh.vao.render(self.mesh.index_count);
+            shader_program.use_program(false);
 
-        if self.texture.is_some() {
-            self.texture.unwrap().bind(0, false);
+            if is_2d && depth_test_enabled {
+                gl::Enable(gl::DEPTH_TEST);
+            }
         }
+    }
 
-        shader_program.use_program(false);
+    // Textures usually have soft or see-through edges.
+    fn is_transparent(&self) -> bool {
+        true
+    }
+
+    fn get_state_key(&self) -> u64 {
+        let texture_id = self.get_current_texture().map(|(texture, _)| texture.texture_id).unwrap_or(0);
+        RenderQueue::get_state_key(self.material.get_shader_program().program_id, texture_id)
+    }
+
+    fn get_z_order(&self) -> i32 {
+        self.z_order
     }
 }
 
@@ -384,8 +626,978 @@ impl Drop for RenderableSprite {
     fn drop(&mut self) {
         self.mesh.delete();
 
-        if self.texture.is_some() {
-            self.texture.unwrap().delete();
+        // Shared textures are deleted by their last user.
+        if let (Some(texture), None) = (&self.texture, &self.shared_texture) {
+            texture.delete();
+        }
+    }
+}
+
+const SKYBOX_SHADER_VERSION: &str = "#version 450 core";
+
+// A cubemap drawn behind everything else in a View3D. Only the camera's rotation is used, so the sky never gets
+// closer however far the camera moves. Render it first or last, depth is left untouched and it only fills the pixels
+// nothing else drew to. The cubemap is deleted with the skybox.
+pub struct Skybox {
+    pub cubemap: Cubemap,
+    pub shader_program: ShaderProgram,
+    pub vertex_array_id: GLuint,
+}
+
+impl Skybox {
+    pub fn new(cubemap: &Cubemap) -> Result<Self, ShaderError> {
+        let shader_program = ShaderProgram::from_builders(
+            &ShaderBuilderTemplate::skybox_vertex_shader(SKYBOX_SHADER_VERSION),
+            &ShaderBuilderTemplate::skybox_fragment_shader(SKYBOX_SHADER_VERSION),
+        )?;
+
+        // Core profile won't draw without a vertex array bound, even an empty one.
+        let mut vertex_array_id: GLuint = 0;
+
+        unsafe {
+            gl::GenVertexArrays(1, &mut vertex_array_id);
+        }
+
+        Ok(Skybox {
+            cubemap: *cubemap,
+            shader_program,
+            vertex_array_id,
+        })
+    }
+}
+
+impl Renderable for Skybox {
+    fn get_model_matrix(&self) -> Mat4 {
+        Mat4::IDENTITY
+    }
+
+    fn render(&self, layer: &GraphicsLayer) {
+        let view = match &layer.view {
+            View::View3D(view) => view,
+            View::View2D(_) => return,
+        };
+
+        let inverse_view_projection = (view.get_projection_matrix() * view.get_rotation_matrix()).inverse();
+
+        self.shader_program.set_uniform_mat4_f32("inverse_view_projection", &inverse_view_projection);
+        self.shader_program.set_uniform_i32("skybox", 0);
+        self.cubemap.bind(0, true);
+
+        unsafe {
+            let mut depth_func: GLint = 0;
+            let mut depth_mask: GLboolean = gl::TRUE;
+            gl::GetIntegerv(gl::DEPTH_FUNC, &mut depth_func);
+            gl::GetBooleanv(gl::DEPTH_WRITEMASK, &mut depth_mask);
+
+            // The triangle sits exactly on the far plane, LEQUAL lets it through where the depth was cleared.
+            gl::DepthFunc(gl::LEQUAL);
+            gl::DepthMask(gl::FALSE);
+
+            self.shader_program.use_program(true);
+            gl::BindVertexArray(self.vertex_array_id);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::BindVertexArray(0);
+            self.shader_program.use_program(false);
+
+            gl::DepthFunc(depth_func as GLenum);
+            gl::DepthMask(depth_mask);
+        }
+
+        self.cubemap.bind(0, false);
+    }
+}
+
+impl Drop for Skybox {
+    fn drop(&mut self) {
+        self.cubemap.delete();
+
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vertex_array_id);
+        }
+    }
+}
+
+// A string drawn with a Font. Sizes are the font's pixels times scale, so under a View2D of the window's size a
+// scale of one draws the text at the size it was rasterized at. The mesh is rebuilt whenever the text or layout
+// changes, not every frame. Fonts are shared between texts and aren't deleted with them.
+pub struct RenderableText {
+    pub font: Rc<Font>,
+    pub text: String,
+    pub layout: TextLayout,
+    pub color: Color,
+    pub position: Vec3,
+    pub rotation: Vec3,
+    pub scale: Vec3,
+    pub mesh: Option<StaticMesh>,
+}
+
+impl RenderableText {
+    pub fn new(font: &Rc<Font>, text: &str) -> Self {
+        let mut renderable_text = RenderableText {
+            font: font.clone(),
+            text: String::from(text),
+            layout: TextLayout::new(),
+            color: Color::new(255, 255, 255, 255),
+            position: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Vec3::new(0.0, 0.0, 0.0),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+            mesh: None,
+        };
+
+        renderable_text.rebuild();
+        renderable_text
+    }
+
+    pub fn with_position(mut self, position: &Vec3) -> Self {
+        self.position = *position;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: &Vec3) -> Self {
+        self.rotation = *rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: &Vec3) -> Self {
+        self.scale = *scale;
+        self
+    }
+
+    pub fn with_color(mut self, color: &Color) -> Self {
+        self.color = *color;
+        self
+    }
+
+    pub fn with_layout(mut self, layout: &TextLayout) -> Self {
+        self.set_layout(layout);
+        self
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        if self.text != text {
+            self.text = String::from(text);
+            self.rebuild();
+        }
+    }
+
+    pub fn set_layout(&mut self, layout: &TextLayout) {
+        self.layout = *layout;
+        self.rebuild();
+    }
+
+    pub fn set_font(&mut self, font: &Rc<Font>) {
+        self.font = font.clone();
+        self.rebuild();
+    }
+
+    // Before scale.
+    pub fn get_size(&self) -> Vec2 {
+        self.font.measure_text(&self.text, &self.layout)
+    }
+
+    // One quad per visible glyph.
+    fn rebuild(&mut self) {
+        if let Some(mesh) = self.mesh.take() {
+            mesh.delete();
         }
+
+        let glyphs = self.font.layout_text(&self.text, &self.layout);
+
+        if glyphs.is_empty() {
+            return;
+        }
+
+        let mut vertices = Vec::with_capacity(glyphs.len() * 4);
+        let mut indices = Vec::with_capacity(glyphs.len() * 6);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+
+        for positioned_glyph in glyphs {
+            let min = positioned_glyph.position;
+            let max = min + positioned_glyph.glyph.size;
+            let uv_rect = positioned_glyph.glyph.region.unwrap().uv_rect;
+            let first = vertices.len() as u32;
+
+            vertices.push(Vertex::new(&Vec3::new(min.x, min.y, 0.0), &Vec3::new(uv_rect.x, uv_rect.y, 0.0), &normal));
+            vertices.push(Vertex::new(&Vec3::new(max.x, min.y, 0.0), &Vec3::new(uv_rect.z, uv_rect.y, 0.0), &normal));
+            vertices.push(Vertex::new(&Vec3::new(max.x, max.y, 0.0), &Vec3::new(uv_rect.z, uv_rect.w, 0.0), &normal));
+            vertices.push(Vertex::new(&Vec3::new(min.x, max.y, 0.0), &Vec3::new(uv_rect.x, uv_rect.w, 0.0), &normal));
+
+            indices.extend_from_slice(&[first, first + 1, first + 2, first + 2, first + 3, first]);
+        }
+
+        self.mesh = Some(StaticMeshData::new(&vertices, &indices).build(&self.font.shader_program));
+    }
+}
+
+impl Renderable for RenderableText {
+    fn get_model_matrix(&self) -> Mat4 {
+        let translation = Mat4::from_translation(self.position);
+
+        let rotation_x = Mat4::from_rotation_x(Deg(self.rotation.x).to_radians().as_float());
+        let rotation_y = Mat4::from_rotation_y(Deg(self.rotation.y).to_radians().as_float());
+        let rotation_z = Mat4::from_rotation_z(Deg(self.rotation.z).to_radians().as_float());
+
+        let rotation = rotation_x * rotation_y * rotation_z;
+
+        let scale = Mat4::from_scale(self.scale);
+
+        translation * rotation * scale
+    }
+
+    fn render(&self, layer: &GraphicsLayer) {
+        let mesh = match &self.mesh {
+            Some(mesh) => mesh,
+            None => return,
+        };
+
+        let view_matrix = match &layer.view {
+            View::View2D(view) => view.get_view_matrix(),
+            View::View3D(view) => view.get_view_matrix(),
+        };
+
+        let shader_program = &self.font.shader_program;
+        let mvp = view_matrix * layer.get_graphics_layer_matrix() * self.get_model_matrix();
+
+        shader_program.set_uniform_mat4_f32("mvp", &mvp);
+        shader_program.set_uniform_vec4_f32("text_color", &self.color.to_vec4());
+        shader_program.set_uniform_bool("use_sdf", self.font.is_sdf());
+        shader_program.set_uniform_i32("glyph_atlas", 0);
+
+        self.font.atlas.texture.bind(0, true);
+
+        shader_program.use_program(true);
+        mesh.vao.render(mesh.index_count);
+        shader_program.use_program(false);
     }
-}
\ No newline at end of file
+
+    fn is_transparent(&self) -> bool {
+        true
+    }
+
+    fn get_state_key(&self) -> u64 {
+        RenderQueue::get_state_key(self.font.shader_program.program_id, self.font.atlas.texture.texture_id)
+    }
+}
+
+impl Drop for RenderableText {
+    fn drop(&mut self) {
+        if let Some(mesh) = &self.mesh {
+            mesh.delete();
+        }
+    }
+}
+
+thread_local! {
+    // Compiled the first time a shape is drawn and shared by all of them.
+    static SHAPE_SHADER_PROGRAM: RefCell<Option<ShaderProgram>> = const { RefCell::new(None) };
+}
+
+const SHAPE_SHADER_VERSION: &str = "#version 450 core";
+
+// A filled and/or stroked 2D shape with anti-aliased edges, no texture needed. Units are View2D pixels at a scale
+// of one, like sprites. The geometry is rebuilt on the next render after the path or stroke changes, paints are
+// uniforms and cost nothing to change.
+pub struct RenderableShape {
+    pub path: ShapePath,
+    pub fill: Option<Paint>,
+    pub stroke: Option<Paint>,
+    pub stroke_width: f32,
+    pub line_join: LineJoin,
+    pub line_cap: LineCap,
+    // Wide enough for a pixel of anti-aliasing at scale one. Shapes drawn much smaller than their units need more.
+    pub fringe_width: f32,
+    pub position: Vec3,
+    pub rotation: Vec3,
+    pub scale: Vec3,
+    vertex_array_id: Cell<GLuint>,
+    buffer_id: Cell<GLuint>,
+    vertex_count: Cell<usize>,
+    dirty: Cell<bool>,
+}
+
+impl RenderableShape {
+    pub fn new(path: &ShapePath) -> Self {
+        RenderableShape {
+            path: path.clone(),
+            fill: Some(Paint::Solid(Color::new(255, 255, 255, 255))),
+            stroke: None,
+            stroke_width: 1.0,
+            line_join: LineJoin::Miter,
+            line_cap: LineCap::Butt,
+            fringe_width: 1.0,
+            position: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Vec3::new(0.0, 0.0, 0.0),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+            vertex_array_id: Cell::new(0),
+            buffer_id: Cell::new(0),
+            vertex_count: Cell::new(0),
+            dirty: Cell::new(true),
+        }
+    }
+
+    pub fn rect(size: &Vec2) -> Self {
+        Self::new(&ShapePath::Rect { size: *size, corner_radius: 0.0 })
+    }
+
+    pub fn rounded_rect(size: &Vec2, corner_radius: f32) -> Self {
+        Self::new(&ShapePath::Rect { size: *size, corner_radius })
+    }
+
+    pub fn circle(radius: f32) -> Self {
+        Self::new(&ShapePath::Ellipse { radii: Vec2::new(radius, radius) })
+    }
+
+    pub fn ellipse(radii: &Vec2) -> Self {
+        Self::new(&ShapePath::Ellipse { radii: *radii })
+    }
+
+    // Either winding works.
+    pub fn polygon(points: &[Vec2]) -> Self {
+        Self::new(&ShapePath::Polygon(points.to_vec()))
+    }
+
+    // Stroked white and unfilled.
+    pub fn polyline(points: &[Vec2], width: f32) -> Self {
+        Self::new(&ShapePath::Polyline(points.to_vec()))
+            .without_fill()
+            .with_stroke(&Paint::Solid(Color::new(255, 255, 255, 255)), width)
+    }
+
+    pub fn with_fill(mut self, paint: &Paint) -> Self {
+        self.set_fill(Some(*paint));
+        self
+    }
+
+    pub fn without_fill(mut self) -> Self {
+        self.set_fill(None);
+        self
+    }
+
+    pub fn with_stroke(mut self, paint: &Paint, width: f32) -> Self {
+        self.set_stroke(Some(*paint), width);
+        self
+    }
+
+    pub fn with_line_join(mut self, line_join: LineJoin) -> Self {
+        self.line_join = line_join;
+        self.dirty.set(true);
+        self
+    }
+
+    pub fn with_line_cap(mut self, line_cap: LineCap) -> Self {
+        self.line_cap = line_cap;
+        self.dirty.set(true);
+        self
+    }
+
+    pub fn with_fringe_width(mut self, fringe_width: f32) -> Self {
+        self.fringe_width = fringe_width;
+        self.dirty.set(true);
+        self
+    }
+
+    pub fn with_position(mut self, position: &Vec3) -> Self {
+        self.position = *position;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: &Vec3) -> Self {
+        self.rotation = *rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: &Vec3) -> Self {
+        self.scale = *scale;
+        self
+    }
+
+    pub fn set_path(&mut self, path: &ShapePath) {
+        self.path = path.clone();
+        self.dirty.set(true);
+    }
+
+    pub fn set_fill(&mut self, fill: Option<Paint>) {
+        if self.fill.is_some() != fill.is_some() {
+            self.dirty.set(true);
+        }
+
+        self.fill = fill;
+    }
+
+    pub fn set_stroke(&mut self, stroke: Option<Paint>, width: f32) {
+        if self.stroke.is_some() != stroke.is_some() || self.stroke_width != width {
+            self.dirty.set(true);
+        }
+
+        self.stroke = stroke;
+        self.stroke_width = width;
+    }
+
+    fn get_shader_program() -> ShaderProgram {
+        SHAPE_SHADER_PROGRAM.with(|shader_program| {
+            shader_program.borrow_mut().get_or_insert_with(|| {
+                ShaderProgram::from_builders(
+                    &ShaderBuilderTemplate::shape_vertex_shader(SHAPE_SHADER_VERSION),
+                    &ShaderBuilderTemplate::shape_fragment_shader(SHAPE_SHADER_VERSION),
+                ).unwrap()
+            }).clone()
+        })
+    }
+
+    fn rebuild(&self) {
+        let (contour, closed) = self.path.get_contour();
+        let mut tessellator = ShapeTessellator::new(self.fringe_width);
+
+        if self.fill.is_some() && closed {
+            tessellator.fill(&contour);
+        }
+
+        if self.stroke.is_some() {
+            tessellator.stroke(&contour, closed, self.stroke_width, self.line_join, self.line_cap);
+        }
+
+        unsafe {
+            if self.vertex_array_id.get() == 0 {
+                let (mut vertex_array_id, mut buffer_id) = (0, 0);
+                gl::GenVertexArrays(1, &mut vertex_array_id);
+                gl::GenBuffers(1, &mut buffer_id);
+
+                gl::BindVertexArray(vertex_array_id);
+                gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
+
+                let stride = (4 * std::mem::size_of::<f32>()) as GLsizei;
+                gl::EnableVertexAttribArray(0);
+                gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, std::ptr::null());
+                gl::EnableVertexAttribArray(1);
+                gl::VertexAttribPointer(1, 1, gl::FLOAT, gl::FALSE, stride, (2 * std::mem::size_of::<f32>()) as *const std::ffi::c_void);
+                gl::EnableVertexAttribArray(2);
+                gl::VertexAttribPointer(2, 1, gl::FLOAT, gl::FALSE, stride, (3 * std::mem::size_of::<f32>()) as *const std::ffi::c_void);
+
+                gl::BindVertexArray(0);
+
+                self.vertex_array_id.set(vertex_array_id);
+                self.buffer_id.set(buffer_id);
+            }
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer_id.get());
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                std::mem::size_of_val(tessellator.vertices.as_slice()) as GLsizeiptr,
+                tessellator.vertices.as_ptr() as *const std::ffi::c_void,
+                gl::STATIC_DRAW,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+
+        self.vertex_count.set(tessellator.vertices.len() / 4);
+        self.dirty.set(false);
+    }
+}
+
+impl Renderable for RenderableShape {
+    fn get_model_matrix(&self) -> Mat4 {
+        let translation = Mat4::from_translation(self.position);
+
+        let rotation_x = Mat4::from_rotation_x(Deg(self.rotation.x).to_radians().as_float());
+        let rotation_y = Mat4::from_rotation_y(Deg(self.rotation.y).to_radians().as_float());
+        let rotation_z = Mat4::from_rotation_z(Deg(self.rotation.z).to_radians().as_float());
+
+        let rotation = rotation_x * rotation_y * rotation_z;
+
+        let scale = Mat4::from_scale(self.scale);
+
+        translation * rotation * scale
+    }
+
+    fn render(&self, layer: &GraphicsLayer) {
+        if self.dirty.get() {
+            self.rebuild();
+        }
+
+        if self.vertex_count.get() == 0 {
+            return;
+        }
+
+        let view_matrix = match &layer.view {
+            View::View2D(view) => view.get_view_matrix(),
+            View::View3D(view) => view.get_view_matrix(),
+        };
+
+        let shader_program = Self::get_shader_program();
+        let mvp = view_matrix * layer.get_graphics_layer_matrix() * self.get_model_matrix();
+        shader_program.set_uniform_mat4_f32("mvp", &mvp);
+
+        // A missing paint has no triangles, so whatever its slot holds doesn't matter.
+        for (i, paint) in [self.fill, self.stroke].iter().enumerate() {
+            if let Some(paint) = paint {
+                let (kind, first_color, second_color, points) = paint.get_shader_data();
+
+                shader_program.set_uniform_i32(&format!("paint_kinds[{i}]"), kind);
+                shader_program.set_uniform_vec4_f32(&format!("paint_colors[{}]", i * 2), &first_color.to_vec4());
+                shader_program.set_uniform_vec4_f32(&format!("paint_colors[{}]", i * 2 + 1), &second_color.to_vec4());
+                shader_program.set_uniform_vec4_f32(&format!("paint_points[{i}]"), &points);
+            }
+        }
+
+        shader_program.use_program(true);
+
+        unsafe {
+            gl::BindVertexArray(self.vertex_array_id.get());
+            gl::DrawArrays(gl::TRIANGLES, 0, self.vertex_count.get() as GLsizei);
+            gl::BindVertexArray(0);
+        }
+
+        shader_program.use_program(false);
+    }
+
+    // The anti-aliased edges blend.
+    fn is_transparent(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for RenderableShape {
+    fn drop(&mut self) {
+        if self.vertex_array_id.get() != 0 {
+            unsafe {
+                gl::DeleteVertexArrays(1, &self.vertex_array_id.get());
+                gl::DeleteBuffers(1, &self.buffer_id.get());
+            }
+        }
+    }
+}
+
+thread_local! {
+    static NINE_SLICE_SHADER_PROGRAM: RefCell<Option<ShaderProgram>> = const { RefCell::new(None) };
+}
+
+const NINE_SLICE_SHADER_VERSION: &str = "#version 450 core";
+
+// How the edges and the middle of a nine-slice fill the space between the corners.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NineSliceMode {
+    Stretch,
+    // Repeated at their size in the texture, the last copy is cut off.
+    Tile,
+}
+
+// Border widths in texture pixels, measured in from each side of the image.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NineSliceInsets {
+    pub left: f32,
+    pub right: f32,
+    pub top: f32,
+    pub bottom: f32,
+}
+
+impl NineSliceInsets {
+    pub fn new(left: f32, right: f32, top: f32, bottom: f32) -> Self {
+        NineSliceInsets {
+            left,
+            right,
+            top,
+            bottom,
+        }
+    }
+
+    pub fn uniform(inset: f32) -> Self {
+        Self::new(inset, inset, inset, inset)
+    }
+}
+
+// A texture cut into a 3x3 grid by its insets and stretched to any size without distorting the corners, for panels
+// and buttons. size is the whole quad in View2D pixels, centered on position like sprites. Corners keep their size
+// in texture pixels times border_scale, and shrink together when size is too small to fit them. Geometry is rebuilt
+// on the next render after a set_ call. The texture is shared, it isn't deleted with the nine-slice.
+pub struct RenderableNineSlice {
+    pub texture: Texture,
+    // Drawn instead of the whole texture when set, for nine-slices packed in an atlas.
+    pub region: Option<TextureRegion>,
+    pub insets: NineSliceInsets,
+    pub size: Vec2,
+    pub border_scale: f32,
+    pub edge_mode: NineSliceMode,
+    pub center_mode: NineSliceMode,
+    pub color: Color,
+    pub position: Vec3,
+    pub rotation: Vec3,
+    pub scale: Vec3,
+    vertex_array_id: Cell<GLuint>,
+    buffer_id: Cell<GLuint>,
+    vertex_count: Cell<usize>,
+    dirty: Cell<bool>,
+}
+
+impl RenderableNineSlice {
+    pub fn new(texture: &Texture, insets: &NineSliceInsets, size: &Vec2) -> Self {
+        RenderableNineSlice {
+            texture: *texture,
+            region: None,
+            insets: *insets,
+            size: *size,
+            border_scale: 1.0,
+            edge_mode: NineSliceMode::Stretch,
+            center_mode: NineSliceMode::Stretch,
+            color: Color::new(255, 255, 255, 255),
+            position: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Vec3::new(0.0, 0.0, 0.0),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+            vertex_array_id: Cell::new(0),
+            buffer_id: Cell::new(0),
+            vertex_count: Cell::new(0),
+            dirty: Cell::new(true),
+        }
+    }
+
+    pub fn with_region(mut self, region: &TextureRegion) -> Self {
+        self.set_region(Some(*region));
+        self
+    }
+
+    pub fn with_border_scale(mut self, border_scale: f32) -> Self {
+        self.border_scale = border_scale;
+        self.dirty.set(true);
+        self
+    }
+
+    pub fn with_edge_mode(mut self, edge_mode: NineSliceMode) -> Self {
+        self.edge_mode = edge_mode;
+        self.dirty.set(true);
+        self
+    }
+
+    pub fn with_center_mode(mut self, center_mode: NineSliceMode) -> Self {
+        self.center_mode = center_mode;
+        self.dirty.set(true);
+        self
+    }
+
+    pub fn with_color(mut self, color: &Color) -> Self {
+        self.color = *color;
+        self
+    }
+
+    pub fn with_position(mut self, position: &Vec3) -> Self {
+        self.position = *position;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: &Vec3) -> Self {
+        self.rotation = *rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: &Vec3) -> Self {
+        self.scale = *scale;
+        self
+    }
+
+    pub fn set_size(&mut self, size: &Vec2) {
+        if self.size != *size {
+            self.size = *size;
+            self.dirty.set(true);
+        }
+    }
+
+    pub fn set_insets(&mut self, insets: &NineSliceInsets) {
+        self.insets = *insets;
+        self.dirty.set(true);
+    }
+
+    pub fn set_region(&mut self, region: Option<TextureRegion>) {
+        self.region = region;
+        self.dirty.set(true);
+    }
+
+    // The texture or region's size in pixels and its uv_rect.
+    fn get_source(&self) -> (Vec2, Vec4) {
+        match self.region {
+            Some(region) => (region.size.as_vec2(), region.uv_rect),
+            None => (self.texture.get_size().as_vec2(), Vec4::new(0.0, 0.0, 1.0, 1.0)),
+        }
+    }
+
+    // Splits one axis into its three slices, as (start, length) in texture pixels and in units. The first slice is
+    // the left or top one.
+    fn get_slices(source_size: f32, first_inset: f32, last_inset: f32, size: f32, border_scale: f32) -> [(f32, f32, f32, f32); 3] {
+        let border = (first_inset + last_inset) * border_scale;
+        let fit = if border > size && border > 0.0 { size / border } else { 1.0 };
+        let first = first_inset * border_scale * fit;
+        let last = last_inset * border_scale * fit;
+
+        [
+            (0.0, first_inset, 0.0, first),
+            (first_inset, (source_size - first_inset - last_inset).max(0.0), first, size - first - last),
+            (source_size - last_inset, last_inset, size - last, last),
+        ]
+    }
+
+    // One slice of an axis as pieces of (start, length) in units and (start, end) in texture pixels. Tiled slices
+    // repeat the source at border_scale, cutting the last piece short.
+    fn get_pieces(slice: (f32, f32, f32, f32), mode: NineSliceMode, border_scale: f32) -> Vec<(f32, f32, f32, f32)> {
+        let (source_start, source_length, start, length) = slice;
+        let tile_length = source_length * border_scale;
+
+        if length <= 0.0 {
+            return Vec::new();
+        }
+
+        if mode == NineSliceMode::Stretch || tile_length <= 0.0 {
+            return vec![(start, length, source_start, source_start + source_length)];
+        }
+
+        let mut pieces = Vec::new();
+        let mut offset = 0.0;
+
+        while offset < length {
+            let piece_length = tile_length.min(length - offset);
+            pieces.push((start + offset, piece_length, source_start, source_start + piece_length / border_scale));
+            offset += tile_length;
+        }
+
+        pieces
+    }
+
+    fn get_shader_program() -> ShaderProgram {
+        NINE_SLICE_SHADER_PROGRAM.with(|shader_program| {
+            shader_program.borrow_mut().get_or_insert_with(|| {
+                ShaderProgram::from_builders(
+                    &ShaderBuilderTemplate::nine_slice_vertex_shader(NINE_SLICE_SHADER_VERSION),
+                    &ShaderBuilderTemplate::nine_slice_fragment_shader(NINE_SLICE_SHADER_VERSION),
+                ).unwrap()
+            }).clone()
+        })
+    }
+
+    fn rebuild(&self) {
+        let (source_size, uv_rect) = self.get_source();
+        let columns = Self::get_slices(source_size.x, self.insets.left, self.insets.right, self.size.x, self.border_scale);
+        let rows = Self::get_slices(source_size.y, self.insets.top, self.insets.bottom, self.size.y, self.border_scale);
+        let half_size = self.size / 2.0;
+        let mut vertices: Vec<f32> = Vec::new();
+
+        // Texture pixels count from the top left, v goes up.
+        let get_uv = |x: f32, y: f32| Vec2::new(
+            uv_rect.x + x / source_size.x.max(1.0) * (uv_rect.z - uv_rect.x),
+            uv_rect.w - y / source_size.y.max(1.0) * (uv_rect.w - uv_rect.y),
+        );
+
+        for (row_index, row) in rows.iter().enumerate() {
+            for (column_index, column) in columns.iter().enumerate() {
+                let mode = match (column_index, row_index) {
+                    (1, 1) => self.center_mode,
+                    _ => self.edge_mode,
+                };
+
+                let column_mode = if column_index == 1 { mode } else { NineSliceMode::Stretch };
+                let row_mode = if row_index == 1 { mode } else { NineSliceMode::Stretch };
+
+                for (y, height, source_top, source_bottom) in Self::get_pieces(*row, row_mode, self.border_scale) {
+                    for (x, width, source_left, source_right) in Self::get_pieces(*column, column_mode, self.border_scale) {
+                        let min = Vec2::new(x - half_size.x, half_size.y - y - height);
+                        let max = Vec2::new(x + width - half_size.x, half_size.y - y);
+                        let uv_min = get_uv(source_left, source_bottom);
+                        let uv_max = get_uv(source_right, source_top);
+
+                        for (position, uv) in [
+                            (min, uv_min),
+                            (Vec2::new(max.x, min.y), Vec2::new(uv_max.x, uv_min.y)),
+                            (max, uv_max),
+                            (max, uv_max),
+                            (Vec2::new(min.x, max.y), Vec2::new(uv_min.x, uv_max.y)),
+                            (min, uv_min),
+                        ] {
+                            vertices.extend_from_slice(&[position.x, position.y, uv.x, uv.y]);
+                        }
+                    }
+                }
+            }
+        }
+
+        unsafe {
+            if self.vertex_array_id.get() == 0 {
+                let (mut vertex_array_id, mut buffer_id) = (0, 0);
+                gl::GenVertexArrays(1, &mut vertex_array_id);
+                gl::GenBuffers(1, &mut buffer_id);
+
+                gl::BindVertexArray(vertex_array_id);
+                gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
+
+                let stride = (4 * std::mem::size_of::<f32>()) as GLsizei;
+                gl::EnableVertexAttribArray(0);
+                gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, std::ptr::null());
+                gl::EnableVertexAttribArray(1);
+                gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, (2 * std::mem::size_of::<f32>()) as *const std::ffi::c_void);
+
+                gl::BindVertexArray(0);
+
+                self.vertex_array_id.set(vertex_array_id);
+                self.buffer_id.set(buffer_id);
+            }
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer_id.get());
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                std::mem::size_of_val(vertices.as_slice()) as GLsizeiptr,
+                vertices.as_ptr() as *const std::ffi::c_void,
+                gl::STATIC_DRAW,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+
+        self.vertex_count.set(vertices.len() / 4);
+        self.dirty.set(false);
+    }
+}
+
+impl Renderable for RenderableNineSlice {
+    fn get_model_matrix(&self) -> Mat4 {
+        let translation = Mat4::from_translation(self.position);
+
+        let rotation_x = Mat4::from_rotation_x(Deg(self.rotation.x).to_radians().as_float());
+        let rotation_y = Mat4::from_rotation_y(Deg(self.rotation.y).to_radians().as_float());
+        let rotation_z = Mat4::from_rotation_z(Deg(self.rotation.z).to_radians().as_float());
+
+        let rotation = rotation_x * rotation_y * rotation_z;
+
+        let scale = Mat4::from_scale(self.scale);
+
+        translation * rotation * scale
+    }
+
+    fn render(&self, layer: &GraphicsLayer) {
+        if self.dirty.get() {
+            self.rebuild();
+        }
+
+        if self.vertex_count.get() == 0 {
+            return;
+        }
+
+        let view_matrix = match &layer.view {
+            View::View2D(view) => view.get_view_matrix(),
+            View::View3D(view) => view.get_view_matrix(),
+        };
+
+        let texture = self.region.map(|region| region.texture).unwrap_or(self.texture);
+        let shader_program = Self::get_shader_program();
+        let mvp = view_matrix * layer.get_graphics_layer_matrix() * self.get_model_matrix();
+
+        shader_program.set_uniform_mat4_f32("mvp", &mvp);
+        shader_program.set_uniform_vec4_f32("color", &self.color.to_vec4());
+        shader_program.set_uniform_i32("nine_slice_texture", 0);
+        texture.bind(0, true);
+
+        shader_program.use_program(true);
+
+        unsafe {
+            gl::BindVertexArray(self.vertex_array_id.get());
+            gl::DrawArrays(gl::TRIANGLES, 0, self.vertex_count.get() as GLsizei);
+            gl::BindVertexArray(0);
+        }
+
+        shader_program.use_program(false);
+    }
+
+    fn is_transparent(&self) -> bool {
+        true
+    }
+
+    // They all share one program.
+    fn get_state_key(&self) -> u64 {
+        let texture = self.region.map(|region| region.texture).unwrap_or(self.texture);
+        RenderQueue::get_state_key(0, texture.texture_id)
+    }
+}
+
+impl Drop for RenderableNineSlice {
+    fn drop(&mut self) {
+        if self.vertex_array_id.get() != 0 {
+            unsafe {
+                gl::DeleteVertexArrays(1, &self.vertex_array_id.get());
+                gl::DeleteBuffers(1, &self.buffer_id.get());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nine_slice_slices_keep_the_corners() {
+        let slices = RenderableNineSlice::get_slices(32.0, 4.0, 8.0, 100.0, 1.0);
+
+        assert_eq!(slices, [
+            (0.0, 4.0, 0.0, 4.0),
+            (4.0, 20.0, 4.0, 88.0),
+            (24.0, 8.0, 92.0, 8.0),
+        ]);
+    }
+
+    #[test]
+    fn nine_slice_slices_scale_the_border() {
+        let slices = RenderableNineSlice::get_slices(32.0, 4.0, 8.0, 100.0, 2.0);
+
+        assert_eq!(slices, [
+            (0.0, 4.0, 0.0, 8.0),
+            (4.0, 20.0, 8.0, 76.0),
+            (24.0, 8.0, 84.0, 16.0),
+        ]);
+    }
+
+    #[test]
+    fn nine_slice_slices_shrink_the_corners_to_fit() {
+        let slices = RenderableNineSlice::get_slices(32.0, 4.0, 12.0, 8.0, 1.0);
+
+        assert_eq!(slices, [
+            (0.0, 4.0, 0.0, 2.0),
+            (4.0, 16.0, 2.0, 0.0),
+            (20.0, 12.0, 2.0, 6.0),
+        ]);
+    }
+
+    #[test]
+    fn nine_slice_slices_without_insets() {
+        let slices = RenderableNineSlice::get_slices(32.0, 0.0, 0.0, 0.0, 1.0);
+
+        assert_eq!(slices[1], (0.0, 32.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn nine_slice_stretched_pieces() {
+        let pieces = RenderableNineSlice::get_pieces((4.0, 20.0, 4.0, 88.0), NineSliceMode::Stretch, 1.0);
+
+        assert_eq!(pieces, vec![(4.0, 88.0, 4.0, 24.0)]);
+    }
+
+    #[test]
+    fn nine_slice_tiled_pieces_cut_the_last_one_short() {
+        let pieces = RenderableNineSlice::get_pieces((4.0, 20.0, 4.0, 50.0), NineSliceMode::Tile, 1.0);
+
+        assert_eq!(pieces, vec![
+            (4.0, 20.0, 4.0, 24.0),
+            (24.0, 20.0, 4.0, 24.0),
+            (44.0, 10.0, 4.0, 14.0),
+        ]);
+    }
+
+    #[test]
+    fn nine_slice_tiled_pieces_scale_with_the_border() {
+        let pieces = RenderableNineSlice::get_pieces((4.0, 20.0, 0.0, 50.0), NineSliceMode::Tile, 2.0);
+
+        assert_eq!(pieces, vec![
+            (0.0, 40.0, 4.0, 24.0),
+            (40.0, 10.0, 4.0, 9.0),
+        ]);
+    }
+
+    #[test]
+    fn nine_slice_empty_pieces() {
+        assert!(RenderableNineSlice::get_pieces((4.0, 20.0, 4.0, 0.0), NineSliceMode::Tile, 1.0).is_empty());
+
+        // Nothing to repeat, so it stretches.
+        assert_eq!(RenderableNineSlice::get_pieces((4.0, 0.0, 4.0, 10.0), NineSliceMode::Tile, 1.0), vec![(4.0, 10.0, 4.0, 4.0)]);
+    }
+}
@@ -298,20 +298,29 @@ impl ShaderFunction {
         self
     }
 
-    pub fn to_string(&self) -> String {
-        let mut function_string = String::new();
+    // The return type, name and arguments, without the body.
+    pub fn get_signature(&self) -> String {
+        let mut signature = String::new();
 
-        function_string.push_str(format!("{} {}(", self.return_type, self.name).as_str());
+        signature.push_str(format!("{} {}(", self.return_type, self.name).as_str());
 
         for (i, arg) in self.arguments.iter().enumerate() {
-            function_string.push_str(arg.as_str());
+            signature.push_str(arg.as_str());
 
             if i < self.arguments.len() - 1 {
-                function_string.push_str(", ");
+                signature.push_str(", ");
             }
         }
 
-        function_string.push_str(") {");
+        signature.push(')');
+
+        signature
+    }
+
+    pub fn to_string(&self) -> String {
+        let mut function_string = self.get_signature();
+
+        function_string.push_str(" {");
         function_string.push_str(self.closure.to_string().as_str());
         function_string.push_str("}");
 
@@ -377,6 +386,19 @@ impl ShaderBuilder {
         self.uniforms.push(format!("uniform {} {};", type_name, name).replace(";", "") + ";");
     }
 
+    // A std140 uniform block, for data shared between programs through a uniform buffer.
+    pub fn dec_uniform_block(&mut self, block_name: &str, members: &Vec<(&str, &str)>) {
+        let mut block = format!("layout (std140) uniform {} {{\n", block_name);
+
+        for (type_name, name) in members {
+            block.push_str(format!("    {} {};\n", type_name, name).as_str());
+        }
+
+        block.push_str("};");
+
+        self.uniforms.push(block);
+    }
+
     pub fn dec_function(&mut self, function: &ShaderFunction) {
         self.functions.insert(function.name.clone(), function.clone());
     }
@@ -411,6 +433,12 @@ impl ShaderBuilder {
             shader_string.push_str("\n");
         }
 
+        // Functions come out in no particular order, declaring them all first lets them call each other.
+        for function in &self.functions {
+            shader_string.push_str(function.1.get_signature().as_str());
+            shader_string.push_str(";\n");
+        }
+
         for function in &self.functions {
             shader_string.push_str(function.1.to_string().as_str());
             shader_string.push_str("\n");
@@ -450,11 +478,14 @@ impl ShaderBuilderTemplate {
         shader_builder.dec_out("vec3", "vertex_color");
 
         shader_builder.dec_uniform("mat4", "mvp");
+        // Set by sprites drawing a TextureRegion, the quad's 0..1 coordinates are mapped into uv_rect.
+        shader_builder.dec_uniform("bool", "use_uv_rect");
+        shader_builder.dec_uniform("vec4", "uv_rect");
 
         shader_builder.main.do_action("gl_Position = mvp * vec4(in_position, 1.0)");
 
         shader_builder.main.do_action("vertex_position = in_position");
-        shader_builder.main.do_action("tex_coords = in_tex_coords");
+        shader_builder.main.do_action("tex_coords = use_uv_rect ? vec3(mix(uv_rect.xy, uv_rect.zw, in_tex_coords.xy), in_tex_coords.z) : in_tex_coords");
         shader_builder.main.do_action("vertex_normals = in_normal");
         shader_builder.main.do_action("vertex_color = in_color");
 
@@ -598,6 +629,9 @@ impl ShaderBuilderTemplate {
 
         uniform bool should_sample_texture;
         uniform sampler2D sampler_objs[32];
+        uniform bool use_tint;
+        uniform vec4 tint;
+        uniform float opacity;
 
         void main() {
             highp int sampler_index = int(tex_coords.z);
@@ -607,6 +641,10 @@ impl ShaderBuilderTemplate {
             } else {
                 output_color = vec4(vertex_color, 1.0);
             }
+
+            if (use_tint) {
+                output_color *= vec4(tint.rgb, tint.a * opacity);
+            }
         }
          */
 
@@ -621,6 +659,10 @@ impl ShaderBuilderTemplate {
 
         shader_builder.dec_uniform("bool", "should_sample_texture");
         shader_builder.dec_uniform("sampler2D", "sampler_objs[32]");
+        // Set by sprites through Material::apply_with, which resets them for meshes and batches sharing the program.
+        shader_builder.dec_uniform("bool", "use_tint");
+        shader_builder.dec_uniform("vec4", "tint");
+        shader_builder.dec_uniform("float", "opacity");
 
         shader_builder.main.dec_var("highp int", "sampler_index", "int(tex_coords.z)");
 
@@ -634,64 +676,738 @@ impl ShaderBuilderTemplate {
                 .with_do_action("output_color = vec4(vertex_color, 1.0)")
         );
 
+        shader_builder.main.if_statement("use_tint", &ShaderClosure::new()
+            .with_do_action("output_color *= vec4(tint.rgb, tint.a * opacity)")
+        );
+
+        shader_builder
+    }
+
+    pub fn fullscreen_vertex_shader(version: &str) -> ShaderBuilder {
+        /*
+        #version 450 core
+
+        out vec2 tex_coords;
+
+        void main() {
+            vec2 position = vec2(float((gl_VertexID & 1) << 2) - 1.0, float((gl_VertexID & 2) << 1) - 1.0);
+            tex_coords = position * 0.5 + 0.5;
+            gl_Position = vec4(position, 0.0, 1.0);
+        }
+         */
+
+        // One triangle covering the screen, drawn with glDrawArrays(3) and no vertex buffer.
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_out("vec2", "tex_coords");
+
+        shader_builder.main.dec_var("vec2", "position", "vec2(float((gl_VertexID & 1) << 2) - 1.0, float((gl_VertexID & 2) << 1) - 1.0)");
+        shader_builder.main.do_action("tex_coords = position * 0.5 + 0.5");
+        shader_builder.main.do_action("gl_Position = vec4(position, 0.0, 1.0)");
+
+        shader_builder
+    }
+
+    // Reads screen_texture into color. Post processing effects start from this, change color and write output_color.
+    pub fn post_process_fragment_shader(version: &str) -> ShaderBuilder {
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in_no_location("vec2", "tex_coords");
+
+        shader_builder.dec_out("vec4", "output_color");
+
+        shader_builder.dec_uniform("sampler2D", "screen_texture");
+        shader_builder.dec_uniform("vec2", "screen_size");
+
+        shader_builder.main.dec_var("vec4", "color", "texture(screen_texture, tex_coords)");
+
+        shader_builder
+    }
+
+
+    pub fn lit_vertex_shader(version: &str) -> ShaderBuilder {
+        /*
+        Like animated_vertex_shader, but vertex_position, vertex_normals and the tangents come out in world space
+        for lighting, which needs the model matrix on its own as well as mvp.
+
+        uniform mat4 mvp;
+        uniform mat4 model;
+        uniform mat4 joint_transforms[MAX_BONES];
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_const("int", "MAX_BONES", "100");
+        shader_builder.dec_const("int", "MAX_WEIGHTS", "3");
+
+        shader_builder.dec_in(0, "vec3", "in_position");
+        shader_builder.dec_in(1, "vec3", "in_tex_coords");
+        shader_builder.dec_in(2, "vec3", "in_normal");
+        shader_builder.dec_in(3, "vec3", "in_bone_ids");
+        shader_builder.dec_in(4, "vec3", "in_bone_weights");
+        shader_builder.dec_in(5, "vec3", "in_color");
+        shader_builder.dec_in(6, "vec3", "in_tangent");
+        shader_builder.dec_in(7, "vec3", "in_bitangent");
+
+        shader_builder.dec_out("vec3", "vertex_position");
+        shader_builder.dec_out("vec3", "tex_coords");
+        shader_builder.dec_out("vec3", "vertex_normals");
+        shader_builder.dec_out("vec3", "vertex_color");
+        shader_builder.dec_out("vec3", "vertex_tangent");
+        shader_builder.dec_out("vec3", "vertex_bitangent");
+
+        shader_builder.dec_uniform("mat4", "mvp");
+        shader_builder.dec_uniform("mat4", "model");
+        shader_builder.dec_uniform("mat4", "joint_transforms[MAX_BONES]");
+
+        shader_builder.main.dec_var("vec4", "local_position", "vec4(in_position, 1.0)");
+        shader_builder.main.dec_var("vec4", "local_normal", "vec4(in_normal, 0.0)");
+        shader_builder.main.dec_var("vec4", "local_tangent", "vec4(in_tangent, 0.0)");
+        shader_builder.main.dec_var("vec4", "local_bitangent", "vec4(in_bitangent, 0.0)");
+
+        shader_builder.main.if_statement(
+            "in_bone_ids.x != 0.0 || in_bone_ids.y != 0.0 || in_bone_ids.z != 0.0", &ShaderClosure::new()
+                .with_do_action("local_position = vec4(0.0)")
+                .with_do_action("local_normal = vec4(0.0)")
+                .with_do_action("local_tangent = vec4(0.0)")
+                .with_do_action("local_bitangent = vec4(0.0)")
+                .with_for_loop("int i = 0; i < MAX_WEIGHTS; i++", &ShaderClosure::new()
+                    .with_dec_var("mat4", "joint_transform", "joint_transforms[int(in_bone_ids[i])]")
+                    .with_do_action("local_position += joint_transform * vec4(in_position, 1.0) * in_bone_weights[i]")
+                    .with_do_action("local_normal += joint_transform * vec4(in_normal, 0.0) * in_bone_weights[i]")
+                    .with_do_action("local_tangent += joint_transform * vec4(in_tangent, 0.0) * in_bone_weights[i]")
+                    .with_do_action("local_bitangent += joint_transform * vec4(in_bitangent, 0.0) * in_bone_weights[i]")
+                )
+        );
+
+        shader_builder.main.do_action("gl_Position = mvp * local_position");
+
+        shader_builder.main.do_action("vertex_position = (model * local_position).xyz");
+        shader_builder.main.do_action("tex_coords = in_tex_coords");
+        shader_builder.main.do_action("vertex_normals = mat3(transpose(inverse(model))) * local_normal.xyz");
+        shader_builder.main.do_action("vertex_color = in_color");
+        shader_builder.main.do_action("vertex_tangent = mat3(model) * local_tangent.xyz");
+        shader_builder.main.do_action("vertex_bitangent = mat3(model) * local_bitangent.xyz");
+
         shader_builder
     }
 
+    pub fn lighting_fragment_shader(version: &str, max_lights: usize, max_shadow_maps: usize) -> ShaderBuilder {
+        /*
+        Blinn-Phong for the lights a LightSet uploads into the Lights uniform block, four vec4s per light:
+
+        position.xyz, type (0 directional, 1 point, 2 spot)
+        direction.xyz, cos(inner angle)
+        color.xyz * intensity, cos(outer angle)
+        constant, linear and quadratic attenuation, first shadow map or -1
+
+        Shadow maps are layers of shadow_maps with their matrices in the Shadows block, which ShadowMaps fills.
+        Directional lights have one layer per cascade, picked by the distance from the camera along view_forward.
+        shadow_settings holds the depth bias, normal offset, PCF radius in texels and the size of a texel.
+
+        Surfaces take their color from the texture or vertex color. Specular highlights come from the shininess and
+        specular_color uniforms, which default to 0 and so to no highlights, set them on the material. With
+        use_normal_map the normal comes from normal_map, in the tangent space of the mesh's MikkTSpace tangents.
+         */
 
-    //TODO: Fix this: I'm bad at lighting lol
-    /*
-    pub fn lighting_fragment_shader(version: &str) -> ShaderBuilder {
         let mut shader_builder = ShaderBuilder::new(version);
 
-        shader_builder.dec_const("int", "MAX_LIGHTS", "10");
+        shader_builder.dec_const("int", "MAX_LIGHTS", max_lights.max(1).to_string().as_str());
+        shader_builder.dec_const("int", "MAX_SHADOW_MAPS", max_shadow_maps.max(1).to_string().as_str());
 
         shader_builder.dec_in_no_location("vec3", "vertex_position");
         shader_builder.dec_in_no_location("vec3", "tex_coords");
         shader_builder.dec_in_no_location("vec3", "vertex_normals");
         shader_builder.dec_in_no_location("vec3", "vertex_color");
+        shader_builder.dec_in_no_location("vec3", "vertex_tangent");
+        shader_builder.dec_in_no_location("vec3", "vertex_bitangent");
 
         shader_builder.dec_out("vec4", "output_color");
 
         shader_builder.dec_uniform("bool", "should_sample_texture");
-        shader_builder.dec_uniform("sampler2D", "sampler_objs[32]");
-        shader_builder.dec_uniform("vec3", "light_sources[MAX_LIGHTS]");
-        shader_builder.dec_uniform("vec3", "light_colors[MAX_LIGHTS]");
+        shader_builder.dec_uniform("bool", "use_normal_map");
+        // Units 0 to 13 are textures, 14 is normal_map and 15 shadow_maps. Keeps the program within the 16 units
+        // every GPU has.
+        shader_builder.dec_uniform("sampler2D", "sampler_objs[14]");
+        shader_builder.dec_uniform("sampler2D", "normal_map");
+        shader_builder.dec_uniform("sampler2DArrayShadow", "shadow_maps");
+        shader_builder.dec_uniform("float", "shininess");
+        shader_builder.dec_uniform("vec3", "specular_color");
+
+        shader_builder.dec_uniform_block("Lights", &vec![
+            ("vec4", "ambient_light"),
+            ("vec4", "view_position"),
+            ("ivec4", "light_count"),
+            ("vec4", "lights[MAX_LIGHTS * 4]"),
+        ]);
+
+        shader_builder.dec_uniform_block("Shadows", &vec![
+            ("mat4", "shadow_matrices[MAX_SHADOW_MAPS]"),
+            ("vec4", "cascade_splits"),
+            ("vec4", "view_forward"),
+            ("vec4", "shadow_settings"),
+        ]);
+
+        let mut calculate_shadow = ShaderFunction::new("float", "calculate_shadow", &vec![
+            String::from("int first_map"),
+            String::from("int light_type"),
+            String::from("vec3 normal"),
+            String::from("vec3 light_direction"),
+        ]);
+
+        calculate_shadow.dec_var("int", "shadow_map", "first_map");
+        calculate_shadow.dec_var("int", "cascade_count", "int(view_forward.w)");
+        calculate_shadow.dec_var("float", "view_depth", "dot(vertex_position - view_position.xyz, view_forward.xyz)");
+        calculate_shadow.dec_var("float", "bias", "shadow_settings.x * max(1.0 - dot(normal, light_direction), 0.1)");
+        calculate_shadow.dec_var("int", "pcf_radius", "int(shadow_settings.z)");
+        calculate_shadow.dec_var("vec4", "light_space", "vec4(0.0)");
+        calculate_shadow.dec_var("vec3", "projected", "vec3(0.0)");
+        calculate_shadow.dec_var("float", "lit", "0.0");
+
+        // Past the last cascade nothing is shadowed.
+        calculate_shadow.if_statement(
+            "light_type == 0", &ShaderClosure::new()
+                .with_if_statement("view_depth > cascade_splits[cascade_count - 1]", &ShaderClosure::new()
+                    .with_return_statement("1.0")
+                )
+                .with_for_loop("int i = 0; i < cascade_count - 1; i++", &ShaderClosure::new()
+                    .with_if_statement("view_depth > cascade_splits[i]", &ShaderClosure::new()
+                        .with_do_action("shadow_map++")
+                    )
+                )
+        );
+
+        calculate_shadow.do_action("light_space = shadow_matrices[shadow_map] * vec4(vertex_position + normal * shadow_settings.y, 1.0)");
+        calculate_shadow.do_action("projected = light_space.xyz / light_space.w * 0.5 + 0.5");
+
+        calculate_shadow.if_statement(
+            "projected.z > 1.0 || any(lessThan(projected.xy, vec2(0.0))) || any(greaterThan(projected.xy, vec2(1.0)))", &ShaderClosure::new()
+                .with_return_statement("1.0")
+        );
 
-        shader_builder.dec_uniform("vec3", "light_position_uni");
+        calculate_shadow.dec_for_loop("int x = -pcf_radius; x <= pcf_radius; x++", &ShaderClosure::new()
+            .with_for_loop("int y = -pcf_radius; y <= pcf_radius; y++", &ShaderClosure::new()
+                .with_do_action("lit += texture(shadow_maps, vec4(projected.xy + vec2(x, y) * shadow_settings.w, float(shadow_map), projected.z - bias))")
+            )
+        );
 
-        shader_builder.main.dec_var("vec3", "normal", "normalize(vertex_normals.xyz)");
+        calculate_shadow.return_statement("lit / float((pcf_radius * 2 + 1) * (pcf_radius * 2 + 1))");
+
+        shader_builder.dec_function(&calculate_shadow);
+
+        let mut calculate_light = ShaderFunction::new("vec3", "calculate_light", &vec![
+            String::from("int index"),
+            String::from("vec3 normal"),
+            String::from("vec3 view_direction"),
+            String::from("vec3 base_color"),
+        ]);
+
+        calculate_light.dec_var("vec4", "position_type", "lights[index * 4]");
+        calculate_light.dec_var("vec4", "direction_inner", "lights[index * 4 + 1]");
+        calculate_light.dec_var("vec4", "color_outer", "lights[index * 4 + 2]");
+        calculate_light.dec_var("vec4", "attenuation", "lights[index * 4 + 3]");
+        calculate_light.dec_var("int", "light_type", "int(position_type.w)");
+        calculate_light.dec_var("vec3", "light_direction", "normalize(-direction_inner.xyz)");
+        calculate_light.dec_var("float", "falloff", "1.0");
+        calculate_light.dec_var("float", "diffuse", "0.0");
+        calculate_light.dec_var("float", "specular", "0.0");
+        calculate_light.dec_var("float", "shadow", "1.0");
+
+        calculate_light.if_statement(
+            "light_type != 0", &ShaderClosure::new()
+                .with_dec_var("vec3", "to_light", "position_type.xyz - vertex_position")
+                .with_dec_var("float", "light_distance", "length(to_light)")
+                .with_do_action("light_direction = to_light / light_distance")
+                .with_do_action("falloff = 1.0 / (attenuation.x + attenuation.y * light_distance + attenuation.z * light_distance * light_distance)")
+        );
 
-        shader_builder.main.dec_var("vec3", "light_position", "vec3(0.0, 0.0, 10.0)");
-        shader_builder.main.dec_var("vec3", "light_color", "light_position_uni");
-        shader_builder.main.dec_var("vec3", "light_direction", "normalize(light_position - vertex_position)");
-        shader_builder.main.dec_var("float", "ambient_light", "0.1");
+        calculate_light.if_statement(
+            "light_type == 2", &ShaderClosure::new()
+                .with_dec_var("float", "theta", "dot(light_direction, normalize(-direction_inner.xyz))")
+                .with_do_action("falloff *= clamp((theta - color_outer.w) / max(direction_inner.w - color_outer.w, 0.0001), 0.0, 1.0)")
+        );
 
-        shader_builder.main.dec_var("float", "diffuse_strength", "max(0.0, dot(light_direction, normal))");
-        shader_builder.main.dec_var("vec3", "diffuse_color", "diffuse_strength * light_color");
+        calculate_light.do_action("diffuse = max(dot(normal, light_direction), 0.0)");
 
-        shader_builder.main.dec_var("vec3", "lighting", "ambient_light + diffuse_color");
+        calculate_light.if_statement(
+            "diffuse > 0.0 && attenuation.w >= 0.0", &ShaderClosure::new()
+                .with_do_action("shadow = calculate_shadow(int(attenuation.w), light_type, normal, light_direction)")
+        );
 
-        shader_builder.main.dec_var("highp int", "sampler_index", "int(tex_coords.z)");
+        calculate_light.if_statement(
+            "diffuse > 0.0 && shininess > 0.0", &ShaderClosure::new()
+                .with_do_action("specular = pow(max(dot(normal, normalize(light_direction + view_direction)), 0.0), shininess)")
+        );
+
+        calculate_light.return_statement("(diffuse * base_color + specular * specular_color) * color_outer.xyz * falloff * shadow");
 
-        shader_builder.main.dec_var("vec3", "final_vertex_color", "vec3(0.0)");
+        shader_builder.dec_function(&calculate_light);
+
+        shader_builder.main.dec_var("vec3", "normal", "normalize(vertex_normals)");
+        shader_builder.main.dec_var("vec3", "view_direction", "normalize(view_position.xyz - vertex_position)");
+        shader_builder.main.dec_var("vec4", "base_color", "vec4(vertex_color, 1.0)");
+        shader_builder.main.dec_var("vec3", "lighting", "vec3(0.0)");
 
         shader_builder.main.if_statement(
             "should_sample_texture", &ShaderClosure::new()
-                .with_do_action("final_vertex_color = texture(sampler_objs[sampler_index], tex_coords.xy).xyz")
+                .with_do_action("base_color = texture(sampler_objs[int(tex_coords.z)], tex_coords.xy)")
         );
 
-        shader_builder.main.else_statement(
-            &ShaderClosure::new()
-                .with_do_action("final_vertex_color = vertex_color")
+        // The bitangent is rebuilt from the interpolated normal and tangent, like MikkTSpace bakers expect.
+        shader_builder.main.if_statement(
+            "use_normal_map", &ShaderClosure::new()
+                .with_dec_var("float", "bitangent_sign", "dot(cross(normal, vertex_tangent), vertex_bitangent) < 0.0 ? -1.0 : 1.0")
+                .with_dec_var("vec3", "bitangent", "bitangent_sign * cross(normal, vertex_tangent)")
+                .with_dec_var("vec3", "mapped_normal", "texture(normal_map, tex_coords.xy).xyz * 2.0 - 1.0")
+                .with_do_action("normal = normalize(mapped_normal.x * vertex_tangent + mapped_normal.y * bitangent + mapped_normal.z * normal)")
+        );
+
+        shader_builder.main.do_action("lighting = ambient_light.xyz * base_color.rgb");
+
+        shader_builder.main.dec_for_loop("int i = 0; i < min(light_count.x, MAX_LIGHTS); i++", &ShaderClosure::new()
+            .with_do_action("lighting += calculate_light(i, normal, view_direction, base_color.rgb)")
+        );
+
+        shader_builder.main.do_action("output_color = vec4(lighting, base_color.a)");
+
+        shader_builder
+    }
+
+    pub fn shadow_vertex_shader(version: &str) -> ShaderBuilder {
+        /*
+        Depth pass for shadow maps. Skins like animated_vertex_shader so animated meshes cast the shadow of their
+        current pose, mvp goes to the light's space instead of the camera's.
+
+        uniform mat4 mvp;
+        uniform mat4 joint_transforms[MAX_BONES];
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_const("int", "MAX_BONES", "100");
+        shader_builder.dec_const("int", "MAX_WEIGHTS", "3");
+
+        shader_builder.dec_in(0, "vec3", "in_position");
+        shader_builder.dec_in(3, "vec3", "in_bone_ids");
+        shader_builder.dec_in(4, "vec3", "in_bone_weights");
+
+        shader_builder.dec_uniform("mat4", "mvp");
+        shader_builder.dec_uniform("mat4", "joint_transforms[MAX_BONES]");
+
+        shader_builder.main.dec_var("vec4", "local_position", "vec4(in_position, 1.0)");
+
+        shader_builder.main.if_statement(
+            "in_bone_ids.x != 0.0 || in_bone_ids.y != 0.0 || in_bone_ids.z != 0.0", &ShaderClosure::new()
+                .with_do_action("local_position = vec4(0.0)")
+                .with_for_loop("int i = 0; i < MAX_WEIGHTS; i++", &ShaderClosure::new()
+                    .with_do_action("local_position += joint_transforms[int(in_bone_ids[i])] * vec4(in_position, 1.0) * in_bone_weights[i]")
+                )
+        );
+
+        shader_builder.main.do_action("gl_Position = mvp * local_position");
+
+        shader_builder
+    }
+
+    pub fn shadow_fragment_shader(version: &str) -> ShaderBuilder {
+        // Only depth gets written.
+        ShaderBuilder::new(version)
+    }
+
+    pub fn skybox_vertex_shader(version: &str) -> ShaderBuilder {
+        /*
+        No vertex buffer, gl_VertexID makes a triangle covering the screen at the far plane. Each corner gets the
+        direction it looks at through the inverse of projection * camera rotation.
+
+        uniform mat4 inverse_view_projection;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_out("vec3", "view_direction");
+
+        shader_builder.dec_uniform("mat4", "inverse_view_projection");
+
+        shader_builder.main.dec_var("vec2", "position", "vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0");
+        shader_builder.main.dec_var("vec4", "far_point", "inverse_view_projection * vec4(position, 1.0, 1.0)");
+
+        shader_builder.main.do_action("view_direction = far_point.xyz / far_point.w");
+        shader_builder.main.do_action("gl_Position = vec4(position, 1.0, 1.0)");
+
+        shader_builder
+    }
+
+    pub fn skybox_fragment_shader(version: &str) -> ShaderBuilder {
+        /*
+        uniform samplerCube skybox;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in_no_location("vec3", "view_direction");
+        shader_builder.dec_out("vec4", "output_color");
+
+        shader_builder.dec_uniform("samplerCube", "skybox");
+
+        shader_builder.main.do_action("output_color = texture(skybox, view_direction)");
+
+        shader_builder
+    }
+
+    pub fn text_vertex_shader(version: &str) -> ShaderBuilder {
+        /*
+        uniform mat4 mvp;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in(0, "vec3", "in_position");
+        shader_builder.dec_in(1, "vec3", "in_tex_coords");
+        shader_builder.dec_out("vec2", "tex_coords");
+
+        shader_builder.dec_uniform("mat4", "mvp");
+
+        shader_builder.main.do_action("tex_coords = in_tex_coords.xy");
+        shader_builder.main.do_action("gl_Position = mvp * vec4(in_position, 1.0)");
+
+        shader_builder
+    }
+
+    pub fn text_fragment_shader(version: &str) -> ShaderBuilder {
+        /*
+        The glyph atlas holds coverage in alpha, or with use_sdf the distance to the outline with 0.5 on it. fwidth
+        keeps the edge about a pixel wide at any scale.
+
+        uniform sampler2D glyph_atlas;
+        uniform vec4 text_color;
+        uniform bool use_sdf;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in_no_location("vec2", "tex_coords");
+        shader_builder.dec_out("vec4", "output_color");
+
+        shader_builder.dec_uniform("sampler2D", "glyph_atlas");
+        shader_builder.dec_uniform("vec4", "text_color");
+        shader_builder.dec_uniform("bool", "use_sdf");
+
+        shader_builder.main.dec_var("float", "coverage", "texture(glyph_atlas, tex_coords).a");
+
+        shader_builder.main.if_statement("use_sdf", &ShaderClosure::new()
+            .with_do_action("coverage = smoothstep(0.5 - fwidth(coverage), 0.5 + fwidth(coverage), coverage)")
+        );
+
+        shader_builder.main.do_action("output_color = vec4(text_color.rgb, text_color.a * coverage)");
+
+        shader_builder
+    }
+
+    pub fn debug_vertex_shader(version: &str) -> ShaderBuilder {
+        /*
+        Debug lines are already in world space, colored per vertex.
+
+        uniform mat4 view_projection;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in(0, "vec3", "in_position");
+        shader_builder.dec_in(1, "vec4", "in_color");
+        shader_builder.dec_out("vec4", "line_color");
+
+        shader_builder.dec_uniform("mat4", "view_projection");
+
+        shader_builder.main.do_action("line_color = in_color");
+        shader_builder.main.do_action("gl_Position = view_projection * vec4(in_position, 1.0)");
+
+        shader_builder
+    }
+
+    pub fn debug_fragment_shader(version: &str) -> ShaderBuilder {
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in_no_location("vec4", "line_color");
+        shader_builder.dec_out("vec4", "output_color");
+
+        shader_builder.main.do_action("output_color = line_color");
+
+        shader_builder
+    }
+
+    pub fn shape_vertex_shader(version: &str) -> ShaderBuilder {
+        /*
+        Vertices from ShapeTessellator, in the shape's own 2D space.
+
+        uniform mat4 mvp;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in(0, "vec2", "in_position");
+        shader_builder.dec_in(1, "float", "in_edge_distance");
+        shader_builder.dec_in(2, "float", "in_paint");
+        shader_builder.dec_out("vec2", "local_position");
+        shader_builder.dec_out("float", "edge_distance");
+        shader_builder.dec_out("float", "paint");
+
+        shader_builder.dec_uniform("mat4", "mvp");
+
+        shader_builder.main.do_action("local_position = in_position");
+        shader_builder.main.do_action("edge_distance = in_edge_distance");
+        shader_builder.main.do_action("paint = in_paint");
+        shader_builder.main.do_action("gl_Position = mvp * vec4(in_position, 0.0, 1.0)");
+
+        shader_builder
+    }
+
+    pub fn shape_fragment_shader(version: &str) -> ShaderBuilder {
+        /*
+        Paint 0 is the fill and 1 the stroke. Kinds are 0 solid, 1 linear gradient with points start.xy and end.zw,
+        2 radial gradient with the center in xy and radius in z. Each paint has two colors, solid ones the same twice.
+        edge_distance over how much it changes per pixel is how far into the shape the pixel is, in pixels.
+
+        uniform int paint_kinds[2];
+        uniform vec4 paint_colors[4];
+        uniform vec4 paint_points[2];
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in_no_location("vec2", "local_position");
+        shader_builder.dec_in_no_location("float", "edge_distance");
+        shader_builder.dec_in_no_location("float", "paint");
+        shader_builder.dec_out("vec4", "output_color");
+
+        shader_builder.dec_uniform("int", "paint_kinds[2]");
+        shader_builder.dec_uniform("vec4", "paint_colors[4]");
+        shader_builder.dec_uniform("vec4", "paint_points[2]");
+
+        shader_builder.main.dec_var("int", "index", "int(paint + 0.5)");
+        shader_builder.main.dec_var("vec4", "points", "paint_points[index]");
+        shader_builder.main.dec_var("vec2", "axis", "points.zw - points.xy");
+        shader_builder.main.dec_var("float", "t", "0.0");
+        shader_builder.main.dec_var("float", "coverage", "clamp(0.5 + edge_distance / max(fwidth(edge_distance), 0.000001), 0.0, 1.0)");
+
+        shader_builder.main.if_statement("paint_kinds[index] == 1", &ShaderClosure::new()
+            .with_do_action("t = clamp(dot(local_position - points.xy, axis) / max(dot(axis, axis), 0.000001), 0.0, 1.0)")
+        );
+
+        shader_builder.main.if_statement("paint_kinds[index] == 2", &ShaderClosure::new()
+            .with_do_action("t = clamp(length(local_position - points.xy) / max(points.z, 0.000001), 0.0, 1.0)")
+        );
+
+        shader_builder.main.do_action("output_color = mix(paint_colors[index * 2], paint_colors[index * 2 + 1], t)");
+        shader_builder.main.do_action("output_color.a *= coverage");
+
+        shader_builder
+    }
+
+    pub fn particle_vertex_shader(version: &str) -> ShaderBuilder {
+        /*
+        One instance per particle, gl_VertexID picks the corner of its quad. Corners are spread along the camera's
+        right and up so the quad always faces it.
+
+        uniform mat4 view_projection;
+        uniform vec3 camera_right;
+        uniform vec3 camera_up;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in(0, "vec3", "in_center");
+        shader_builder.dec_in(1, "float", "in_size");
+        shader_builder.dec_in(2, "float", "in_rotation");
+        shader_builder.dec_in(3, "vec4", "in_color");
+        shader_builder.dec_in(4, "vec4", "in_uv_rect");
+        shader_builder.dec_out("vec2", "uv");
+        shader_builder.dec_out("vec2", "corner_position");
+        shader_builder.dec_out("vec4", "particle_color");
+
+        shader_builder.dec_const("vec2", "CORNERS[6]", "vec2[6](vec2(-0.5, -0.5), vec2(0.5, -0.5), vec2(0.5, 0.5), vec2(0.5, 0.5), vec2(-0.5, 0.5), vec2(-0.5, -0.5))");
+
+        shader_builder.dec_uniform("mat4", "view_projection");
+        shader_builder.dec_uniform("vec3", "camera_right");
+        shader_builder.dec_uniform("vec3", "camera_up");
+
+        shader_builder.main.dec_var("vec2", "corner", "CORNERS[gl_VertexID]");
+        shader_builder.main.dec_var("float", "s", "sin(in_rotation)");
+        shader_builder.main.dec_var("float", "c", "cos(in_rotation)");
+        shader_builder.main.dec_var("vec2", "rotated", "vec2(corner.x * c - corner.y * s, corner.x * s + corner.y * c) * in_size");
+
+        shader_builder.main.do_action("uv = mix(in_uv_rect.xy, in_uv_rect.zw, corner + 0.5)");
+        shader_builder.main.do_action("corner_position = corner * 2.0");
+        shader_builder.main.do_action("particle_color = in_color");
+        shader_builder.main.do_action("gl_Position = view_projection * vec4(in_center + camera_right * rotated.x + camera_up * rotated.y, 1.0)");
+
+        shader_builder
+    }
+
+    pub fn particle_fragment_shader(version: &str) -> ShaderBuilder {
+        /*
+        Without a texture particles are round dots fading out towards their edge.
+
+        uniform sampler2D particle_texture;
+        uniform bool use_texture;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in_no_location("vec2", "uv");
+        shader_builder.dec_in_no_location("vec2", "corner_position");
+        shader_builder.dec_in_no_location("vec4", "particle_color");
+        shader_builder.dec_out("vec4", "output_color");
+
+        shader_builder.dec_uniform("sampler2D", "particle_texture");
+        shader_builder.dec_uniform("bool", "use_texture");
+
+        shader_builder.main.do_action("output_color = particle_color");
+
+        shader_builder.main.if_statement("use_texture", &ShaderClosure::new()
+            .with_do_action("output_color *= texture(particle_texture, uv)")
+        );
+
+        shader_builder.main.if_statement("!use_texture", &ShaderClosure::new()
+            .with_do_action("output_color.a *= 1.0 - smoothstep(0.5, 1.0, length(corner_position))")
+        );
+
+        shader_builder
+    }
+
+    pub fn tilemap_vertex_shader(version: &str) -> ShaderBuilder {
+        /*
+        Tilemap chunk vertices in the map's own space. tex_coords.z is the tileset's texture slot.
+
+        uniform mat4 mvp;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in(0, "vec2", "in_position");
+        shader_builder.dec_in(1, "vec3", "in_tex_coords");
+        shader_builder.dec_out("vec3", "tex_coords");
+
+        shader_builder.dec_uniform("mat4", "mvp");
+
+        shader_builder.main.do_action("tex_coords = in_tex_coords");
+        shader_builder.main.do_action("gl_Position = mvp * vec4(in_position, 0.0, 1.0)");
+
+        shader_builder
+    }
+
+    pub fn tilemap_fragment_shader(version: &str, max_tilesets: usize) -> ShaderBuilder {
+        /*
+        uniform sampler2D tilesets[max_tilesets];
+        uniform float opacity;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in_no_location("vec3", "tex_coords");
+        shader_builder.dec_out("vec4", "output_color");
+
+        shader_builder.dec_uniform("sampler2D", format!("tilesets[{}]", max_tilesets).as_str());
+        shader_builder.dec_uniform("float", "opacity");
+
+        shader_builder.main.do_action("output_color = texture(tilesets[int(tex_coords.z + 0.5)], tex_coords.xy)");
+        shader_builder.main.do_action("output_color.a *= opacity");
+
+        shader_builder
+    }
+
+    pub fn nine_slice_vertex_shader(version: &str) -> ShaderBuilder {
+        /*
+        uniform mat4 mvp;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in(0, "vec2", "in_position");
+        shader_builder.dec_in(1, "vec2", "in_tex_coords");
+        shader_builder.dec_out("vec2", "tex_coords");
+
+        shader_builder.dec_uniform("mat4", "mvp");
+
+        shader_builder.main.do_action("tex_coords = in_tex_coords");
+        shader_builder.main.do_action("gl_Position = mvp * vec4(in_position, 0.0, 1.0)");
+
+        shader_builder
+    }
+
+    pub fn nine_slice_fragment_shader(version: &str) -> ShaderBuilder {
+        /*
+        uniform sampler2D nine_slice_texture;
+        uniform vec4 color;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in_no_location("vec2", "tex_coords");
+        shader_builder.dec_out("vec4", "output_color");
+
+        shader_builder.dec_uniform("sampler2D", "nine_slice_texture");
+        shader_builder.dec_uniform("vec4", "color");
+
+        shader_builder.main.do_action("output_color = texture(nine_slice_texture, tex_coords) * color");
+
+        shader_builder
+    }
+
+    pub fn ui_vertex_shader(version: &str) -> ShaderBuilder {
+        /*
+        UI vertices are in screen pixels. in_tex_coords.z is 1 for glyphs and 0 for solid quads.
+
+        uniform mat4 projection;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in(0, "vec2", "in_position");
+        shader_builder.dec_in(1, "vec3", "in_tex_coords");
+        shader_builder.dec_in(2, "vec4", "in_color");
+        shader_builder.dec_out("vec3", "tex_coords");
+        shader_builder.dec_out("vec4", "color");
+
+        shader_builder.dec_uniform("mat4", "projection");
+
+        shader_builder.main.do_action("tex_coords = in_tex_coords");
+        shader_builder.main.do_action("color = in_color");
+        shader_builder.main.do_action("gl_Position = projection * vec4(in_position, 0.0, 1.0)");
+
+        shader_builder
+    }
+
+    pub fn ui_fragment_shader(version: &str) -> ShaderBuilder {
+        /*
+        Glyphs are read from the font's atlas like text_fragment_shader does, everything else is just its color.
+
+        uniform sampler2D glyph_atlas;
+        uniform bool use_sdf;
+         */
+
+        let mut shader_builder = ShaderBuilder::new(version);
+
+        shader_builder.dec_in_no_location("vec3", "tex_coords");
+        shader_builder.dec_in_no_location("vec4", "color");
+        shader_builder.dec_out("vec4", "output_color");
+
+        shader_builder.dec_uniform("sampler2D", "glyph_atlas");
+        shader_builder.dec_uniform("bool", "use_sdf");
+
+        shader_builder.main.dec_var("float", "coverage", "1.0");
+
+        shader_builder.main.if_statement("tex_coords.z > 0.5", &ShaderClosure::new()
+            .with_do_action("coverage = texture(glyph_atlas, tex_coords.xy).a")
         );
 
-        shader_builder.main.do_action("output_color = vec4(final_vertex_color * lighting, 1.0)");
+        shader_builder.main.if_statement("tex_coords.z > 0.5 && use_sdf", &ShaderClosure::new()
+            .with_do_action("coverage = smoothstep(0.5 - fwidth(coverage), 0.5 + fwidth(coverage), coverage)")
+        );
 
-        println!("{}", shader_builder.to_string());
+        shader_builder.main.do_action("output_color = vec4(color.rgb, color.a * coverage)");
 
         shader_builder
     }
-    */
 }
 
 #[derive(Debug, Clone)]
@@ -799,6 +1515,33 @@ impl Drop for CompiledShader {
     }
 }
 
+// A uniform value that can be stored and set later, for post processing passes and materials.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UniformValue {
+    Bool(bool),
+    I32(i32),
+    F32(f32),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Vec4(Vec4),
+    Mat4(Mat4),
+}
+
+impl UniformValue {
+    // The same type set to zero, what GL initializes uniforms to when a program is linked.
+    pub fn zeroed(&self) -> UniformValue {
+        match self {
+            UniformValue::Bool(_) => UniformValue::Bool(false),
+            UniformValue::I32(_) => UniformValue::I32(0),
+            UniformValue::F32(_) => UniformValue::F32(0.0),
+            UniformValue::Vec2(_) => UniformValue::Vec2(Vec2::ZERO),
+            UniformValue::Vec3(_) => UniformValue::Vec3(Vec3::ZERO),
+            UniformValue::Vec4(_) => UniformValue::Vec4(Vec4::ZERO),
+            UniformValue::Mat4(_) => UniformValue::Mat4(Mat4::ZERO),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ShaderProgram {
     pub program_id: GLuint,
@@ -829,6 +1572,19 @@ impl ShaderProgram {
         }
     }
 
+    // Compiles both builders and links them, the usual way of getting a program out of the templates.
+    pub fn from_builders(vertex_shader: &ShaderBuilder, fragment_shader: &ShaderBuilder) -> Result<ShaderProgram, ShaderError> {
+        let vertex_shader = vertex_shader.build(&ShaderType::VERTEX).compile()?;
+        let fragment_shader = fragment_shader.build(&ShaderType::FRAGMENT).compile()?;
+
+        let mut shader_program = ShaderProgram::new();
+        shader_program.attach_shader(&vertex_shader);
+        shader_program.attach_shader(&fragment_shader);
+        shader_program.build();
+
+        Ok(shader_program)
+    }
+
     pub fn attach_shader(&mut self, shader: &CompiledShader) {
         self.compiled_shader_list.push(shader.clone());
     }
@@ -1026,6 +1782,31 @@ impl ShaderProgram {
         self.use_program(false);
     }
 
+    // Points a uniform block at a uniform buffer binding. Does nothing if the program has no such block.
+    pub fn bind_uniform_block(&self, block_name: &str, binding: u32) {
+        let c_str = CString::new(block_name.as_bytes()).unwrap();
+
+        unsafe {
+            let index = gl::GetUniformBlockIndex(self.program_id, c_str.as_ptr());
+
+            if index != gl::INVALID_INDEX {
+                gl::UniformBlockBinding(self.program_id, index, binding);
+            }
+        }
+    }
+
+    pub fn set_uniform(&self, name: &str, value: &UniformValue) {
+        match value {
+            UniformValue::Bool(value) => self.set_uniform_bool(name, *value),
+            UniformValue::I32(value) => self.set_uniform_i32(name, *value),
+            UniformValue::F32(value) => self.set_uniform_f32(name, *value),
+            UniformValue::Vec2(value) => self.set_uniform_vec2_f32(name, value),
+            UniformValue::Vec3(value) => self.set_uniform_vec3_f32(name, value),
+            UniformValue::Vec4(value) => self.set_uniform_vec4_f32(name, value),
+            UniformValue::Mat4(value) => self.set_uniform_mat4_f32(name, value),
+        }
+    }
+
     pub fn set_uniform_bool(&self, name: &str, value: bool) {
         self.set_uniform_i32(name, value as i32);
     }
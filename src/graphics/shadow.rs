@@ -0,0 +1,378 @@
+use std::ptr;
+
+use gl::types::*;
+use glam::{Mat4, Vec3};
+
+use crate::util::entity::Entity;
+use super::light::{Light, LightKind, LightSet};
+use super::shader::{ShaderBuilderTemplate, ShaderError, ShaderProgram};
+use super::view::{GraphicsLayer, View};
+
+const SHADOW_SHADER_VERSION: &str = "#version 450 core";
+
+pub const SHADOWS_BLOCK_NAME: &str = "Shadows";
+pub const SHADOWS_BLOCK_BINDING: u32 = 1;
+
+// The unit ShaderBuilderTemplate::lighting_fragment_shader reads shadow_maps from.
+pub const SHADOW_MAP_TEXTURE_UNIT: u32 = 15;
+
+pub const MAX_CASCADES: usize = 4;
+
+// What GraphicsLayer::render_object draws with while ShadowMaps renders. Renderables that cast shadows draw their
+// geometry with shader_program and light_matrix in place of their material and the view.
+#[derive(Clone)]
+pub struct DepthPass {
+    pub shader_program: ShaderProgram,
+    pub light_matrix: Mat4,
+}
+
+// Depth maps for the shadow casting lights of a LightSet, all layers of one depth texture array. A spot light takes
+// one layer, a directional light one per cascade. Lights that don't fit in max_maps don't get shadows.
+pub struct ShadowMaps {
+    pub size: u32,
+    pub max_maps: usize,
+    pub cascade_count: usize,
+    // How far from the camera directional lights still cast shadows.
+    pub shadow_distance: f32,
+    // Between 0 for evenly spaced cascades and 1 for logarithmic ones, which keep more detail close to the camera.
+    pub cascade_lambda: f32,
+    // How far beyond a cascade, towards the light, things still cast shadows into it.
+    pub caster_margin: f32,
+    pub spot_far: f32,
+    pub depth_bias: f32,
+    pub normal_bias: f32,
+    // In texels, 0 is a single hardware filtered sample, 1 a 3x3 grid and so on.
+    pub pcf_radius: u32,
+    pub matrices: Vec<Mat4>,
+    pub cascade_splits: Vec<f32>,
+    pub texture_id: GLuint,
+    pub framebuffer_id: GLuint,
+    pub buffer_id: GLuint,
+    pub shader_program: Option<ShaderProgram>,
+}
+
+impl ShadowMaps {
+    pub fn new(size: u32, max_maps: usize) -> Self {
+        ShadowMaps {
+            size,
+            max_maps,
+            cascade_count: 3,
+            shadow_distance: 50.0,
+            cascade_lambda: 0.75,
+            caster_margin: 25.0,
+            spot_far: 100.0,
+            depth_bias: 0.0005,
+            normal_bias: 0.02,
+            pcf_radius: 1,
+            matrices: vec![Mat4::IDENTITY; max_maps],
+            cascade_splits: Vec::new(),
+            texture_id: 0,
+            framebuffer_id: 0,
+            buffer_id: 0,
+            shader_program: None,
+        }
+    }
+
+    pub fn with_cascades(mut self, cascade_count: usize) -> Self {
+        self.cascade_count = cascade_count.clamp(1, MAX_CASCADES);
+        self
+    }
+
+    pub fn with_shadow_distance(mut self, shadow_distance: f32) -> Self {
+        self.shadow_distance = shadow_distance;
+        self
+    }
+
+    pub fn with_bias(mut self, depth_bias: f32, normal_bias: f32) -> Self {
+        self.depth_bias = depth_bias;
+        self.normal_bias = normal_bias;
+        self
+    }
+
+    pub fn with_pcf_radius(mut self, pcf_radius: u32) -> Self {
+        self.pcf_radius = pcf_radius;
+        self
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.shader_program.is_some()
+    }
+
+    // Creates the depth texture array and compiles the depth pass. Needs a current GL context.
+    pub fn init(&mut self) -> Result<(), ShaderError> {
+        self.shader_program = Some(ShaderProgram::from_builders(
+            &ShaderBuilderTemplate::shadow_vertex_shader(SHADOW_SHADER_VERSION),
+            &ShaderBuilderTemplate::shadow_fragment_shader(SHADOW_SHADER_VERSION),
+        )?);
+
+        unsafe {
+            gl::GenTextures(1, &mut self.texture_id);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.texture_id);
+
+            gl::TexImage3D(
+                gl::TEXTURE_2D_ARRAY, 0, gl::DEPTH_COMPONENT32F as GLint,
+                self.size as GLsizei, self.size as GLsizei, self.max_maps as GLsizei,
+                0, gl::DEPTH_COMPONENT, gl::FLOAT, ptr::null(),
+            );
+
+            // Linear filtering with comparison gives every sample a 2x2 PCF for free.
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
+
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+
+            gl::GenFramebuffers(1, &mut self.framebuffer_id);
+            gl::GenBuffers(1, &mut self.buffer_id);
+        }
+
+        Ok(())
+    }
+
+    // Connects a lit program to the shadow maps. Needed once per program, like LightSet::bind_shader.
+    pub fn bind_shader(shader_program: &ShaderProgram) {
+        shader_program.bind_uniform_block(SHADOWS_BLOCK_NAME, SHADOWS_BLOCK_BINDING);
+        shader_program.set_uniform_i32("shadow_maps", SHADOW_MAP_TEXTURE_UNIT as i32);
+    }
+
+    // Renders the entity tree from every shadow casting light and assigns the lights their layers. Call it before
+    // LightSet::upload, which passes the layers on to the shader.
+    pub fn render(&mut self, lights: &mut LightSet, layer: &mut GraphicsLayer, entity: &mut Entity) {
+        self.render_with(lights, layer, |layer| entity.render(layer));
+    }
+
+    // Like render, but draw decides what gets rendered, through layer.render_object.
+    pub fn render_with<F>(&mut self, lights: &mut LightSet, layer: &mut GraphicsLayer, mut draw: F)
+    where F: FnMut(&mut GraphicsLayer) {
+        let shader_program = self.shader_program.clone().expect("ShadowMaps::init wasn't called");
+
+        self.cascade_splits = self.get_cascade_splits(&layer.view);
+
+        let mut next_map = 0;
+        let max_lights = lights.max_lights;
+
+        for (i, light) in lights.lights.iter_mut().enumerate() {
+            light.shadow_map = None;
+
+            if !light.casts_shadows || i >= max_lights {
+                continue;
+            }
+
+            let matrices = match light.kind {
+                LightKind::Directional => self.get_cascade_matrices(light, &layer.view),
+                LightKind::Spot => vec![self.get_spot_matrix(light)],
+                LightKind::Point => continue,
+            };
+
+            if next_map + matrices.len() > self.max_maps {
+                continue;
+            }
+
+            light.shadow_map = Some(next_map);
+
+            for matrix in matrices {
+                self.matrices[next_map] = matrix;
+                next_map += 1;
+            }
+        }
+
+        unsafe {
+            let mut previous_framebuffer: GLint = 0;
+            let mut previous_viewport: [GLint; 4] = [0; 4];
+
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut previous_framebuffer);
+            gl::GetIntegerv(gl::VIEWPORT, previous_viewport.as_mut_ptr());
+
+            let depth_test = gl::IsEnabled(gl::DEPTH_TEST) == gl::TRUE;
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer_id);
+            gl::DrawBuffer(gl::NONE);
+            gl::ReadBuffer(gl::NONE);
+            gl::Viewport(0, 0, self.size as i32, self.size as i32);
+
+            gl::Enable(gl::DEPTH_TEST);
+            gl::Enable(gl::POLYGON_OFFSET_FILL);
+            gl::PolygonOffset(2.0, 4.0);
+
+            for map in 0..next_map {
+                gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, self.texture_id, 0, map as GLint);
+
+                let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+
+                if status != gl::FRAMEBUFFER_COMPLETE {
+                    panic!("shadow map framebuffer is incomplete: 0x{status:X}");
+                }
+
+                gl::Clear(gl::DEPTH_BUFFER_BIT);
+
+                layer.depth_pass = Some(DepthPass {
+                    shader_program: shader_program.clone(),
+                    light_matrix: self.matrices[map],
+                });
+
+                draw(layer);
+            }
+
+            layer.depth_pass = None;
+
+            gl::Disable(gl::POLYGON_OFFSET_FILL);
+
+            if !depth_test {
+                gl::Disable(gl::DEPTH_TEST);
+            }
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous_framebuffer as GLuint);
+            gl::Viewport(previous_viewport[0], previous_viewport[1], previous_viewport[2], previous_viewport[3]);
+        }
+
+        self.upload(&layer.view);
+    }
+
+    // Fills the Shadows block and binds the maps for the lit programs.
+    fn upload(&self, view: &View) {
+        let front = match view {
+            View::View2D(view) => view.front,
+            View::View3D(view) => view.front,
+        };
+
+        let mut splits = [0.0; MAX_CASCADES];
+
+        for (i, split) in self.cascade_splits.iter().enumerate() {
+            splits[i] = *split;
+        }
+
+        let mut floats: Vec<f32> = self.matrices.iter().flat_map(|matrix| matrix.to_cols_array()).collect();
+        floats.extend_from_slice(&splits);
+        floats.extend_from_slice(&front.normalize_or_zero().extend(self.cascade_count as f32).to_array());
+        floats.extend_from_slice(&[self.depth_bias, self.normal_bias, self.pcf_radius as f32, 1.0 / self.size as f32]);
+
+        unsafe {
+            gl::BindBuffer(gl::UNIFORM_BUFFER, self.buffer_id);
+            gl::BufferData(
+                gl::UNIFORM_BUFFER,
+                (floats.len() * std::mem::size_of::<f32>()) as GLsizeiptr,
+                floats.as_ptr() as *const std::ffi::c_void,
+                gl::DYNAMIC_DRAW,
+            );
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, SHADOWS_BLOCK_BINDING, self.buffer_id);
+
+            gl::ActiveTexture(gl::TEXTURE0 + SHADOW_MAP_TEXTURE_UNIT);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.texture_id);
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+    }
+
+    // The far end of every cascade, as a distance from the camera along its front.
+    fn get_cascade_splits(&self, view: &View) -> Vec<f32> {
+        let (near, far) = Self::get_clip_planes(view);
+        let distance = self.shadow_distance.min(far);
+
+        (1..=self.cascade_count).map(|i| {
+            let fraction = i as f32 / self.cascade_count as f32;
+
+            let uniform = near + (distance - near) * fraction;
+            let logarithmic = near * (distance / near).powf(fraction);
+
+            uniform + (logarithmic - uniform) * self.cascade_lambda
+        }).collect()
+    }
+
+    fn get_clip_planes(view: &View) -> (f32, f32) {
+        match view {
+            View::View3D(view) => (view.near, view.far),
+            // The planes View2D::get_view_matrix uses.
+            View::View2D(_) => (0.1, 100.0),
+        }
+    }
+
+    // An orthographic projection around each cascade's slice of the camera's view.
+    fn get_cascade_matrices(&self, light: &Light, view: &View) -> Vec<Mat4> {
+        let (near, far) = Self::get_clip_planes(view);
+
+        let inverse_view = match view {
+            View::View2D(view) => view.get_view_matrix(),
+            View::View3D(view) => view.get_view_matrix(),
+        }.inverse();
+
+        let corner = |x: f32, y: f32, z: f32| inverse_view.project_point3(Vec3::new(x, y, z));
+
+        let near_corners = [corner(-1.0, -1.0, 0.0), corner(1.0, -1.0, 0.0), corner(1.0, 1.0, 0.0), corner(-1.0, 1.0, 0.0)];
+        let far_corners = [corner(-1.0, -1.0, 1.0), corner(1.0, -1.0, 1.0), corner(1.0, 1.0, 1.0), corner(-1.0, 1.0, 1.0)];
+
+        let up = Self::get_up(&light.direction);
+        let light_rotation = Mat4::look_at_lh(Vec3::ZERO, light.direction, up);
+
+        let mut slice_start = near;
+
+        self.cascade_splits.iter().map(|slice_end| {
+            // Depth along the corner rays is linear, so the slice's corners are a lerp between the two planes.
+            let t0 = (slice_start - near) / (far - near);
+            let t1 = (slice_end - near) / (far - near);
+            slice_start = *slice_end;
+
+            let mut slice_corners = Vec::with_capacity(8);
+
+            for (near_corner, far_corner) in near_corners.iter().zip(far_corners.iter()) {
+                slice_corners.push(near_corner.lerp(*far_corner, t0));
+                slice_corners.push(near_corner.lerp(*far_corner, t1));
+            }
+
+            let center = slice_corners.iter().copied().sum::<Vec3>() / slice_corners.len() as f32;
+            let radius = slice_corners.iter().map(|corner| corner.distance(center)).fold(0.0, f32::max);
+
+            // A bounding sphere and a center snapped to whole texels keep the shadow edges from shimmering as the
+            // camera moves and turns.
+            let radius = (radius * 16.0).ceil() / 16.0;
+            let texel_size = radius * 2.0 / self.size as f32;
+
+            let mut light_center = light_rotation.transform_point3(center);
+            light_center.x = (light_center.x / texel_size).floor() * texel_size;
+            light_center.y = (light_center.y / texel_size).floor() * texel_size;
+            let center = light_rotation.inverse().transform_point3(light_center);
+
+            let eye = center - light.direction * (radius + self.caster_margin);
+
+            Mat4::orthographic_lh(-radius, radius, -radius, radius, 0.0, radius * 2.0 + self.caster_margin)
+                * Mat4::look_at_lh(eye, center, up)
+        }).collect()
+    }
+
+    fn get_spot_matrix(&self, light: &Light) -> Mat4 {
+        let fov = (light.outer_angle.as_float() * 2.0 + 2.0).min(170.0).to_radians();
+
+        Mat4::perspective_lh(fov, 1.0, 0.1, self.spot_far)
+            * Mat4::look_at_lh(light.position, light.position + light.direction, Self::get_up(&light.direction))
+    }
+
+    fn get_up(direction: &Vec3) -> Vec3 {
+        match direction.y.abs() > 0.99 {
+            true => Vec3::Z,
+            false => Vec3::Y,
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.texture_id != 0 {
+            unsafe {
+                gl::DeleteTextures(1, &self.texture_id);
+                gl::DeleteFramebuffers(1, &self.framebuffer_id);
+                gl::DeleteBuffers(1, &self.buffer_id);
+            }
+
+            self.texture_id = 0;
+            self.framebuffer_id = 0;
+            self.buffer_id = 0;
+        }
+    }
+}
+
+impl Drop for ShadowMaps {
+    fn drop(&mut self) {
+        self.delete();
+    }
+}
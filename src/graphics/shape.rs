@@ -0,0 +1,593 @@
+use std::f32::consts::PI;
+
+use glam::{Vec2, Vec4};
+
+use super::color::Color;
+
+// Segments in a full ellipse, rounded corners get a quarter each.
+const CURVE_SEGMENTS: usize = 64;
+
+// Round joins and caps get a segment per this many radians.
+const ROUND_STEP: f32 = PI / 8.0;
+
+// Miter joins sharper than this many times the stroke width are beveled instead.
+const MITER_LIMIT: f32 = 4.0;
+
+#[derive(Clone, Copy)]
+pub enum Paint {
+    Solid(Color),
+    // Blends from start_color at start to end_color at end and keeps the end colors past them. Points are in the
+    // shape's space, where its position is the origin.
+    LinearGradient { start: Vec2, end: Vec2, start_color: Color, end_color: Color },
+    RadialGradient { center: Vec2, radius: f32, inner_color: Color, outer_color: Color },
+}
+
+impl Paint {
+    // Kind, the two colors and the points, the way the shape shader reads them.
+    pub fn get_shader_data(&self) -> (i32, Color, Color, Vec4) {
+        match self {
+            Paint::Solid(color) => (0, *color, *color, Vec4::ZERO),
+            Paint::LinearGradient { start, end, start_color, end_color } => {
+                (1, *start_color, *end_color, Vec4::new(start.x, start.y, end.x, end.y))
+            },
+            Paint::RadialGradient { center, radius, inner_color, outer_color } => {
+                (2, *inner_color, *outer_color, Vec4::new(center.x, center.y, *radius, 0.0))
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineJoin {
+    Miter,
+    Bevel,
+    Round,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LineCap {
+    Butt,
+    Square,
+    Round,
+}
+
+// Shapes are centered on their position, polygons and polylines are drawn around it the way they're given.
+#[derive(Clone, Debug)]
+pub enum ShapePath {
+    Rect { size: Vec2, corner_radius: f32 },
+    Ellipse { radii: Vec2 },
+    Polygon(Vec<Vec2>),
+    // Open, so only strokes draw it.
+    Polyline(Vec<Vec2>),
+}
+
+impl ShapePath {
+    // The outline as points, and whether the last one connects back to the first. Closed outlines go
+    // counterclockwise.
+    pub fn get_contour(&self) -> (Vec<Vec2>, bool) {
+        let (mut points, closed) = match self {
+            ShapePath::Rect { size, corner_radius } => (Self::get_rect_contour(size, *corner_radius), true),
+            ShapePath::Ellipse { radii } => {
+                let points = (0..CURVE_SEGMENTS).map(|i| {
+                    let angle = i as f32 / CURVE_SEGMENTS as f32 * 2.0 * PI;
+                    Vec2::new(angle.cos() * radii.x, angle.sin() * radii.y)
+                }).collect();
+
+                (points, true)
+            },
+            ShapePath::Polygon(points) => (points.clone(), true),
+            ShapePath::Polyline(points) => (points.clone(), false),
+        };
+
+        points.dedup_by(|a, b| a.distance_squared(*b) < 1e-10);
+
+        if closed {
+            while points.len() > 1 && points[0].distance_squared(points[points.len() - 1]) < 1e-10 {
+                points.pop();
+            }
+
+            if get_signed_area(&points) < 0.0 {
+                points.reverse();
+            }
+        }
+
+        (points, closed)
+    }
+
+    fn get_rect_contour(size: &Vec2, corner_radius: f32) -> Vec<Vec2> {
+        let half = *size / 2.0;
+        let radius = corner_radius.clamp(0.0, half.x.min(half.y));
+
+        if radius <= 0.0 {
+            return vec![
+                Vec2::new(-half.x, -half.y),
+                Vec2::new(half.x, -half.y),
+                Vec2::new(half.x, half.y),
+                Vec2::new(-half.x, half.y),
+            ];
+        }
+
+        let corner_segments = CURVE_SEGMENTS / 4;
+        let corners = [
+            (Vec2::new(half.x - radius, -half.y + radius), -PI / 2.0),
+            (Vec2::new(half.x - radius, half.y - radius), 0.0),
+            (Vec2::new(-half.x + radius, half.y - radius), PI / 2.0),
+            (Vec2::new(-half.x + radius, -half.y + radius), PI),
+        ];
+
+        let mut points = Vec::with_capacity((corner_segments + 1) * 4);
+
+        for (center, start_angle) in corners {
+            for i in 0..=corner_segments {
+                let angle = start_angle + i as f32 / corner_segments as f32 * PI / 2.0;
+                points.push(center + Vec2::new(angle.cos(), angle.sin()) * radius);
+            }
+        }
+
+        points
+    }
+}
+
+// Counterclockwise is positive.
+fn get_signed_area(points: &[Vec2]) -> f32 {
+    let mut area = 0.0;
+
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        area += a.perp_dot(b);
+    }
+
+    area / 2.0
+}
+
+// Triangles for a shape, each vertex as x, y, distance to the nearest edge and paint, 0 for the fill and 1 for the
+// stroke. Edges get a fringe_width wide strip the distance goes from fringe_width / 2 to -fringe_width / 2 across,
+// which the shader turns into about a pixel of anti-aliasing at any scale.
+pub struct ShapeTessellator {
+    pub fringe_width: f32,
+    pub vertices: Vec<f32>,
+}
+
+impl ShapeTessellator {
+    pub fn new(fringe_width: f32) -> Self {
+        ShapeTessellator {
+            fringe_width,
+            vertices: Vec::new(),
+        }
+    }
+
+    fn push_vertex(&mut self, position: Vec2, edge_distance: f32, paint: f32) {
+        self.vertices.extend_from_slice(&[position.x, position.y, edge_distance, paint]);
+    }
+
+    fn push_quad(&mut self, corners: [(Vec2, f32); 4], paint: f32) {
+        for i in [0, 1, 2, 2, 3, 0] {
+            self.push_vertex(corners[i].0, corners[i].1, paint);
+        }
+    }
+
+    // Fills a closed counterclockwise contour, concave ones too as long as they don't cross themselves.
+    pub fn fill(&mut self, contour: &[Vec2]) {
+        if contour.len() < 3 {
+            return;
+        }
+
+        let half_fringe = self.fringe_width / 2.0;
+        let inset: Vec<Vec2> = (0..contour.len()).map(|i| contour[i] - get_miter(contour, i) * half_fringe).collect();
+        let outset: Vec<Vec2> = (0..contour.len()).map(|i| contour[i] + get_miter(contour, i) * half_fringe).collect();
+
+        for [a, b, c] in triangulate(&inset) {
+            self.push_vertex(inset[a], half_fringe, 0.0);
+            self.push_vertex(inset[b], half_fringe, 0.0);
+            self.push_vertex(inset[c], half_fringe, 0.0);
+        }
+
+        for i in 0..contour.len() {
+            let next = (i + 1) % contour.len();
+
+            self.push_quad([
+                (inset[i], half_fringe),
+                (outset[i], -half_fringe),
+                (outset[next], -half_fringe),
+                (inset[next], half_fringe),
+            ], 0.0);
+        }
+    }
+
+    // A line width wide centered on the points.
+    pub fn stroke(&mut self, points: &[Vec2], closed: bool, width: f32, join: LineJoin, cap: LineCap) {
+        if points.len() < 2 {
+            return;
+        }
+
+        let half_fringe = self.fringe_width / 2.0;
+        let pairs = get_stroke_pairs(points, closed, width / 2.0, join, cap, half_fringe);
+
+        let rows: Vec<[(Vec2, f32); 4]> = pairs.iter().map(|pair| {
+            let edge_distance = if pair.edge { -half_fringe } else { half_fringe };
+
+            [
+                (pair.left + pair.across * half_fringe, -half_fringe),
+                (pair.left - pair.across * half_fringe, edge_distance),
+                (pair.right + pair.across * half_fringe, edge_distance),
+                (pair.right - pair.across * half_fringe, -half_fringe),
+            ]
+        }).collect();
+
+        for rows in rows.windows(2) {
+            for k in 0..3 {
+                self.push_quad([rows[0][k], rows[0][k + 1], rows[1][k + 1], rows[1][k]], 1.0);
+            }
+        }
+    }
+}
+
+// Outward bisector of the corner at i, long enough that offsetting along it moves both edges by the same distance.
+fn get_miter(contour: &[Vec2], i: usize) -> Vec2 {
+    let previous = contour[(i + contour.len() - 1) % contour.len()];
+    let next = contour[(i + 1) % contour.len()];
+
+    let normal_in = -(contour[i] - previous).normalize_or_zero().perp();
+    let normal_out = -(next - contour[i]).normalize_or_zero().perp();
+    let miter = (normal_in + normal_out).normalize_or_zero();
+
+    miter / miter.dot(normal_in).max(0.25)
+}
+
+// Ear clipping. Returns counterclockwise triangles of indices into contour.
+fn triangulate(contour: &[Vec2]) -> Vec<[usize; 3]> {
+    let mut remaining: Vec<usize> = (0..contour.len()).collect();
+    let mut triangles = Vec::with_capacity(contour.len() - 2);
+    let mut attempts = 0;
+    let mut i = 0;
+
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let (a, b, c) = (remaining[(i + count - 1) % count], remaining[i % count], remaining[(i + 1) % count]);
+
+        let is_convex = (contour[b] - contour[a]).perp_dot(contour[c] - contour[b]) > 0.0;
+        let is_ear = is_convex && !remaining.iter().any(|&other| {
+            other != a && other != b && other != c && is_inside_triangle(contour[other], contour[a], contour[b], contour[c])
+        });
+
+        if is_ear {
+            triangles.push([a, b, c]);
+            remaining.remove(i % count);
+            attempts = 0;
+        } else {
+            i += 1;
+            attempts += 1;
+
+            // Nothing left looks like an ear, which happens with crossing or degenerate outlines. Fanning the rest
+            // draws something close instead of looping forever.
+            if attempts > count {
+                break;
+            }
+        }
+    }
+
+    for k in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[k], remaining[k + 1]]);
+    }
+
+    triangles
+}
+
+fn is_inside_triangle(point: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    (b - a).perp_dot(point - a) >= 0.0 && (c - b).perp_dot(point - b) >= 0.0 && (a - c).perp_dot(point - c) >= 0.0
+}
+
+// One slice across a stroke. across points from right to left, edge slices are the fringe past an open end.
+struct StrokePair {
+    left: Vec2,
+    right: Vec2,
+    across: Vec2,
+    edge: bool,
+}
+
+impl StrokePair {
+    fn new(left: Vec2, right: Vec2, fallback_across: Vec2) -> Self {
+        StrokePair {
+            left,
+            right,
+            across: (left - right).try_normalize().unwrap_or(fallback_across),
+            edge: false,
+        }
+    }
+}
+
+fn get_stroke_pairs(points: &[Vec2], closed: bool, half_width: f32, join: LineJoin, cap: LineCap, half_fringe: f32) -> Vec<StrokePair> {
+    let count = points.len();
+    let mut pairs = Vec::new();
+
+    if closed {
+        for i in 0..count {
+            push_join(&mut pairs, points[(i + count - 1) % count], points[i], points[(i + 1) % count], half_width, join);
+        }
+
+        let first = StrokePair::new(pairs[0].left, pairs[0].right, pairs[0].across);
+        pairs.push(first);
+
+        return pairs;
+    }
+
+    let start_direction = (points[1] - points[0]).normalize_or_zero();
+    let end_direction = (points[count - 1] - points[count - 2]).normalize_or_zero();
+
+    // The caps move in by half a fringe and the end fringes stick out as far past the ends, so the edge stays where
+    // the line ends. The start cap is built walking backwards, which swaps its sides.
+    let mut start_pairs = get_cap_pairs(points[0], -start_direction, half_width, cap);
+    start_pairs.reverse();
+
+    for pair in start_pairs.iter_mut() {
+        std::mem::swap(&mut pair.left, &mut pair.right);
+        pair.across = -pair.across;
+        pair.left += start_direction * half_fringe;
+        pair.right += start_direction * half_fringe;
+    }
+
+    pairs.push(get_edge_pair(&start_pairs[0], -start_direction * half_fringe * 2.0));
+    pairs.extend(start_pairs);
+
+    for i in 1..count - 1 {
+        push_join(&mut pairs, points[i - 1], points[i], points[i + 1], half_width, join);
+    }
+
+    let mut end_pairs = get_cap_pairs(points[count - 1], end_direction, half_width, cap);
+
+    for pair in end_pairs.iter_mut() {
+        pair.left -= end_direction * half_fringe;
+        pair.right -= end_direction * half_fringe;
+    }
+
+    let edge = get_edge_pair(&end_pairs[end_pairs.len() - 1], end_direction * half_fringe * 2.0);
+    pairs.extend(end_pairs);
+    pairs.push(edge);
+
+    pairs
+}
+
+fn get_edge_pair(pair: &StrokePair, offset: Vec2) -> StrokePair {
+    StrokePair {
+        left: pair.left + offset,
+        right: pair.right + offset,
+        across: pair.across,
+        edge: true,
+    }
+}
+
+// The cap at an end of the line, direction pointing out of it. Ordered from the line's body to the tip, with left
+// and right as seen walking towards the tip.
+fn get_cap_pairs(point: Vec2, direction: Vec2, half_width: f32, cap: LineCap) -> Vec<StrokePair> {
+    let normal = direction.perp();
+
+    match cap {
+        LineCap::Butt => vec![StrokePair::new(point + normal * half_width, point - normal * half_width, normal)],
+        LineCap::Square => {
+            let end = point + direction * half_width;
+            vec![StrokePair::new(end + normal * half_width, end - normal * half_width, normal)]
+        },
+        LineCap::Round => {
+            let segments = ((PI / 2.0) / ROUND_STEP).ceil() as usize;
+
+            (0..=segments).map(|i| {
+                let angle = i as f32 / segments as f32 * PI / 2.0;
+                let forward = point + direction * half_width * angle.sin();
+                let side = normal * half_width * angle.cos();
+
+                StrokePair::new(forward + side, forward - side, normal)
+            }).collect()
+        },
+    }
+}
+
+// The corner at current. The inner side is always mitered, the outer side gets the join.
+fn push_join(pairs: &mut Vec<StrokePair>, previous: Vec2, current: Vec2, next: Vec2, half_width: f32, join: LineJoin) {
+    let direction_in = (current - previous).normalize_or_zero();
+    let direction_out = (next - current).normalize_or_zero();
+    let normal_in = direction_in.perp();
+    let normal_out = direction_out.perp();
+    let turn = direction_in.perp_dot(direction_out);
+
+    if turn.abs() < 1e-4 && direction_in.dot(direction_out) > 0.0 {
+        pairs.push(StrokePair::new(current + normal_in * half_width, current - normal_in * half_width, normal_in));
+        return;
+    }
+
+    let miter = (normal_in + normal_out).try_normalize().unwrap_or(direction_in);
+    let miter_scale = 1.0 / miter.dot(normal_in).max(1.0 / MITER_LIMIT);
+
+    // Turning left the inside is on the left.
+    let outer_side = if turn > 0.0 { -1.0 } else { 1.0 };
+    let inner = current - miter * half_width * miter_scale * outer_side;
+
+    let outer_points = match join {
+        LineJoin::Miter if 1.0 / miter.dot(normal_in).max(1e-6) <= MITER_LIMIT => {
+            vec![current + miter * half_width * miter_scale * outer_side]
+        },
+        LineJoin::Round => {
+            let start = normal_in * outer_side;
+            let sweep = start.angle_between(normal_out * outer_side);
+            let segments = (sweep.abs() / ROUND_STEP).ceil().max(1.0) as usize;
+
+            (0..=segments).map(|i| {
+                current + Vec2::from_angle(sweep * i as f32 / segments as f32).rotate(start) * half_width
+            }).collect()
+        },
+        _ => vec![current + normal_in * half_width * outer_side, current + normal_out * half_width * outer_side],
+    };
+
+    for outer in outer_points {
+        let pair = match outer_side > 0.0 {
+            true => StrokePair::new(outer, inner, miter),
+            false => StrokePair::new(inner, outer, miter),
+        };
+
+        pairs.push(pair);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Total area of the tessellated triangles, vertices are 4 floats each.
+    fn get_area(vertices: &[f32]) -> f32 {
+        vertices.chunks(12).map(|triangle| {
+            let a = Vec2::new(triangle[0], triangle[1]);
+            let b = Vec2::new(triangle[4], triangle[5]);
+            let c = Vec2::new(triangle[8], triangle[9]);
+
+            (b - a).perp_dot(c - a).abs() / 2.0
+        }).sum()
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn closed_contours_turn_counterclockwise() {
+        let clockwise = vec![Vec2::new(0.0, 0.0), Vec2::new(0.0, 1.0), Vec2::new(1.0, 1.0), Vec2::new(1.0, 0.0)];
+        let (points, closed) = ShapePath::Polygon(clockwise).get_contour();
+
+        assert!(closed);
+        assert!(get_signed_area(&points) > 0.0);
+    }
+
+    #[test]
+    fn contours_drop_repeated_points() {
+        let (points, _) = ShapePath::Polygon(vec![
+            Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(1.0, 1.0), Vec2::new(0.0, 0.0),
+        ]).get_contour();
+
+        assert_eq!(points, vec![Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(1.0, 1.0)]);
+    }
+
+    #[test]
+    fn polylines_stay_open_and_in_order() {
+        let line = vec![Vec2::new(1.0, 0.0), Vec2::new(0.0, 0.0), Vec2::new(0.0, 1.0)];
+        let (points, closed) = ShapePath::Polyline(line.clone()).get_contour();
+
+        assert!(!closed);
+        assert_eq!(points, line);
+    }
+
+    #[test]
+    fn rect_contours() {
+        let (square, _) = ShapePath::Rect { size: Vec2::new(2.0, 4.0), corner_radius: 0.0 }.get_contour();
+        assert_eq!(square.len(), 4);
+        assert_close(get_signed_area(&square), 8.0);
+
+        let (rounded, _) = ShapePath::Rect { size: Vec2::new(2.0, 4.0), corner_radius: 0.5 }.get_contour();
+        assert_eq!(rounded.len(), (CURVE_SEGMENTS / 4 + 1) * 4);
+
+        // The radius is clamped to half the shorter side, where the top and bottom corners meet.
+        let (capsule, _) = ShapePath::Rect { size: Vec2::new(2.0, 4.0), corner_radius: 5.0 }.get_contour();
+        assert_eq!(capsule.len(), (CURVE_SEGMENTS / 4 + 1) * 4 - 2);
+        assert!(capsule.iter().all(|point| point.x.abs() <= 1.0 + 1e-5 && point.y.abs() <= 2.0 + 1e-5));
+    }
+
+    #[test]
+    fn fill_covers_the_contour() {
+        let (contour, _) = ShapePath::Rect { size: Vec2::new(2.0, 3.0), corner_radius: 0.0 }.get_contour();
+        let mut tessellator = ShapeTessellator::new(0.0);
+        tessellator.fill(&contour);
+
+        // Two triangles inside and a quad per edge for the fringe.
+        assert_eq!(tessellator.vertices.len(), (2 * 3 + 4 * 6) * 4);
+        assert_close(get_area(&tessellator.vertices), 6.0);
+        assert!(tessellator.vertices.chunks(4).all(|vertex| vertex[3] == 0.0));
+    }
+
+    #[test]
+    fn fill_handles_concave_contours() {
+        let l_shape = vec![
+            Vec2::new(0.0, 0.0), Vec2::new(2.0, 0.0), Vec2::new(2.0, 1.0),
+            Vec2::new(1.0, 1.0), Vec2::new(1.0, 2.0), Vec2::new(0.0, 2.0),
+        ];
+
+        let mut tessellator = ShapeTessellator::new(0.0);
+        tessellator.fill(&l_shape);
+
+        assert_eq!(triangulate(&l_shape).len(), 4);
+        assert_close(get_area(&tessellator.vertices), 3.0);
+    }
+
+    #[test]
+    fn fill_fringe_straddles_the_edge() {
+        let (contour, _) = ShapePath::Rect { size: Vec2::new(2.0, 2.0), corner_radius: 0.0 }.get_contour();
+        let mut tessellator = ShapeTessellator::new(0.5);
+        tessellator.fill(&contour);
+
+        for vertex in tessellator.vertices.chunks(4) {
+            let inside = vertex[2] > 0.0;
+            let extent = vertex[0].abs().max(vertex[1].abs());
+
+            assert_close(extent, if inside { 0.75 } else { 1.25 });
+        }
+    }
+
+    #[test]
+    fn fill_skips_degenerate_contours() {
+        let mut tessellator = ShapeTessellator::new(1.0);
+        tessellator.fill(&[Vec2::ZERO, Vec2::ONE]);
+
+        assert!(tessellator.vertices.is_empty());
+    }
+
+    #[test]
+    fn stroke_caps() {
+        let line = [Vec2::new(0.0, 0.0), Vec2::new(4.0, 0.0)];
+
+        let mut butt = ShapeTessellator::new(0.0);
+        butt.stroke(&line, false, 1.0, LineJoin::Miter, LineCap::Butt);
+        assert_close(get_area(&butt.vertices), 4.0);
+        assert!(butt.vertices.chunks(4).all(|vertex| vertex[3] == 1.0));
+
+        let mut square = ShapeTessellator::new(0.0);
+        square.stroke(&line, false, 1.0, LineJoin::Miter, LineCap::Square);
+        assert_close(get_area(&square.vertices), 5.0);
+
+        let mut round = ShapeTessellator::new(0.0);
+        round.stroke(&line, false, 1.0, LineJoin::Miter, LineCap::Round);
+        let area = get_area(&round.vertices);
+        assert!(area > 4.0 && area < 4.0 + PI * 0.25, "{}", area);
+    }
+
+    #[test]
+    fn stroke_joins() {
+        let corner = [Vec2::new(0.0, 0.0), Vec2::new(2.0, 0.0), Vec2::new(2.0, 2.0)];
+        let mut areas = Vec::new();
+
+        for join in [LineJoin::Bevel, LineJoin::Round, LineJoin::Miter] {
+            let mut tessellator = ShapeTessellator::new(0.0);
+            tessellator.stroke(&corner, false, 1.0, join, LineCap::Butt);
+            areas.push(get_area(&tessellator.vertices));
+        }
+
+        // Both legs are 2 long, overlapping in a half by half square on the inside of the corner. Bevel adds a
+        // triangle on the outside, round a quarter circle and miter the full square.
+        assert_close(areas[0], 4.0 - 0.25 + 0.125);
+        assert!(areas[1] > areas[0] && areas[1] < areas[2]);
+        assert_close(areas[2], 4.0);
+    }
+
+    #[test]
+    fn closed_strokes_wrap_around() {
+        let (contour, closed) = ShapePath::Rect { size: Vec2::new(2.0, 2.0), corner_radius: 0.0 }.get_contour();
+        let mut tessellator = ShapeTessellator::new(0.0);
+        tessellator.stroke(&contour, closed, 0.5, LineJoin::Miter, LineCap::Butt);
+
+        // A 2.5 wide square with a 1.5 wide hole.
+        assert_close(get_area(&tessellator.vertices), 2.5 * 2.5 - 1.5 * 1.5);
+    }
+
+    #[test]
+    fn stroke_skips_single_points() {
+        let mut tessellator = ShapeTessellator::new(1.0);
+        tessellator.stroke(&[Vec2::ZERO], false, 1.0, LineJoin::Miter, LineCap::Round);
+
+        assert!(tessellator.vertices.is_empty());
+    }
+}
@@ -1,9 +1,237 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+use std::f32::consts::PI;
+
 use gl::types::*;
-use glam::IVec2;
-use image::{imageops::flip_vertical, io::Reader, RgbaImage};
+use glam::{IVec2, UVec2, Vec3, Vec4};
+use image::{imageops::flip_vertical, io::Reader, ImageError, Rgba, RgbaImage};
+use rayon::prelude::*;
 
 use crate::graphics::color::ColorBuffer;
 
+// Core in GL 4.6 and the same values as EXT_texture_filter_anisotropic, which every 4.5 driver has.
+const TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FE;
+const MAX_TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FF;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextureFilter {
+    Nearest,
+    Linear,
+}
+
+impl TextureFilter {
+    pub fn to_gl(&self) -> GLenum {
+        match self {
+            TextureFilter::Nearest => gl::NEAREST,
+            TextureFilter::Linear => gl::LINEAR,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextureWrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder,
+}
+
+impl TextureWrap {
+    pub fn to_gl(&self) -> GLenum {
+        match self {
+            TextureWrap::Repeat => gl::REPEAT,
+            TextureWrap::MirroredRepeat => gl::MIRRORED_REPEAT,
+            TextureWrap::ClampToEdge => gl::CLAMP_TO_EDGE,
+            TextureWrap::ClampToBorder => gl::CLAMP_TO_BORDER,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextureFormat {
+    Rgba8,
+    // Color textures authored in sRGB, so lighting and blending happen in linear space.
+    Srgba8,
+    R8,
+    R16F,
+    R32F,
+    Rgba16F,
+    Rgba32F,
+    // Depth attachments for render targets and shadow maps.
+    Depth24,
+    Depth32F,
+}
+
+impl TextureFormat {
+    pub fn internal_format(&self) -> GLenum {
+        match self {
+            TextureFormat::Rgba8 => gl::RGBA8,
+            TextureFormat::Srgba8 => gl::SRGB8_ALPHA8,
+            TextureFormat::R8 => gl::R8,
+            TextureFormat::R16F => gl::R16F,
+            TextureFormat::R32F => gl::R32F,
+            TextureFormat::Rgba16F => gl::RGBA16F,
+            TextureFormat::Rgba32F => gl::RGBA32F,
+            TextureFormat::Depth24 => gl::DEPTH_COMPONENT24,
+            TextureFormat::Depth32F => gl::DEPTH_COMPONENT32F,
+        }
+    }
+
+    pub fn pixel_format(&self) -> GLenum {
+        match (self.is_depth(), self.channels()) {
+            (true, _) => gl::DEPTH_COMPONENT,
+            (false, 1) => gl::RED,
+            (false, _) => gl::RGBA,
+        }
+    }
+
+    pub fn pixel_type(&self) -> GLenum {
+        match (self, self.is_float()) {
+            (TextureFormat::Depth24, _) => gl::UNSIGNED_INT,
+            (_, true) => gl::FLOAT,
+            (_, false) => gl::UNSIGNED_BYTE,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        match self {
+            TextureFormat::R8 | TextureFormat::R16F | TextureFormat::R32F => 1,
+            TextureFormat::Depth24 | TextureFormat::Depth32F => 1,
+            _ => 4,
+        }
+    }
+
+    // Size of one channel in the data given to GL, see pixel_type.
+    pub fn bytes_per_channel(&self) -> usize {
+        match (self, self.is_float()) {
+            (TextureFormat::Depth24, _) | (_, true) => 4,
+            (_, false) => 1,
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, TextureFormat::R16F | TextureFormat::R32F | TextureFormat::Rgba16F | TextureFormat::Rgba32F | TextureFormat::Depth32F)
+    }
+
+    pub fn is_depth(&self) -> bool {
+        matches!(self, TextureFormat::Depth24 | TextureFormat::Depth32F)
+    }
+}
+
+// How a texture is stored and sampled. The defaults suit ordinary sprites and models: linear filtering with
+// mipmaps, clamped edges and 8 bit RGBA.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextureDescriptor {
+    pub min_filter: TextureFilter,
+    pub mag_filter: TextureFilter,
+    // None turns mipmaps off.
+    pub mipmap_filter: Option<TextureFilter>,
+    pub wrap_s: TextureWrap,
+    pub wrap_t: TextureWrap,
+    // 1.0 is off, higher values are clamped to what the driver supports.
+    pub anisotropy: f32,
+    pub format: TextureFormat,
+}
+
+impl Default for TextureDescriptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextureDescriptor {
+    // The GL minification filter, which is where the mipmap filter goes too.
+    pub fn get_min_filter(&self) -> GLenum {
+        match (self.min_filter, self.mipmap_filter) {
+            (TextureFilter::Nearest, None) => gl::NEAREST,
+            (TextureFilter::Linear, None) => gl::LINEAR,
+            (TextureFilter::Nearest, Some(TextureFilter::Nearest)) => gl::NEAREST_MIPMAP_NEAREST,
+            (TextureFilter::Nearest, Some(TextureFilter::Linear)) => gl::NEAREST_MIPMAP_LINEAR,
+            (TextureFilter::Linear, Some(TextureFilter::Nearest)) => gl::LINEAR_MIPMAP_NEAREST,
+            (TextureFilter::Linear, Some(TextureFilter::Linear)) => gl::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+
+    pub fn new() -> Self {
+        TextureDescriptor {
+            min_filter: TextureFilter::Linear,
+            mag_filter: TextureFilter::Linear,
+            mipmap_filter: Some(TextureFilter::Linear),
+            wrap_s: TextureWrap::ClampToEdge,
+            wrap_t: TextureWrap::ClampToEdge,
+            anisotropy: 1.0,
+            format: TextureFormat::Rgba8,
+        }
+    }
+
+    // Sharp pixels at any scale.
+    pub fn pixel_art() -> Self {
+        Self::new()
+            .with_filter(TextureFilter::Nearest)
+            .without_mipmaps()
+    }
+
+    // Drawn at its own size, so mipmaps are wasted memory.
+    pub fn ui() -> Self {
+        Self::new().without_mipmaps()
+    }
+
+    pub fn with_filter(mut self, filter: TextureFilter) -> Self {
+        self.min_filter = filter;
+        self.mag_filter = filter;
+        self
+    }
+
+    pub fn with_min_filter(mut self, filter: TextureFilter) -> Self {
+        self.min_filter = filter;
+        self
+    }
+
+    pub fn with_mag_filter(mut self, filter: TextureFilter) -> Self {
+        self.mag_filter = filter;
+        self
+    }
+
+    pub fn with_mipmaps(mut self, filter: TextureFilter) -> Self {
+        self.mipmap_filter = Some(filter);
+        self
+    }
+
+    pub fn without_mipmaps(mut self) -> Self {
+        self.mipmap_filter = None;
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: TextureWrap) -> Self {
+        self.wrap_s = wrap;
+        self.wrap_t = wrap;
+        self
+    }
+
+    pub fn with_wrap_s(mut self, wrap: TextureWrap) -> Self {
+        self.wrap_s = wrap;
+        self
+    }
+
+    pub fn with_wrap_t(mut self, wrap: TextureWrap) -> Self {
+        self.wrap_t = wrap;
+        self
+    }
+
+    pub fn with_anisotropy(mut self, anisotropy: f32) -> Self {
+        self.anisotropy = anisotropy;
+        self
+    }
+
+    pub fn with_format(mut self, format: TextureFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Texture {
     pub texture_id: GLuint,
@@ -11,6 +239,41 @@ pub struct Texture {
 
 impl Texture {
     pub fn from_vec(size: &IVec2, image_data: &Vec<u8>) -> Self {
+        Self::from_vec_with(size, image_data, &TextureDescriptor::new())
+    }
+
+    // image_data has to match the descriptor's format, one byte per channel.
+    pub fn from_vec_with(size: &IVec2, image_data: &Vec<u8>, descriptor: &TextureDescriptor) -> Self {
+        assert!(!descriptor.format.is_float(), "float texture formats take f32 data, use from_f32_vec");
+
+        // GL reads as much as the size and format say, whatever the length of the data.
+        let expected = Self::get_value_count(size, descriptor) * descriptor.format.bytes_per_channel();
+        assert_eq!(image_data.len(), expected, "{}x{} {:?} texture data has to be {} bytes", size.x, size.y, descriptor.format, expected);
+
+        Self::upload(size, image_data.as_ptr() as *const std::ffi::c_void, descriptor)
+    }
+
+    // For Rgba16F, Rgba32F, R16F and R32F textures, one f32 per channel.
+    pub fn from_f32_vec(size: &IVec2, data: &[f32], descriptor: &TextureDescriptor) -> Self {
+        assert!(descriptor.format.is_float(), "byte texture formats take u8 data, use from_vec_with");
+
+        let expected = Self::get_value_count(size, descriptor);
+        assert_eq!(data.len(), expected, "{}x{} {:?} texture data has to be {} floats", size.x, size.y, descriptor.format, expected);
+
+        Self::upload(size, data.as_ptr() as *const std::ffi::c_void, descriptor)
+    }
+
+    // Channels in the whole texture.
+    fn get_value_count(size: &IVec2, descriptor: &TextureDescriptor) -> usize {
+        size.x.max(0) as usize * size.y.max(0) as usize * descriptor.format.channels()
+    }
+
+    // Uninitialized storage, for render targets and anything else the GPU writes into.
+    pub fn empty(size: &IVec2, descriptor: &TextureDescriptor) -> Self {
+        Self::upload(size, std::ptr::null(), descriptor)
+    }
+
+    fn upload(size: &IVec2, data: *const std::ffi::c_void, descriptor: &TextureDescriptor) -> Self {
         let mut texture: GLuint = 0;
 
         unsafe {
@@ -18,36 +281,82 @@ impl Texture {
             gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_2D, texture);
 
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as i32);
-	        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as i32);
-	        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
-	        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
-
-	        gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA as i32, size.x, size.y, 0, gl::RGBA, gl::UNSIGNED_BYTE, image_data.as_ptr() as *const std::ffi::c_void);
-	        gl::GenerateMipmap(gl::TEXTURE_2D);
+            // Single channel rows aren't 4 byte aligned.
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexImage2D(
+                gl::TEXTURE_2D, 0, descriptor.format.internal_format() as i32, size.x, size.y, 0,
+                descriptor.format.pixel_format(), descriptor.format.pixel_type(), data,
+            );
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
 
-	        gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
         }
 
-        Texture {
+        let texture = Texture {
             texture_id: texture,
+        };
+
+        texture.apply_descriptor(descriptor);
+
+        texture
+    }
+
+    // Sets filtering, wrapping and anisotropy, and generates mipmaps if the descriptor uses them. The format only
+    // matters when the texture is created.
+    pub fn apply_descriptor(&self, descriptor: &TextureDescriptor) {
+        let min_filter = descriptor.get_min_filter();
+
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture_id);
+
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, descriptor.wrap_s.to_gl() as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, descriptor.wrap_t.to_gl() as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, descriptor.mag_filter.to_gl() as i32);
+
+            if descriptor.anisotropy > 1.0 {
+                let mut max_anisotropy: GLfloat = 1.0;
+                gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max_anisotropy);
+
+                gl::TexParameterf(gl::TEXTURE_2D, TEXTURE_MAX_ANISOTROPY, descriptor.anisotropy.min(max_anisotropy));
+            }
+
+            if descriptor.mipmap_filter.is_some() {
+                gl::GenerateMipmap(gl::TEXTURE_2D);
+            }
+
+            gl::BindTexture(gl::TEXTURE_2D, 0);
         }
     }
 
     pub fn from_color_buffer(color_buffer: &ColorBuffer) -> Texture {
-        Texture::from_vec(&IVec2::new(color_buffer.width as i32, color_buffer.height as i32), &color_buffer.to_byte_vec())
+        Texture::from_color_buffer_with(color_buffer, &TextureDescriptor::new())
+    }
+
+    pub fn from_color_buffer_with(color_buffer: &ColorBuffer, descriptor: &TextureDescriptor) -> Texture {
+        Texture::from_vec_with(&IVec2::new(color_buffer.width as i32, color_buffer.height as i32), &color_buffer.to_byte_vec(), descriptor)
     }
 
     pub fn from_image(image: &RgbaImage) -> Self {
+        Self::from_image_with(image, &TextureDescriptor::new())
+    }
+
+    // Only Rgba8 and Srgba8 make sense here, images are always decoded to RGBA.
+    pub fn from_image_with(image: &RgbaImage, descriptor: &TextureDescriptor) -> Self {
         let flipped_image = flip_vertical(image);
         let data_array = flipped_image.as_raw();
         let size = IVec2::new(image.width() as i32, image.height() as i32);
         
-        Self::from_vec(&size, data_array)
+        Self::from_vec_with(&size, data_array, descriptor)
     }
 
     pub fn from_file(file: &str) -> Self {
-        Self::from_image(&Reader::open(file).unwrap().decode().unwrap().into_rgba8())
+        Self::from_file_with(file, &TextureDescriptor::new())
+    }
+
+    pub fn from_file_with(file: &str, descriptor: &TextureDescriptor) -> Self {
+        Self::from_image_with(&Reader::open(file).unwrap().decode().unwrap().into_rgba8(), descriptor)
     }
 
     pub fn bind(&self, unit_slot: u32, should_bind: bool) {
@@ -93,14 +402,18 @@ impl Texture {
         height as u32
     }
 
-    pub fn get_sub_texture(&self, position: &IVec2, size: &IVec2) -> Self {
-        let binding = Vec::<u8>::new();
+    pub fn get_size(&self) -> UVec2 {
+        UVec2::new(self.get_width(), self.get_height())
+    }
 
-        unsafe {
-            gl::TexSubImage2D(self.texture_id, 0, position.x, position.y, size.x, size.y, gl::RGBA, gl::UNSIGNED_BYTE, binding.as_ptr() as *const std::ffi::c_void);
-        }
+    // position is the top left corner in image pixels, like TextureRegion::from_pixels.
+    pub fn get_region(&self, position: &UVec2, size: &UVec2) -> TextureRegion {
+        TextureRegion::from_pixels(self, &self.get_size(), position, size)
+    }
 
-        Texture::from_vec(&size, &binding)
+    // Copies part of the texture into a new one, which has to be deleted separately.
+    pub fn get_sub_texture(&self, position: &IVec2, size: &IVec2) -> Self {
+        self.get_region(&position.as_uvec2(), &size.as_uvec2()).to_texture()
     }
 
     pub fn get_color_buffer(&self) -> ColorBuffer {
@@ -129,4 +442,346 @@ impl Texture {
         color_buffer.clone()
     }
 
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone)]
+pub struct CubemapError {
+    pub error_log: String,
+}
+
+impl fmt::Display for CubemapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error_log.as_str())
+    }
+}
+
+impl Error for CubemapError {}
+
+impl CubemapError {
+    pub fn new(string: String) -> Self {
+        CubemapError {
+            error_log: string,
+        }
+    }
+}
+
+// Six square faces sampled by direction, for skyboxes. Faces go in GL order: +X, -X, +Y, -Y, +Z, -Z, which with the
+// engine's +Z forward are right, left, top, bottom, front and back.
+#[derive(Clone, Copy)]
+pub struct Cubemap {
+    pub texture_id: GLuint,
+    pub size: u32,
+}
+
+impl Cubemap {
+    pub fn from_images(faces: &[RgbaImage; 6]) -> Result<Self, CubemapError> {
+        Self::from_images_with(faces, &TextureDescriptor::new())
+    }
+
+    // Unlike Texture the faces aren't flipped, cubemaps want their rows top to bottom. Wrapping is always
+    // ClampToEdge, and Window turns on seamless filtering, so the seams between faces don't show.
+    pub fn from_images_with(faces: &[RgbaImage; 6], descriptor: &TextureDescriptor) -> Result<Self, CubemapError> {
+        let size = faces[0].width();
+
+        if size == 0 {
+            return Err(CubemapError::new("cubemap faces can't be empty".to_string()));
+        }
+
+        for face in faces {
+            if face.width() != size || face.height() != size {
+                return Err(CubemapError::new(format!(
+                    "cubemap faces have to be squares of the same size, got {}x{} and {size}x{size}", face.width(), face.height(),
+                )));
+            }
+        }
+
+        let mut texture_id: GLuint = 0;
+
+        unsafe {
+            gl::GenTextures(1, &mut texture_id);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, texture_id);
+
+            for (i, face) in faces.iter().enumerate() {
+                gl::TexImage2D(
+                    gl::TEXTURE_CUBE_MAP_POSITIVE_X + i as u32, 0, descriptor.format.internal_format() as i32,
+                    size as i32, size as i32, 0, gl::RGBA, gl::UNSIGNED_BYTE, face.as_ptr() as *const std::ffi::c_void,
+                );
+            }
+
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, descriptor.get_min_filter() as i32);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, descriptor.mag_filter.to_gl() as i32);
+
+            if descriptor.mipmap_filter.is_some() {
+                gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+            }
+
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        }
+
+        Ok(Cubemap {
+            texture_id,
+            size,
+        })
+    }
+
+    pub fn from_files(files: &[&str; 6]) -> Result<Self, CubemapError> {
+        Self::from_files_with(files, &TextureDescriptor::new())
+    }
+
+    pub fn from_files_with(files: &[&str; 6], descriptor: &TextureDescriptor) -> Result<Self, CubemapError> {
+        let mut faces = Vec::with_capacity(6);
+
+        for file in files {
+            faces.push(Self::load_image(file)?);
+        }
+
+        Self::from_images_with(&faces.try_into().unwrap(), descriptor)
+    }
+
+    // Projects a 360 degree panorama, 2:1 with the horizon across the middle, onto faces of face_size pixels. The
+    // middle of the image ends up in front, at +Z.
+    pub fn from_equirectangular(image: &RgbaImage, face_size: u32) -> Result<Self, CubemapError> {
+        Self::from_equirectangular_with(image, face_size, &TextureDescriptor::new())
+    }
+
+    pub fn from_equirectangular_with(image: &RgbaImage, face_size: u32, descriptor: &TextureDescriptor) -> Result<Self, CubemapError> {
+        if image.width() == 0 || image.height() == 0 {
+            return Err(CubemapError::new("equirectangular image can't be empty".to_string()));
+        }
+
+        let faces: Vec<RgbaImage> = (0..6).into_par_iter().map(|face| {
+            RgbaImage::from_fn(face_size, face_size, |x, y| {
+                let s = (x as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                let t = (y as f32 + 0.5) / face_size as f32 * 2.0 - 1.0;
+                let direction = Self::get_face_direction(face, s, t).normalize();
+
+                let u = 0.5 + direction.x.atan2(direction.z) / (2.0 * PI);
+                let v = 0.5 - direction.y.clamp(-1.0, 1.0).asin() / PI;
+
+                Self::sample_bilinear(image, u, v)
+            })
+        }).collect();
+
+        Self::from_images_with(&faces.try_into().unwrap(), descriptor)
+    }
+
+    pub fn from_equirectangular_file(file: &str, face_size: u32) -> Result<Self, CubemapError> {
+        Self::from_equirectangular(&Self::load_image(file)?, face_size)
+    }
+
+    fn load_image(file: &str) -> Result<RgbaImage, CubemapError> {
+        let image = Reader::open(file).map_err(ImageError::from).and_then(|reader| reader.decode())
+            .map_err(|error| CubemapError::new(format!("{file}: {error}")))?;
+
+        Ok(image.into_rgba8())
+    }
+
+    // Where a face's texel points, from the table in the GL spec. s and t go from -1 to 1 across the face.
+    fn get_face_direction(face: usize, s: f32, t: f32) -> Vec3 {
+        match face {
+            0 => Vec3::new(1.0, -t, -s),
+            1 => Vec3::new(-1.0, -t, s),
+            2 => Vec3::new(s, 1.0, t),
+            3 => Vec3::new(s, -1.0, -t),
+            4 => Vec3::new(s, -t, 1.0),
+            _ => Vec3::new(-s, -t, -1.0),
+        }
+    }
+
+    // u wraps around, v is clamped at the poles. v is 0 at the top of the image.
+    fn sample_bilinear(image: &RgbaImage, u: f32, v: f32) -> Rgba<u8> {
+        let width = image.width() as i64;
+        let height = image.height() as i64;
+
+        let x = u * width as f32 - 0.5;
+        let y = v * height as f32 - 0.5;
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+
+        let get = |x: i64, y: i64| {
+            let pixel = image.get_pixel(x.rem_euclid(width) as u32, y.clamp(0, height - 1) as u32);
+            Vec4::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32, pixel[3] as f32)
+        };
+
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = get(x0, y0).lerp(get(x0 + 1, y0), fx);
+        let bottom = get(x0, y0 + 1).lerp(get(x0 + 1, y0 + 1), fx);
+        let color = top.lerp(bottom, fy).round();
+
+        Rgba([color.x as u8, color.y as u8, color.z as u8, color.w as u8])
+    }
+
+    pub fn bind(&self, unit_slot: u32, should_bind: bool) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + unit_slot);
+
+            match should_bind {
+                true => gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.texture_id),
+                false => gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0),
+            }
+        }
+    }
+
+    pub fn delete(&self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture_id);
+        }
+    }
+}
+
+// A rectangle of a texture, for atlas entries and sprite sheet frames. uv_rect is (u_min, v_min, u_max, v_max) and
+// size is the rectangle's size in pixels.
+#[derive(Clone, Copy)]
+pub struct TextureRegion {
+    pub texture: Texture,
+    pub uv_rect: Vec4,
+    pub size: UVec2,
+}
+
+impl TextureRegion {
+    pub fn new(texture: &Texture, uv_rect: &Vec4, size: &UVec2) -> Self {
+        TextureRegion {
+            texture: *texture,
+            uv_rect: *uv_rect,
+            size: *size,
+        }
+    }
+
+    pub fn from_texture(texture: &Texture) -> Self {
+        Self::new(texture, &Vec4::new(0.0, 0.0, 1.0, 1.0), &texture.get_size())
+    }
+
+    // position is the top left corner in image pixels, the way image editors and atlas files count. Images are
+    // uploaded flipped by from_image, so v is measured from the bottom of the texture.
+    pub fn from_pixels(texture: &Texture, texture_size: &UVec2, position: &UVec2, size: &UVec2) -> Self {
+        let texture_size = texture_size.as_vec2();
+
+        let uv_rect = Vec4::new(
+            position.x as f32 / texture_size.x,
+            1.0 - (position.y + size.y) as f32 / texture_size.y,
+            (position.x + size.x) as f32 / texture_size.x,
+            1.0 - position.y as f32 / texture_size.y,
+        );
+
+        Self::new(texture, &uv_rect, size)
+    }
+
+    // Bottom left corner in texture pixels, the way GL and ColorBuffer count rows.
+    pub fn get_pixel_position(&self, texture_size: &UVec2) -> UVec2 {
+        UVec2::new(
+            (self.uv_rect.x * texture_size.x as f32).round() as u32,
+            (self.uv_rect.y * texture_size.y as f32).round() as u32,
+        )
+    }
+
+    // Reads the whole texture back and crops it, so keep this to load time.
+    pub fn to_color_buffer(&self) -> ColorBuffer {
+        let color_buffer = self.texture.get_color_buffer();
+        let position = self.get_pixel_position(&UVec2::new(color_buffer.width, color_buffer.height));
+
+        color_buffer.get_sub_buffer(position.x, position.y, self.size.x, self.size.y)
+    }
+
+    pub fn to_texture(&self) -> Texture {
+        Texture::from_color_buffer(&self.to_color_buffer())
+    }
+}
+
+// A texture with shared ownership, deleted when the last clone is dropped. TextureCache hands these out, renderables
+// given one keep it alive instead of deleting the texture themselves.
+#[derive(Clone)]
+pub struct SharedTexture {
+    pub texture: Texture,
+    owner: Rc<SharedTextureOwner>,
+}
+
+struct SharedTextureOwner {
+    texture_id: GLuint,
+}
+
+impl Drop for SharedTextureOwner {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.texture_id);
+        }
+    }
+}
+
+impl SharedTexture {
+    pub fn new(texture: Texture) -> Self {
+        SharedTexture {
+            texture,
+            owner: Rc::new(SharedTextureOwner {
+                texture_id: texture.texture_id,
+            }),
+        }
+    }
+
+    pub fn get_use_count(&self) -> usize {
+        Rc::strong_count(&self.owner)
+    }
+}
+
+// Keeps one copy of every texture loaded from disk so spawning the same thing many times doesn't re-upload it. Each
+// texture is deleted once it's out of the cache and nothing else holds a clone of it.
+pub struct TextureCache {
+    pub textures: HashMap<String, SharedTexture>,
+}
+
+impl Default for TextureCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        TextureCache {
+            textures: HashMap::new(),
+        }
+    }
+
+    pub fn load(&mut self, file: &str) -> SharedTexture {
+        self.load_with(file, &TextureDescriptor::new())
+    }
+
+    // The descriptor is only used the first time a file is loaded.
+    pub fn load_with(&mut self, file: &str, descriptor: &TextureDescriptor) -> SharedTexture {
+        self.textures.entry(file.to_string())
+            .or_insert_with(|| SharedTexture::new(Texture::from_file_with(file, descriptor)))
+            .clone()
+    }
+
+    // For files that may be missing or broken, like ones named by scripts and data files.
+    pub fn try_load(&mut self, file: &str) -> Result<SharedTexture, ImageError> {
+        if let Some(texture) = self.textures.get(file) {
+            return Ok(texture.clone());
+        }
+
+        let texture = SharedTexture::new(Texture::from_image(&image::open(file)?.into_rgba8()));
+        self.textures.insert(file.to_string(), texture.clone());
+
+        Ok(texture)
+    }
+
+    pub fn contains(&self, file: &str) -> bool {
+        self.textures.contains_key(file)
+    }
+
+    pub fn remove(&mut self, file: &str) -> Option<SharedTexture> {
+        self.textures.remove(file)
+    }
+
+    // Textures still in use are deleted when their last user drops them.
+    pub fn delete_all(&mut self) {
+        self.textures.clear();
+    }
+}
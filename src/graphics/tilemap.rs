@@ -0,0 +1,1596 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use base64::Engine;
+use gl::types::*;
+use glam::{IVec2, Mat4, UVec2, Vec2, Vec3};
+use roxmltree::{Document, Node};
+use serde_json::Value;
+
+use crate::util::entity::{Entity, EntityVariableArray};
+use crate::util::prefab::PrefabLibrary;
+use super::batch::MAX_BATCH_TEXTURES;
+use super::color::Color;
+use super::math::Deg;
+use super::renderable::Renderable;
+use super::shader::{ShaderBuilderTemplate, ShaderProgram};
+use super::texture::{Texture, TextureDescriptor, TextureRegion};
+use super::view::{GraphicsLayer, View};
+
+const TILEMAP_SHADER_VERSION: &str = "#version 450 core";
+
+// Floats per vertex: position, uv and the tileset's texture slot.
+const VERTEX_FLOATS: usize = 5;
+
+thread_local! {
+    // Compiled the first time a tilemap is drawn and shared by all of them.
+    static TILEMAP_SHADER_PROGRAM: RefCell<Option<ShaderProgram>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone)]
+pub struct TilemapError {
+    pub error_log: String,
+}
+
+impl fmt::Display for TilemapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error_log.as_str())
+    }
+}
+
+impl Error for TilemapError {}
+
+impl TilemapError {
+    pub fn new(string: String) -> Self {
+        TilemapError {
+            error_log: string,
+        }
+    }
+}
+
+// A custom property set in Tiled. Object references are their object id, files their path.
+#[derive(Clone)]
+pub enum MapProperty {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    String(String),
+    Color(Color),
+}
+
+impl MapProperty {
+    pub fn from_string(type_name: &str, value: &str) -> Self {
+        match type_name {
+            "bool" => MapProperty::Bool(value == "true"),
+            "int" | "object" => MapProperty::Int(value.parse().unwrap_or(0)),
+            "float" => MapProperty::Float(value.parse().unwrap_or(0.0)),
+            "color" => parse_color(value).map(MapProperty::Color).unwrap_or(MapProperty::String(String::from(value))),
+            _ => MapProperty::String(String::from(value)),
+        }
+    }
+
+    // Stored with the types prefab files use, so properties can override prefab defaults.
+    pub fn insert_into(&self, name: &str, variables: &mut EntityVariableArray) {
+        match self {
+            MapProperty::Bool(value) => variables.insert(name, *value),
+            MapProperty::Int(value) => variables.insert(name, *value),
+            MapProperty::Float(value) => variables.insert(name, *value),
+            MapProperty::String(value) => variables.insert(name, value.clone()),
+            MapProperty::Color(value) => variables.insert(name, *value),
+        }
+    }
+}
+
+// One cell of a tile layer. gid 0 is empty, otherwise it's the first_gid of a tileset plus the tile's index in it.
+// Flips are applied diagonal first, then horizontal and vertical, which is how Tiled stores rotated tiles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tile {
+    pub gid: u32,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    pub flip_diagonal: bool,
+}
+
+impl Tile {
+    const FLIP_HORIZONTAL: u32 = 0x80000000;
+    const FLIP_VERTICAL: u32 = 0x40000000;
+    const FLIP_DIAGONAL: u32 = 0x20000000;
+    // Hexagonal maps use the next bit for 120 degree rotations, it's dropped along with the flags.
+    const FLAGS: u32 = 0xF0000000;
+
+    pub fn new(gid: u32) -> Self {
+        Tile {
+            gid,
+            flip_horizontal: false,
+            flip_vertical: false,
+            flip_diagonal: false,
+        }
+    }
+
+    // A gid as Tiled writes it, with the flip flags in the top bits.
+    pub fn from_raw(raw: u32) -> Self {
+        Tile {
+            gid: raw & !Self::FLAGS,
+            flip_horizontal: raw & Self::FLIP_HORIZONTAL != 0,
+            flip_vertical: raw & Self::FLIP_VERTICAL != 0,
+            flip_diagonal: raw & Self::FLIP_DIAGONAL != 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gid == 0
+    }
+
+    // Where in the tile's image a corner of the cell samples from, both from the top left.
+    fn get_source_corner(&self, corner: Vec2) -> Vec2 {
+        let mut corner = corner;
+
+        if self.flip_vertical {
+            corner.y = 1.0 - corner.y;
+        }
+
+        if self.flip_horizontal {
+            corner.x = 1.0 - corner.x;
+        }
+
+        if self.flip_diagonal {
+            corner = Vec2::new(corner.y, corner.x);
+        }
+
+        corner
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TileAnimationFrame {
+    pub tile_id: u32,
+    // Seconds.
+    pub duration: f32,
+}
+
+// A grid of equally sized tiles cut out of one texture. Tile ids count left to right, top to bottom from 0.
+pub struct Tileset {
+    pub name: String,
+    pub first_gid: u32,
+    pub texture: Texture,
+    pub texture_size: UVec2,
+    pub tile_size: UVec2,
+    pub columns: u32,
+    pub tile_count: u32,
+    pub margin: u32,
+    pub spacing: u32,
+    // Moves every tile of the set when drawn, in world units.
+    pub offset: Vec2,
+    pub animations: HashMap<u32, Vec<TileAnimationFrame>>,
+}
+
+impl Tileset {
+    // Fails on a zero tile width or height.
+    pub fn new(name: &str, first_gid: u32, texture: &Texture, tile_size: &UVec2) -> Result<Self, TilemapError> {
+        check_tile_size(*tile_size)?;
+        let texture_size = texture.get_size();
+
+        Ok(Tileset {
+            name: String::from(name),
+            first_gid,
+            texture: *texture,
+            texture_size,
+            tile_size: *tile_size,
+            columns: texture_size.x / tile_size.x,
+            tile_count: (texture_size.x / tile_size.x) * (texture_size.y / tile_size.y),
+            margin: 0,
+            spacing: 0,
+            offset: Vec2::ZERO,
+            animations: HashMap::new(),
+        })
+    }
+
+    // margin is around the whole image, spacing between tiles, both in pixels.
+    pub fn with_spacing(mut self, margin: u32, spacing: u32) -> Self {
+        let columns = (self.texture_size.x.saturating_sub(margin * 2) + spacing) / (self.tile_size.x + spacing).max(1);
+        let rows = (self.texture_size.y.saturating_sub(margin * 2) + spacing) / (self.tile_size.y + spacing).max(1);
+
+        self.margin = margin;
+        self.spacing = spacing;
+        self.columns = columns;
+        self.tile_count = columns * rows;
+        self
+    }
+
+    pub fn with_animation(mut self, tile_id: u32, frames: &[TileAnimationFrame]) -> Self {
+        self.animations.insert(tile_id, frames.to_vec());
+        self
+    }
+
+    pub fn contains(&self, gid: u32) -> bool {
+        gid >= self.first_gid && gid < self.first_gid + self.tile_count
+    }
+
+    pub fn get_region(&self, tile_id: u32) -> TextureRegion {
+        let columns = self.columns.max(1);
+        let position = UVec2::new(
+            self.margin + (tile_id % columns) * (self.tile_size.x + self.spacing),
+            self.margin + (tile_id / columns) * (self.tile_size.y + self.spacing),
+        );
+
+        TextureRegion::from_pixels(&self.texture, &self.texture_size, &position, &self.tile_size)
+    }
+
+    // The tile an animated tile shows at time seconds, looping. Tiles without an animation show themselves.
+    pub fn get_animated_tile(&self, tile_id: u32, time: f32) -> u32 {
+        let frames = match self.animations.get(&tile_id) {
+            Some(frames) if !frames.is_empty() => frames,
+            _ => return tile_id,
+        };
+
+        let total: f32 = frames.iter().map(|frame| frame.duration).sum();
+
+        if total <= 0.0 {
+            return frames[0].tile_id;
+        }
+
+        let mut time = time.rem_euclid(total);
+
+        for frame in frames {
+            if time < frame.duration {
+                return frame.tile_id;
+            }
+
+            time -= frame.duration;
+        }
+
+        frames[frames.len() - 1].tile_id
+    }
+}
+
+// Tiles in rows from the top. origin is the top left tile's coordinates, which only infinite maps move away from 0.
+pub struct TileLayer {
+    pub name: String,
+    pub origin: IVec2,
+    pub size: UVec2,
+    pub tiles: Vec<Tile>,
+    pub visible: bool,
+    pub opacity: f32,
+    // World units, group offsets included.
+    pub offset: Vec2,
+    pub properties: HashMap<String, MapProperty>,
+}
+
+impl TileLayer {
+    pub fn new(name: &str, size: &UVec2) -> Self {
+        TileLayer {
+            name: String::from(name),
+            origin: IVec2::ZERO,
+            size: *size,
+            tiles: vec![Tile::default(); (size.x * size.y) as usize],
+            visible: true,
+            opacity: 1.0,
+            offset: Vec2::ZERO,
+            properties: HashMap::new(),
+        }
+    }
+
+    fn get_index(&self, x: i32, y: i32) -> Option<usize> {
+        let local = IVec2::new(x, y) - self.origin;
+
+        if local.x < 0 || local.y < 0 || local.x >= self.size.x as i32 || local.y >= self.size.y as i32 {
+            return None;
+        }
+
+        Some((local.y as u32 * self.size.x + local.x as u32) as usize)
+    }
+
+    // Empty outside the layer.
+    pub fn get(&self, x: i32, y: i32) -> Tile {
+        self.get_index(x, y).map(|index| self.tiles[index]).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectShape {
+    Rectangle,
+    Ellipse,
+    Point,
+    // Points are relative to the object's position.
+    Polygon(Vec<Vec2>),
+    Polyline(Vec<Vec2>),
+    Tile(Tile),
+}
+
+// Positions are in world units with y flipped from Tiled, so the map's top left corner is at 0 and rows go down.
+// position is the top left corner for rectangles and ellipses and the bottom left for tile objects, like in Tiled.
+// Rotation is counterclockwise in degrees around position.
+#[derive(Clone)]
+pub struct MapObject {
+    pub id: u32,
+    pub name: String,
+    // Tiled's class, called type before Tiled 1.9. Matched against prefab names by Tilemap::spawn_entities.
+    pub class: String,
+    pub position: Vec2,
+    pub size: Vec2,
+    pub rotation: f32,
+    pub shape: ObjectShape,
+    pub visible: bool,
+    pub properties: HashMap<String, MapProperty>,
+}
+
+impl MapObject {
+    pub fn get_center(&self) -> Vec2 {
+        let half_size = match self.shape {
+            ObjectShape::Tile(_) => Vec2::new(self.size.x, self.size.y) * 0.5,
+            _ => Vec2::new(self.size.x, -self.size.y) * 0.5,
+        };
+
+        self.position + Mat4::from_rotation_z(Deg(self.rotation).to_radians().as_float()).transform_vector3(half_size.extend(0.0)).truncate()
+    }
+
+    // The object's properties, plus its name when it has one.
+    pub fn get_variables(&self) -> EntityVariableArray {
+        let mut variables = EntityVariableArray::new();
+
+        if !self.name.is_empty() {
+            variables.insert("name", self.name.clone());
+        }
+
+        for (name, property) in &self.properties {
+            property.insert_into(name, &mut variables);
+        }
+
+        variables
+    }
+}
+
+pub struct ObjectLayer {
+    pub name: String,
+    pub objects: Vec<MapObject>,
+    pub visible: bool,
+    pub properties: HashMap<String, MapProperty>,
+}
+
+impl ObjectLayer {
+    pub fn get(&self, name: &str) -> Option<&MapObject> {
+        self.objects.iter().find(|object| object.name == name)
+    }
+}
+
+// Up to chunk_size x chunk_size tiles of one layer in one vertex buffer, built the first time they're drawn.
+struct TilemapChunk {
+    layer: usize,
+    coordinates: IVec2,
+    vertex_array_id: GLuint,
+    buffer_id: GLuint,
+    vertex_count: usize,
+    min: Vec2,
+    max: Vec2,
+    has_animations: bool,
+    dirty: bool,
+}
+
+// A 2D map of tile layers drawn from tilesets, plus the object layers placed on it. The map's top left corner is at
+// position and rows go down, one world unit per pixel at a scale of one like sprites. Layers are split into chunks
+// that are each drawn in one call, and chunks off screen are skipped with View2D. Call update every tick for
+// animated tiles.
+pub struct Tilemap {
+    pub size: UVec2,
+    pub tile_size: UVec2,
+    pub tilesets: Vec<Tileset>,
+    pub layers: Vec<TileLayer>,
+    pub object_layers: Vec<ObjectLayer>,
+    pub properties: HashMap<String, MapProperty>,
+    pub background_color: Option<Color>,
+    pub position: Vec3,
+    pub rotation: Vec3,
+    pub scale: Vec3,
+    pub chunk_size: u32,
+    pub time: f32,
+    chunks: RefCell<Vec<TilemapChunk>>,
+    chunks_built: Cell<bool>,
+}
+
+impl Tilemap {
+    pub fn new(size: &UVec2, tile_size: &UVec2) -> Self {
+        Tilemap {
+            size: *size,
+            tile_size: *tile_size,
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            object_layers: Vec::new(),
+            properties: HashMap::new(),
+            background_color: None,
+            position: Vec3::ZERO,
+            rotation: Vec3::ZERO,
+            scale: Vec3::ONE,
+            chunk_size: 16,
+            time: 0.0,
+            chunks: RefCell::new(Vec::new()),
+            chunks_built: Cell::new(false),
+        }
+    }
+
+    // Picks the format from the extension, .tmx for XML and .json or .tmj for JSON.
+    pub fn from_file(file: &str) -> Result<Self, TilemapError> {
+        match Path::new(file).extension().and_then(|extension| extension.to_str()) {
+            Some("tmx") => Self::from_tmx_file(file),
+            Some("json") | Some("tmj") => Self::from_json_file(file),
+            _ => Err(TilemapError::new(format!("{}: unknown map format", file))),
+        }
+    }
+
+    pub fn from_tmx_file(file: &str) -> Result<Self, TilemapError> {
+        let source = read_file(Path::new(file))?;
+        let directory = Path::new(file).parent().unwrap_or(Path::new(""));
+
+        Self::from_tmx_source_string(&source, directory).map_err(|error| TilemapError::new(format!("{}: {}", file, error)))
+    }
+
+    pub fn from_json_file(file: &str) -> Result<Self, TilemapError> {
+        let source = read_file(Path::new(file))?;
+        let directory = Path::new(file).parent().unwrap_or(Path::new(""));
+
+        Self::from_json_source_string(&source, directory).map_err(|error| TilemapError::new(format!("{}: {}", file, error)))
+    }
+
+    // Images and external tilesets are looked up relative to directory, the folder the map was saved in.
+    pub fn from_tmx_source_string(source: &str, directory: &Path) -> Result<Self, TilemapError> {
+        let document = Document::parse(source).map_err(|error| TilemapError::new(error.to_string()))?;
+        let map = document.root_element();
+
+        if !map.has_tag_name("map") {
+            return Err(TilemapError::new(String::from("root element isn't a map")));
+        }
+
+        check_orientation(map.attribute("orientation").unwrap_or("orthogonal"))?;
+
+        let mut tilemap = Tilemap::new(
+            &UVec2::new(required_attribute(map, "width")?, required_attribute(map, "height")?),
+            &check_tile_size(UVec2::new(required_attribute(map, "tilewidth")?, required_attribute(map, "tileheight")?))?,
+        );
+
+        tilemap.background_color = map.attribute("backgroundcolor").and_then(parse_color);
+        tilemap.properties = parse_tmx_properties(map);
+
+        for node in map.children().filter(|node| node.has_tag_name("tileset")) {
+            let first_gid = required_attribute(node, "firstgid")?;
+
+            let tileset = match node.attribute("source") {
+                Some(source) => load_external_tileset(&directory.join(source), first_gid)?,
+                None => parse_tmx_tileset(node, first_gid, directory)?,
+            };
+
+            tilemap.add_tileset(tileset)?;
+        }
+
+        tilemap.parse_tmx_layers(map, Vec2::ZERO, 1.0, true)?;
+
+        Ok(tilemap)
+    }
+
+    pub fn from_json_source_string(source: &str, directory: &Path) -> Result<Self, TilemapError> {
+        let json: Value = serde_json::from_str(source).map_err(|error| TilemapError::new(error.to_string()))?;
+
+        check_orientation(json["orientation"].as_str().unwrap_or("orthogonal"))?;
+
+        let mut tilemap = Tilemap::new(
+            &UVec2::new(required_u32(&json, "width")?, required_u32(&json, "height")?),
+            &check_tile_size(UVec2::new(required_u32(&json, "tilewidth")?, required_u32(&json, "tileheight")?))?,
+        );
+
+        tilemap.background_color = json["backgroundcolor"].as_str().and_then(parse_color);
+        tilemap.properties = parse_json_properties(&json);
+
+        for tileset in json["tilesets"].as_array().into_iter().flatten() {
+            let first_gid = required_u32(tileset, "firstgid")?;
+
+            let tileset = match tileset["source"].as_str() {
+                Some(source) => load_external_tileset(&directory.join(source), first_gid)?,
+                None => parse_json_tileset(tileset, first_gid, directory)?,
+            };
+
+            tilemap.add_tileset(tileset)?;
+        }
+
+        tilemap.parse_json_layers(&json, Vec2::ZERO, 1.0, true)?;
+
+        Ok(tilemap)
+    }
+
+    // Every tileset is bound for every chunk, so a map can use as many as a sprite batch call can.
+    pub fn add_tileset(&mut self, tileset: Tileset) -> Result<(), TilemapError> {
+        if self.tilesets.len() >= MAX_BATCH_TEXTURES {
+            return Err(TilemapError::new(format!("maps can use at most {} tilesets", MAX_BATCH_TEXTURES)));
+        }
+
+        self.tilesets.push(tileset);
+
+        Ok(())
+    }
+
+    pub fn with_position(mut self, position: Vec3) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Vec3) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vec3) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    // Bigger chunks mean fewer draw calls but more tiles drawn off screen and rebuilt per animation frame.
+    pub fn with_chunk_size(mut self, chunk_size: u32) -> Self {
+        self.delete_chunks();
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    fn parse_tmx_layers(&mut self, parent: Node, offset: Vec2, opacity: f32, visible: bool) -> Result<(), TilemapError> {
+        for node in parent.children().filter(|node| node.is_element()) {
+            let name = node.attribute("name").unwrap_or("");
+            let layer_offset = offset + Vec2::new(attribute(node, "offsetx").unwrap_or(0.0), attribute(node, "offsety").unwrap_or(0.0));
+            let layer_opacity = opacity * attribute(node, "opacity").unwrap_or(1.0);
+            let layer_visible = visible && node.attribute("visible") != Some("0");
+
+            match node.tag_name().name() {
+                "layer" => {
+                    let data = node.children().find(|child| child.has_tag_name("data"))
+                        .ok_or_else(|| TilemapError::new(format!("layer '{}' has no data", name)))?;
+
+                    let encoding = data.attribute("encoding");
+                    let compression = data.attribute("compression");
+                    let mut chunks = Vec::new();
+
+                    // Infinite maps split the data into chunks, finite ones are one chunk the size of the layer.
+                    if data.children().any(|child| child.has_tag_name("chunk")) {
+                        for chunk in data.children().filter(|child| child.has_tag_name("chunk")) {
+                            chunks.push((
+                                IVec2::new(required_attribute(chunk, "x")?, required_attribute(chunk, "y")?),
+                                UVec2::new(required_attribute(chunk, "width")?, required_attribute(chunk, "height")?),
+                                decode_tmx_data(chunk, encoding, compression)?,
+                            ));
+                        }
+                    } else {
+                        chunks.push((
+                            IVec2::ZERO,
+                            UVec2::new(required_attribute(node, "width")?, required_attribute(node, "height")?),
+                            decode_tmx_data(data, encoding, compression)?,
+                        ));
+                    }
+
+                    let mut layer = assemble_tile_layer(name, &chunks)?;
+                    layer.visible = layer_visible;
+                    layer.opacity = layer_opacity;
+                    layer.offset = Vec2::new(layer_offset.x, -layer_offset.y);
+                    layer.properties = parse_tmx_properties(node);
+
+                    self.layers.push(layer);
+                },
+                "objectgroup" => {
+                    let mut objects = Vec::new();
+
+                    for object in node.children().filter(|child| child.has_tag_name("object")) {
+                        let shape = if let Some(gid) = attribute::<u32>(object, "gid") {
+                            ObjectShape::Tile(Tile::from_raw(gid))
+                        } else if object.children().any(|child| child.has_tag_name("ellipse")) {
+                            ObjectShape::Ellipse
+                        } else if object.children().any(|child| child.has_tag_name("point")) {
+                            ObjectShape::Point
+                        } else if let Some(polygon) = object.children().find(|child| child.has_tag_name("polygon")) {
+                            ObjectShape::Polygon(parse_tmx_points(polygon.attribute("points").unwrap_or(""))?)
+                        } else if let Some(polyline) = object.children().find(|child| child.has_tag_name("polyline")) {
+                            ObjectShape::Polyline(parse_tmx_points(polyline.attribute("points").unwrap_or(""))?)
+                        } else {
+                            ObjectShape::Rectangle
+                        };
+
+                        let position = Vec2::new(required_attribute(object, "x")?, required_attribute(object, "y")?) + layer_offset;
+
+                        objects.push(MapObject {
+                            id: attribute(object, "id").unwrap_or(0),
+                            name: String::from(object.attribute("name").unwrap_or("")),
+                            class: String::from(object.attribute("class").or(object.attribute("type")).unwrap_or("")),
+                            position: Vec2::new(position.x, -position.y),
+                            size: Vec2::new(attribute(object, "width").unwrap_or(0.0), attribute(object, "height").unwrap_or(0.0)),
+                            rotation: -attribute(object, "rotation").unwrap_or(0.0),
+                            shape,
+                            visible: object.attribute("visible") != Some("0"),
+                            properties: parse_tmx_properties(object),
+                        });
+                    }
+
+                    self.object_layers.push(ObjectLayer {
+                        name: String::from(name),
+                        objects,
+                        visible: layer_visible,
+                        properties: parse_tmx_properties(node),
+                    });
+                },
+                "group" => self.parse_tmx_layers(node, layer_offset, layer_opacity, layer_visible)?,
+                // Image layers aren't supported, draw them as sprites.
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_json_layers(&mut self, parent: &Value, offset: Vec2, opacity: f32, visible: bool) -> Result<(), TilemapError> {
+        for layer in parent["layers"].as_array().into_iter().flatten() {
+            let name = layer["name"].as_str().unwrap_or("");
+            let layer_offset = offset + Vec2::new(layer["offsetx"].as_f64().unwrap_or(0.0) as f32, layer["offsety"].as_f64().unwrap_or(0.0) as f32);
+            let layer_opacity = opacity * layer["opacity"].as_f64().unwrap_or(1.0) as f32;
+            let layer_visible = visible && layer["visible"].as_bool().unwrap_or(true);
+
+            match layer["type"].as_str().unwrap_or("") {
+                "tilelayer" => {
+                    let encoding = layer["encoding"].as_str();
+                    let compression = layer["compression"].as_str();
+                    let mut chunks = Vec::new();
+
+                    if let Some(layer_chunks) = layer["chunks"].as_array() {
+                        for chunk in layer_chunks {
+                            chunks.push((
+                                IVec2::new(chunk["x"].as_i64().unwrap_or(0) as i32, chunk["y"].as_i64().unwrap_or(0) as i32),
+                                UVec2::new(required_u32(chunk, "width")?, required_u32(chunk, "height")?),
+                                decode_json_data(&chunk["data"], encoding, compression)?,
+                            ));
+                        }
+                    } else {
+                        chunks.push((
+                            IVec2::ZERO,
+                            UVec2::new(required_u32(layer, "width")?, required_u32(layer, "height")?),
+                            decode_json_data(&layer["data"], encoding, compression)?,
+                        ));
+                    }
+
+                    let mut tile_layer = assemble_tile_layer(name, &chunks)?;
+                    tile_layer.visible = layer_visible;
+                    tile_layer.opacity = layer_opacity;
+                    tile_layer.offset = Vec2::new(layer_offset.x, -layer_offset.y);
+                    tile_layer.properties = parse_json_properties(layer);
+
+                    self.layers.push(tile_layer);
+                },
+                "objectgroup" => {
+                    let mut objects = Vec::new();
+
+                    for object in layer["objects"].as_array().into_iter().flatten() {
+                        let shape = if let Some(gid) = object["gid"].as_u64() {
+                            ObjectShape::Tile(Tile::from_raw(gid as u32))
+                        } else if object["ellipse"].as_bool().unwrap_or(false) {
+                            ObjectShape::Ellipse
+                        } else if object["point"].as_bool().unwrap_or(false) {
+                            ObjectShape::Point
+                        } else if let Some(polygon) = object["polygon"].as_array() {
+                            ObjectShape::Polygon(parse_json_points(polygon))
+                        } else if let Some(polyline) = object["polyline"].as_array() {
+                            ObjectShape::Polyline(parse_json_points(polyline))
+                        } else {
+                            ObjectShape::Rectangle
+                        };
+
+                        let position = Vec2::new(object["x"].as_f64().unwrap_or(0.0) as f32, object["y"].as_f64().unwrap_or(0.0) as f32) + layer_offset;
+
+                        objects.push(MapObject {
+                            id: object["id"].as_u64().unwrap_or(0) as u32,
+                            name: String::from(object["name"].as_str().unwrap_or("")),
+                            class: String::from(object["class"].as_str().or(object["type"].as_str()).unwrap_or("")),
+                            position: Vec2::new(position.x, -position.y),
+                            size: Vec2::new(object["width"].as_f64().unwrap_or(0.0) as f32, object["height"].as_f64().unwrap_or(0.0) as f32),
+                            rotation: -object["rotation"].as_f64().unwrap_or(0.0) as f32,
+                            shape,
+                            visible: object["visible"].as_bool().unwrap_or(true),
+                            properties: parse_json_properties(object),
+                        });
+                    }
+
+                    self.object_layers.push(ObjectLayer {
+                        name: String::from(name),
+                        objects,
+                        visible: layer_visible,
+                        properties: parse_json_properties(layer),
+                    });
+                },
+                "group" => self.parse_json_layers(layer, layer_offset, layer_opacity, layer_visible)?,
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_layer(&self, name: &str) -> Option<&TileLayer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    pub fn get_layer_index(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.name == name)
+    }
+
+    pub fn get_object_layer(&self, name: &str) -> Option<&ObjectLayer> {
+        self.object_layers.iter().find(|layer| layer.name == name)
+    }
+
+    // Objects of a class across all object layers, for example every "spawn" point.
+    pub fn get_objects(&self, class: &str) -> Vec<&MapObject> {
+        self.object_layers.iter().flat_map(|layer| layer.objects.iter()).filter(|object| object.class == class).collect()
+    }
+
+    pub fn get_tileset_index(&self, gid: u32) -> Option<usize> {
+        self.tilesets.iter().position(|tileset| tileset.contains(gid))
+    }
+
+    pub fn get_tile(&self, layer: usize, x: i32, y: i32) -> Tile {
+        self.layers[layer].get(x, y)
+    }
+
+    // Tiles outside the layer are ignored. Only the chunk holding the tile is rebuilt.
+    pub fn set_tile(&mut self, layer: usize, x: i32, y: i32, tile: Tile) {
+        let index = match self.layers[layer].get_index(x, y) {
+            Some(index) => index,
+            None => return,
+        };
+
+        self.layers[layer].tiles[index] = tile;
+
+        if !self.chunks_built.get() {
+            return;
+        }
+
+        let coordinates = IVec2::new(x.div_euclid(self.chunk_size as i32), y.div_euclid(self.chunk_size as i32));
+        let chunks = self.chunks.get_mut();
+
+        match chunks.iter_mut().find(|chunk| chunk.layer == layer && chunk.coordinates == coordinates) {
+            Some(chunk) => chunk.dirty = true,
+            None => {
+                chunks.push(TilemapChunk::new(layer, coordinates));
+                // Layers draw in order, so new chunks go behind the layers above theirs.
+                chunks.sort_by_key(|chunk| chunk.layer);
+            },
+        }
+    }
+
+    // The tile under a world position.
+    pub fn world_to_tile(&self, position: &Vec3) -> IVec2 {
+        let local = self.get_model_matrix().inverse().transform_point3(*position);
+
+        IVec2::new((local.x / self.tile_size.x as f32).floor() as i32, (-local.y / self.tile_size.y as f32).floor() as i32)
+    }
+
+    // The center of a tile in the world.
+    pub fn tile_to_world(&self, tile: &IVec2) -> Vec3 {
+        let local = Vec3::new(
+            (tile.x as f32 + 0.5) * self.tile_size.x as f32,
+            -(tile.y as f32 + 0.5) * self.tile_size.y as f32,
+            0.0,
+        );
+
+        self.get_model_matrix().transform_point3(local)
+    }
+
+    // Instantiates a prefab for every object whose class names one in the library, with the object's properties as
+    // overrides and its center in the world as "position". Objects of other classes are left alone.
+    pub fn spawn_entities(&self, library: &mut PrefabLibrary) -> Vec<Entity> {
+        let model_matrix = self.get_model_matrix();
+        let mut entities = Vec::new();
+
+        for object in self.object_layers.iter().flat_map(|layer| layer.objects.iter()) {
+            if !library.contains(&object.class) {
+                continue;
+            }
+
+            let mut variables = object.get_variables();
+            variables.insert("position", model_matrix.transform_point3(object.get_center().extend(0.0)));
+
+            entities.extend(library.instantiate_with(&object.class, variables));
+        }
+
+        entities
+    }
+
+    // Advances animated tiles. Chunks holding any animated tile are rebuilt when an animation changes frame.
+    pub fn update(&mut self, delta: &Duration) {
+        let previous_time = self.time;
+        self.time += delta.as_secs_f32();
+
+        let has_changed = self.tilesets.iter().any(|tileset| {
+            tileset.animations.keys().any(|tile_id| {
+                tileset.get_animated_tile(*tile_id, previous_time) != tileset.get_animated_tile(*tile_id, self.time)
+            })
+        });
+
+        if has_changed {
+            for chunk in self.chunks.get_mut().iter_mut().filter(|chunk| chunk.has_animations) {
+                chunk.dirty = true;
+            }
+        }
+    }
+
+    fn build_chunks(&self) {
+        let chunk_size = self.chunk_size as i32;
+        let mut chunks = self.chunks.borrow_mut();
+
+        for (layer_index, layer) in self.layers.iter().enumerate() {
+            let mut coordinates: BTreeSet<(i32, i32)> = BTreeSet::new();
+
+            for (index, tile) in layer.tiles.iter().enumerate() {
+                if !tile.is_empty() {
+                    let position = layer.origin + IVec2::new(index as i32 % layer.size.x as i32, index as i32 / layer.size.x as i32);
+                    coordinates.insert((position.y.div_euclid(chunk_size), position.x.div_euclid(chunk_size)));
+                }
+            }
+
+            for (y, x) in coordinates {
+                chunks.push(TilemapChunk::new(layer_index, IVec2::new(x, y)));
+            }
+        }
+
+        self.chunks_built.set(true);
+    }
+
+    fn rebuild_chunk(&self, chunk: &mut TilemapChunk) {
+        let layer = &self.layers[chunk.layer];
+        let tile_size = self.tile_size.as_vec2();
+        let start = chunk.coordinates * self.chunk_size as i32;
+
+        let mut vertices: Vec<f32> = Vec::new();
+        chunk.min = Vec2::splat(f32::MAX);
+        chunk.max = Vec2::splat(f32::MIN);
+        chunk.has_animations = false;
+
+        for y in start.y..start.y + self.chunk_size as i32 {
+            for x in start.x..start.x + self.chunk_size as i32 {
+                let tile = layer.get(x, y);
+
+                let tileset_index = match self.get_tileset_index(tile.gid) {
+                    Some(tileset_index) => tileset_index,
+                    None => continue,
+                };
+
+                let tileset = &self.tilesets[tileset_index];
+                let tile_id = tile.gid - tileset.first_gid;
+
+                if tileset.animations.contains_key(&tile_id) {
+                    chunk.has_animations = true;
+                }
+
+                let uv_rect = tileset.get_region(tileset.get_animated_tile(tile_id, self.time)).uv_rect;
+
+                // Tiles bigger than the grid stick out of the top right of their cell, like in Tiled.
+                let min = Vec2::new(x as f32 * tile_size.x, -(y + 1) as f32 * tile_size.y) + tileset.offset + layer.offset;
+                let max = min + tileset.tile_size.as_vec2();
+
+                chunk.min = chunk.min.min(min);
+                chunk.max = chunk.max.max(max);
+
+                // Counterclockwise from the bottom left, corner y going up.
+                for corner in [Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(1.0, 1.0), Vec2::new(1.0, 1.0), Vec2::new(0.0, 1.0), Vec2::new(0.0, 0.0)] {
+                    let position = min + (max - min) * corner;
+                    let source = tile.get_source_corner(Vec2::new(corner.x, 1.0 - corner.y));
+
+                    vertices.extend_from_slice(&[
+                        position.x,
+                        position.y,
+                        uv_rect.x + (uv_rect.z - uv_rect.x) * source.x,
+                        uv_rect.w + (uv_rect.y - uv_rect.w) * source.y,
+                        tileset_index as f32,
+                    ]);
+                }
+            }
+        }
+
+        unsafe {
+            if chunk.vertex_array_id == 0 {
+                gl::GenVertexArrays(1, &mut chunk.vertex_array_id);
+                gl::GenBuffers(1, &mut chunk.buffer_id);
+
+                gl::BindVertexArray(chunk.vertex_array_id);
+                gl::BindBuffer(gl::ARRAY_BUFFER, chunk.buffer_id);
+
+                let stride = (VERTEX_FLOATS * std::mem::size_of::<f32>()) as GLsizei;
+
+                gl::EnableVertexAttribArray(0);
+                gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, std::ptr::null());
+                gl::EnableVertexAttribArray(1);
+                gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, stride, (2 * std::mem::size_of::<f32>()) as *const std::ffi::c_void);
+
+                gl::BindVertexArray(0);
+            }
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, chunk.buffer_id);
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                std::mem::size_of_val(vertices.as_slice()) as GLsizeiptr,
+                vertices.as_ptr() as *const std::ffi::c_void,
+                if chunk.has_animations { gl::DYNAMIC_DRAW } else { gl::STATIC_DRAW },
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+
+        chunk.vertex_count = vertices.len() / VERTEX_FLOATS;
+        chunk.dirty = false;
+    }
+
+    // Whether any of a chunk's bounds are inside the clip space of mvp.
+    fn is_chunk_visible(chunk: &TilemapChunk, mvp: &Mat4) -> bool {
+        let corners = [
+            Vec3::new(chunk.min.x, chunk.min.y, 0.0),
+            Vec3::new(chunk.max.x, chunk.min.y, 0.0),
+            Vec3::new(chunk.max.x, chunk.max.y, 0.0),
+            Vec3::new(chunk.min.x, chunk.max.y, 0.0),
+        ];
+
+        let mut min = Vec2::splat(f32::MAX);
+        let mut max = Vec2::splat(f32::MIN);
+
+        for corner in corners {
+            let clip = mvp.project_point3(corner).truncate();
+            min = min.min(clip);
+            max = max.max(clip);
+        }
+
+        max.x >= -1.0 && min.x <= 1.0 && max.y >= -1.0 && min.y <= 1.0
+    }
+
+    fn get_shader_program() -> ShaderProgram {
+        TILEMAP_SHADER_PROGRAM.with(|shader_program| {
+            shader_program.borrow_mut().get_or_insert_with(|| {
+                let shader_program = ShaderProgram::from_builders(
+                    &ShaderBuilderTemplate::tilemap_vertex_shader(TILEMAP_SHADER_VERSION),
+                    &ShaderBuilderTemplate::tilemap_fragment_shader(TILEMAP_SHADER_VERSION, MAX_BATCH_TEXTURES),
+                ).unwrap();
+
+                shader_program.set_uniform_vec_i32("tilesets", &(0..MAX_BATCH_TEXTURES as i32).collect());
+                shader_program
+            }).clone()
+        })
+    }
+
+    fn delete_chunks(&self) {
+        for chunk in self.chunks.borrow_mut().drain(..) {
+            if chunk.vertex_array_id != 0 {
+                unsafe {
+                    gl::DeleteVertexArrays(1, &chunk.vertex_array_id);
+                    gl::DeleteBuffers(1, &chunk.buffer_id);
+                }
+            }
+        }
+
+        self.chunks_built.set(false);
+    }
+
+    // Deletes the chunks' buffers and the tileset textures.
+    pub fn delete(&self) {
+        self.delete_chunks();
+
+        for tileset in &self.tilesets {
+            unsafe {
+                gl::DeleteTextures(1, &tileset.texture.texture_id);
+            }
+        }
+    }
+}
+
+impl TilemapChunk {
+    fn new(layer: usize, coordinates: IVec2) -> Self {
+        TilemapChunk {
+            layer,
+            coordinates,
+            vertex_array_id: 0,
+            buffer_id: 0,
+            vertex_count: 0,
+            min: Vec2::ZERO,
+            max: Vec2::ZERO,
+            has_animations: false,
+            dirty: true,
+        }
+    }
+}
+
+impl Renderable for Tilemap {
+    fn get_model_matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.position)
+            * Mat4::from_rotation_x(Deg(self.rotation.x).to_radians().as_float())
+            * Mat4::from_rotation_y(Deg(self.rotation.y).to_radians().as_float())
+            * Mat4::from_rotation_z(Deg(self.rotation.z).to_radians().as_float())
+            * Mat4::from_scale(self.scale)
+    }
+
+    fn render(&self, layer: &GraphicsLayer) {
+        if !self.chunks_built.get() {
+            self.build_chunks();
+        }
+
+        let mut chunks = self.chunks.borrow_mut();
+
+        for chunk in chunks.iter_mut().filter(|chunk| chunk.dirty) {
+            self.rebuild_chunk(chunk);
+        }
+
+        let (view_matrix, should_cull) = match &layer.view {
+            View::View2D(view) => (view.get_view_matrix(), true),
+            // Chunks behind a perspective camera project to nonsense, so everything is drawn.
+            View::View3D(view) => (view.get_view_matrix(), false),
+        };
+
+        let mvp = view_matrix * layer.get_graphics_layer_matrix() * self.get_model_matrix();
+        let shader_program = Self::get_shader_program();
+        shader_program.set_uniform_mat4_f32("mvp", &mvp);
+
+        for (slot, tileset) in self.tilesets.iter().enumerate() {
+            tileset.texture.bind(slot as u32, true);
+        }
+
+        let mut depth_mask: GLboolean = gl::TRUE;
+        let mut current_layer = None;
+
+        unsafe {
+            gl::GetBooleanv(gl::DEPTH_WRITEMASK, &mut depth_mask);
+            // Layers are flat and drawn in order, they'd fail the depth test against each other.
+            gl::DepthMask(gl::FALSE);
+        }
+
+        for chunk in chunks.iter() {
+            let tile_layer = &self.layers[chunk.layer];
+
+            if !tile_layer.visible || chunk.vertex_count == 0 || (should_cull && !Self::is_chunk_visible(chunk, &mvp)) {
+                continue;
+            }
+
+            if current_layer != Some(chunk.layer) {
+                shader_program.set_uniform_f32("opacity", tile_layer.opacity);
+                current_layer = Some(chunk.layer);
+            }
+
+            shader_program.use_program(true);
+
+            unsafe {
+                gl::BindVertexArray(chunk.vertex_array_id);
+                gl::DrawArrays(gl::TRIANGLES, 0, chunk.vertex_count as GLsizei);
+                gl::BindVertexArray(0);
+            }
+
+            shader_program.use_program(false);
+        }
+
+        unsafe {
+            gl::DepthMask(depth_mask);
+        }
+    }
+
+    fn is_transparent(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for Tilemap {
+    fn drop(&mut self) {
+        self.delete();
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, TilemapError> {
+    fs::read_to_string(path).map_err(|error| TilemapError::new(format!("{}: {}", path.display(), error)))
+}
+
+fn check_orientation(orientation: &str) -> Result<(), TilemapError> {
+    match orientation {
+        "orthogonal" => Ok(()),
+        _ => Err(TilemapError::new(format!("{} maps aren't supported, only orthogonal ones", orientation))),
+    }
+}
+
+// Tilesets divide their texture by the tile size and maps divide positions by it.
+fn check_tile_size(tile_size: UVec2) -> Result<UVec2, TilemapError> {
+    if tile_size.x == 0 || tile_size.y == 0 {
+        return Err(TilemapError::new(format!("invalid tile size {}x{}", tile_size.x, tile_size.y)));
+    }
+
+    Ok(tile_size)
+}
+
+// Tiled writes colors as #RRGGBB or #AARRGGBB, the # is optional for tileset transparency colors.
+fn parse_color(hex: &str) -> Option<Color> {
+    let hex = hex.trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16).ok()?;
+
+    match hex.len() {
+        6 => Some(Color::new((value >> 16) as u8, (value >> 8) as u8, value as u8, 255)),
+        8 => Some(Color::new((value >> 16) as u8, (value >> 8) as u8, value as u8, (value >> 24) as u8)),
+        _ => None,
+    }
+}
+
+fn attribute<T: FromStr>(node: Node, name: &str) -> Option<T> {
+    node.attribute(name).and_then(|value| value.parse().ok())
+}
+
+fn required_attribute<T: FromStr>(node: Node, name: &str) -> Result<T, TilemapError> {
+    attribute(node, name).ok_or_else(|| TilemapError::new(format!("<{}> has no valid {}", node.tag_name().name(), name)))
+}
+
+fn required_u32(value: &Value, key: &str) -> Result<u32, TilemapError> {
+    value[key].as_u64().map(|value| value as u32).ok_or_else(|| TilemapError::new(format!("missing {}", key)))
+}
+
+fn parse_tmx_properties(node: Node) -> HashMap<String, MapProperty> {
+    let mut properties = HashMap::new();
+
+    for properties_node in node.children().filter(|child| child.has_tag_name("properties")) {
+        for property in properties_node.children().filter(|child| child.has_tag_name("property")) {
+            // Multiline strings are the element's text instead of a value attribute.
+            let value = property.attribute("value").or(property.text()).unwrap_or("");
+
+            properties.insert(
+                String::from(property.attribute("name").unwrap_or("")),
+                MapProperty::from_string(property.attribute("type").unwrap_or("string"), value),
+            );
+        }
+    }
+
+    properties
+}
+
+fn parse_json_properties(value: &Value) -> HashMap<String, MapProperty> {
+    let mut properties = HashMap::new();
+
+    for property in value["properties"].as_array().into_iter().flatten() {
+        let value = match &property["value"] {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+
+        properties.insert(
+            String::from(property["name"].as_str().unwrap_or("")),
+            MapProperty::from_string(property["type"].as_str().unwrap_or("string"), &value),
+        );
+    }
+
+    properties
+}
+
+fn parse_tmx_points(points: &str) -> Result<Vec<Vec2>, TilemapError> {
+    points.split_whitespace().map(|point| {
+        let (x, y) = point.split_once(',').ok_or_else(|| TilemapError::new(format!("invalid point '{}'", point)))?;
+        let parse = |value: &str| value.parse::<f32>().map_err(|_| TilemapError::new(format!("invalid point '{}'", point)));
+
+        Ok(Vec2::new(parse(x)?, -parse(y)?))
+    }).collect()
+}
+
+fn parse_json_points(points: &[Value]) -> Vec<Vec2> {
+    points.iter().map(|point| Vec2::new(point["x"].as_f64().unwrap_or(0.0) as f32, -point["y"].as_f64().unwrap_or(0.0) as f32)).collect()
+}
+
+// The transparency color is keyed out to alpha 0, for old tilesets without an alpha channel.
+fn load_tileset_texture(path: &Path, transparent_color: Option<&str>) -> Result<Texture, TilemapError> {
+    let mut image = image::open(path).map_err(|error| TilemapError::new(format!("{}: {}", path.display(), error)))?.into_rgba8();
+
+    if let Some(color) = transparent_color.and_then(parse_color) {
+        for pixel in image.pixels_mut() {
+            if pixel[0] == color.r && pixel[1] == color.g && pixel[2] == color.b {
+                pixel[3] = 0;
+            }
+        }
+    }
+
+    // Filtering would bleed neighbouring tiles into each other's edges.
+    Ok(Texture::from_image_with(&image, &TextureDescriptor::pixel_art()))
+}
+
+// .tsx tilesets are XML, anything else is taken to be JSON.
+fn load_external_tileset(path: &Path, first_gid: u32) -> Result<Tileset, TilemapError> {
+    let source = read_file(path)?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let error = |error: TilemapError| TilemapError::new(format!("{}: {}", path.display(), error));
+
+    if path.extension().and_then(|extension| extension.to_str()) == Some("tsx") {
+        let document = Document::parse(&source).map_err(|parse_error| error(TilemapError::new(parse_error.to_string())))?;
+        parse_tmx_tileset(document.root_element(), first_gid, directory).map_err(error)
+    } else {
+        let json: Value = serde_json::from_str(&source).map_err(|parse_error| error(TilemapError::new(parse_error.to_string())))?;
+        parse_json_tileset(&json, first_gid, directory).map_err(error)
+    }
+}
+
+fn parse_tmx_tileset(node: Node, first_gid: u32, directory: &Path) -> Result<Tileset, TilemapError> {
+    let name = node.attribute("name").unwrap_or("");
+    let image = node.children().find(|child| child.has_tag_name("image"))
+        .ok_or_else(|| TilemapError::new(format!("tileset '{}' has no image, image collection tilesets aren't supported", name)))?;
+
+    let tile_size = check_tile_size(UVec2::new(required_attribute(node, "tilewidth")?, required_attribute(node, "tileheight")?))?;
+    let texture = load_tileset_texture(&directory.join(image.attribute("source").unwrap_or("")), image.attribute("trans"))?;
+
+    let mut tileset = Tileset::new(name, first_gid, &texture, &tile_size)?
+        .with_spacing(attribute(node, "margin").unwrap_or(0), attribute(node, "spacing").unwrap_or(0));
+
+    tileset.columns = attribute(node, "columns").unwrap_or(tileset.columns);
+    tileset.tile_count = attribute(node, "tilecount").unwrap_or(tileset.tile_count);
+
+    if let Some(offset) = node.children().find(|child| child.has_tag_name("tileoffset")) {
+        tileset.offset = Vec2::new(attribute(offset, "x").unwrap_or(0.0), -attribute(offset, "y").unwrap_or(0.0));
+    }
+
+    for tile in node.children().filter(|child| child.has_tag_name("tile")) {
+        if let Some(animation) = tile.children().find(|child| child.has_tag_name("animation")) {
+            let frames = animation.children().filter(|child| child.has_tag_name("frame")).map(|frame| TileAnimationFrame {
+                tile_id: attribute(frame, "tileid").unwrap_or(0),
+                duration: attribute::<f32>(frame, "duration").unwrap_or(100.0) / 1000.0,
+            }).collect();
+
+            tileset.animations.insert(required_attribute(tile, "id")?, frames);
+        }
+    }
+
+    Ok(tileset)
+}
+
+fn parse_json_tileset(json: &Value, first_gid: u32, directory: &Path) -> Result<Tileset, TilemapError> {
+    let name = json["name"].as_str().unwrap_or("");
+    let image = json["image"].as_str()
+        .ok_or_else(|| TilemapError::new(format!("tileset '{}' has no image, image collection tilesets aren't supported", name)))?;
+
+    let tile_size = check_tile_size(UVec2::new(required_u32(json, "tilewidth")?, required_u32(json, "tileheight")?))?;
+    let texture = load_tileset_texture(&directory.join(image), json["transparentcolor"].as_str())?;
+
+    let mut tileset = Tileset::new(name, first_gid, &texture, &tile_size)?
+        .with_spacing(json["margin"].as_u64().unwrap_or(0) as u32, json["spacing"].as_u64().unwrap_or(0) as u32);
+
+    tileset.columns = json["columns"].as_u64().map(|columns| columns as u32).unwrap_or(tileset.columns);
+    tileset.tile_count = json["tilecount"].as_u64().map(|tile_count| tile_count as u32).unwrap_or(tileset.tile_count);
+    tileset.offset = Vec2::new(
+        json["tileoffset"]["x"].as_f64().unwrap_or(0.0) as f32,
+        -json["tileoffset"]["y"].as_f64().unwrap_or(0.0) as f32,
+    );
+
+    for tile in json["tiles"].as_array().into_iter().flatten() {
+        if let Some(animation) = tile["animation"].as_array() {
+            let frames = animation.iter().map(|frame| TileAnimationFrame {
+                tile_id: frame["tileid"].as_u64().unwrap_or(0) as u32,
+                duration: frame["duration"].as_f64().unwrap_or(100.0) as f32 / 1000.0,
+            }).collect();
+
+            tileset.animations.insert(required_u32(tile, "id")?, frames);
+        }
+    }
+
+    Ok(tileset)
+}
+
+// Raw gids from CSV or base64 data, optionally zlib or gzip compressed.
+fn decode_tile_data(data: &str, encoding: Option<&str>, compression: Option<&str>) -> Result<Vec<u32>, TilemapError> {
+    match encoding {
+        Some("csv") => data.split(',')
+            .map(|gid| gid.trim())
+            .filter(|gid| !gid.is_empty())
+            .map(|gid| gid.parse::<u32>().map_err(|_| TilemapError::new(format!("invalid tile '{}'", gid))))
+            .collect(),
+        Some("base64") => {
+            let data: String = data.chars().filter(|character| !character.is_whitespace()).collect();
+            let bytes = base64::engine::general_purpose::STANDARD.decode(data).map_err(|error| TilemapError::new(error.to_string()))?;
+            let mut decompressed = Vec::new();
+
+            let result = match compression {
+                None | Some("") => {
+                    decompressed = bytes;
+                    Ok(0)
+                },
+                Some("zlib") => flate2::read::ZlibDecoder::new(bytes.as_slice()).read_to_end(&mut decompressed),
+                Some("gzip") => flate2::read::GzDecoder::new(bytes.as_slice()).read_to_end(&mut decompressed),
+                Some(compression) => return Err(TilemapError::new(format!("{} compression isn't supported", compression))),
+            };
+
+            result.map_err(|error| TilemapError::new(error.to_string()))?;
+
+            Ok(decompressed.chunks_exact(4).map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]])).collect())
+        },
+        Some(encoding) => Err(TilemapError::new(format!("{} encoding isn't supported", encoding))),
+        None => Err(TilemapError::new(String::from("tile data has no encoding"))),
+    }
+}
+
+// Without an encoding TMX data is a <tile gid=".."/> element per tile.
+fn decode_tmx_data(node: Node, encoding: Option<&str>, compression: Option<&str>) -> Result<Vec<u32>, TilemapError> {
+    match encoding {
+        Some(encoding) => decode_tile_data(node.text().unwrap_or(""), Some(encoding), compression),
+        None => Ok(node.children().filter(|child| child.has_tag_name("tile")).map(|tile| attribute(tile, "gid").unwrap_or(0)).collect()),
+    }
+}
+
+// JSON data is an array of gids, or a base64 string.
+fn decode_json_data(data: &Value, encoding: Option<&str>, compression: Option<&str>) -> Result<Vec<u32>, TilemapError> {
+    match data {
+        Value::Array(gids) => Ok(gids.iter().map(|gid| gid.as_u64().unwrap_or(0) as u32).collect()),
+        Value::String(data) => decode_tile_data(data, Some(encoding.unwrap_or("base64")), compression),
+        _ => Err(TilemapError::new(String::from("layer has no data"))),
+    }
+}
+
+// Puts (position, size, gids) chunks into one layer covering all of them.
+fn assemble_tile_layer(name: &str, chunks: &[(IVec2, UVec2, Vec<u32>)]) -> Result<TileLayer, TilemapError> {
+    let min = chunks.iter().map(|(position, _, _)| *position).reduce(IVec2::min).unwrap_or(IVec2::ZERO);
+    let max = chunks.iter().map(|(position, size, _)| *position + size.as_ivec2()).reduce(IVec2::max).unwrap_or(IVec2::ZERO);
+
+    let mut layer = TileLayer::new(name, &(max - min).as_uvec2());
+    layer.origin = min;
+
+    for (position, size, gids) in chunks {
+        if gids.len() != (size.x * size.y) as usize {
+            return Err(TilemapError::new(format!("layer '{}' has {} tiles instead of {}", name, gids.len(), size.x * size.y)));
+        }
+
+        for (index, gid) in gids.iter().enumerate() {
+            let tile_position = *position + IVec2::new(index as i32 % size.x as i32, index as i32 / size.x as i32);
+
+            if let Some(tile_index) = layer.get_index(tile_position.x, tile_position.y) {
+                layer.tiles[tile_index] = Tile::from_raw(*gid);
+            }
+        }
+    }
+
+    Ok(layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn encode_gids(gids: &[u32], compression: Option<&str>) -> String {
+        let bytes: Vec<u8> = gids.iter().flat_map(|gid| gid.to_le_bytes()).collect();
+
+        let compressed = match compression {
+            Some("zlib") => {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&bytes).unwrap();
+                encoder.finish().unwrap()
+            },
+            Some("gzip") => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&bytes).unwrap();
+                encoder.finish().unwrap()
+            },
+            _ => bytes,
+        };
+
+        base64::engine::general_purpose::STANDARD.encode(compressed)
+    }
+
+    fn get_gids(layer: &TileLayer) -> Vec<u32> {
+        layer.tiles.iter().map(|tile| tile.gid).collect()
+    }
+
+    fn get_error<T>(result: Result<T, TilemapError>) -> String {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(error) => error.error_log,
+        }
+    }
+
+    #[test]
+    fn tile_from_raw_splits_the_flags() {
+        assert_eq!(Tile::from_raw(5), Tile::new(5));
+        assert!(Tile::from_raw(0).is_empty());
+
+        let flipped = Tile::from_raw(0x80000000 | 0x20000000 | 7);
+        assert_eq!(flipped.gid, 7);
+        assert!(flipped.flip_horizontal && !flipped.flip_vertical && flipped.flip_diagonal);
+
+        let vertical = Tile::from_raw(0x40000000 | 3);
+        assert_eq!(vertical.gid, 3);
+        assert!(!vertical.flip_horizontal && vertical.flip_vertical && !vertical.flip_diagonal);
+
+        // The hexagonal rotation bit is dropped.
+        assert_eq!(Tile::from_raw(0x10000000 | 9), Tile::new(9));
+    }
+
+    #[test]
+    fn decodes_csv() {
+        assert_eq!(decode_tile_data("\n1,2,\n3,0\n", Some("csv"), None).unwrap(), vec![1, 2, 3, 0]);
+        assert_eq!(get_error(decode_tile_data("1,x", Some("csv"), None)), "invalid tile 'x'");
+    }
+
+    #[test]
+    fn decodes_base64() {
+        let gids = [1, 0, 0x80000002, 42];
+
+        for compression in [None, Some("zlib"), Some("gzip")] {
+            let data = format!("\n   {}\n", encode_gids(&gids, compression));
+            assert_eq!(decode_tile_data(&data, Some("base64"), compression).unwrap(), gids.to_vec());
+        }
+
+        assert_eq!(get_error(decode_tile_data("", Some("base64"), Some("zstd"))), "zstd compression isn't supported");
+        assert_eq!(get_error(decode_tile_data("", Some("xml"), None)), "xml encoding isn't supported");
+        assert_eq!(get_error(decode_tile_data("", None, None)), "tile data has no encoding");
+    }
+
+    #[test]
+    fn decodes_json_data() {
+        assert_eq!(decode_json_data(&serde_json::json!([1, 2, 3]), None, None).unwrap(), vec![1, 2, 3]);
+
+        let data = Value::String(encode_gids(&[4, 5], Some("zlib")));
+        assert_eq!(decode_json_data(&data, None, Some("zlib")).unwrap(), vec![4, 5]);
+    }
+
+    #[test]
+    fn parses_tmx_maps() {
+        let source = r##"
+            <map orientation="orthogonal" width="3" height="2" tilewidth="16" tileheight="8" backgroundcolor="#80ff0000">
+                <properties>
+                    <property name="music" value="theme.ogg"/>
+                </properties>
+                <layer name="ground" width="3" height="2">
+                    <data encoding="csv">1,2,3,4,5,2147483654</data>
+                </layer>
+                <layer name="xml" width="2" height="1" visible="0">
+                    <data><tile gid="7"/><tile/></data>
+                </layer>
+                <group name="group" offsetx="10" offsety="20" opacity="0.5">
+                    <layer name="nested" width="1" height="1" offsetx="1" offsety="2" opacity="0.5">
+                        <data encoding="csv">9</data>
+                    </layer>
+                    <objectgroup name="objects">
+                        <object id="1" name="spawn" class="player" x="16" y="8" width="4" height="6">
+                            <properties>
+                                <property name="health" type="int" value="3"/>
+                                <property name="speed" type="float" value="1.5"/>
+                                <property name="boss" type="bool" value="true"/>
+                            </properties>
+                        </object>
+                        <object id="2" type="enemy" x="0" y="0"><ellipse/></object>
+                        <object id="3" x="0" y="0"><point/></object>
+                        <object id="4" x="0" y="0"><polygon points="0,0 4,0 4,4"/></object>
+                        <object id="5" x="0" y="0"><polyline points="0,0 1,1"/></object>
+                        <object id="6" gid="1073741826" x="0" y="0" rotation="90" visible="0"/>
+                    </objectgroup>
+                </group>
+            </map>
+        "##;
+
+        let tilemap = Tilemap::from_tmx_source_string(source, Path::new("")).unwrap();
+
+        assert_eq!(tilemap.size, UVec2::new(3, 2));
+        assert_eq!(tilemap.tile_size, UVec2::new(16, 8));
+        assert_eq!(tilemap.background_color.map(|color| (color.r, color.g, color.b, color.a)), Some((255, 0, 0, 128)));
+        assert!(matches!(&tilemap.properties["music"], MapProperty::String(music) if music == "theme.ogg"));
+
+        let ground = tilemap.get_layer("ground").unwrap();
+        assert_eq!(get_gids(ground), vec![1, 2, 3, 4, 5, 6]);
+        assert!(ground.get(2, 1).flip_horizontal);
+        assert!(ground.get(3, 0).is_empty());
+
+        let xml = tilemap.get_layer("xml").unwrap();
+        assert_eq!(get_gids(xml), vec![7, 0]);
+        assert!(!xml.visible);
+
+        let nested = tilemap.get_layer("nested").unwrap();
+        assert_eq!(nested.offset, Vec2::new(11.0, -22.0));
+        assert_eq!(nested.opacity, 0.25);
+
+        let objects = tilemap.get_object_layer("objects").unwrap();
+        let spawn = objects.get("spawn").unwrap();
+        assert_eq!(spawn.class, "player");
+        assert_eq!(spawn.position, Vec2::new(26.0, -28.0));
+        assert_eq!(spawn.size, Vec2::new(4.0, 6.0));
+        assert_eq!(spawn.shape, ObjectShape::Rectangle);
+        assert!(matches!(spawn.properties["health"], MapProperty::Int(3)));
+        assert!(matches!(spawn.properties["speed"], MapProperty::Float(speed) if speed == 1.5));
+        assert!(matches!(spawn.properties["boss"], MapProperty::Bool(true)));
+
+        let shapes: Vec<&ObjectShape> = objects.objects.iter().map(|object| &object.shape).collect();
+        assert_eq!(shapes[1..], [
+            &ObjectShape::Ellipse,
+            &ObjectShape::Point,
+            &ObjectShape::Polygon(vec![Vec2::new(0.0, 0.0), Vec2::new(4.0, 0.0), Vec2::new(4.0, -4.0)]),
+            &ObjectShape::Polyline(vec![Vec2::new(0.0, 0.0), Vec2::new(1.0, -1.0)]),
+            &ObjectShape::Tile(Tile { gid: 2, flip_horizontal: false, flip_vertical: true, flip_diagonal: false }),
+        ]);
+
+        assert_eq!(objects.objects[1].class, "enemy");
+        assert_eq!(objects.objects[5].rotation, -90.0);
+        assert!(!objects.objects[5].visible);
+        assert_eq!(tilemap.get_objects("player").len(), 1);
+    }
+
+    #[test]
+    fn parses_infinite_tmx_maps() {
+        let source = r#"
+            <map width="1" height="1" tilewidth="16" tileheight="16" infinite="1">
+                <layer name="ground" width="1" height="1">
+                    <data encoding="csv">
+                        <chunk x="-2" y="0" width="2" height="1">1,2</chunk>
+                        <chunk x="0" y="-1" width="1" height="1">3</chunk>
+                    </data>
+                </layer>
+            </map>
+        "#;
+
+        let tilemap = Tilemap::from_tmx_source_string(source, Path::new("")).unwrap();
+        let ground = tilemap.get_layer("ground").unwrap();
+
+        assert_eq!(ground.origin, IVec2::new(-2, -1));
+        assert_eq!(ground.size, UVec2::new(3, 2));
+        assert_eq!(ground.get(-2, 0).gid, 1);
+        assert_eq!(ground.get(-1, 0).gid, 2);
+        assert_eq!(ground.get(0, -1).gid, 3);
+        assert!(ground.get(-2, -1).is_empty());
+    }
+
+    #[test]
+    fn parses_json_maps() {
+        let source = format!(r#"{{
+            "width": 2, "height": 2, "tilewidth": 32, "tileheight": 32,
+            "properties": [{{ "name": "level", "type": "int", "value": 4 }}],
+            "layers": [
+                {{ "type": "tilelayer", "name": "ground", "width": 2, "height": 2, "data": [1, 2, 3, 2147483652] }},
+                {{ "type": "tilelayer", "name": "packed", "width": 2, "height": 1, "encoding": "base64", "compression": "gzip", "data": "{}" }},
+                {{ "type": "group", "name": "group", "offsetx": 5, "offsety": 5, "visible": false, "layers": [
+                    {{ "type": "tilelayer", "name": "chunked", "chunks": [
+                        {{ "x": -1, "y": 0, "width": 1, "height": 1, "data": [8] }}
+                    ] }},
+                    {{ "type": "objectgroup", "name": "objects", "objects": [
+                        {{ "id": 1, "name": "door", "type": "door", "x": 32, "y": 64, "width": 32, "height": 32,
+                            "properties": [{{ "name": "locked", "type": "bool", "value": true }}] }},
+                        {{ "id": 2, "x": 0, "y": 0, "ellipse": true }},
+                        {{ "id": 3, "x": 0, "y": 0, "polygon": [{{ "x": 0, "y": 0 }}, {{ "x": 2, "y": 2 }}, {{ "x": 0, "y": 2 }}] }},
+                        {{ "id": 4, "x": 0, "y": 0, "gid": 3221225473 }}
+                    ] }}
+                ] }}
+            ]
+        }}"#, encode_gids(&[6, 7], Some("gzip")));
+
+        let tilemap = Tilemap::from_json_source_string(&source, Path::new("")).unwrap();
+
+        assert!(matches!(tilemap.properties["level"], MapProperty::Int(4)));
+
+        let ground = tilemap.get_layer("ground").unwrap();
+        assert_eq!(get_gids(ground), vec![1, 2, 3, 4]);
+        assert!(ground.get(1, 1).flip_horizontal);
+
+        assert_eq!(get_gids(tilemap.get_layer("packed").unwrap()), vec![6, 7]);
+
+        let chunked = tilemap.get_layer("chunked").unwrap();
+        assert_eq!(chunked.get(-1, 0).gid, 8);
+        assert_eq!(chunked.offset, Vec2::new(5.0, -5.0));
+        assert!(!chunked.visible);
+
+        let objects = tilemap.get_object_layer("objects").unwrap();
+        let door = objects.get("door").unwrap();
+        assert_eq!(door.position, Vec2::new(37.0, -69.0));
+        assert!(matches!(door.properties["locked"], MapProperty::Bool(true)));
+        assert_eq!(objects.objects[1].shape, ObjectShape::Ellipse);
+        assert_eq!(objects.objects[2].shape, ObjectShape::Polygon(vec![Vec2::new(0.0, 0.0), Vec2::new(2.0, -2.0), Vec2::new(0.0, -2.0)]));
+        assert_eq!(objects.objects[3].shape, ObjectShape::Tile(Tile { gid: 1, flip_horizontal: true, flip_vertical: true, flip_diagonal: false }));
+    }
+
+    #[test]
+    fn rejects_bad_maps() {
+        let error = get_error(Tilemap::from_tmx_source_string(r#"<map orientation="isometric" width="1" height="1" tilewidth="16" tileheight="16"/>"#, Path::new("")));
+        assert_eq!(error, "isometric maps aren't supported, only orthogonal ones");
+
+        let error = get_error(Tilemap::from_tmx_source_string(r#"<map width="1" height="1" tilewidth="0" tileheight="16"/>"#, Path::new("")));
+        assert_eq!(error, "invalid tile size 0x16");
+
+        let error = get_error(Tilemap::from_tmx_source_string(r#"<tileset/>"#, Path::new("")));
+        assert_eq!(error, "root element isn't a map");
+
+        let source = r#"{ "width": 2, "height": 1, "tilewidth": 8, "tileheight": 8, "layers": [
+            { "type": "tilelayer", "name": "short", "width": 2, "height": 1, "data": [1] }
+        ] }"#;
+        assert_eq!(get_error(Tilemap::from_json_source_string(source, Path::new(""))), "layer 'short' has 1 tiles instead of 2");
+
+        let source = r#"{ "width": 2, "height": 1, "tileheight": 8 }"#;
+        assert!(Tilemap::from_json_source_string(source, Path::new("")).is_err());
+    }
+}
@@ -0,0 +1,1059 @@
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use gl::types::*;
+use glam::{Mat4, Vec2, Vec3};
+use winit::event::MouseButton;
+use winit::keyboard::KeyCode;
+
+use crate::util::event::Input;
+use super::color::Color;
+use super::font::{Font, TextLayout};
+use super::renderable::Renderable;
+use super::shader::{ShaderBuilderTemplate, ShaderProgram};
+use super::view::{GraphicsLayer, View, View2D};
+
+const UI_SHADER_VERSION: &str = "#version 450 core";
+
+// Floats per vertex: position, uv with the textured flag, and color.
+const VERTEX_FLOATS: usize = 9;
+
+thread_local! {
+    // Compiled the first time a UI is drawn and shared by all of them.
+    static UI_SHADER_PROGRAM: RefCell<Option<ShaderProgram>> = const { RefCell::new(None) };
+}
+
+// A rectangle in UI pixels, from the top left of the screen with y going down.
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct UiRect {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl UiRect {
+    pub fn new(position: Vec2, size: Vec2) -> Self {
+        UiRect {
+            min: position,
+            max: position + size,
+        }
+    }
+
+    pub fn get_size(&self) -> Vec2 {
+        self.max - self.min
+    }
+
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.x >= self.min.x && point.y >= self.min.y && point.x < self.max.x && point.y < self.max.y
+    }
+
+    // Empty rectangles keep their min, so they still contain nothing.
+    pub fn intersect(&self, other: &UiRect) -> UiRect {
+        let min = self.min.max(other.min);
+
+        UiRect {
+            min,
+            max: self.max.min(other.max).max(min),
+        }
+    }
+
+    pub fn shrink(&self, amount: Vec2) -> UiRect {
+        UiRect {
+            min: self.min + amount,
+            max: (self.max - amount).max(self.min + amount),
+        }
+    }
+}
+
+// Where an area sits on the screen. Offsets move it right and down from there.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    // How far across and down the screen the anchor is, from 0 to 1.
+    pub fn get_factor(&self) -> Vec2 {
+        match self {
+            Anchor::TopLeft => Vec2::new(0.0, 0.0),
+            Anchor::Top => Vec2::new(0.5, 0.0),
+            Anchor::TopRight => Vec2::new(1.0, 0.0),
+            Anchor::Left => Vec2::new(0.0, 0.5),
+            Anchor::Center => Vec2::new(0.5, 0.5),
+            Anchor::Right => Vec2::new(1.0, 0.5),
+            Anchor::BottomLeft => Vec2::new(0.0, 1.0),
+            Anchor::Bottom => Vec2::new(0.5, 1.0),
+            Anchor::BottomRight => Vec2::new(1.0, 1.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct UiStyle {
+    pub text_color: Color,
+    pub widget_color: Color,
+    pub widget_hover_color: Color,
+    pub widget_active_color: Color,
+    // Checkmarks, slider fills and the outline of the focused text field.
+    pub accent_color: Color,
+    pub panel_color: Color,
+    pub title_color: Color,
+    // Inside widgets, around their text.
+    pub padding: Vec2,
+    // Between widgets and inside windows and panels.
+    pub spacing: f32,
+    pub slider_width: f32,
+    pub text_field_width: f32,
+    pub scrollbar_width: f32,
+    // Pixels per wheel line.
+    pub scroll_speed: f32,
+}
+
+impl Default for UiStyle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UiStyle {
+    pub fn new() -> Self {
+        UiStyle {
+            text_color: Color::new(230, 230, 230, 255),
+            widget_color: Color::new(60, 63, 70, 255),
+            widget_hover_color: Color::new(80, 84, 94, 255),
+            widget_active_color: Color::new(45, 47, 53, 255),
+            accent_color: Color::new(66, 150, 250, 255),
+            panel_color: Color::new(30, 32, 36, 235),
+            title_color: Color::new(45, 80, 130, 255),
+            padding: Vec2::new(8.0, 4.0),
+            spacing: 6.0,
+            slider_width: 160.0,
+            text_field_width: 200.0,
+            scrollbar_width: 8.0,
+            scroll_speed: 40.0,
+        }
+    }
+}
+
+// What happened to a widget this frame. clicked is a press and release on it, changed means the widget edited its
+// value.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct UiResponse {
+    pub hovered: bool,
+    pub held: bool,
+    pub clicked: bool,
+    pub changed: bool,
+    pub rect: UiRect,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LayoutDirection {
+    Row,
+    Column,
+}
+
+struct UiLayout {
+    direction: LayoutDirection,
+    start: Vec2,
+    cursor: Vec2,
+    // The bottom right corner of everything placed so far.
+    max: Vec2,
+    // What separators span.
+    width: f32,
+}
+
+struct UiWindow {
+    rect: UiRect,
+    last_frame: u64,
+}
+
+#[derive(Clone, Copy, Default)]
+struct UiScrollState {
+    offset: f32,
+    content_height: f32,
+}
+
+// A run of vertices drawn with one scissor rectangle.
+#[derive(Clone, Copy)]
+struct UiBatch {
+    first: usize,
+    count: usize,
+    clip: UiRect,
+}
+
+#[derive(Default)]
+struct UiDrawList {
+    vertices: Vec<f32>,
+    batches: Vec<UiBatch>,
+}
+
+// An immediate mode UI. Widgets are functions called every tick between begin and end that draw themselves and
+// return what the mouse did to them, the UI keeps nothing about them but ids:
+//
+//     ui.begin(input);
+//     ui.begin_window("Settings", Vec2::new(20.0, 20.0), Vec2::new(300.0, 200.0));
+//     if ui.button("Play").clicked { ... }
+//     ui.slider("Volume", &mut volume, 0.0, 1.0);
+//     ui.end_window();
+//     ui.end(input);
+//
+// Positions are pixels from the top left of the screen. Labels double as ids, add "##something" to tell widgets with
+// the same text apart, it isn't shown. While the mouse is over the UI or a text field has focus, begin and end mark the
+// input consumed for the rest of the tick so the game ignores it, entities updated before begin don't know yet. Render
+// it last, it draws on top of everything with its own View2D of the screen.
+pub struct Ui {
+    pub font: Rc<Font>,
+    pub style: UiStyle,
+    screen_size: Cell<Vec2>,
+    frame: u64,
+    mouse: Vec2,
+    mouse_last: Vec2,
+    mouse_down: bool,
+    mouse_pressed: bool,
+    mouse_released: bool,
+    scroll: f32,
+    text: String,
+    keys_pressed: Vec<KeyCode>,
+    hovered: Option<u64>,
+    active: Option<u64>,
+    focused: Option<u64>,
+    text_cursor: usize,
+    is_over_ui: bool,
+    id_stack: Vec<u64>,
+    layouts: Vec<UiLayout>,
+    clip_stack: Vec<UiRect>,
+    windows: HashMap<u64, UiWindow>,
+    // Back to front.
+    window_order: Vec<u64>,
+    window_stack: Vec<u64>,
+    hovered_window: Option<u64>,
+    scroll_states: HashMap<u64, UiScrollState>,
+    scroll_stack: Vec<(u64, UiRect)>,
+    draw_lists: Vec<(Option<u64>, UiDrawList)>,
+    draw_list_stack: Vec<usize>,
+    // The last finished frame, drawn until the next one ends.
+    finished: UiDrawList,
+    vertex_array_id: Cell<GLuint>,
+    buffer_id: Cell<GLuint>,
+}
+
+impl Ui {
+    pub fn new(font: &Rc<Font>) -> Self {
+        Ui {
+            font: font.clone(),
+            style: UiStyle::new(),
+            screen_size: Cell::new(Vec2::ZERO),
+            frame: 0,
+            mouse: Vec2::ZERO,
+            mouse_last: Vec2::ZERO,
+            mouse_down: false,
+            mouse_pressed: false,
+            mouse_released: false,
+            scroll: 0.0,
+            text: String::new(),
+            keys_pressed: Vec::new(),
+            hovered: None,
+            active: None,
+            focused: None,
+            text_cursor: 0,
+            is_over_ui: false,
+            id_stack: Vec::new(),
+            layouts: Vec::new(),
+            clip_stack: Vec::new(),
+            windows: HashMap::new(),
+            window_order: Vec::new(),
+            window_stack: Vec::new(),
+            hovered_window: None,
+            scroll_states: HashMap::new(),
+            scroll_stack: Vec::new(),
+            draw_lists: Vec::new(),
+            draw_list_stack: Vec::new(),
+            finished: UiDrawList::default(),
+            vertex_array_id: Cell::new(0),
+            buffer_id: Cell::new(0),
+        }
+    }
+
+    pub fn with_style(mut self, style: &UiStyle) -> Self {
+        self.style = *style;
+        self
+    }
+
+    // Rendering keeps this up to date with the layer's view, set it yourself to lay out before the first frame.
+    pub fn with_screen_size(self, screen_size: &Vec2) -> Self {
+        self.screen_size.set(*screen_size);
+        self
+    }
+
+    pub fn get_screen_size(&self) -> Vec2 {
+        self.screen_size.get()
+    }
+
+    // Starts a frame, reading the mouse and keyboard directly so the UI sees them even while it consumes them.
+    pub fn begin(&mut self, input: &mut Input) {
+        let screen_size = self.screen_size.get();
+        let left = Input::mouse_button_to_index(MouseButton::Left) as usize;
+
+        self.frame += 1;
+        self.mouse_last = self.mouse;
+        self.mouse = Vec2::new(
+            input.mouse_position.x as f32 + screen_size.x / 2.0,
+            screen_size.y / 2.0 - input.mouse_position.y as f32,
+        );
+
+        self.mouse_down = input.buttons[left];
+        self.mouse_pressed = input.buttons[left] && !input.buttons_last[left];
+        self.mouse_released = !input.buttons[left] && input.buttons_last[left];
+        self.scroll = input.scroll_delta.y as f32;
+        self.text = input.text.clone();
+
+        self.keys_pressed = [
+            KeyCode::Backspace, KeyCode::Delete, KeyCode::ArrowLeft, KeyCode::ArrowRight,
+            KeyCode::Home, KeyCode::End, KeyCode::Enter, KeyCode::Escape,
+        ].into_iter().filter(|key| {
+            let scancode = Input::key_to_scancode(*key) as usize;
+            scancode < input.keys.len() && input.keys[scancode] && !input.keys_last[scancode]
+        }).collect();
+
+        // Clicking anywhere drops the focus, a text field clicked this frame takes it right back.
+        if self.mouse_pressed {
+            self.focused = None;
+        }
+
+        // Windows are hit tested where they were last frame, the topmost one wins.
+        let previous_frame = self.frame - 1;
+        self.hovered_window = self.window_order.iter().rev()
+            .find(|id| self.windows[id].last_frame == previous_frame && self.windows[id].rect.contains(self.mouse))
+            .copied();
+
+        self.hovered = None;
+        self.is_over_ui = self.hovered_window.is_some();
+        self.id_stack.clear();
+        self.clip_stack = vec![UiRect::new(Vec2::ZERO, screen_size)];
+        self.draw_lists = vec![(None, UiDrawList::default())];
+        self.draw_list_stack = vec![0];
+
+        self.layouts = vec![UiLayout {
+            direction: LayoutDirection::Column,
+            start: Vec2::splat(self.style.spacing),
+            cursor: Vec2::splat(self.style.spacing),
+            max: Vec2::splat(self.style.spacing),
+            width: screen_size.x - self.style.spacing * 2.0,
+        }];
+
+        // What's already known, end adds the widgets hovered this frame.
+        input.mouse_consumed = self.is_using_mouse();
+        input.keyboard_consumed = self.is_using_keyboard();
+    }
+
+    // Finishes the frame for rendering and tells the input whether the UI used it.
+    pub fn end(&mut self, input: &mut Input) {
+        if self.active.is_some() && !self.mouse_down {
+            self.active = None;
+        }
+
+        // Windows that weren't drawn this frame are forgotten by the order, so they come back on top.
+        let frame = self.frame;
+        let windows = &self.windows;
+        self.window_order.retain(|id| windows[id].last_frame == frame);
+
+        let mut finished = UiDrawList::default();
+        let mut draw_lists: Vec<(Option<u64>, UiDrawList)> = self.draw_lists.drain(..).collect();
+
+        // The screen's widgets first, then windows back to front.
+        draw_lists.sort_by_key(|(window, _)| window.and_then(|window| self.window_order.iter().position(|id| *id == window)).map(|index| index + 1).unwrap_or(0));
+
+        for (_, draw_list) in draw_lists {
+            let first = finished.vertices.len() / VERTEX_FLOATS;
+
+            finished.batches.extend(draw_list.batches.iter().map(|batch| UiBatch {
+                first: batch.first + first,
+                count: batch.count,
+                clip: batch.clip,
+            }));
+
+            finished.vertices.extend(draw_list.vertices);
+        }
+
+        self.finished = finished;
+
+        input.mouse_consumed = self.is_over_ui || self.active.is_some();
+        input.keyboard_consumed = self.focused.is_some();
+    }
+
+    pub fn is_using_mouse(&self) -> bool {
+        self.is_over_ui || self.active.is_some()
+    }
+
+    pub fn is_using_keyboard(&self) -> bool {
+        self.focused.is_some()
+    }
+
+    pub fn push_id(&mut self, id: &str) {
+        let id = self.get_id(id);
+        self.id_stack.push(id);
+    }
+
+    pub fn pop_id(&mut self) {
+        self.id_stack.pop();
+    }
+
+    fn get_id(&self, label: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.id_stack.last().copied().unwrap_or(0).hash(&mut hasher);
+        label.hash(&mut hasher);
+
+        hasher.finish()
+    }
+
+    // The part of a label that's shown.
+    fn get_display_text(label: &str) -> &str {
+        label.split("##").next().unwrap_or("")
+    }
+
+    fn get_text_size(&self, text: &str) -> Vec2 {
+        Vec2::new(self.font.measure_text(text, &TextLayout::new()).x, self.get_text_height())
+    }
+
+    fn get_text_height(&self) -> f32 {
+        self.font.ascent - self.font.descent
+    }
+
+    fn get_widget_height(&self) -> f32 {
+        self.get_text_height() + self.style.padding.y * 2.0
+    }
+
+    fn get_clip(&self) -> UiRect {
+        *self.clip_stack.last().unwrap()
+    }
+
+    fn push_clip(&mut self, rect: &UiRect) {
+        let clip = self.get_clip().intersect(rect);
+        self.clip_stack.push(clip);
+    }
+
+    fn pop_clip(&mut self) {
+        self.clip_stack.pop();
+    }
+
+    fn push_layout(&mut self, direction: LayoutDirection, start: Vec2, width: f32) {
+        self.layouts.push(UiLayout {
+            direction,
+            start,
+            cursor: start,
+            max: start,
+            width,
+        });
+    }
+
+    // Takes the next spot in the current row or column.
+    fn allocate(&mut self, size: Vec2) -> UiRect {
+        let spacing = self.style.spacing;
+        let layout = self.layouts.last_mut().unwrap();
+        let rect = UiRect::new(layout.cursor, size);
+
+        match layout.direction {
+            LayoutDirection::Column => layout.cursor.y += size.y + spacing,
+            LayoutDirection::Row => layout.cursor.x += size.x + spacing,
+        }
+
+        layout.max = layout.max.max(rect.max);
+
+        rect
+    }
+
+    // Only the topmost window under the mouse, or the screen when there's none, gets hovered.
+    fn is_hoverable(&self, rect: &UiRect) -> bool {
+        rect.contains(self.mouse) && self.get_clip().contains(self.mouse) && self.window_stack.last().copied() == self.hovered_window
+    }
+
+    // Press and release button behavior. A widget stays active while the mouse is held after pressing it, even when
+    // the mouse leaves it.
+    fn interact(&mut self, id: u64, rect: &UiRect) -> UiResponse {
+        let hovered = self.is_hoverable(rect) && self.active.map(|active| active == id).unwrap_or(true);
+
+        if hovered {
+            self.hovered = Some(id);
+            self.is_over_ui = true;
+
+            if self.mouse_pressed {
+                self.active = Some(id);
+            }
+        }
+
+        let is_active = self.active == Some(id);
+
+        UiResponse {
+            hovered,
+            held: is_active && self.mouse_down,
+            clicked: is_active && self.mouse_released && hovered,
+            changed: false,
+            rect: *rect,
+        }
+    }
+
+    fn get_widget_color(&self, response: &UiResponse) -> Color {
+        if response.held {
+            self.style.widget_active_color
+        } else if response.hovered {
+            self.style.widget_hover_color
+        } else {
+            self.style.widget_color
+        }
+    }
+
+    fn get_draw_list(&mut self) -> &mut UiDrawList {
+        let index = *self.draw_list_stack.last().unwrap();
+        &mut self.draw_lists[index].1
+    }
+
+    fn push_vertices(&mut self, vertices: &[f32]) {
+        let clip = self.get_clip();
+        let draw_list = self.get_draw_list();
+        let first = draw_list.vertices.len() / VERTEX_FLOATS;
+        let count = vertices.len() / VERTEX_FLOATS;
+
+        draw_list.vertices.extend_from_slice(vertices);
+
+        match draw_list.batches.last_mut() {
+            Some(batch) if batch.clip == clip => batch.count += count,
+            _ => draw_list.batches.push(UiBatch { first, count, clip }),
+        }
+    }
+
+    // uv_rect is (u_min, v_min, u_max, v_max), None for a solid color. v_max is at the top.
+    fn add_quad(&mut self, rect: &UiRect, uv_rect: Option<glam::Vec4>, color: &Color) {
+        let (uv, textured) = match uv_rect {
+            Some(uv_rect) => (uv_rect, 1.0),
+            None => (glam::Vec4::ZERO, 0.0),
+        };
+
+        let color = color.to_vec4();
+        let mut vertices: Vec<f32> = Vec::with_capacity(6 * VERTEX_FLOATS);
+
+        // y is flipped here, the View2D the UI renders with has it going up.
+        for (x, y, u, v) in [
+            (rect.min.x, rect.max.y, uv.x, uv.y),
+            (rect.max.x, rect.max.y, uv.z, uv.y),
+            (rect.max.x, rect.min.y, uv.z, uv.w),
+            (rect.max.x, rect.min.y, uv.z, uv.w),
+            (rect.min.x, rect.min.y, uv.x, uv.w),
+            (rect.min.x, rect.max.y, uv.x, uv.y),
+        ] {
+            vertices.extend_from_slice(&[x, -y, u, v, textured, color.x, color.y, color.z, color.w]);
+        }
+
+        self.push_vertices(&vertices);
+    }
+
+    pub fn draw_rect(&mut self, rect: &UiRect, color: &Color) {
+        self.add_quad(rect, None, color);
+    }
+
+    pub fn draw_outline(&mut self, rect: &UiRect, thickness: f32, color: &Color) {
+        let size = rect.get_size();
+
+        self.draw_rect(&UiRect::new(rect.min, Vec2::new(size.x, thickness)), color);
+        self.draw_rect(&UiRect::new(Vec2::new(rect.min.x, rect.max.y - thickness), Vec2::new(size.x, thickness)), color);
+        self.draw_rect(&UiRect::new(rect.min, Vec2::new(thickness, size.y)), color);
+        self.draw_rect(&UiRect::new(Vec2::new(rect.max.x - thickness, rect.min.y), Vec2::new(thickness, size.y)), color);
+    }
+
+    // position is the top left of the text's first line. Glyphs go on whole pixels so small text stays sharp.
+    pub fn draw_text(&mut self, position: Vec2, text: &str, color: &Color) {
+        let font = self.font.clone();
+        let position = position.round();
+
+        for positioned_glyph in font.layout_text(text, &TextLayout::new()) {
+            let glyph = positioned_glyph.glyph;
+            let min = position + Vec2::new(positioned_glyph.position.x, -(positioned_glyph.position.y + glyph.size.y));
+
+            self.add_quad(&UiRect::new(min, glyph.size), glyph.region.map(|region| region.uv_rect), color);
+        }
+    }
+
+    // Places an area on the screen for the widgets until end_area, laid out in a column.
+    pub fn begin_area(&mut self, anchor: Anchor, offset: Vec2, size: Vec2) {
+        let position = (self.screen_size.get() - size) * anchor.get_factor() + offset;
+        self.push_layout(LayoutDirection::Column, position, size.x);
+    }
+
+    pub fn end_area(&mut self) {
+        self.layouts.pop();
+    }
+
+    // Widgets until end_row go side by side.
+    pub fn begin_row(&mut self) {
+        let layout = self.layouts.last().unwrap();
+        let width = layout.width - (layout.cursor.x - layout.start.x);
+        self.push_layout(LayoutDirection::Row, layout.cursor, width);
+    }
+
+    pub fn end_row(&mut self) {
+        let layout = self.layouts.pop().unwrap();
+        self.allocate((layout.max - layout.start).max(Vec2::ZERO));
+    }
+
+    // Widgets until end_column go under each other, inside a row for example.
+    pub fn begin_column(&mut self) {
+        let layout = self.layouts.last().unwrap();
+        let width = layout.width - (layout.cursor.x - layout.start.x);
+        self.push_layout(LayoutDirection::Column, layout.cursor, width);
+    }
+
+    pub fn end_column(&mut self) {
+        self.end_row();
+    }
+
+    // Empty space in the current direction.
+    pub fn space(&mut self, amount: f32) {
+        self.allocate(Vec2::splat(amount));
+    }
+
+    pub fn separator(&mut self) {
+        let style = self.style;
+        let width = self.layouts.last().unwrap().width;
+        let rect = self.allocate(Vec2::new(width, 1.0));
+        self.draw_rect(&rect, &style.widget_hover_color);
+    }
+
+    pub fn label(&mut self, text: &str) -> UiResponse {
+        let style = self.style;
+        let size = self.get_text_size(text) + Vec2::new(0.0, style.padding.y * 2.0);
+        let rect = self.allocate(size);
+
+        self.draw_text(rect.min + Vec2::new(0.0, style.padding.y), text, &style.text_color);
+
+        UiResponse {
+            hovered: self.is_hoverable(&rect),
+            rect,
+            ..Default::default()
+        }
+    }
+
+    pub fn button(&mut self, label: &str) -> UiResponse {
+        let style = self.style;
+        let id = self.get_id(label);
+        let text = Self::get_display_text(label);
+        let rect = self.allocate(self.get_text_size(text) + style.padding * 2.0);
+        let response = self.interact(id, &rect);
+
+        self.draw_rect(&rect, &self.get_widget_color(&response));
+        self.draw_text(rect.min + style.padding, text, &style.text_color);
+
+        response
+    }
+
+    pub fn checkbox(&mut self, label: &str, value: &mut bool) -> UiResponse {
+        let style = self.style;
+        let id = self.get_id(label);
+        let text = Self::get_display_text(label);
+        let box_size = self.get_text_height();
+        let text_size = self.get_text_size(text);
+        let rect = self.allocate(Vec2::new(box_size + style.padding.x + text_size.x, self.get_widget_height()));
+        let mut response = self.interact(id, &rect);
+
+        if response.clicked {
+            *value = !*value;
+            response.changed = true;
+        }
+
+        let box_rect = UiRect::new(rect.min + Vec2::new(0.0, style.padding.y), Vec2::splat(box_size));
+        self.draw_rect(&box_rect, &self.get_widget_color(&response));
+
+        if *value {
+            self.draw_rect(&box_rect.shrink(Vec2::splat((box_size / 4.0).floor())), &style.accent_color);
+        }
+
+        self.draw_text(rect.min + Vec2::new(box_size + style.padding.x, style.padding.y), text, &style.text_color);
+
+        response
+    }
+
+    // Dragging anywhere on the track sets the value. The label and the value are shown to the right.
+    pub fn slider(&mut self, label: &str, value: &mut f32, min: f32, max: f32) -> UiResponse {
+        let style = self.style;
+        let id = self.get_id(label);
+        let text = format!("{} {:.2}", Self::get_display_text(label), value);
+        let text_size = self.get_text_size(&text);
+        let height = self.get_widget_height();
+        let rect = self.allocate(Vec2::new(style.slider_width + style.padding.x + text_size.x, height));
+        let track = UiRect::new(rect.min, Vec2::new(style.slider_width, height));
+        let mut response = self.interact(id, &track);
+
+        if response.held && max > min {
+            let t = ((self.mouse.x - track.min.x) / track.get_size().x).clamp(0.0, 1.0);
+            let new_value = min + (max - min) * t;
+
+            if new_value != *value {
+                *value = new_value;
+                response.changed = true;
+            }
+        }
+
+        let t = if max > min { ((*value - min) / (max - min)).clamp(0.0, 1.0) } else { 0.0 };
+        let knob_width = (height / 2.0).floor();
+        let knob_x = track.min.x + (track.get_size().x - knob_width) * t;
+
+        self.draw_rect(&track.shrink(Vec2::new(0.0, (height / 2.0 - 2.0).floor())), &style.widget_color);
+        self.draw_rect(&UiRect::new(Vec2::new(track.min.x, track.min.y + (height / 2.0 - 2.0).floor()), Vec2::new(knob_x - track.min.x, 4.0)), &style.accent_color);
+        self.draw_rect(&UiRect::new(Vec2::new(knob_x, track.min.y), Vec2::new(knob_width, height)), &self.get_widget_color(&response));
+        self.draw_text(Vec2::new(track.max.x + style.padding.x, rect.min.y + style.padding.y), &text, &style.text_color);
+
+        response
+    }
+
+    // Single line. Clicking focuses it, Enter, Escape or clicking elsewhere drops the focus.
+    pub fn text_field(&mut self, label: &str, text: &mut String) -> UiResponse {
+        let style = self.style;
+        let id = self.get_id(label);
+        let rect = self.allocate(Vec2::new(style.text_field_width, self.get_widget_height()));
+        let mut response = self.interact(id, &rect);
+
+        if response.hovered && self.mouse_pressed {
+            self.focused = Some(id);
+            self.text_cursor = text.chars().count();
+        }
+
+        let is_focused = self.focused == Some(id);
+
+        if is_focused {
+            let length = text.chars().count();
+            let mut cursor = self.text_cursor.min(length);
+            let byte_index = |text: &String, cursor: usize| text.char_indices().nth(cursor).map(|(index, _)| index).unwrap_or(text.len());
+
+            for key in self.keys_pressed.clone() {
+                match key {
+                    KeyCode::Backspace if cursor > 0 => {
+                        cursor -= 1;
+                        text.remove(byte_index(text, cursor));
+                        response.changed = true;
+                    },
+                    KeyCode::Delete if cursor < text.chars().count() => {
+                        text.remove(byte_index(text, cursor));
+                        response.changed = true;
+                    },
+                    KeyCode::ArrowLeft => cursor = cursor.saturating_sub(1),
+                    KeyCode::ArrowRight => cursor = (cursor + 1).min(text.chars().count()),
+                    KeyCode::Home => cursor = 0,
+                    KeyCode::End => cursor = text.chars().count(),
+                    KeyCode::Enter | KeyCode::Escape => self.focused = None,
+                    _ => {},
+                }
+            }
+
+            if !self.text.is_empty() {
+                text.insert_str(byte_index(text, cursor), &self.text);
+                cursor += self.text.chars().count();
+                response.changed = true;
+            }
+
+            self.text_cursor = cursor;
+        }
+
+        let inner = rect.shrink(style.padding);
+        let before_cursor: String = text.chars().take(self.text_cursor).collect();
+        let cursor_x = self.get_text_size(&before_cursor).x;
+
+        // Long text slides left so the cursor stays in view.
+        let scroll = if is_focused { (cursor_x - inner.get_size().x + 1.0).max(0.0) } else { 0.0 };
+
+        self.draw_rect(&rect, &self.get_widget_color(&UiResponse { held: false, ..response }));
+
+        if is_focused {
+            self.draw_outline(&rect, 1.0, &style.accent_color);
+        }
+
+        self.push_clip(&inner);
+        self.draw_text(inner.min - Vec2::new(scroll, 0.0), text, &style.text_color);
+
+        if is_focused {
+            self.draw_rect(&UiRect::new(Vec2::new(inner.min.x + cursor_x - scroll, inner.min.y), Vec2::new(1.0, self.get_text_height())), &style.text_color);
+        }
+
+        self.pop_clip();
+
+        response
+    }
+
+    // A fixed size panel whose content scrolls with the wheel or by dragging the bar. Widgets until
+    // end_scroll_panel go inside, in a column.
+    pub fn begin_scroll_panel(&mut self, label: &str, size: Vec2) {
+        let style = self.style;
+        let id = self.get_id(label);
+        let rect = self.allocate(size);
+        let state = self.scroll_states.get(&id).copied().unwrap_or_default();
+        let mut offset = state.offset;
+
+        if self.is_hoverable(&rect) {
+            self.is_over_ui = true;
+            offset -= self.scroll * style.scroll_speed;
+        }
+
+        offset = offset.clamp(0.0, (state.content_height - size.y).max(0.0));
+        self.scroll_states.insert(id, UiScrollState { offset, content_height: state.content_height });
+
+        self.draw_rect(&rect, &style.panel_color);
+        self.push_clip(&rect);
+
+        let padding = style.spacing;
+        self.push_layout(LayoutDirection::Column, rect.min + Vec2::new(padding, padding - offset), size.x - padding * 2.0 - style.scrollbar_width);
+
+        self.scroll_stack.push((id, rect));
+        self.id_stack.push(id);
+    }
+
+    pub fn end_scroll_panel(&mut self) {
+        let style = self.style;
+        self.id_stack.pop();
+
+        let layout = self.layouts.pop().unwrap();
+        let (id, rect) = self.scroll_stack.pop().unwrap();
+        let mut state = self.scroll_states[&id];
+
+        state.content_height = layout.max.y - layout.start.y + style.spacing * 2.0;
+        self.pop_clip();
+
+        let height = rect.get_size().y;
+        let scrollable = state.content_height - height;
+
+        if scrollable > 0.0 {
+            let thumb_height = (height * height / state.content_height).max(style.scrollbar_width * 2.0);
+            let track = UiRect::new(Vec2::new(rect.max.x - style.scrollbar_width, rect.min.y), Vec2::new(style.scrollbar_width, height));
+            let thumb = UiRect::new(
+                Vec2::new(track.min.x, track.min.y + state.offset / scrollable * (height - thumb_height)),
+                Vec2::new(style.scrollbar_width, thumb_height),
+            );
+
+            let response = self.interact(self.get_id("##scrollbar"), &thumb);
+
+            if response.held {
+                let drag = self.mouse.y - self.mouse_last.y;
+                state.offset = (state.offset + drag * scrollable / (height - thumb_height).max(1.0)).clamp(0.0, scrollable);
+            }
+
+            self.draw_rect(&track, &style.widget_color);
+            self.draw_rect(&thumb, &self.get_widget_color(&response));
+        }
+
+        self.scroll_states.insert(id, state);
+    }
+
+    // A movable window, dragged by its title bar. position and size are only used the first time, after that it
+    // stays where it was dragged. Widgets until end_window go inside, in a column. Clicking a window brings it to
+    // the front.
+    pub fn begin_window(&mut self, title: &str, position: Vec2, size: Vec2) {
+        let style = self.style;
+        let id = self.get_id(title);
+        let frame = self.frame;
+
+        self.windows.entry(id).or_insert(UiWindow { rect: UiRect::new(position, size), last_frame: 0 }).last_frame = frame;
+
+        if !self.window_order.contains(&id) {
+            self.window_order.push(id);
+        }
+
+        if self.mouse_pressed && self.hovered_window == Some(id) {
+            self.window_order.retain(|window| *window != id);
+            self.window_order.push(id);
+        }
+
+        self.draw_lists.push((Some(id), UiDrawList::default()));
+        self.draw_list_stack.push(self.draw_lists.len() - 1);
+        self.window_stack.push(id);
+        self.id_stack.push(id);
+
+        let title_height = self.get_widget_height();
+        let rect = self.windows[&id].rect;
+        let title_bar = UiRect::new(rect.min, Vec2::new(rect.get_size().x, title_height));
+        let response = self.interact(self.get_id("##title"), &title_bar);
+
+        if response.held {
+            let drag = self.mouse - self.mouse_last;
+            let window = self.windows.get_mut(&id).unwrap();
+            window.rect.min += drag;
+            window.rect.max += drag;
+        }
+
+        let rect = self.windows[&id].rect;
+        let title_bar = UiRect::new(rect.min, Vec2::new(rect.get_size().x, title_height));
+
+        self.draw_rect(&rect, &style.panel_color);
+        self.draw_rect(&title_bar, &style.title_color);
+        self.push_clip(&title_bar);
+        self.draw_text(title_bar.min + style.padding, Self::get_display_text(title), &style.text_color);
+        self.pop_clip();
+
+        let content = UiRect { min: Vec2::new(rect.min.x, title_bar.max.y), max: rect.max };
+        let padding = style.spacing;
+
+        self.push_clip(&content);
+        self.push_layout(LayoutDirection::Column, content.min + Vec2::splat(padding), content.get_size().x - padding * 2.0);
+    }
+
+    pub fn end_window(&mut self) {
+        self.layouts.pop();
+        self.pop_clip();
+        self.id_stack.pop();
+        self.window_stack.pop();
+        self.draw_list_stack.pop();
+    }
+
+    fn get_shader_program() -> ShaderProgram {
+        UI_SHADER_PROGRAM.with(|shader_program| {
+            shader_program.borrow_mut().get_or_insert_with(|| {
+                ShaderProgram::from_builders(
+                    &ShaderBuilderTemplate::ui_vertex_shader(UI_SHADER_VERSION),
+                    &ShaderBuilderTemplate::ui_fragment_shader(UI_SHADER_VERSION),
+                ).unwrap()
+            }).clone()
+        })
+    }
+
+    fn init_buffers(&self) {
+        let (mut vertex_array_id, mut buffer_id) = (0, 0);
+
+        unsafe {
+            gl::GenVertexArrays(1, &mut vertex_array_id);
+            gl::GenBuffers(1, &mut buffer_id);
+
+            gl::BindVertexArray(vertex_array_id);
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
+
+            let stride = (VERTEX_FLOATS * std::mem::size_of::<f32>()) as GLsizei;
+            let mut offset = 0;
+
+            for (location, size) in [2, 3, 4].iter().enumerate() {
+                gl::EnableVertexAttribArray(location as GLuint);
+                gl::VertexAttribPointer(
+                    location as GLuint, *size, gl::FLOAT, gl::FALSE, stride,
+                    (offset * std::mem::size_of::<f32>()) as *const std::ffi::c_void,
+                );
+
+                offset += *size as usize;
+            }
+
+            gl::BindVertexArray(0);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+
+        self.vertex_array_id.set(vertex_array_id);
+        self.buffer_id.set(buffer_id);
+    }
+
+    pub fn delete(&self) {
+        if self.vertex_array_id.get() != 0 {
+            unsafe {
+                gl::DeleteVertexArrays(1, &self.vertex_array_id.get());
+                gl::DeleteBuffers(1, &self.buffer_id.get());
+            }
+
+            self.vertex_array_id.set(0);
+            self.buffer_id.set(0);
+        }
+    }
+}
+
+impl Renderable for Ui {
+    // The UI is always in screen pixels.
+    fn get_model_matrix(&self) -> Mat4 {
+        Mat4::IDENTITY
+    }
+
+    // Only the layer's view size is used, the UI covers the whole screen whatever the layer's camera does.
+    fn render(&self, layer: &GraphicsLayer) {
+        let screen_size = match &layer.view {
+            View::View2D(view) => view.size,
+            View::View3D(view) => view.size,
+        };
+
+        self.screen_size.set(screen_size);
+
+        if self.finished.batches.is_empty() {
+            return;
+        }
+
+        if self.vertex_array_id.get() == 0 {
+            self.init_buffers();
+        }
+
+        // Centered on the screen's middle so the top left corner is at 0.
+        let view = View2D::new(screen_size).with_position(Vec3::new(screen_size.x / 2.0, -screen_size.y / 2.0, -1.0));
+
+        let shader_program = Self::get_shader_program();
+        shader_program.set_uniform_mat4_f32("projection", &view.get_view_matrix());
+        shader_program.set_uniform_bool("use_sdf", self.font.is_sdf());
+        shader_program.set_uniform_i32("glyph_atlas", 0);
+        self.font.atlas.texture.bind(0, true);
+
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer_id.get());
+            gl::BufferData(
+                gl::ARRAY_BUFFER,
+                std::mem::size_of_val(self.finished.vertices.as_slice()) as GLsizeiptr,
+                self.finished.vertices.as_ptr() as *const std::ffi::c_void,
+                gl::STREAM_DRAW,
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+            let depth_test_enabled = gl::IsEnabled(gl::DEPTH_TEST) == gl::TRUE;
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Enable(gl::SCISSOR_TEST);
+
+            shader_program.use_program(true);
+            gl::BindVertexArray(self.vertex_array_id.get());
+
+            for batch in self.finished.batches.iter() {
+                let size = batch.clip.get_size();
+
+                if size.x <= 0.0 || size.y <= 0.0 {
+                    continue;
+                }
+
+                // Scissor rectangles count from the bottom left of the framebuffer.
+                gl::Scissor(
+                    batch.clip.min.x.floor() as GLint,
+                    (screen_size.y - batch.clip.max.y).floor() as GLint,
+                    size.x.ceil() as GLsizei,
+                    size.y.ceil() as GLsizei,
+                );
+
+                gl::DrawArrays(gl::TRIANGLES, batch.first as GLint, batch.count as GLsizei);
+            }
+
+            gl::BindVertexArray(0);
+            shader_program.use_program(false);
+
+            gl::Disable(gl::SCISSOR_TEST);
+
+            if depth_test_enabled {
+                gl::Enable(gl::DEPTH_TEST);
+            }
+        }
+    }
+
+    fn is_transparent(&self) -> bool {
+        true
+    }
+
+    // Over everything else in the queue.
+    fn get_z_order(&self) -> i32 {
+        i32::MAX
+    }
+}
+
+impl Drop for Ui {
+    fn drop(&mut self) {
+        self.delete();
+    }
+}
@@ -1,5 +1,6 @@
 use core::{fmt, slice};
 use std::{ffi::{c_void, CString}, mem};
+use bevy_mikktspace::Geometry;
 use gl::types::*;
 use glam::Vec3;
 
@@ -21,6 +22,9 @@ pub struct Vertex {
     pub bone_ids: Vec3,
     pub bone_weights: Vec3,
     pub color: Vec3,
+    // Tangent space for normal maps, zero until imported or generated.
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
 }
 
 impl Vertex {
@@ -32,8 +36,68 @@ impl Vertex {
             bone_ids: Vec3::new(0.0, 0.0, 0.0),
             bone_weights: Vec3::new(0.0, 0.0, 0.0),
             color: Vec3::new(1.0, 1.0, 1.0),
+            tangent: Vec3::new(0.0, 0.0, 0.0),
+            bitangent: Vec3::new(0.0, 0.0, 0.0),
         }
     }
+
+    // MikkTSpace tangents, the ones normal maps are baked against by Blender and most other tools. Needs normals
+    // and texture coordinates. Vertices shared between triangles keep the tangent of the last one, like most
+    // engines do with indexed meshes.
+    pub fn generate_tangents(vertex_array: &mut Vec<Vertex>, index_array: &Vec<u32>) {
+        let mut geometry = TangentGeometry {
+            vertex_array,
+            index_array,
+        };
+
+        bevy_mikktspace::generate_tangents(&mut geometry);
+    }
+
+    pub fn has_tangents(vertex_array: &[Vertex]) -> bool {
+        vertex_array.iter().any(|vertex| vertex.tangent != Vec3::ZERO)
+    }
+}
+
+struct TangentGeometry<'a> {
+    vertex_array: &'a mut Vec<Vertex>,
+    index_array: &'a Vec<u32>,
+}
+
+impl<'a> TangentGeometry<'a> {
+    fn get_vertex(&self, face: usize, vert: usize) -> &Vertex {
+        &self.vertex_array[self.index_array[face * 3 + vert] as usize]
+    }
+}
+
+impl<'a> Geometry for TangentGeometry<'a> {
+    fn num_faces(&self) -> usize {
+        self.index_array.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.get_vertex(face, vert).position.to_array()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.get_vertex(face, vert).normals.to_array()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.get_vertex(face, vert).texture.truncate().to_array()
+    }
+
+    // w is the sign of the bitangent, which is the cross product of the normal and the tangent.
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        let index = self.index_array[face * 3 + vert] as usize;
+        let vertex = &mut self.vertex_array[index];
+
+        vertex.tangent = Vec3::new(tangent[0], tangent[1], tangent[2]);
+        vertex.bitangent = vertex.normals.cross(vertex.tangent) * tangent[3];
+    }
 }
 
 impl fmt::Display for Vertex {
@@ -92,6 +156,13 @@ impl VAO {
     
         unsafe {
             let attrib_loc: i32 = gl::GetAttribLocation(shader_program.program_id, c_str.as_ptr());
+
+            // Shaders that don't use the attribute still need the offset to move past it.
+            if attrib_loc < 0 {
+                self.component_count += attribute_component_count;
+                return;
+            }
+
             gl::EnableVertexAttribArray(attrib_loc as u32);
             gl::VertexAttribPointer(attrib_loc as u32, 3, gl::FLOAT, gl::FALSE, i32::try_from(VERTEX_SIZE).unwrap(), (self.component_count*FLOAT_SIZE) as *const c_void);
         }
@@ -170,6 +241,8 @@ impl VAO {
         vao.set_vertex_attribute(shader_program.clone(), "in_bone_ids", 3);
         vao.set_vertex_attribute(shader_program.clone(), "in_bone_weights", 3);
         vao.set_vertex_attribute(shader_program.clone(), "in_color", 3);
+        vao.set_vertex_attribute(shader_program.clone(), "in_tangent", 3);
+        vao.set_vertex_attribute(shader_program.clone(), "in_bitangent", 3);
 
         shader_program.use_program(true);
 
@@ -227,6 +300,19 @@ impl VBO {
         }
     }
 
+    // Reserves room for vertex_count vertices that get filled in later with update_data.
+    pub fn allocate(&self, vertex_count: usize) {
+        unsafe {
+            gl::BufferData(gl::ARRAY_BUFFER, vertex_count as isize * VERTEX_SIZE, std::ptr::null(), gl::DYNAMIC_DRAW);
+        }
+    }
+
+    pub fn update_data(&self, first_vertex: usize, vertex_data: &[Vertex]) {
+        unsafe {
+            gl::BufferSubData(gl::ARRAY_BUFFER, first_vertex as isize * VERTEX_SIZE, vertex_data.len() as isize * VERTEX_SIZE, vertex_data.as_ptr() as *const c_void);
+        }
+    }
+
     pub fn delete(&self) {
         unsafe {
             gl::DeleteBuffers(1, &self.id);
@@ -271,6 +357,13 @@ impl IBO {
         }
     }
 
+    // Same as add_data without the 32767 index limit, for buffers that hold a lot of quads.
+    pub fn add_large_data(&self, index_data: &[u32]) {
+        unsafe {
+            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, index_data.len() as isize * mem::size_of::<u32>() as isize, index_data.as_ptr() as *const c_void, gl::STATIC_DRAW);
+        }
+    }
+
     pub fn delete(&self) {
         unsafe {
             gl::DeleteBuffers(1, &self.id);
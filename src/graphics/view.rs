@@ -1,7 +1,14 @@
+use std::rc::Rc;
+
 use glam::{Vec2, Vec3, Mat4};
 use super::color::Color;
-use super::renderable::Renderable;
+use super::debug::DebugDraw;
+use super::font::Font;
+use super::framebuffer::RenderTarget;
+use super::render_queue::RenderQueue;
+use super::renderable::{Renderable, RenderableSprite};
 use super::math::Deg;
+use super::shadow::DepthPass;
 
 #[derive(Clone)]
 pub enum View {
@@ -16,6 +23,8 @@ pub struct View3D {
     pub front: Vec3,
     pub up: Vec3,
     pub fov: Deg,
+    pub near: f32,
+    pub far: f32,
 }
 
 impl View3D {
@@ -26,6 +35,8 @@ impl View3D {
             front: Vec3::new(0.0, 0.0, 1.0),
             up: Vec3::new(0.0, 1.0, 0.0),
             fov: Deg(70.0),
+            near: 0.1,
+            far: 100.0,
         }
     }
 
@@ -49,11 +60,26 @@ impl View3D {
         self
     }
 
-    pub fn get_view_matrix(&self) -> Mat4 {
+    pub fn with_clip_planes(mut self, near: f32, far: f32) -> Self {
+        self.near = near;
+        self.far = far;
+        self
+    }
+
+    pub fn get_projection_matrix(&self) -> Mat4 {
         Mat4::perspective_lh(
             self.fov.to_radians().as_float(), 
-            self.size.x / self.size.y, 0.1, 100.0
-        ) * Mat4::look_at_lh(
+            self.size.x / self.size.y, self.near, self.far
+        )
+    }
+
+    // The camera without its position, for things infinitely far away like the skybox.
+    pub fn get_rotation_matrix(&self) -> Mat4 {
+        Mat4::look_at_lh(Vec3::ZERO, self.front, self.up)
+    }
+
+    pub fn get_view_matrix(&self) -> Mat4 {
+        self.get_projection_matrix() * Mat4::look_at_lh(
             self.position, 
             self.position + self.front, 
             self.up
@@ -114,6 +140,18 @@ pub struct GraphicsLayer {
     pub rotation: Vec3,
     pub scale: Vec3,
     pub parent: Option<Box<GraphicsLayer>>,
+    // Set while ShadowMaps renders, objects only draw their depth from the light.
+    pub depth_pass: Option<DepthPass>,
+    // Shared with clones of the layer. The window flushes its default layer's every frame.
+    pub debug_draw: DebugDraw,
+    // Debug labels aren't drawn without one.
+    pub debug_font: Option<Rc<Font>>,
+    // Shared with clones of the layer like debug_draw, and with children. The window flushes its default layer's
+    // every frame, before debug drawing.
+    pub render_queue: RenderQueue,
+    // Replaces position, rotation, scale and parent in get_graphics_layer_matrix when set. RenderQueue uses it to draw
+    // objects with the transform of the layer they were submitted through.
+    pub matrix_override: Option<Mat4>,
 }
 
 impl GraphicsLayer {
@@ -124,9 +162,19 @@ impl GraphicsLayer {
             rotation: Vec3::new(0.0, 0.0, 0.0),
             scale: Vec3::new(1.0, 1.0, 1.0),
             parent: None,
+            depth_pass: None,
+            debug_draw: DebugDraw::new(),
+            debug_font: None,
+            render_queue: RenderQueue::new(),
+            matrix_override: None,
         }
     }
 
+    pub fn with_debug_font(mut self, font: &Rc<Font>) -> Self {
+        self.debug_font = Some(font.clone());
+        self
+    }
+
     pub fn with_position(mut self, position: Vec3) -> Self {
         self.position = position;
         self
@@ -143,6 +191,10 @@ impl GraphicsLayer {
     }
 
     pub fn get_graphics_layer_matrix(&self) -> Mat4 {
+        if let Some(matrix) = self.matrix_override {
+            return matrix;
+        }
+
         let translation = Mat4::from_translation(self.position);
 
         let rotation_x = Mat4::from_rotation_x(Deg(self.rotation.x).to_radians().as_float());
@@ -162,11 +214,57 @@ impl GraphicsLayer {
     }
 
     pub fn add_child(self, child: &mut GraphicsLayer) {
+        child.render_queue = self.render_queue.clone();
         child.parent = Some(Box::new(self));
     }
 
     pub fn render_object(&self, obj: &mut dyn Renderable) {
-        obj.render(self);
+        match self.get_depth_pass() {
+            Some(depth_pass) => obj.render_depth(self, depth_pass),
+            None => obj.render(self),
+        }
+    }
+
+    // Draws back to front by z order, sprites with the same z order in the order given.
+    pub fn render_sprites(&self, sprites: &mut [&mut RenderableSprite]) {
+        sprites.sort_by_key(|sprite| sprite.z_order);
+
+        for sprite in sprites.iter_mut() {
+            self.render_object(*sprite);
+        }
+    }
+
+    // Queues the object to be drawn sorted with everything else submitted this frame, see RenderQueue. The window
+    // only flushes its own layer's queue, which clones and children of it share. A separate layer made with new has
+    // its own, flush it yourself.
+    pub fn submit<T: Renderable + 'static>(&self, obj: &Rc<T>) {
+        self.render_queue.submit(self, obj);
+    }
+
+    pub fn flush_render_queue(&self) {
+        self.render_queue.flush(self);
+    }
+
+    // Child layers take the depth pass from their parents.
+    pub fn get_depth_pass(&self) -> Option<&DepthPass> {
+        match &self.depth_pass {
+            Some(depth_pass) => Some(depth_pass),
+            None => self.parent.as_ref().and_then(|parent| parent.get_depth_pass()),
+        }
+    }
+
+    // Renders into the target instead of the screen, with this layer's view and transform.
+    pub fn render_object_to(&self, target: &RenderTarget, obj: &mut dyn Renderable) {
+        target.render_object(self, obj);
+    }
+
+    // Debug drawing in this layer's space, so shapes follow its position, rotation and scale.
+    pub fn get_debug_draw(&self) -> DebugDraw {
+        self.debug_draw.clone().with_transform(&self.get_graphics_layer_matrix())
+    }
+
+    pub fn flush_debug_draw(&self) {
+        self.debug_draw.flush(&self.view, self.debug_font.as_deref()).unwrap();
     }
 
     pub fn clear_screen(&self, color: Color) {
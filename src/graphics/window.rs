@@ -3,7 +3,7 @@ use glutin::config::Config;
 use glutin::surface::WindowSurface;
 use winit::dpi::{LogicalSize, PhysicalSize};
 use raw_window_handle::HasRawWindowHandle;
-use winit::event::ElementState;
+use winit::event::{ElementState, MouseScrollDelta};
 pub use winit::event::{Event, KeyEvent, WindowEvent};
 use winit::platform::scancode::PhysicalKeyExtScancode;
 use winit::window::WindowBuilder;
@@ -20,6 +20,7 @@ use crate::util::entity::Entity;
 use crate::graphics::view::{GraphicsLayer, View};
 use std::time::{Duration, Instant};
 use super::color::Color;
+use super::post_process::PostProcessStack;
 
 extern crate gl;
 
@@ -38,6 +39,7 @@ pub struct Window {
     pub current_frames_per_second: u64,
     pub current_ticks_per_second: u64,
     pub default_graphics_layer: GraphicsLayer,
+    pub post_process: Option<PostProcessStack>,
 }
 
 impl Window {
@@ -116,9 +118,16 @@ impl Window {
             current_frames_per_second: 0,
             current_ticks_per_second: 0,
             default_graphics_layer: default_graphics_layer.clone(),
+            post_process: None,
         })
     }
 
+    // Every frame is rendered into the stack and goes through its passes before it's presented.
+    pub fn with_post_processing(mut self, post_process: PostProcessStack) -> Self {
+        self.post_process = Some(post_process);
+        self
+    }
+
     pub fn run(mut self, loop_handler: &mut Entity, target_ticks_per_second: u64, target_frames_per_second: u64) {
         let mut last_tick_time = Instant::now();
         let mut last_frame_time = Instant::now();
@@ -167,6 +176,13 @@ impl Window {
 
                         gl::Enable( gl::BLEND );
                         gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+                        // Cubemaps filter across face edges instead of clamping at them.
+                        gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+                    }
+
+                    if let Some(post_process) = &mut self.post_process {
+                        post_process.init(&UVec2::new(self.size.width, self.size.height)).unwrap();
                     }
 
                     loop_handler.init();
@@ -214,8 +230,19 @@ impl Window {
                 },
                 Event::WindowEvent { event, .. } => match event {
                     WindowEvent::RedrawRequested => {
+                        if let Some(post_process) = &self.post_process {
+                            post_process.begin();
+                        }
+
                         Window::clear_screen(Color::new(0, 0, 0, 255));
                         loop_handler.render(&mut self.default_graphics_layer);
+                        self.default_graphics_layer.flush_render_queue();
+                        self.default_graphics_layer.flush_debug_draw();
+
+                        if let Some(post_process) = &self.post_process {
+                            post_process.end();
+                        }
+
                         self.context_surface.as_ref().unwrap().swap_buffers(self.current_context.as_ref().unwrap()).unwrap();
                     },
                     WindowEvent::Resized(size) => {
@@ -233,15 +260,23 @@ impl Window {
                         unsafe {
                             gl::Viewport(0, 0, self.size.width as i32, self.size.height as i32);
                         }
+
+                        if let Some(post_process) = &mut self.post_process {
+                            post_process.resize(&UVec2::new(size.width, size.height));
+                        }
                     },
                     WindowEvent::CloseRequested => {
                         elwt.exit();
                         loop_handler.exit();
                     },
                     WindowEvent::KeyboardInput { event, .. } => match event {
-                        KeyEvent { physical_key, logical_key: _, text: _, location: _, state, repeat, .. } => {
+                        KeyEvent { physical_key, logical_key: _, text, location: _, state, repeat, .. } => {
                             match state {
                                 ElementState::Pressed => {
+                                    if let Some(text) = text {
+                                        self.input.add_text(text.as_str());
+                                    }
+
                                     if repeat == false {
                                         self.input.set_key_pressed(physical_key.to_scancode().unwrap());
                                     }
@@ -264,6 +299,15 @@ impl Window {
                             }
                         }
                     },
+                    WindowEvent::MouseWheel { device_id: _, delta, phase: _ } => {
+                        // Touchpads scroll in pixels, about 20 of them make a line.
+                        let delta = match delta {
+                            MouseScrollDelta::LineDelta(x, y) => DVec2::new(x as f64, y as f64),
+                            MouseScrollDelta::PixelDelta(position) => DVec2::new(position.x, position.y) / 20.0,
+                        };
+
+                        self.input.add_scroll(&delta);
+                    },
                     WindowEvent::CursorMoved { device_id, position } => {
                         self.input.set_mouse_position(
                             &DVec2::new(position.x as f64, position.y as f64), 
@@ -4,49 +4,32 @@ pub mod util;
 use std::time::Duration;
 
 use glam::{Vec2, Vec3};
-use util::{entity::Entity, event::EventQueue};
-use graphics::{animation::SpriteAnimation, color::Color, mesh::{AnimatedMesh, AnimatedMeshData, Mesh}, renderable::{RenderableMesh, RenderableSprite}, shader::{ShaderBuilder, ShaderFunction, ShaderProgram}, view::{GraphicsLayer, View, View2D, View3D}, window::{self, Window}};
-use util::event::Input;
-
-use crate::graphics::{shader::{ShaderSource, *}, texture::Texture};
-use crate::graphics::shader::ShaderType;
+use util::{app::App, entity::Entity};
+use graphics::{animation::SpriteAnimation, atlas::TextureAtlasBuilder, renderable::RenderableSprite, shader::{ShaderBuilderTemplate, ShaderProgram}, view::{View, View2D}};
 
 
 fn main() {
-    let mut application = Entity::new()
+    let application = Entity::new()
         .with_init(|entity| {
-            let vertex_shader = ShaderBuilderTemplate::basic_vertex_shader("#version 450 core")
-                .build(&ShaderType::VERTEX)
-                .compile()
-                .unwrap()
-            ;
-
-            let fragment_shader = ShaderBuilderTemplate::texture_fragment_shader("#version 450 core")
-                .build(&ShaderType::FRAGMENT)
-                .compile()
-                .unwrap()
-            ;
-
-            let mut shader_program = ShaderProgram::new();
-            shader_program.attach_shader(&vertex_shader);
-            shader_program.attach_shader(&fragment_shader);
-
-            shader_program.build();
-
-            let animation_frames = vec![
-                Texture::from_file("./res/Idle/Idle1.png"),
-                Texture::from_file("./res/Idle/Idle2.png"),
-                Texture::from_file("./res/Idle/Idle3.png"),
-                Texture::from_file("./res/Idle/Idle4.png"),
-                Texture::from_file("./res/Idle/Idle5.png"),
-                Texture::from_file("./res/Idle/Idle6.png"),
-            ];
+            let shader_program = ShaderProgram::from_builders(
+                &ShaderBuilderTemplate::basic_vertex_shader("#version 450 core"),
+                &ShaderBuilderTemplate::texture_fragment_shader("#version 450 core"),
+            ).unwrap();
+
+            let atlas = TextureAtlasBuilder::new()
+                .with_file("./res/Idle/Idle1.png")
+                .with_file("./res/Idle/Idle2.png")
+                .with_file("./res/Idle/Idle3.png")
+                .with_file("./res/Idle/Idle4.png")
+                .with_file("./res/Idle/Idle5.png")
+                .with_file("./res/Idle/Idle6.png")
+                .build();
 
             let sprite = RenderableSprite::new(
                 &Vec2::new(800.0, 800.0),
                 &shader_program,
             )
-                .with_animation(&SpriteAnimation::new(&animation_frames))
+                .with_animation(&SpriteAnimation::from_regions(&atlas.get_regions()))
                 .with_position(&Vec3::new(-5.0, -200.0, 0.0))
             ;
 
@@ -59,7 +42,7 @@ fn main() {
 
             entity.variables.insert("sprite", sprite);
         })
-        .with_update(|entity, event_queue, input, delta| {
+        .with_update(|entity, _event_queue, _input, delta| {
             let mut sprite = entity.variables.take_out::<RenderableSprite>("sprite");
 
             sprite.animate(delta, &Duration::from_secs_f32(0.5));
@@ -68,12 +51,10 @@ fn main() {
         })
     ;
 
-    let view = View::View2D(
-        View2D::new(Vec2::new(1920.0, 1080.0))
-    );
-    let graphics = GraphicsLayer::new(&view);
-
-    let mut window = Window::new("Rustler", &graphics).unwrap();
-
-    window.run(&mut application, 20, 0);
+    App::new("Rustler")
+        .with_view(View::View2D(View2D::new(Vec2::new(1920.0, 1080.0))))
+        .with_ticks_per_second(20)
+        .with_root(application)
+        .run()
+        .unwrap();
 }
\ No newline at end of file
@@ -0,0 +1,141 @@
+use std::any::Any;
+use std::error::Error;
+
+use glam::Vec2;
+use winit::event::Event;
+
+use crate::graphics::post_process::PostProcessStack;
+use crate::graphics::view::{GraphicsLayer, View, View2D};
+use crate::graphics::window::Window;
+use super::entity::Entity;
+use super::system::{Resources, System, SystemScheduler};
+
+pub type EventHandler = fn(event: &Event<()>, resources: &Resources);
+
+// A subsystem (input mapping, audio, physics, debug overlay...) that sets itself up on an App in one call.
+pub trait Plugin {
+    fn build(&self, app: &mut App);
+
+    // Plugins with the same name are only built once.
+    fn name(&self) -> String {
+        String::from(std::any::type_name::<Self>())
+    }
+}
+
+pub struct App {
+    pub title: String,
+    pub view: View,
+    pub target_ticks_per_second: u64,
+    // 0 means as fast as possible.
+    pub target_frames_per_second: u64,
+    pub scheduler: SystemScheduler,
+    pub root: Entity,
+    pub plugins: Vec<String>,
+    pub post_process: Option<PostProcessStack>,
+}
+
+impl App {
+    pub fn new(title: &str) -> Self {
+        App {
+            title: String::from(title),
+            view: View::View2D(View2D::new(Vec2::new(1920.0, 1080.0))),
+            target_ticks_per_second: 20,
+            target_frames_per_second: 0,
+            scheduler: SystemScheduler::new(),
+            root: Entity::new(),
+            plugins: Vec::new(),
+            post_process: None,
+        }
+    }
+
+    pub fn with_view(mut self, view: View) -> Self {
+        self.view = view;
+        self
+    }
+
+    pub fn with_ticks_per_second(mut self, target_ticks_per_second: u64) -> Self {
+        self.target_ticks_per_second = target_ticks_per_second;
+        self
+    }
+
+    pub fn with_frames_per_second(mut self, target_frames_per_second: u64) -> Self {
+        self.target_frames_per_second = target_frames_per_second;
+        self
+    }
+
+    // The entity tree the window runs, for example a plain Entity or StateStack::build().
+    pub fn with_root(mut self, root: Entity) -> Self {
+        self.root = root;
+        self
+    }
+
+    // Passes run on every frame before it's presented, see PostProcessStack.
+    pub fn with_post_processing(mut self, post_process: PostProcessStack) -> Self {
+        self.post_process = Some(post_process);
+        self
+    }
+
+    pub fn add_plugin<P: Plugin>(&mut self, plugin: P) {
+        let name = plugin.name();
+
+        if self.plugins.contains(&name) {
+            return;
+        }
+
+        self.plugins.push(name);
+        plugin.build(self);
+    }
+
+    pub fn with_plugin<P: Plugin>(mut self, plugin: P) -> Self {
+        self.add_plugin(plugin);
+        self
+    }
+
+    pub fn has_plugin(&self, name: &str) -> bool {
+        self.plugins.iter().any(|plugin| plugin == name)
+    }
+
+    pub fn add_system(&mut self, system: System) {
+        self.scheduler.add_system(system);
+    }
+
+    pub fn with_system(mut self, system: System) -> Self {
+        self.add_system(system);
+        self
+    }
+
+    pub fn insert_resource<T: Any + Send + Sync>(&mut self, value: T) {
+        self.scheduler.insert_resource(value);
+    }
+
+    pub fn with_resource<T: Any + Send + Sync>(mut self, value: T) -> Self {
+        self.insert_resource(value);
+        self
+    }
+
+    pub fn add_event_handler(&mut self, handler: EventHandler) {
+        self.scheduler.add_event_handler(handler);
+    }
+
+    pub fn with_event_handler(mut self, handler: EventHandler) -> Self {
+        self.add_event_handler(handler);
+        self
+    }
+
+    pub fn run(mut self) -> Result<(), Box<dyn Error>> {
+        let graphics = GraphicsLayer::new(&self.view);
+
+        // Systems draw through the same DebugDraw the window flushes.
+        self.scheduler.insert_resource(graphics.debug_draw.clone());
+        let mut root = self.scheduler.build(self.root)?;
+        let mut window = Window::new(self.title.as_str(), &graphics)?;
+
+        if let Some(post_process) = self.post_process {
+            window = window.with_post_processing(post_process);
+        }
+
+        window.run(&mut root, self.target_ticks_per_second, self.target_frames_per_second);
+
+        Ok(())
+    }
+}
@@ -7,11 +7,15 @@ use crate::graphics::view::GraphicsLayer;
 use crate::graphics::window::Window;
 use super::event::EventQueue;
 use super::event::Input;
+use super::timer::Scheduler;
 
 pub struct Entity {
     pub children: Vec<Entity>,
     pub variables: Box<EntityVariableArray>,
     pub window: Option<Box<Window>>,
+    pub scheduler: Scheduler,
+    // Set by destroy. The parent removes the entity after updating its children, the root has no parent and stays.
+    pub is_destroyed: bool,
 
     pub init_func: fn(entity: &mut Entity),
     pub render_func: fn(entity: &mut Entity, graphics: &mut GraphicsLayer),
@@ -25,6 +29,8 @@ impl Entity {
             children: Vec::new(),
             variables: Box::new(EntityVariableArray::new()),
             window: None,
+            scheduler: Scheduler::new(),
+            is_destroyed: false,
 
             init_func: |entity: &mut Entity| {},
             render_func: |entity: &mut Entity, graphics: &mut GraphicsLayer| {},
@@ -66,6 +72,10 @@ impl Entity {
         child
     }
 
+    pub fn destroy(&mut self) {
+        self.is_destroyed = true;
+    }
+
     pub fn init(&mut self) {
         (self.init_func)(self);
     }
@@ -83,6 +93,17 @@ impl Entity {
             self.children[i].update(event_queue, input, delta);
         }
 
+        let mut i = 0;
+        while i < self.children.len() {
+            if self.children[i].is_destroyed {
+                self.pop(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        Scheduler::update(self, delta);
+
         (self.update_func)(self, event_queue, input, delta);
     }
 
@@ -110,6 +131,18 @@ impl EntityVariableArray {
         self.variables.insert(name.to_string(), Box::new(value));
     }
 
+    pub fn with<T: 'static>(mut self, name: &str, value: T) -> Self {
+        self.insert(name, value);
+        self
+    }
+
+    // Moves every variable of other into this array, replacing the ones with the same name.
+    pub fn merge(&mut self, other: EntityVariableArray) {
+        for (name, value) in other.variables {
+            self.variables.insert(name, value);
+        }
+    }
+
     pub fn contains(&self, name: &str) -> bool {
         self.variables.contains_key(name)
     }
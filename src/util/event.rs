@@ -41,6 +41,7 @@ impl EventQueue {
     }
 }
 
+#[derive(Clone)]
 pub struct Input {
     pub keys: [bool; 255],
     pub keys_last: [bool; 255],
@@ -48,6 +49,14 @@ pub struct Input {
     pub buttons_last: [bool; 32],
     pub mouse_position: DVec2,
     pub mouse_position_last: DVec2,
+    // Wheel lines since the last update, positive y scrolls up.
+    pub scroll_delta: DVec2,
+    // Characters typed since the last update, key repeats included.
+    pub text: String,
+    // Set by the UI while it uses the mouse or keyboard, so the game doesn't react to the same input. The query
+    // methods below act as if nothing was pressed while they're set. Cleared every tick by update.
+    pub mouse_consumed: bool,
+    pub keyboard_consumed: bool,
 }
 
 impl Input {
@@ -65,6 +74,10 @@ impl Input {
             buttons_last: buttons_last,
             mouse_position: DVec2::new(0.0, 0.0),
             mouse_position_last: DVec2::new(0.0, 0.0),
+            scroll_delta: DVec2::new(0.0, 0.0),
+            text: String::new(),
+            mouse_consumed: false,
+            keyboard_consumed: false,
         }
     }
 
@@ -107,6 +120,15 @@ impl Input {
         self.mouse_position = position;
     }
 
+    pub fn add_scroll(&mut self, delta: &DVec2) {
+        self.scroll_delta += *delta;
+    }
+
+    // Control characters are left to the key states.
+    pub fn add_text(&mut self, text: &str) {
+        self.text.extend(text.chars().filter(|character| !character.is_control()));
+    }
+
     pub fn update(&mut self) {
         for i in 0..self.keys.len() {
             self.keys_last[i] = self.keys[i];
@@ -117,12 +139,85 @@ impl Input {
         }
 
         self.mouse_position_last = self.mouse_position;
+        self.scroll_delta = DVec2::new(0.0, 0.0);
+        self.text.clear();
+
+        self.mouse_consumed = false;
+        self.keyboard_consumed = false;
     }
 
     pub fn key_to_scancode(key: KeyCode) -> u32 {
         PhysicalKey::Code(key).to_scancode().unwrap()
     }
 
+    // Names as written by people (or scripts): "A", "7", "Space", "Left", "LeftShift", "F1"... Case doesn't matter.
+    pub fn key_from_name(name: &str) -> Option<KeyCode> {
+        let name = name.to_lowercase();
+
+        let letters = [
+            KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF, KeyCode::KeyG,
+            KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL, KeyCode::KeyM, KeyCode::KeyN,
+            KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR, KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU,
+            KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX, KeyCode::KeyY, KeyCode::KeyZ,
+        ];
+
+        let digits = [
+            KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+            KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+        ];
+
+        let function_keys = [
+            KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6,
+            KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+        ];
+
+        if name.len() == 1 {
+            let character = name.chars().next().unwrap();
+
+            if character.is_ascii_lowercase() {
+                return Some(letters[(character as u8 - b'a') as usize]);
+            }
+
+            if character.is_ascii_digit() {
+                return Some(digits[(character as u8 - b'0') as usize]);
+            }
+        }
+
+        if let Some(number) = name.strip_prefix('f').and_then(|number| number.parse::<usize>().ok()) {
+            return function_keys.get(number.wrapping_sub(1)).copied();
+        }
+
+        match name.as_str() {
+            "space" => Some(KeyCode::Space),
+            "enter" => Some(KeyCode::Enter),
+            "escape" => Some(KeyCode::Escape),
+            "tab" => Some(KeyCode::Tab),
+            "backspace" => Some(KeyCode::Backspace),
+            "up" => Some(KeyCode::ArrowUp),
+            "down" => Some(KeyCode::ArrowDown),
+            "left" => Some(KeyCode::ArrowLeft),
+            "right" => Some(KeyCode::ArrowRight),
+            "leftshift" => Some(KeyCode::ShiftLeft),
+            "rightshift" => Some(KeyCode::ShiftRight),
+            "leftcontrol" => Some(KeyCode::ControlLeft),
+            "rightcontrol" => Some(KeyCode::ControlRight),
+            "leftalt" => Some(KeyCode::AltLeft),
+            "rightalt" => Some(KeyCode::AltRight),
+            _ => None,
+        }
+    }
+
+    pub fn mouse_button_from_name(name: &str) -> Option<MouseButton> {
+        match name.to_lowercase().as_str() {
+            "left" => Some(MouseButton::Left),
+            "right" => Some(MouseButton::Right),
+            "middle" => Some(MouseButton::Middle),
+            "back" => Some(MouseButton::Back),
+            "forward" => Some(MouseButton::Forward),
+            _ => None,
+        }
+    }
+
     pub fn mouse_button_to_index(button: MouseButton) -> u32 {
         match button {
             MouseButton::Left => { 0 },
@@ -135,7 +230,7 @@ impl Input {
     }
 
     pub fn was_key_just_pressed(&self, key: KeyCode) -> bool {
-        if Self::key_to_scancode(key) as usize >= self.keys.len() {
+        if self.keyboard_consumed || Self::key_to_scancode(key) as usize >= self.keys.len() {
             return false;
         }
 
@@ -143,7 +238,7 @@ impl Input {
     }
 
     pub fn was_key_just_released(&self, key: KeyCode) -> bool {
-        if Self::key_to_scancode(key) as usize >= self.keys.len() {
+        if self.keyboard_consumed || Self::key_to_scancode(key) as usize >= self.keys.len() {
             return false;
         }
 
@@ -151,7 +246,7 @@ impl Input {
     }
 
     pub fn is_key_being_held_down(&self, key: KeyCode) -> bool {
-        if Self::key_to_scancode(key) as usize >= self.keys.len() {
+        if self.keyboard_consumed || Self::key_to_scancode(key) as usize >= self.keys.len() {
             return false;
         }
 
@@ -159,7 +254,7 @@ impl Input {
     }
 
     pub fn was_button_just_pressed(&self, button: MouseButton) -> bool {
-        if Self::mouse_button_to_index(button) as usize >= self.buttons.len() {
+        if self.mouse_consumed || Self::mouse_button_to_index(button) as usize >= self.buttons.len() {
             return false;
         }
 
@@ -167,7 +262,7 @@ impl Input {
     }
 
     pub fn was_button_just_released(&self, button: MouseButton) -> bool {
-        if Self::mouse_button_to_index(button) as usize >= self.buttons.len() {
+        if self.mouse_consumed || Self::mouse_button_to_index(button) as usize >= self.buttons.len() {
             return false;
         }
 
@@ -175,7 +270,7 @@ impl Input {
     }
 
     pub fn is_button_being_held(&self, button: MouseButton) -> bool {
-        if Self::mouse_button_to_index(button) as usize >= self.buttons.len() {
+        if self.mouse_consumed || Self::mouse_button_to_index(button) as usize >= self.buttons.len() {
             return false;
         }
 
@@ -186,6 +281,14 @@ impl Input {
         self.mouse_position
     }
 
+    pub fn get_scroll_delta(&self) -> DVec2 {
+        if self.mouse_consumed {
+            return DVec2::new(0.0, 0.0);
+        }
+
+        self.scroll_delta
+    }
+
     pub fn get_mouse_speed_per_frame(&self) -> DVec2 {
         self.mouse_position - self.mouse_position_last
     }
@@ -1,4 +1,10 @@
 
 
+pub mod app;
 pub mod entity;
-pub mod event;
\ No newline at end of file
+pub mod event;
+pub mod prefab;
+pub mod script;
+pub mod state;
+pub mod system;
+pub mod timer;
\ No newline at end of file
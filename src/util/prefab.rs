@@ -0,0 +1,410 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::time::Duration;
+
+use glam::{Vec2, Vec3};
+
+use crate::graphics::texture::TextureCache;
+use crate::graphics::view::GraphicsLayer;
+use super::entity::{Entity, EntityVariableArray};
+use super::event::{EventQueue, Input};
+
+// Anything that can be stored as a prefab default. Every instance gets its own clone of the value.
+pub trait PrefabVariable {
+    fn to_variable(&self) -> Box<dyn Any>;
+    fn clone_prefab_variable(&self) -> Box<dyn PrefabVariable>;
+}
+
+impl<T: Any + Clone> PrefabVariable for T {
+    fn to_variable(&self) -> Box<dyn Any> {
+        Box::new(self.clone())
+    }
+
+    fn clone_prefab_variable(&self) -> Box<dyn PrefabVariable> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PrefabError {
+    pub error_log: String,
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error_log.as_str())
+    }
+}
+
+impl Error for PrefabError {}
+
+impl PrefabError {
+    pub fn new(string: String) -> Self {
+        PrefabError {
+            error_log: string,
+        }
+    }
+}
+
+pub struct Prefab {
+    pub name: String,
+    pub variables: HashMap<String, Box<dyn PrefabVariable>>,
+    // Variable name -> texture file. Loaded through the library's TextureCache when instantiated, every instance gets
+    // a clone of the same SharedTexture. Give it to sprites with with_shared_texture.
+    pub textures: HashMap<String, String>,
+
+    pub init_func: fn(entity: &mut Entity),
+    pub render_func: fn(entity: &mut Entity, graphics: &mut GraphicsLayer),
+    pub update_func: fn(entity: &mut Entity, event_queue: &mut EventQueue, input: &mut Input, delta: &Duration),
+    pub exit_func: fn(entity: &mut Entity),
+}
+
+impl Prefab {
+    pub fn new(name: &str) -> Self {
+        Prefab {
+            name: String::from(name),
+            variables: HashMap::new(),
+            textures: HashMap::new(),
+
+            init_func: |_entity: &mut Entity| {},
+            render_func: |_entity: &mut Entity, _graphics: &mut GraphicsLayer| {},
+            update_func: |_entity: &mut Entity, _event_queue: &mut EventQueue, _input: &mut Input, _delta: &Duration| {},
+            exit_func: |_entity: &mut Entity| {},
+        }
+    }
+
+    // Takes the behavior of an existing Entity builder chain. Its variables are not copied, use with_variable for those.
+    pub fn from_entity(name: &str, entity: &Entity) -> Self {
+        Prefab::new(name)
+            .with_init(entity.init_func)
+            .with_render(entity.render_func)
+            .with_update(entity.update_func)
+            .with_exit(entity.exit_func)
+    }
+
+    pub fn with_init(mut self, init_func: fn(entity: &mut Entity)) -> Self {
+        self.init_func = init_func;
+        self
+    }
+
+    pub fn with_render(mut self, render_func: fn(entity: &mut Entity, graphics: &mut GraphicsLayer)) -> Self {
+        self.render_func = render_func;
+        self
+    }
+
+    pub fn with_update(mut self, update_func: fn(entity: &mut Entity, event_queue: &mut EventQueue, input: &mut Input, delta: &Duration)) -> Self {
+        self.update_func = update_func;
+        self
+    }
+
+    pub fn with_exit(mut self, exit_func: fn(entity: &mut Entity)) -> Self {
+        self.exit_func = exit_func;
+        self
+    }
+
+    pub fn with_variable<T: Any + Clone>(mut self, name: &str, value: T) -> Self {
+        self.variables.insert(name.to_string(), Box::new(value));
+        self
+    }
+
+    pub fn with_texture(mut self, name: &str, file: &str) -> Self {
+        self.textures.insert(name.to_string(), file.to_string());
+        self
+    }
+
+    // Builds a new entity from the template. Overrides replace defaults with the same name.
+    // The entity is not initialized, push it into a parent (or hand it to Window::run) for that.
+    pub fn instantiate(&self, texture_cache: &mut TextureCache, overrides: EntityVariableArray) -> Entity {
+        let mut entity = Entity::new()
+            .with_init(self.init_func)
+            .with_render(self.render_func)
+            .with_update(self.update_func)
+            .with_exit(self.exit_func)
+        ;
+
+        for (name, value) in &self.variables {
+            entity.variables.variables.insert(name.clone(), value.as_ref().to_variable());
+        }
+
+        for (name, file) in &self.textures {
+            entity.variables.insert(name, texture_cache.load(file));
+        }
+
+        entity.variables.merge(overrides);
+
+        entity
+    }
+
+    // Prefab files describe variations of a prefab registered in code, since behavior can't live in a file:
+    //
+    //     name boss
+    //     extends enemy
+    //     f32 health 500
+    //     vec3 position 0 0 0
+    //     texture sprite_texture ./res/Idle/Idle1.png
+    //
+    // Supported variable types are bool, i32, f32, string, vec2 and vec3. Lines starting with # are ignored. extends
+    // starts from a copy of the base, so it has to come before any texture or variable lines, and only once.
+    pub fn from_source_string(source: &str, library: &PrefabLibrary) -> Result<Prefab, PrefabError> {
+        let mut prefab: Option<Prefab> = None;
+        let mut name: Option<String> = None;
+
+        for (line_number, line) in source.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let error = |message: &str| PrefabError::new(format!("line {}: {}", line_number + 1, message));
+
+            if words.len() < 2 {
+                return Err(error("expected at least two words"));
+            }
+
+            match words[0] {
+                "name" => {
+                    name = Some(words[1].to_string());
+                },
+                "extends" => {
+                    if prefab.is_some() {
+                        return Err(error("extends has to come before any texture or variable, and only once"));
+                    }
+
+                    let base = library.get(words[1]).ok_or_else(|| error(format!("unknown prefab '{}'", words[1]).as_str()))?;
+                    prefab = Some(base.clone());
+                },
+                "texture" => {
+                    if words.len() < 3 {
+                        return Err(error("texture expects a variable name and a file"));
+                    }
+
+                    let file = words[2..].join(" ");
+                    prefab = Some(prefab.unwrap_or_else(|| Prefab::new("")).with_texture(words[1], file.as_str()));
+                },
+                type_name => {
+                    let variable_name = words[1];
+                    let values = &words[2..];
+                    let current = prefab.unwrap_or_else(|| Prefab::new(""));
+
+                    let parse_floats = |count: usize| -> Result<Vec<f32>, PrefabError> {
+                        if values.len() != count {
+                            return Err(error(format!("{} expects {} values", type_name, count).as_str()));
+                        }
+
+                        values.iter().map(|value| value.parse::<f32>().map_err(|_| error(format!("'{}' is not a number", value).as_str()))).collect()
+                    };
+
+                    prefab = Some(match type_name {
+                        "bool" => current.with_variable(variable_name, match values {
+                            ["true"] => true,
+                            ["false"] => false,
+                            _ => return Err(error("expected true or false")),
+                        }),
+                        "i32" => current.with_variable(variable_name, match values {
+                            [value] => value.parse::<i32>().map_err(|_| error(format!("'{}' is not an integer", value).as_str()))?,
+                            _ => return Err(error("expected an integer")),
+                        }),
+                        "f32" => current.with_variable(variable_name, parse_floats(1)?[0]),
+                        "string" => current.with_variable(variable_name, values.join(" ")),
+                        "vec2" => {
+                            let floats = parse_floats(2)?;
+                            current.with_variable(variable_name, Vec2::new(floats[0], floats[1]))
+                        },
+                        "vec3" => {
+                            let floats = parse_floats(3)?;
+                            current.with_variable(variable_name, Vec3::new(floats[0], floats[1], floats[2]))
+                        },
+                        _ => return Err(error(format!("unknown type '{}'", type_name).as_str())),
+                    });
+                },
+            }
+        }
+
+        let mut prefab = prefab.unwrap_or_else(|| Prefab::new(""));
+        prefab.name = name.ok_or_else(|| PrefabError::new(String::from("prefab file has no name")))?;
+
+        Ok(prefab)
+    }
+
+    pub fn from_file(file: &str, library: &PrefabLibrary) -> Result<Prefab, PrefabError> {
+        let source = fs::read_to_string(file).map_err(|error| PrefabError::new(format!("{}: {}", file, error)))?;
+
+        Self::from_source_string(source.as_str(), library)
+    }
+}
+
+impl Clone for Prefab {
+    fn clone(&self) -> Self {
+        Prefab {
+            name: self.name.clone(),
+            variables: self.variables.iter().map(|(name, value)| (name.clone(), value.as_ref().clone_prefab_variable())).collect(),
+            textures: self.textures.clone(),
+
+            init_func: self.init_func,
+            render_func: self.render_func,
+            update_func: self.update_func,
+            exit_func: self.exit_func,
+        }
+    }
+}
+
+// Named prefabs plus the textures they share.
+pub struct PrefabLibrary {
+    pub prefabs: HashMap<String, Prefab>,
+    pub texture_cache: TextureCache,
+}
+
+impl Default for PrefabLibrary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrefabLibrary {
+    pub fn new() -> Self {
+        PrefabLibrary {
+            prefabs: HashMap::new(),
+            texture_cache: TextureCache::new(),
+        }
+    }
+
+    pub fn register(&mut self, prefab: Prefab) {
+        self.prefabs.insert(prefab.name.clone(), prefab);
+    }
+
+    pub fn with_prefab(mut self, prefab: Prefab) -> Self {
+        self.register(prefab);
+        self
+    }
+
+    pub fn load_file(&mut self, file: &str) -> Result<String, PrefabError> {
+        let prefab = Prefab::from_file(file, self)?;
+        let name = prefab.name.clone();
+
+        self.register(prefab);
+
+        Ok(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.prefabs.contains_key(name)
+    }
+
+    // None if there's no prefab with the name, names often come from data files.
+    pub fn instantiate(&mut self, name: &str) -> Option<Entity> {
+        self.instantiate_with(name, EntityVariableArray::new())
+    }
+
+    pub fn instantiate_with(&mut self, name: &str, overrides: EntityVariableArray) -> Option<Entity> {
+        let prefab = self.prefabs.get(name)?;
+
+        Some(prefab.instantiate(&mut self.texture_cache, overrides))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enemy_library() -> PrefabLibrary {
+        PrefabLibrary::new().with_prefab(
+            Prefab::new("enemy")
+                .with_init(|entity| entity.variables.insert("initialized", true))
+                .with_variable("health", 100.0_f32)
+                .with_variable("speed", 2.0_f32)
+        )
+    }
+
+    fn get_error(source: &str) -> String {
+        match Prefab::from_source_string(source, &enemy_library()) {
+            Ok(_) => panic!("expected an error for {:?}", source),
+            Err(error) => error.error_log,
+        }
+    }
+
+    #[test]
+    fn parses_every_variable_type() {
+        let source = "
+            # comment
+            name thing
+            bool visible true
+            i32 lives -3
+            f32 health 12.5
+            string title Big Boss
+            vec2 size 1 2
+            vec3 position 1 2 3
+        ";
+
+        let mut library = PrefabLibrary::new().with_prefab(Prefab::from_source_string(source, &PrefabLibrary::new()).unwrap());
+        let mut entity = library.instantiate("thing").unwrap();
+
+        assert!(entity.variables.take_out::<bool>("visible"));
+        assert_eq!(entity.variables.take_out::<i32>("lives"), -3);
+        assert_eq!(entity.variables.take_out::<f32>("health"), 12.5);
+        assert_eq!(entity.variables.take_out::<String>("title"), "Big Boss");
+        assert_eq!(entity.variables.take_out::<Vec2>("size"), Vec2::new(1.0, 2.0));
+        assert_eq!(entity.variables.take_out::<Vec3>("position"), Vec3::new(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn texture_lines_keep_spaces_in_the_file() {
+        let prefab = Prefab::from_source_string("name thing\ntexture sprite ./res/my sprite.png", &PrefabLibrary::new()).unwrap();
+
+        assert_eq!(prefab.textures["sprite"], "./res/my sprite.png");
+    }
+
+    #[test]
+    fn extends_copies_the_base() {
+        let mut library = enemy_library();
+        let boss = Prefab::from_source_string("name boss\nextends enemy\nf32 health 500", &library).unwrap();
+        library.register(boss);
+
+        let mut entity = library.instantiate("boss").unwrap();
+        entity.init();
+
+        assert_eq!(entity.variables.take_out::<f32>("health"), 500.0);
+        assert_eq!(entity.variables.take_out::<f32>("speed"), 2.0);
+        assert!(entity.variables.take_out::<bool>("initialized"));
+
+        let mut enemy = library.instantiate("enemy").unwrap();
+        assert_eq!(enemy.variables.take_out::<f32>("health"), 100.0);
+    }
+
+    #[test]
+    fn overrides_replace_defaults() {
+        let mut library = enemy_library();
+        let mut entity = library.instantiate_with("enemy", EntityVariableArray::new().with("health", 1.0_f32)).unwrap();
+
+        assert_eq!(entity.variables.take_out::<f32>("health"), 1.0);
+    }
+
+    #[test]
+    fn unknown_prefabs_are_none() {
+        assert!(enemy_library().instantiate("missing").is_none());
+    }
+
+    #[test]
+    fn rejects_bad_sources() {
+        assert_eq!(get_error("name boss\nextends missing"), "line 2: unknown prefab 'missing'");
+        assert_eq!(get_error("name boss\nf32 health 5\nextends enemy"), "line 3: extends has to come before any texture or variable, and only once");
+        assert_eq!(get_error("name boss\nextends enemy\nextends enemy"), "line 3: extends has to come before any texture or variable, and only once");
+        assert_eq!(get_error("name boss\ntexture sprite"), "line 2: texture expects a variable name and a file");
+        assert_eq!(get_error("name boss\nbool visible yes"), "line 2: expected true or false");
+        assert_eq!(get_error("name boss\nbool visible"), "line 2: expected true or false");
+        assert_eq!(get_error("name boss\ni32 lives 1.5"), "line 2: '1.5' is not an integer");
+        assert_eq!(get_error("name boss\nf32 health lots"), "line 2: 'lots' is not a number");
+        assert_eq!(get_error("name boss\nvec3 position 1 2"), "line 2: vec3 expects 3 values");
+        assert_eq!(get_error("name boss\nu8 level 1"), "line 2: unknown type 'u8'");
+        assert_eq!(get_error("name"), "line 1: expected at least two words");
+        assert_eq!(get_error("f32 health 5"), "prefab file has no name");
+    }
+}
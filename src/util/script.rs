@@ -0,0 +1,464 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+use glam::{Vec2, Vec3};
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST, FLOAT, INT};
+
+use crate::graphics::renderable::RenderableSprite;
+use crate::graphics::shader::{ShaderBuilderTemplate, ShaderProgram};
+use crate::graphics::texture::TextureCache;
+use crate::graphics::view::GraphicsLayer;
+use super::entity::{Entity, EntityVariableArray};
+use super::event::{EventQueue, Input};
+
+thread_local! {
+    // Compiled on the first sprite any script draws and shared by every script after that.
+    static SCRIPT_SHADER_PROGRAM: RefCell<Option<ShaderProgram>> = const { RefCell::new(None) };
+    // Every script drawing the same file shares its texture.
+    static SCRIPT_TEXTURE_CACHE: RefCell<TextureCache> = RefCell::new(TextureCache::new());
+}
+
+const SCRIPT_SHADER_VERSION: &str = "#version 450 core";
+
+pub const SCRIPT_VARIABLE: &str = "script";
+
+// Limits for what one hook call can do, so a runaway script errors out instead of hanging or eating memory.
+pub const SCRIPT_MAX_OPERATIONS: u64 = 1_000_000;
+pub const SCRIPT_MAX_CALL_LEVELS: usize = 64;
+pub const SCRIPT_MAX_EXPRESSION_DEPTH: usize = 64;
+pub const SCRIPT_MAX_STRING_SIZE: usize = 64 * 1024;
+pub const SCRIPT_MAX_ARRAY_SIZE: usize = 10_000;
+pub const SCRIPT_MAX_MAP_SIZE: usize = 10_000;
+
+// Errors kept on a script until they're taken, older ones are dropped past this.
+pub const SCRIPT_MAX_ERRORS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct ScriptError {
+    pub error_log: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error_log.as_str())
+    }
+}
+
+impl Error for ScriptError {}
+
+impl ScriptError {
+    pub fn new(string: String) -> Self {
+        ScriptError {
+            error_log: string,
+        }
+    }
+}
+
+// Things a script asks for that can only happen once the script call is over.
+pub enum ScriptCommand {
+    DrawSprite { file: String, position: Vec3, size: Vec2, rotation: f32 },
+    Spawn { file: String, variables: Map },
+    Destroy,
+}
+
+// Runs an entity's hooks from a Rhai file. The file may define any of:
+//
+//     fn init() { this.health = 10.0; }
+//     fn update(input, delta) { if input.is_key_down("Space") { this.health -= delta; } }
+//     fn render() { draw_sprite("./res/Idle/Idle1.png", vec2(0.0, 0.0), vec2(100.0, 100.0)); }
+//     fn exit() {}
+//     fn reload() {}
+//
+// `this` holds the entity's variables. Numbers, bools, strings, Vec2 and Vec3 are shared with Rust code,
+// anything else the script stores stays script-only. The file is recompiled whenever it changes on disk.
+//
+// Scripts are designer input, so nothing they do takes the game down. Errors, runaway loops hitting the limits above,
+// missing sprite files and broken spawns are kept in errors for the game to take, and the script carries on.
+pub struct Script {
+    pub file: String,
+    pub engine: Engine,
+    pub ast: AST,
+    pub scope: Scope<'static>,
+    pub last_modified: Option<SystemTime>,
+    pub commands: Rc<RefCell<Vec<ScriptCommand>>>,
+    pub sprites: HashMap<String, RenderableSprite>,
+    // Sprite files that failed to load, so they're reported once instead of every frame.
+    pub missing_sprites: HashSet<String>,
+    pub errors: Vec<ScriptError>,
+}
+
+impl Script {
+    pub fn new(file: &str) -> Result<Self, ScriptError> {
+        let commands = Rc::new(RefCell::new(Vec::new()));
+        let engine = Self::create_engine(&commands);
+
+        let ast = engine.compile_file(file.into())
+            .map_err(|error| ScriptError::new(format!("Failed to compile script {}: {}", file, error)))?;
+
+        Ok(Script {
+            file: String::from(file),
+            engine,
+            ast,
+            scope: Scope::new(),
+            last_modified: Self::get_modified_time(file),
+            commands,
+            sprites: HashMap::new(),
+            missing_sprites: HashSet::new(),
+            errors: Vec::new(),
+        })
+    }
+
+    // Keeps the error for take_errors.
+    pub fn report(&mut self, error: ScriptError) {
+        if self.errors.len() >= SCRIPT_MAX_ERRORS {
+            self.errors.remove(0);
+        }
+
+        self.errors.push(error);
+    }
+
+    pub fn take_errors(&mut self) -> Vec<ScriptError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    // Takes the errors of every script in the entity's tree, the entity's own first. Scripts spawned by scripts are
+    // children of their spawner, so calling this on the root once a tick gets all of them.
+    pub fn take_errors_from(entity: &mut Entity) -> Vec<ScriptError> {
+        let mut errors = entity.variables.variables.get_mut(SCRIPT_VARIABLE)
+            .and_then(|script| script.downcast_mut::<Script>())
+            .map(|script| script.take_errors())
+            .unwrap_or_default();
+
+        for child in entity.children.iter_mut() {
+            errors.extend(Self::take_errors_from(child));
+        }
+
+        errors
+    }
+
+    // The whole API a script gets. Nothing in here can touch the GPU directly, drawing and spawning are queued.
+    fn create_engine(commands: &Rc<RefCell<Vec<ScriptCommand>>>) -> Engine {
+        let mut engine = Engine::new();
+
+        engine.set_max_operations(SCRIPT_MAX_OPERATIONS)
+            .set_max_call_levels(SCRIPT_MAX_CALL_LEVELS)
+            .set_max_expr_depths(SCRIPT_MAX_EXPRESSION_DEPTH, SCRIPT_MAX_EXPRESSION_DEPTH)
+            .set_max_string_size(SCRIPT_MAX_STRING_SIZE)
+            .set_max_array_size(SCRIPT_MAX_ARRAY_SIZE)
+            .set_max_map_size(SCRIPT_MAX_MAP_SIZE);
+
+        engine.register_type_with_name::<Vec2>("Vec2")
+            .register_fn("vec2", |x: FLOAT, y: FLOAT| Vec2::new(x as f32, y as f32))
+            .register_get_set("x", |v: &mut Vec2| v.x as FLOAT, |v: &mut Vec2, x: FLOAT| v.x = x as f32)
+            .register_get_set("y", |v: &mut Vec2| v.y as FLOAT, |v: &mut Vec2, y: FLOAT| v.y = y as f32)
+            .register_fn("+", |a: Vec2, b: Vec2| a + b)
+            .register_fn("-", |a: Vec2, b: Vec2| a - b)
+            .register_fn("*", |a: Vec2, b: FLOAT| a * b as f32)
+            .register_fn("length", |v: &mut Vec2| v.length() as FLOAT)
+            .register_fn("normalize", |v: &mut Vec2| v.normalize_or_zero())
+            .register_fn("to_string", |v: &mut Vec2| v.to_string());
+
+        engine.register_type_with_name::<Vec3>("Vec3")
+            .register_fn("vec3", |x: FLOAT, y: FLOAT, z: FLOAT| Vec3::new(x as f32, y as f32, z as f32))
+            .register_get_set("x", |v: &mut Vec3| v.x as FLOAT, |v: &mut Vec3, x: FLOAT| v.x = x as f32)
+            .register_get_set("y", |v: &mut Vec3| v.y as FLOAT, |v: &mut Vec3, y: FLOAT| v.y = y as f32)
+            .register_get_set("z", |v: &mut Vec3| v.z as FLOAT, |v: &mut Vec3, z: FLOAT| v.z = z as f32)
+            .register_fn("+", |a: Vec3, b: Vec3| a + b)
+            .register_fn("-", |a: Vec3, b: Vec3| a - b)
+            .register_fn("*", |a: Vec3, b: FLOAT| a * b as f32)
+            .register_fn("length", |v: &mut Vec3| v.length() as FLOAT)
+            .register_fn("normalize", |v: &mut Vec3| v.normalize_or_zero())
+            .register_fn("to_string", |v: &mut Vec3| v.to_string());
+
+        engine.register_type_with_name::<Input>("Input")
+            .register_fn("is_key_down", |input: &mut Input, key: &str| Input::key_from_name(key).map(|key| input.is_key_being_held_down(key)).unwrap_or(false))
+            .register_fn("was_key_pressed", |input: &mut Input, key: &str| Input::key_from_name(key).map(|key| input.was_key_just_pressed(key)).unwrap_or(false))
+            .register_fn("was_key_released", |input: &mut Input, key: &str| Input::key_from_name(key).map(|key| input.was_key_just_released(key)).unwrap_or(false))
+            .register_fn("is_button_down", |input: &mut Input, button: &str| Input::mouse_button_from_name(button).map(|button| input.is_button_being_held(button)).unwrap_or(false))
+            .register_fn("was_button_pressed", |input: &mut Input, button: &str| Input::mouse_button_from_name(button).map(|button| input.was_button_just_pressed(button)).unwrap_or(false))
+            .register_fn("was_button_released", |input: &mut Input, button: &str| Input::mouse_button_from_name(button).map(|button| input.was_button_just_released(button)).unwrap_or(false))
+            .register_fn("mouse_position", |input: &mut Input| {
+                let position = input.get_mouse_position();
+                Vec2::new(position.x as f32, position.y as f32)
+            });
+
+        let queue = commands.clone();
+        engine.register_fn("draw_sprite", move |file: &str, position: Vec2, size: Vec2| {
+            queue.borrow_mut().push(ScriptCommand::DrawSprite { file: String::from(file), position: position.extend(0.0), size, rotation: 0.0 });
+        });
+
+        let queue = commands.clone();
+        engine.register_fn("draw_sprite", move |file: &str, position: Vec3, size: Vec2, rotation: FLOAT| {
+            queue.borrow_mut().push(ScriptCommand::DrawSprite { file: String::from(file), position, size, rotation: rotation as f32 });
+        });
+
+        let queue = commands.clone();
+        engine.register_fn("spawn_script", move |file: &str, variables: Map| {
+            queue.borrow_mut().push(ScriptCommand::Spawn { file: String::from(file), variables });
+        });
+
+        let queue = commands.clone();
+        engine.register_fn("spawn_script", move |file: &str| {
+            queue.borrow_mut().push(ScriptCommand::Spawn { file: String::from(file), variables: Map::new() });
+        });
+
+        let queue = commands.clone();
+        engine.register_fn("destroy", move || {
+            queue.borrow_mut().push(ScriptCommand::Destroy);
+        });
+
+        engine
+    }
+
+    fn get_modified_time(file: &str) -> Option<SystemTime> {
+        fs::metadata(file).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    // Recompiles the file if it changed. A broken file is reported and the previous version keeps running.
+    pub fn reload_if_changed(&mut self, entity: &mut Entity) {
+        let modified = Self::get_modified_time(self.file.as_str());
+
+        if modified.is_none() || modified == self.last_modified {
+            return;
+        }
+
+        self.last_modified = modified;
+
+        match self.engine.compile_file(self.file.as_str().into()) {
+            Ok(ast) => {
+                self.ast = ast;
+                self.call(entity, "reload", ());
+            },
+            Err(error) => self.report(ScriptError::new(format!("Failed to reload script {}: {}", self.file, error))),
+        }
+    }
+
+    pub fn has_function(&self, name: &str) -> bool {
+        self.ast.iter_functions().any(|function| function.name == name)
+    }
+
+    // Calls a script function with `this` bound to the entity's variables, then writes them back.
+    pub fn call(&mut self, entity: &mut Entity, name: &str, args: impl rhai::FuncArgs) {
+        if !self.has_function(name) {
+            return;
+        }
+
+        let mut this = Dynamic::from_map(Self::variables_to_map(&entity.variables));
+        let options = CallFnOptions::new().eval_ast(false).rewind_scope(false).bind_this_ptr(&mut this);
+
+        if let Err(error) = self.engine.call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, name, args) {
+            self.report(ScriptError::new(format!("Script error in {} ({}): {}", self.file, name, error)));
+        }
+
+        if let Some(map) = this.try_cast::<Map>() {
+            Self::map_to_variables(map, &mut entity.variables);
+        }
+    }
+
+    pub fn variables_to_map(variables: &EntityVariableArray) -> Map {
+        let mut map = Map::new();
+
+        for (name, value) in &variables.variables {
+            if let Some(value) = Self::to_dynamic(value.as_ref()) {
+                map.insert(name.as_str().into(), value);
+            }
+        }
+
+        map
+    }
+
+    fn to_dynamic(value: &dyn Any) -> Option<Dynamic> {
+        if let Some(value) = value.downcast_ref::<f32>() { return Some(Dynamic::from_float(*value as FLOAT)); }
+        if let Some(value) = value.downcast_ref::<f64>() { return Some(Dynamic::from_float(*value as FLOAT)); }
+        if let Some(value) = value.downcast_ref::<i32>() { return Some(Dynamic::from_int(*value as INT)); }
+        if let Some(value) = value.downcast_ref::<i64>() { return Some(Dynamic::from_int(*value as INT)); }
+        if let Some(value) = value.downcast_ref::<bool>() { return Some(Dynamic::from_bool(*value)); }
+        if let Some(value) = value.downcast_ref::<String>() { return Some(Dynamic::from(value.clone())); }
+        if let Some(value) = value.downcast_ref::<Vec2>() { return Some(Dynamic::from(*value)); }
+        if let Some(value) = value.downcast_ref::<Vec3>() { return Some(Dynamic::from(*value)); }
+        if let Some(value) = value.downcast_ref::<Dynamic>() { return Some(value.clone()); }
+
+        None
+    }
+
+    // Values keep the Rust type they already had. New numbers become f32 / i32 like the rest of the engine.
+    pub fn map_to_variables(map: Map, variables: &mut EntityVariableArray) {
+        for (name, value) in map {
+            let name = name.as_str();
+            let existing = variables.variables.get(name);
+
+            let is = |type_check: fn(&dyn Any) -> bool| existing.map(|existing| type_check(existing.as_ref())).unwrap_or(false);
+
+            if let Ok(number) = value.as_float().or_else(|_| value.as_int().map(|int| int as FLOAT)) {
+                if is(|existing| existing.is::<f64>()) {
+                    variables.insert(name, number);
+                } else if is(|existing| existing.is::<i64>()) {
+                    variables.insert(name, number as i64);
+                } else if is(|existing| existing.is::<i32>()) || (existing.is_none() && value.is_int()) {
+                    variables.insert(name, number as i32);
+                } else {
+                    variables.insert(name, number as f32);
+                }
+            } else if let Ok(boolean) = value.as_bool() {
+                variables.insert(name, boolean);
+            } else if value.is_string() {
+                variables.insert(name, value.into_string().unwrap());
+            } else if value.is::<Vec2>() {
+                variables.insert(name, value.cast::<Vec2>());
+            } else if value.is::<Vec3>() {
+                variables.insert(name, value.cast::<Vec3>());
+            } else {
+                variables.insert(name, value);
+            }
+        }
+    }
+
+    // Sprites the script draws are loaded on first use. Missing or broken files are reported once and never drawn.
+    fn load_sprite(&mut self, file: &str) -> Result<(), ScriptError> {
+        if self.sprites.contains_key(file) {
+            return Ok(());
+        }
+
+        if !Path::new(file).is_file() {
+            return Err(ScriptError::new(format!("Script {} drew a sprite from {}, which doesn't exist", self.file, file)));
+        }
+
+        let texture = SCRIPT_TEXTURE_CACHE.with(|texture_cache| texture_cache.borrow_mut().try_load(file))
+            .map_err(|error| ScriptError::new(format!("Script {} drew a sprite from {}, which failed to load: {}", self.file, file, error)))?;
+
+        let sprite = RenderableSprite::new(&Vec2::new(1.0, 1.0), &Self::get_shader_program()?)
+            .with_shared_texture(&texture);
+
+        self.sprites.insert(String::from(file), sprite);
+
+        Ok(())
+    }
+
+    fn get_shader_program() -> Result<ShaderProgram, ScriptError> {
+        SCRIPT_SHADER_PROGRAM.with(|shader_program| {
+            let mut shader_program = shader_program.borrow_mut();
+
+            if shader_program.is_none() {
+                *shader_program = Some(ShaderProgram::from_builders(
+                    &ShaderBuilderTemplate::basic_vertex_shader(SCRIPT_SHADER_VERSION),
+                    &ShaderBuilderTemplate::texture_fragment_shader(SCRIPT_SHADER_VERSION),
+                ).map_err(|error| ScriptError::new(format!("Failed to compile the script sprite shader: {}", error)))?);
+            }
+
+            Ok(shader_program.clone().unwrap())
+        })
+    }
+
+    // Runs what the last script call queued. Draw commands need the graphics layer, everything else happens right away.
+    fn run_commands(&mut self, entity: &mut Entity, graphics: Option<&mut GraphicsLayer>) {
+        let commands: Vec<ScriptCommand> = self.commands.borrow_mut().drain(..).collect();
+
+        for command in commands {
+            match command {
+                ScriptCommand::DrawSprite { file, position, size, rotation } => {
+                    let graphics = match &graphics {
+                        Some(graphics) => graphics,
+                        None => continue,
+                    };
+
+                    if self.missing_sprites.contains(&file) {
+                        continue;
+                    }
+
+                    if let Err(error) = self.load_sprite(file.as_str()) {
+                        self.missing_sprites.insert(file);
+                        self.report(error);
+                        continue;
+                    }
+
+                    let sprite = self.sprites.get_mut(&file).unwrap();
+
+                    sprite.move_to(&position);
+                    sprite.rotate_to(&Vec3::new(0.0, 0.0, rotation));
+                    sprite.scale_to(&Vec3::new(size.x, size.y, 1.0));
+
+                    graphics.render_object(sprite);
+                },
+                ScriptCommand::Spawn { file, variables } => {
+                    let mut overrides = EntityVariableArray::new();
+                    Self::map_to_variables(variables, &mut overrides);
+
+                    match Script::build_with(file.as_str(), overrides) {
+                        Ok(child) => entity.push(child),
+                        Err(error) => self.report(ScriptError::new(format!("Script {} failed to spawn {}: {}", self.file, file, error))),
+                    }
+                },
+                ScriptCommand::Destroy => {
+                    entity.destroy();
+                },
+            }
+        }
+    }
+
+    pub fn init(&mut self, entity: &mut Entity) {
+        self.call(entity, "init", ());
+        self.run_commands(entity, None);
+    }
+
+    pub fn update(&mut self, entity: &mut Entity, input: &Input, delta: &Duration) {
+        self.reload_if_changed(entity);
+
+        self.call(entity, "update", (input.clone(), delta.as_secs_f32() as FLOAT));
+        self.run_commands(entity, None);
+    }
+
+    pub fn render(&mut self, entity: &mut Entity, graphics: &mut GraphicsLayer) {
+        self.call(entity, "render", ());
+        self.run_commands(entity, Some(graphics));
+    }
+
+    pub fn exit(&mut self, entity: &mut Entity) {
+        self.call(entity, "exit", ());
+        self.commands.borrow_mut().clear();
+    }
+
+    pub fn build(file: &str) -> Result<Entity, ScriptError> {
+        Self::build_with(file, EntityVariableArray::new())
+    }
+
+    // The script is kept in the entity's variables, the same way game code keeps sprites around. Fails if the file
+    // doesn't compile.
+    pub fn build_with(file: &str, variables: EntityVariableArray) -> Result<Entity, ScriptError> {
+        let script = Script::new(file)?;
+
+        let mut entity = Entity::new()
+            .with_init(|entity| {
+                let mut script = entity.variables.take_out::<Script>(SCRIPT_VARIABLE);
+                script.init(entity);
+                entity.variables.insert(SCRIPT_VARIABLE, script);
+            })
+            .with_update(|entity, _event_queue: &mut EventQueue, input, delta| {
+                let mut script = entity.variables.take_out::<Script>(SCRIPT_VARIABLE);
+                script.update(entity, input, delta);
+                entity.variables.insert(SCRIPT_VARIABLE, script);
+            })
+            .with_render(|entity, graphics| {
+                let mut script = entity.variables.take_out::<Script>(SCRIPT_VARIABLE);
+                script.render(entity, graphics);
+                entity.variables.insert(SCRIPT_VARIABLE, script);
+            })
+            .with_exit(|entity| {
+                let mut script = entity.variables.take_out::<Script>(SCRIPT_VARIABLE);
+                script.exit(entity);
+                entity.variables.insert(SCRIPT_VARIABLE, script);
+            })
+        ;
+
+        entity.variables.merge(variables);
+        entity.variables.insert(SCRIPT_VARIABLE, script);
+
+        Ok(entity)
+    }
+}
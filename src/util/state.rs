@@ -0,0 +1,284 @@
+use std::time::Duration;
+
+use crate::graphics::view::GraphicsLayer;
+use super::entity::Entity;
+use super::event::{EventQueue, Input};
+
+// States ask for transitions by leaving them in the variables of any entity in their tree under this name, see
+// StateStack::push_state.
+pub const STATE_TRANSITIONS_VARIABLE: &str = "state_transitions";
+pub const STATE_STACK_VARIABLE: &str = "state_stack";
+
+pub enum StateTransition {
+    Push(State),
+    Pop,
+    Replace(State),
+}
+
+// A screen of the game, like the main menu or a pause overlay. The entity's init and exit work as the enter and exit hooks.
+pub struct State {
+    pub name: String,
+    pub entity: Entity,
+    // Whether the states below this one keep updating / rendering while it is on top.
+    pub update_below: bool,
+    pub render_below: bool,
+
+    // Called when another state gets pushed over this one and when it is on top again.
+    pub pause_func: fn(entity: &mut Entity),
+    pub resume_func: fn(entity: &mut Entity),
+}
+
+impl State {
+    pub fn new(name: &str, entity: Entity) -> Self {
+        State {
+            name: String::from(name),
+            entity,
+            update_below: false,
+            render_below: false,
+
+            pause_func: |_entity: &mut Entity| {},
+            resume_func: |_entity: &mut Entity| {},
+        }
+    }
+
+    // For overlays such as a pause menu: the game underneath is still drawn but frozen.
+    pub fn overlay(name: &str, entity: Entity) -> Self {
+        State::new(name, entity).with_render_below(true)
+    }
+
+    pub fn with_update_below(mut self, update_below: bool) -> Self {
+        self.update_below = update_below;
+        self
+    }
+
+    pub fn with_render_below(mut self, render_below: bool) -> Self {
+        self.render_below = render_below;
+        self
+    }
+
+    pub fn with_pause(mut self, pause_func: fn(entity: &mut Entity)) -> Self {
+        self.pause_func = pause_func;
+        self
+    }
+
+    pub fn with_resume(mut self, resume_func: fn(entity: &mut Entity)) -> Self {
+        self.resume_func = resume_func;
+        self
+    }
+}
+
+pub struct StateStack {
+    pub states: Vec<State>,
+    pub is_initialized: bool,
+}
+
+impl Default for StateStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateStack {
+    pub fn new() -> Self {
+        StateStack {
+            states: Vec::new(),
+            is_initialized: false,
+        }
+    }
+
+    // States added before the stack is initialized are entered when the window starts running.
+    pub fn with_state(mut self, state: State) -> Self {
+        self.push(state);
+        self
+    }
+
+    pub fn push(&mut self, mut state: State) {
+        if self.is_initialized {
+            if let Some(top) = self.states.last_mut() {
+                (top.pause_func)(&mut top.entity);
+            }
+
+            state.entity.init();
+        }
+
+        self.states.push(state);
+    }
+
+    pub fn pop(&mut self) -> Option<State> {
+        let mut state = self.states.pop()?;
+
+        if self.is_initialized {
+            state.entity.exit();
+
+            if let Some(top) = self.states.last_mut() {
+                (top.resume_func)(&mut top.entity);
+            }
+        }
+
+        Some(state)
+    }
+
+    pub fn replace(&mut self, mut state: State) -> Option<State> {
+        let mut old_state = self.states.pop();
+
+        if self.is_initialized {
+            if let Some(old_state) = old_state.as_mut() {
+                old_state.entity.exit();
+            }
+
+            state.entity.init();
+        }
+
+        self.states.push(state);
+
+        old_state
+    }
+
+    pub fn apply(&mut self, transition: StateTransition) {
+        match transition {
+            StateTransition::Push(state) => self.push(state),
+            StateTransition::Pop => { self.pop(); },
+            StateTransition::Replace(state) => { self.replace(state); },
+        }
+    }
+
+    pub fn top(&mut self) -> Option<&mut State> {
+        self.states.last_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    // Index of the lowest state that is still affected, walking down from the top while states let things through.
+    fn lowest_active_index(&self, lets_through: fn(state: &State) -> bool) -> usize {
+        let mut index = self.states.len().saturating_sub(1);
+
+        while index > 0 && lets_through(&self.states[index]) {
+            index -= 1;
+        }
+
+        index
+    }
+
+    pub fn init(&mut self) {
+        self.is_initialized = true;
+
+        let top_index = self.states.len().saturating_sub(1);
+
+        for (i, state) in self.states.iter_mut().enumerate() {
+            state.entity.init();
+
+            if i < top_index {
+                (state.pause_func)(&mut state.entity);
+            }
+        }
+    }
+
+    pub fn update(&mut self, event_queue: &mut EventQueue, input: &mut Input, delta: &Duration) {
+        if self.states.is_empty() {
+            return;
+        }
+
+        let lowest = self.lowest_active_index(|state| state.update_below);
+        let mut transitions: Vec<StateTransition> = Vec::new();
+
+        for state in &mut self.states[lowest..] {
+            state.entity.update(event_queue, input, delta);
+            Self::take_transitions(&mut state.entity, &mut transitions);
+        }
+
+        for transition in transitions {
+            self.apply(transition);
+        }
+    }
+
+    // Children update before their parent, so their requests go first.
+    fn take_transitions(entity: &mut Entity, transitions: &mut Vec<StateTransition>) {
+        for child in entity.children.iter_mut() {
+            Self::take_transitions(child, transitions);
+        }
+
+        if entity.variables.contains(STATE_TRANSITIONS_VARIABLE) {
+            transitions.append(&mut entity.variables.take_out::<Vec<StateTransition>>(STATE_TRANSITIONS_VARIABLE));
+        }
+    }
+
+    pub fn render(&mut self, graphics: &mut GraphicsLayer) {
+        if self.states.is_empty() {
+            return;
+        }
+
+        let lowest = self.lowest_active_index(|state| state.render_below);
+
+        for state in &mut self.states[lowest..] {
+            state.entity.render(graphics);
+        }
+    }
+
+    pub fn exit(&mut self) {
+        while let Some(mut state) = self.states.pop() {
+            state.entity.exit();
+        }
+
+        self.is_initialized = false;
+    }
+
+    // Wraps the stack in an entity so it can be handed to Window::run as the root.
+    pub fn build(self) -> Entity {
+        let mut entity = Entity::new()
+            .with_init(|entity| {
+                let mut stack = entity.variables.take_out::<StateStack>(STATE_STACK_VARIABLE);
+                stack.init();
+                entity.variables.insert(STATE_STACK_VARIABLE, stack);
+            })
+            .with_update(|entity, event_queue, input, delta| {
+                let mut stack = entity.variables.take_out::<StateStack>(STATE_STACK_VARIABLE);
+                stack.update(event_queue, input, delta);
+                entity.variables.insert(STATE_STACK_VARIABLE, stack);
+            })
+            .with_render(|entity, graphics| {
+                let mut stack = entity.variables.take_out::<StateStack>(STATE_STACK_VARIABLE);
+                stack.render(graphics);
+                entity.variables.insert(STATE_STACK_VARIABLE, stack);
+            })
+            .with_exit(|entity| {
+                let mut stack = entity.variables.take_out::<StateStack>(STATE_STACK_VARIABLE);
+                stack.exit();
+                entity.variables.insert(STATE_STACK_VARIABLE, stack);
+            })
+        ;
+
+        entity.variables.insert(STATE_STACK_VARIABLE, self);
+
+        entity
+    }
+
+    // Called from inside the update function of a state's entity or any of its children. The transition happens once
+    // every active state has updated.
+    pub fn request(entity: &mut Entity, transition: StateTransition) {
+        let mut transitions = match entity.variables.contains(STATE_TRANSITIONS_VARIABLE) {
+            true => entity.variables.take_out::<Vec<StateTransition>>(STATE_TRANSITIONS_VARIABLE),
+            false => Vec::new(),
+        };
+
+        transitions.push(transition);
+        entity.variables.insert(STATE_TRANSITIONS_VARIABLE, transitions);
+    }
+
+    pub fn push_state(entity: &mut Entity, state: State) {
+        Self::request(entity, StateTransition::Push(state));
+    }
+
+    pub fn pop_state(entity: &mut Entity) {
+        Self::request(entity, StateTransition::Pop);
+    }
+
+    pub fn replace_state(entity: &mut Entity, state: State) {
+        Self::request(entity, StateTransition::Replace(state));
+    }
+}
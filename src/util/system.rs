@@ -0,0 +1,570 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+use rayon::prelude::*;
+
+use crate::graphics::view::GraphicsLayer;
+use super::app::EventHandler;
+use super::entity::Entity;
+use super::event::{EventQueue, Input};
+
+pub const SYSTEM_SCHEDULER_VARIABLE: &str = "system_scheduler";
+pub const SYSTEM_ROOT_VARIABLE: &str = "system_root";
+
+#[derive(Debug, Clone)]
+pub struct SystemError {
+    pub error_log: String,
+}
+
+impl fmt::Display for SystemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error_log.as_str())
+    }
+}
+
+impl Error for SystemError {}
+
+impl SystemError {
+    pub fn new(string: String) -> Self {
+        SystemError {
+            error_log: string,
+        }
+    }
+}
+
+// Stages run in this order every tick. The entity tree handed to SystemScheduler::build updates between Physics and Update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stage {
+    Input,
+    PreUpdate,
+    Physics,
+    Update,
+    PostUpdate,
+    RenderPrep,
+}
+
+impl Stage {
+    pub fn all() -> [Stage; 6] {
+        [Stage::Input, Stage::PreUpdate, Stage::Physics, Stage::Update, Stage::PostUpdate, Stage::RenderPrep]
+    }
+}
+
+// Shared game data that systems read and write. Each resource has its own lock so systems touching different ones can run at the same time.
+pub struct Resources {
+    pub resources: HashMap<TypeId, RwLock<Box<dyn Any + Send + Sync>>>,
+}
+
+impl Default for Resources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resources {
+    pub fn new() -> Self {
+        Resources {
+            resources: HashMap::new(),
+        }
+    }
+
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.resources.insert(TypeId::of::<T>(), RwLock::new(Box::new(value)));
+    }
+
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        let value = self.resources.remove(&TypeId::of::<T>())?;
+
+        value.into_inner().unwrap().downcast::<T>().ok().map(|value| *value)
+    }
+
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn get<T: Any + Send + Sync>(&self) -> ResourceRef<'_, T> {
+        let lock = self.resources.get(&TypeId::of::<T>()).unwrap_or_else(|| panic!("Missing resource {}", std::any::type_name::<T>()));
+
+        ResourceRef {
+            guard: lock.read().unwrap(),
+            marker: PhantomData,
+        }
+    }
+
+    pub fn get_mut<T: Any + Send + Sync>(&self) -> ResourceMut<'_, T> {
+        let lock = self.resources.get(&TypeId::of::<T>()).unwrap_or_else(|| panic!("Missing resource {}", std::any::type_name::<T>()));
+
+        ResourceMut {
+            guard: lock.write().unwrap(),
+            marker: PhantomData,
+        }
+    }
+}
+
+pub struct ResourceRef<'a, T> {
+    guard: RwLockReadGuard<'a, Box<dyn Any + Send + Sync>>,
+    marker: PhantomData<T>,
+}
+
+impl<'a, T: Any> Deref for ResourceRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.downcast_ref::<T>().unwrap()
+    }
+}
+
+pub struct ResourceMut<'a, T> {
+    guard: RwLockWriteGuard<'a, Box<dyn Any + Send + Sync>>,
+    marker: PhantomData<T>,
+}
+
+impl<'a, T: Any> Deref for ResourceMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.downcast_ref::<T>().unwrap()
+    }
+}
+
+impl<'a, T: Any> DerefMut for ResourceMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.downcast_mut::<T>().unwrap()
+    }
+}
+
+// What a running system sees. Access is checked against what the system declared so the parallel schedule stays honest.
+pub struct SystemContext<'a> {
+    pub resources: &'a Resources,
+    pub delta: Duration,
+    pub system: &'a System,
+}
+
+impl<'a> SystemContext<'a> {
+    pub fn read<T: Any + Send + Sync>(&self) -> ResourceRef<'_, T> {
+        let id = TypeId::of::<T>();
+
+        if !self.system.exclusive && !self.system.reads.contains(&id) && !self.system.writes.contains(&id) {
+            panic!("System '{}' reads {} without declaring it", self.system.name, std::any::type_name::<T>());
+        }
+
+        self.resources.get::<T>()
+    }
+
+    pub fn write<T: Any + Send + Sync>(&self) -> ResourceMut<'_, T> {
+        if !self.system.exclusive && !self.system.writes.contains(&TypeId::of::<T>()) {
+            panic!("System '{}' writes {} without declaring it", self.system.name, std::any::type_name::<T>());
+        }
+
+        self.resources.get_mut::<T>()
+    }
+}
+
+pub struct System {
+    pub name: String,
+    pub stage: Stage,
+    pub func: fn(context: &SystemContext),
+    pub reads: Vec<TypeId>,
+    pub writes: Vec<TypeId>,
+    // Exclusive systems run alone and may touch any resource.
+    pub exclusive: bool,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+impl System {
+    pub fn new(name: &str, stage: Stage, func: fn(context: &SystemContext)) -> Self {
+        System {
+            name: String::from(name),
+            stage,
+            func,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            exclusive: false,
+            before: Vec::new(),
+            after: Vec::new(),
+        }
+    }
+
+    pub fn reads<T: Any + Send + Sync>(mut self) -> Self {
+        self.reads.push(TypeId::of::<T>());
+        self
+    }
+
+    pub fn writes<T: Any + Send + Sync>(mut self) -> Self {
+        self.writes.push(TypeId::of::<T>());
+        self
+    }
+
+    pub fn exclusive(mut self) -> Self {
+        self.exclusive = true;
+        self
+    }
+
+    // Ordering constraints only apply to systems in the same stage, stages are already ordered.
+    pub fn before(mut self, system_name: &str) -> Self {
+        self.before.push(String::from(system_name));
+        self
+    }
+
+    pub fn after(mut self, system_name: &str) -> Self {
+        self.after.push(String::from(system_name));
+        self
+    }
+
+    pub fn conflicts_with(&self, other: &System) -> bool {
+        if self.exclusive || other.exclusive {
+            return true;
+        }
+
+        self.writes.iter().any(|id| other.writes.contains(id) || other.reads.contains(id))
+            || other.writes.iter().any(|id| self.reads.contains(id))
+    }
+}
+
+pub struct SystemScheduler {
+    pub systems: Vec<System>,
+    pub resources: Resources,
+    // Per stage, groups of system indices. Systems in the same group don't conflict and run in parallel.
+    pub batches: HashMap<Stage, Vec<Vec<usize>>>,
+    pub is_dirty: bool,
+    pub event_handlers: Vec<EventHandler>,
+}
+
+impl Default for SystemScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SystemScheduler {
+    pub fn new() -> Self {
+        SystemScheduler {
+            systems: Vec::new(),
+            resources: Resources::new(),
+            batches: HashMap::new(),
+            is_dirty: true,
+            event_handlers: Vec::new(),
+        }
+    }
+
+    pub fn add_system(&mut self, system: System) {
+        self.systems.push(system);
+        self.is_dirty = true;
+    }
+
+    pub fn with_system(mut self, system: System) -> Self {
+        self.add_system(system);
+        self
+    }
+
+    pub fn insert_resource<T: Any + Send + Sync>(&mut self, value: T) {
+        self.resources.insert(value);
+    }
+
+    pub fn with_resource<T: Any + Send + Sync>(mut self, value: T) -> Self {
+        self.insert_resource(value);
+        self
+    }
+
+    pub fn add_event_handler(&mut self, handler: EventHandler) {
+        self.event_handlers.push(handler);
+    }
+
+    // Orders a stage's systems by their before/after constraints, then packs them into batches.
+    // A system lands in the first batch after every system it depends on or conflicts with.
+    fn build_stage(&self, stage: Stage) -> Result<Vec<Vec<usize>>, SystemError> {
+        let indices: Vec<usize> = (0..self.systems.len()).filter(|i| self.systems[*i].stage == stage).collect();
+
+        // A misspelled name would silently drop the constraint.
+        for a in &indices {
+            let system = &self.systems[*a];
+
+            for name in system.before.iter().chain(system.after.iter()) {
+                if !indices.iter().any(|b| self.systems[*b].name == *name) {
+                    return Err(SystemError::new(format!("System '{}' is ordered against '{}', which isn't a system in stage {:?}", system.name, name, stage)));
+                }
+            }
+        }
+
+        let depends_on = |a: usize, b: usize| -> bool {
+            let (a, b) = (&self.systems[a], &self.systems[b]);
+            a.after.contains(&b.name) || b.before.contains(&a.name)
+        };
+
+        let mut sorted: Vec<usize> = Vec::new();
+        let mut remaining = indices.clone();
+
+        while !remaining.is_empty() {
+            let next = remaining.iter().position(|a| !remaining.iter().any(|b| a != b && depends_on(*a, *b)));
+
+            match next {
+                Some(position) => sorted.push(remaining.remove(position)),
+                None => {
+                    let names: Vec<&str> = remaining.iter().map(|i| self.systems[*i].name.as_str()).collect();
+                    return Err(SystemError::new(format!("Cyclic system ordering in stage {:?}: {}", stage, names.join(", "))));
+                },
+            }
+        }
+
+        let mut batch_of: HashMap<usize, usize> = HashMap::new();
+        let mut batches: Vec<Vec<usize>> = Vec::new();
+
+        for (position, a) in sorted.iter().enumerate() {
+            let mut batch = 0;
+
+            for b in &sorted[..position] {
+                if depends_on(*a, *b) || self.systems[*a].conflicts_with(&self.systems[*b]) {
+                    batch = batch.max(batch_of[b] + 1);
+                }
+            }
+
+            if batch == batches.len() {
+                batches.push(Vec::new());
+            }
+
+            batches[batch].push(*a);
+            batch_of.insert(*a, batch);
+        }
+
+        Ok(batches)
+    }
+
+    // Fails on before/after names that match no system in the same stage and on cyclic ordering. The previous
+    // schedule is kept when it fails.
+    pub fn build_schedule(&mut self) -> Result<(), SystemError> {
+        let mut batches = HashMap::new();
+
+        for stage in Stage::all() {
+            batches.insert(stage, self.build_stage(stage)?);
+        }
+
+        self.batches = batches;
+        self.is_dirty = false;
+
+        Ok(())
+    }
+
+    pub fn run_stage(&mut self, stage: Stage, delta: &Duration) {
+        // build checks the schedule up front, this only fails for systems added while the game is running.
+        if self.is_dirty {
+            self.build_schedule().unwrap_or_else(|error| panic!("{}", error));
+        }
+
+        let systems = &self.systems;
+        let resources = &self.resources;
+
+        for batch in &self.batches[&stage] {
+            let run = |index: &usize| {
+                let system = &systems[*index];
+                let context = SystemContext {
+                    resources,
+                    delta: *delta,
+                    system,
+                };
+
+                (system.func)(&context);
+            };
+
+            if batch.len() == 1 {
+                run(&batch[0]);
+            } else {
+                batch.par_iter().for_each(run);
+            }
+        }
+    }
+
+    // Runs every stage around the entity tree. A copy of the input is available to systems as a resource.
+    // With event handlers registered, each event is handled once and the queue is emptied at the end of the tick.
+    pub fn update(&mut self, root: &mut Entity, event_queue: &mut EventQueue, input: &mut Input, delta: &Duration) {
+        self.resources.insert(input.clone());
+
+        for event in &event_queue.internal_queue {
+            for handler in &self.event_handlers {
+                handler(event, &self.resources);
+            }
+        }
+
+        self.run_stage(Stage::Input, delta);
+        self.run_stage(Stage::PreUpdate, delta);
+        self.run_stage(Stage::Physics, delta);
+
+        root.update(event_queue, input, delta);
+
+        self.run_stage(Stage::Update, delta);
+        self.run_stage(Stage::PostUpdate, delta);
+        self.run_stage(Stage::RenderPrep, delta);
+
+        if !self.event_handlers.is_empty() {
+            event_queue.ignore_events();
+        }
+    }
+
+    // Wraps the scheduler and the game's root entity so they can be handed to Window::run together. Fails if the
+    // systems can't be ordered, see build_schedule.
+    pub fn build(mut self, root: Entity) -> Result<Entity, SystemError> {
+        self.build_schedule()?;
+
+        let mut entity = Entity::new()
+            .with_init(|entity| {
+                let mut root = entity.variables.take_out::<Entity>(SYSTEM_ROOT_VARIABLE);
+                root.init();
+                entity.variables.insert(SYSTEM_ROOT_VARIABLE, root);
+            })
+            .with_update(|entity, event_queue, input, delta| {
+                let mut scheduler = entity.variables.take_out::<SystemScheduler>(SYSTEM_SCHEDULER_VARIABLE);
+                let mut root = entity.variables.take_out::<Entity>(SYSTEM_ROOT_VARIABLE);
+
+                scheduler.update(&mut root, event_queue, input, delta);
+
+                entity.variables.insert(SYSTEM_ROOT_VARIABLE, root);
+                entity.variables.insert(SYSTEM_SCHEDULER_VARIABLE, scheduler);
+            })
+            .with_render(|entity, graphics: &mut GraphicsLayer| {
+                let mut root = entity.variables.take_out::<Entity>(SYSTEM_ROOT_VARIABLE);
+                root.render(graphics);
+                entity.variables.insert(SYSTEM_ROOT_VARIABLE, root);
+            })
+            .with_exit(|entity| {
+                let mut root = entity.variables.take_out::<Entity>(SYSTEM_ROOT_VARIABLE);
+                root.exit();
+                entity.variables.insert(SYSTEM_ROOT_VARIABLE, root);
+            })
+        ;
+
+        entity.variables.insert(SYSTEM_SCHEDULER_VARIABLE, self);
+        entity.variables.insert(SYSTEM_ROOT_VARIABLE, root);
+
+        Ok(entity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counter(u32);
+    struct Log(Vec<&'static str>);
+
+    fn noop(_context: &SystemContext) {}
+
+    fn increment(context: &SystemContext) {
+        context.write::<Counter>().0 += 1;
+    }
+
+    fn log_first(context: &SystemContext) {
+        context.write::<Log>().0.push("first");
+    }
+
+    fn log_second(context: &SystemContext) {
+        context.write::<Log>().0.push("second");
+    }
+
+    fn get_names(scheduler: &SystemScheduler, stage: Stage) -> Vec<Vec<&str>> {
+        scheduler.batches[&stage].iter()
+            .map(|batch| batch.iter().map(|index| scheduler.systems[*index].name.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn independent_systems_share_a_batch() {
+        let mut scheduler = SystemScheduler::new()
+            .with_system(System::new("a", Stage::Update, noop).reads::<Counter>())
+            .with_system(System::new("b", Stage::Update, noop).reads::<Counter>());
+
+        scheduler.build_schedule().unwrap();
+
+        assert_eq!(get_names(&scheduler, Stage::Update), vec![vec!["a", "b"]]);
+    }
+
+    #[test]
+    fn conflicting_systems_get_separate_batches() {
+        let mut scheduler = SystemScheduler::new()
+            .with_system(System::new("writer", Stage::Update, noop).writes::<Counter>())
+            .with_system(System::new("reader", Stage::Update, noop).reads::<Counter>())
+            .with_system(System::new("other", Stage::Update, noop).reads::<Log>())
+            .with_system(System::new("exclusive", Stage::Update, noop).exclusive());
+
+        scheduler.build_schedule().unwrap();
+
+        assert_eq!(get_names(&scheduler, Stage::Update), vec![vec!["writer", "other"], vec!["reader"], vec!["exclusive"]]);
+    }
+
+    #[test]
+    fn ordering_constraints_are_sorted() {
+        let mut scheduler = SystemScheduler::new()
+            .with_system(System::new("c", Stage::Update, noop).after("b"))
+            .with_system(System::new("b", Stage::Update, noop))
+            .with_system(System::new("a", Stage::Update, noop).before("b"));
+
+        scheduler.build_schedule().unwrap();
+
+        assert_eq!(get_names(&scheduler, Stage::Update), vec![vec!["a"], vec!["b"], vec!["c"]]);
+    }
+
+    #[test]
+    fn stages_are_scheduled_separately() {
+        let mut scheduler = SystemScheduler::new()
+            .with_system(System::new("input", Stage::Input, noop).writes::<Counter>())
+            .with_system(System::new("update", Stage::Update, noop).writes::<Counter>());
+
+        scheduler.build_schedule().unwrap();
+
+        assert_eq!(get_names(&scheduler, Stage::Input), vec![vec!["input"]]);
+        assert_eq!(get_names(&scheduler, Stage::Update), vec![vec!["update"]]);
+        assert!(scheduler.batches[&Stage::Physics].is_empty());
+    }
+
+    #[test]
+    fn unknown_names_fail() {
+        let mut scheduler = SystemScheduler::new()
+            .with_system(System::new("a", Stage::Update, noop).after("missing"));
+
+        assert!(scheduler.build_schedule().is_err());
+    }
+
+    #[test]
+    fn names_in_other_stages_fail() {
+        let mut scheduler = SystemScheduler::new()
+            .with_system(System::new("a", Stage::Update, noop).after("b"))
+            .with_system(System::new("b", Stage::PreUpdate, noop));
+
+        assert!(scheduler.build_schedule().is_err());
+    }
+
+    #[test]
+    fn cycles_fail_and_keep_the_previous_schedule() {
+        let mut scheduler = SystemScheduler::new()
+            .with_system(System::new("a", Stage::Update, noop))
+            .with_system(System::new("b", Stage::Update, noop).after("a"));
+
+        scheduler.build_schedule().unwrap();
+
+        scheduler.add_system(System::new("c", Stage::Update, noop).after("b").before("a"));
+
+        assert!(scheduler.build_schedule().is_err());
+        assert!(scheduler.is_dirty);
+        assert_eq!(get_names(&scheduler, Stage::Update), vec![vec!["a"], vec!["b"]]);
+    }
+
+    #[test]
+    fn run_stage_runs_batches_in_order() {
+        let mut scheduler = SystemScheduler::new()
+            .with_resource(Counter(0))
+            .with_resource(Log(Vec::new()))
+            .with_system(System::new("second", Stage::Update, log_second).writes::<Log>().after("first"))
+            .with_system(System::new("first", Stage::Update, log_first).writes::<Log>())
+            .with_system(System::new("increment", Stage::Update, increment).writes::<Counter>());
+
+        scheduler.run_stage(Stage::Update, &Duration::ZERO);
+        scheduler.run_stage(Stage::Update, &Duration::ZERO);
+
+        assert_eq!(scheduler.resources.get::<Counter>().0, 2);
+        assert_eq!(scheduler.resources.get::<Log>().0, vec!["first", "second", "first", "second"]);
+    }
+}
@@ -0,0 +1,478 @@
+use std::mem;
+use std::time::Duration;
+
+use super::entity::Entity;
+
+pub type TaskId = u64;
+
+#[derive(Clone, Copy)]
+pub enum SequenceStep {
+    Do(fn(entity: &mut Entity)),
+    Wait(Duration),
+    WaitUntil(fn(entity: &mut Entity) -> bool),
+    // Called every tick with the progress from 0.0 to 1.0, handy for moving things over time.
+    During(Duration, fn(entity: &mut Entity, progress: f32)),
+}
+
+// A list of steps run one after the other across ticks, like a coroutine:
+//
+//     Sequence::new()
+//         .then(|entity| { /* move */ })
+//         .wait(2.0)
+//         .then(|entity| { /* play animation */ })
+//
+#[derive(Clone)]
+pub struct Sequence {
+    pub steps: Vec<SequenceStep>,
+    pub current_step: usize,
+    pub elapsed_time: Duration,
+    pub looping: bool,
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sequence {
+    pub fn new() -> Self {
+        Sequence {
+            steps: Vec::new(),
+            current_step: 0,
+            elapsed_time: Duration::from_secs(0),
+            looping: false,
+        }
+    }
+
+    pub fn then(mut self, func: fn(entity: &mut Entity)) -> Self {
+        self.steps.push(SequenceStep::Do(func));
+        self
+    }
+
+    pub fn wait(mut self, seconds: f32) -> Self {
+        self.steps.push(SequenceStep::Wait(seconds_to_duration(seconds)));
+        self
+    }
+
+    pub fn wait_until(mut self, condition: fn(entity: &mut Entity) -> bool) -> Self {
+        self.steps.push(SequenceStep::WaitUntil(condition));
+        self
+    }
+
+    pub fn during(mut self, seconds: f32, func: fn(entity: &mut Entity, progress: f32)) -> Self {
+        self.steps.push(SequenceStep::During(seconds_to_duration(seconds), func));
+        self
+    }
+
+    pub fn looping(mut self) -> Self {
+        self.looping = true;
+        self
+    }
+
+    pub fn reset(&mut self) {
+        self.current_step = 0;
+        self.elapsed_time = Duration::from_secs(0);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.current_step >= self.steps.len()
+    }
+
+    // Runs as many steps as the delta allows. Returns true once the sequence is done.
+    // A looping sequence only starts over once per tick so one without waits can't hang the update loop.
+    pub fn advance(&mut self, entity: &mut Entity, delta: &Duration) -> bool {
+        let mut remaining = *delta;
+        let mut has_looped = false;
+
+        loop {
+            if self.current_step >= self.steps.len() {
+                if !self.looping || self.steps.is_empty() {
+                    return true;
+                }
+
+                if has_looped {
+                    return false;
+                }
+
+                self.reset();
+                has_looped = true;
+            }
+
+            match self.steps[self.current_step] {
+                SequenceStep::Do(func) => {
+                    func(entity);
+                    self.current_step += 1;
+                },
+                SequenceStep::Wait(length) => {
+                    if self.elapsed_time + remaining < length {
+                        self.elapsed_time += remaining;
+                        return false;
+                    }
+
+                    remaining -= length - self.elapsed_time;
+                    self.elapsed_time = Duration::from_secs(0);
+                    self.current_step += 1;
+                },
+                SequenceStep::WaitUntil(condition) => {
+                    if !condition(entity) {
+                        return false;
+                    }
+
+                    self.current_step += 1;
+                },
+                SequenceStep::During(length, func) => {
+                    if self.elapsed_time + remaining < length {
+                        self.elapsed_time += remaining;
+                        func(entity, self.elapsed_time.as_secs_f32() / length.as_secs_f32());
+                        return false;
+                    }
+
+                    func(entity, 1.0);
+                    remaining -= length - self.elapsed_time;
+                    self.elapsed_time = Duration::from_secs(0);
+                    self.current_step += 1;
+                },
+            }
+        }
+    }
+}
+
+// Delays are often computed, so negative and NaN ones count as no delay instead of panicking, and ones too long to
+// represent wait forever.
+fn seconds_to_duration(seconds: f32) -> Duration {
+    Duration::try_from_secs_f32(seconds.max(0.0)).unwrap_or(Duration::MAX)
+}
+
+pub struct ScheduledTask {
+    pub id: TaskId,
+    pub sequence: Sequence,
+}
+
+// Every entity owns one of these, Entity::update advances it with the tick delta.
+pub struct Scheduler {
+    pub tasks: Vec<ScheduledTask>,
+    pub cancelled: Vec<TaskId>,
+    // Set by clear while update is running, so the tasks taken out for the tick get dropped too.
+    pub cleared: bool,
+    pub next_id: TaskId,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::starting_at(0)
+    }
+
+    fn starting_at(next_id: TaskId) -> Self {
+        Scheduler {
+            tasks: Vec::new(),
+            cancelled: Vec::new(),
+            cleared: false,
+            next_id,
+        }
+    }
+
+    pub fn run(&mut self, sequence: Sequence) -> TaskId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.tasks.push(ScheduledTask {
+            id,
+            sequence,
+        });
+
+        id
+    }
+
+    pub fn after(&mut self, seconds: f32, func: fn(entity: &mut Entity)) -> TaskId {
+        self.run(Sequence::new().wait(seconds).then(func))
+    }
+
+    pub fn every(&mut self, seconds: f32, func: fn(entity: &mut Entity)) -> TaskId {
+        self.run(Sequence::new().wait(seconds).then(func).looping())
+    }
+
+    pub fn cancel(&mut self, id: TaskId) {
+        self.tasks.retain(|task| task.id != id);
+
+        // The task may be running right now, in which case it lives in the scheduler taken out by update.
+        self.cancelled.push(id);
+    }
+
+    pub fn clear(&mut self) {
+        for task in &self.tasks {
+            self.cancelled.push(task.id);
+        }
+
+        self.tasks.clear();
+        self.cleared = true;
+    }
+
+    pub fn is_running(&self, id: TaskId) -> bool {
+        self.tasks.iter().any(|task| task.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    // The scheduler is moved out of the entity while its steps run so they can schedule and cancel through entity.scheduler.
+    pub fn update(entity: &mut Entity, delta: &Duration) {
+        if entity.scheduler.tasks.is_empty() {
+            return;
+        }
+
+        let next_id = entity.scheduler.next_id;
+        let mut scheduler = mem::replace(&mut entity.scheduler, Scheduler::starting_at(next_id));
+        scheduler.cancelled.clear();
+        scheduler.cleared = false;
+
+        // Steps run before this one may have cancelled the task or cleared the scheduler through entity.scheduler.
+        scheduler.tasks.retain_mut(|task| {
+            if entity.scheduler.cleared || entity.scheduler.cancelled.contains(&task.id) {
+                return false;
+            }
+
+            !task.sequence.advance(entity, delta)
+        });
+
+        let added = mem::replace(&mut entity.scheduler, scheduler);
+
+        // Tasks scheduled after a clear in the same tick are kept.
+        if added.cleared {
+            entity.scheduler.tasks.clear();
+        }
+
+        entity.scheduler.next_id = added.next_id;
+        entity.scheduler.tasks.extend(added.tasks);
+
+        for id in added.cancelled {
+            entity.scheduler.tasks.retain(|task| task.id != id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(entity: &mut Entity) {
+        let count = entity.variables.take_out::<u32>("count");
+        entity.variables.insert("count", count + 1);
+    }
+
+    fn get_count(entity: &mut Entity) -> u32 {
+        let count = entity.variables.take_out::<u32>("count");
+        entity.variables.insert("count", count);
+        count
+    }
+
+    fn counting_entity() -> Entity {
+        let mut entity = Entity::new();
+        entity.variables.insert("count", 0_u32);
+        entity
+    }
+
+    fn seconds(seconds: f32) -> Duration {
+        Duration::from_secs_f32(seconds)
+    }
+
+    #[test]
+    fn sequence_waits_across_ticks() {
+        let mut entity = counting_entity();
+        let mut sequence = Sequence::new().then(count).wait(1.0).then(count);
+
+        assert!(!sequence.advance(&mut entity, &seconds(0.5)));
+        assert_eq!(get_count(&mut entity), 1);
+
+        assert!(!sequence.advance(&mut entity, &seconds(0.25)));
+        assert_eq!(get_count(&mut entity), 1);
+
+        assert!(sequence.advance(&mut entity, &seconds(0.25)));
+        assert_eq!(get_count(&mut entity), 2);
+        assert!(sequence.is_finished());
+    }
+
+    #[test]
+    fn sequence_carries_leftover_time_into_the_next_wait() {
+        let mut entity = counting_entity();
+        let mut sequence = Sequence::new().wait(1.0).then(count).wait(1.0).then(count);
+
+        assert!(!sequence.advance(&mut entity, &seconds(1.5)));
+        assert_eq!(get_count(&mut entity), 1);
+
+        assert!(sequence.advance(&mut entity, &seconds(0.5)));
+        assert_eq!(get_count(&mut entity), 2);
+    }
+
+    #[test]
+    fn sequence_wait_until_checks_every_tick() {
+        let mut entity = counting_entity();
+        let mut sequence = Sequence::new().wait_until(|entity| entity.variables.contains("ready")).then(count);
+
+        assert!(!sequence.advance(&mut entity, &seconds(1.0)));
+        assert_eq!(get_count(&mut entity), 0);
+
+        entity.variables.insert("ready", true);
+
+        assert!(sequence.advance(&mut entity, &seconds(0.0)));
+        assert_eq!(get_count(&mut entity), 1);
+    }
+
+    #[test]
+    fn sequence_during_reports_progress() {
+        let mut entity = Entity::new();
+        entity.variables.insert("progress", 0.0_f32);
+
+        let mut sequence = Sequence::new().during(2.0, |entity, progress| {
+            entity.variables.take_out::<f32>("progress");
+            entity.variables.insert("progress", progress);
+        });
+
+        assert!(!sequence.advance(&mut entity, &seconds(0.5)));
+        assert_eq!(entity.variables.take_out::<f32>("progress"), 0.25);
+        entity.variables.insert("progress", 0.0_f32);
+
+        assert!(sequence.advance(&mut entity, &seconds(2.0)));
+        assert_eq!(entity.variables.take_out::<f32>("progress"), 1.0);
+    }
+
+    #[test]
+    fn looping_sequence_restarts_once_per_tick() {
+        let mut entity = counting_entity();
+        let mut sequence = Sequence::new().then(count).looping();
+
+        assert!(!sequence.advance(&mut entity, &seconds(1.0)));
+        assert_eq!(get_count(&mut entity), 2);
+        assert!(!sequence.is_finished());
+    }
+
+    #[test]
+    fn negative_and_nan_waits_are_skipped() {
+        let mut entity = counting_entity();
+        let mut sequence = Sequence::new().wait(-1.0).wait(f32::NAN).then(count);
+
+        assert!(sequence.advance(&mut entity, &Duration::ZERO));
+        assert_eq!(get_count(&mut entity), 1);
+    }
+
+    #[test]
+    fn empty_sequence_is_finished() {
+        let mut entity = Entity::new();
+
+        assert!(Sequence::new().advance(&mut entity, &Duration::ZERO));
+        assert!(Sequence::new().looping().advance(&mut entity, &Duration::ZERO));
+    }
+
+    #[test]
+    fn scheduler_runs_after_once_and_every_repeatedly() {
+        let mut entity = counting_entity();
+        let once = entity.scheduler.after(1.0, count);
+        let repeated = entity.scheduler.every(1.0, count);
+
+        for _ in 0..3 {
+            Scheduler::update(&mut entity, &seconds(1.0));
+        }
+
+        assert_eq!(get_count(&mut entity), 4);
+        assert!(!entity.scheduler.is_running(once));
+        assert!(entity.scheduler.is_running(repeated));
+    }
+
+    #[test]
+    fn scheduler_cancel_stops_a_task() {
+        let mut entity = counting_entity();
+        let id = entity.scheduler.every(1.0, count);
+
+        Scheduler::update(&mut entity, &seconds(1.0));
+        entity.scheduler.cancel(id);
+        Scheduler::update(&mut entity, &seconds(1.0));
+
+        assert_eq!(get_count(&mut entity), 1);
+        assert!(entity.scheduler.is_empty());
+    }
+
+    #[test]
+    fn scheduler_cancel_from_a_step() {
+        let mut entity = counting_entity();
+
+        entity.scheduler.after(0.0, |entity| {
+            let victim = entity.variables.take_out::<TaskId>("victim");
+            entity.scheduler.cancel(victim);
+        });
+        let victim = entity.scheduler.every(1.0, count);
+        entity.variables.insert("victim", victim);
+
+        Scheduler::update(&mut entity, &seconds(1.0));
+        Scheduler::update(&mut entity, &seconds(1.0));
+
+        assert_eq!(get_count(&mut entity), 0);
+        assert!(entity.scheduler.is_empty());
+    }
+
+    #[test]
+    fn scheduler_cancel_of_a_task_that_already_ran_this_tick() {
+        let mut entity = counting_entity();
+
+        let victim = entity.scheduler.every(1.0, count);
+        entity.variables.insert("victim", victim);
+        entity.scheduler.after(0.0, |entity| {
+            let victim = entity.variables.take_out::<TaskId>("victim");
+            entity.scheduler.cancel(victim);
+        });
+
+        Scheduler::update(&mut entity, &seconds(1.0));
+        Scheduler::update(&mut entity, &seconds(1.0));
+
+        assert_eq!(get_count(&mut entity), 1);
+        assert!(entity.scheduler.is_empty());
+    }
+
+    #[test]
+    fn scheduler_clear_from_a_step_keeps_tasks_scheduled_afterwards() {
+        let mut entity = counting_entity();
+
+        entity.scheduler.every(1.0, count);
+        entity.scheduler.after(0.0, |entity| {
+            entity.scheduler.clear();
+            entity.scheduler.after(1.0, count);
+        });
+        entity.scheduler.every(1.0, count);
+
+        Scheduler::update(&mut entity, &seconds(1.0));
+        assert_eq!(get_count(&mut entity), 1);
+        assert_eq!(entity.scheduler.len(), 1);
+
+        Scheduler::update(&mut entity, &seconds(1.0));
+        assert_eq!(get_count(&mut entity), 2);
+        assert!(entity.scheduler.is_empty());
+    }
+
+    #[test]
+    fn scheduler_ids_stay_unique_across_updates() {
+        let mut entity = counting_entity();
+        let first = entity.scheduler.after(0.0, |entity| {
+            let id = entity.scheduler.after(1.0, count);
+            entity.variables.insert("second", id);
+        });
+
+        Scheduler::update(&mut entity, &Duration::ZERO);
+
+        let second = entity.variables.take_out::<TaskId>("second");
+        let third = entity.scheduler.after(1.0, count);
+
+        assert_ne!(first, second);
+        assert_ne!(second, third);
+        assert_ne!(first, third);
+    }
+}